@@ -0,0 +1,52 @@
+//! Runs a batch of small, similar programs the way a rules engine would,
+//! and reports how much the shared literal pool saved. Run with
+//! `cargo bench --bench batch`.
+
+use std::fs;
+use std::process::Command;
+use std::time::Instant;
+
+const PROGRAMS: usize = 2000;
+
+fn main() {
+    let dir = std::env::temp_dir().join(format!("lox-batch-{}", std::process::id()));
+    fs::create_dir_all(&dir).expect("failed to create the batch directory");
+    for i in 0..PROGRAMS {
+        let program = format!(
+            r#"var tier = "premium customer";
+var limit = {limit};
+if (tier == "premium customer" and limit > 100) {{
+    print "rule {i} matched: " + "apply the loyalty discount";
+}} else {{
+    print "rule {i} skipped: " + "no discount applies";
+}}
+"#,
+            limit = i * 7 % 300
+        );
+        fs::write(dir.join(format!("rule{:04}.lox", i)), program)
+            .expect("failed to write a batch program");
+    }
+
+    let start = Instant::now();
+    let output = Command::new(env!("CARGO_BIN_EXE_crafting_rust"))
+        .arg("--verbose")
+        .arg("run-all")
+        .arg(&dir)
+        .output()
+        .expect("failed to start the interpreter");
+    let elapsed = start.elapsed();
+    fs::remove_dir_all(&dir).ok();
+    assert!(
+        output.status.success(),
+        "batch failed: {}",
+        String::from_utf8_lossy(&output.stdout)
+    );
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let pool = stderr
+        .lines()
+        .find(|line| line.starts_with("literal pool:"))
+        .expect("no literal pool statistics in --verbose output");
+    println!("batch: {} programs in {:?}", PROGRAMS, elapsed);
+    println!("{}", pool);
+}
@@ -0,0 +1,51 @@
+//! Measures how long the interpreter takes to start and run an empty
+//! program, and how much of that is setting up the scanner. Run with
+//! `cargo bench --bench startup`.
+
+use std::process::Command;
+use std::time::{Duration, Instant};
+
+const RUNS: u32 = 50;
+
+fn main() {
+    let exe = env!("CARGO_BIN_EXE_crafting_rust");
+    let mut total = Duration::ZERO;
+    let mut fastest = Duration::MAX;
+    let mut scanner_setup = 0.0;
+    for _ in 0..RUNS {
+        let start = Instant::now();
+        let output = Command::new(exe)
+            .args(["--verbose", "-e", ""])
+            .output()
+            .expect("failed to start the interpreter");
+        let elapsed = start.elapsed();
+        assert!(
+            output.status.success(),
+            "interpreter exited with {}",
+            output.status
+        );
+        total += elapsed;
+        fastest = fastest.min(elapsed);
+        scanner_setup += phase_millis(&output.stderr, "scanner setup");
+    }
+    println!(
+        "startup: mean {:?}, fastest {:?} over {} runs",
+        total / RUNS,
+        fastest,
+        RUNS
+    );
+    println!(
+        "scanner setup: mean {:.3}ms over {} runs",
+        scanner_setup / RUNS as f64,
+        RUNS
+    );
+}
+
+/// Reads a `phase: 1.234ms` line from `--verbose` output.
+fn phase_millis(stderr: &[u8], phase: &str) -> f64 {
+    String::from_utf8_lossy(stderr)
+        .lines()
+        .find_map(|line| line.strip_prefix(phase)?.strip_prefix(": "))
+        .and_then(|time| time.strip_suffix("ms")?.parse().ok())
+        .unwrap_or_else(|| panic!("no '{}' timing in --verbose output", phase))
+}
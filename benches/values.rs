@@ -0,0 +1,78 @@
+//! Times programs that mostly move values around: calls passing numbers,
+//! strings copied and compared, and a chain of instances. Also prints how
+//! big a `Value` is, since every one of those copies it. Run with
+//! `cargo bench --bench values`.
+
+use std::fs;
+use std::mem::size_of;
+use std::process::Command;
+use std::time::{Duration, Instant};
+
+use crafting_rust::Value;
+
+const RUNS: u32 = 5;
+
+const PROGRAMS: [(&str, &str); 3] = [
+    (
+        "calls",
+        "fun fib(n) { if (n < 2) return n; return fib(n - 1) + fib(n - 2); }
+print fib(25);",
+    ),
+    (
+        "strings",
+        r#"var greeting = "a fairly long string that won't fit inline anywhere";
+var copies = 0;
+for (var i = 0; i < 200000; i = i + 1) {
+    var copy = greeting;
+    if (copy == greeting) copies = copies + 1;
+}
+print copies;"#,
+    ),
+    (
+        "instances",
+        "class Point {}
+var head = nil;
+for (var i = 0; i < 100000; i = i + 1) {
+    var point = Point();
+    point.x = i;
+    point.next = head;
+    head = point;
+}
+var total = 0;
+while (head != nil) {
+    total = total + head.x;
+    head = head.next;
+}
+print total;",
+    ),
+];
+
+fn main() {
+    println!("size of Value: {} bytes", size_of::<Value>());
+
+    let exe = env!("CARGO_BIN_EXE_crafting_rust");
+    let dir = std::env::temp_dir().join(format!("lox-values-{}", std::process::id()));
+    fs::create_dir_all(&dir).expect("failed to create the bench directory");
+    for (name, source) in PROGRAMS {
+        let path = dir.join(format!("{}.lox", name));
+        fs::write(&path, source).expect("failed to write a bench program");
+        let mut fastest = Duration::MAX;
+        for _ in 0..RUNS {
+            let start = Instant::now();
+            let output = Command::new(exe)
+                .arg(&path)
+                .output()
+                .expect("failed to start the interpreter");
+            let elapsed = start.elapsed();
+            assert!(
+                output.status.success(),
+                "{} failed: {}",
+                name,
+                String::from_utf8_lossy(&output.stderr)
+            );
+            fastest = fastest.min(elapsed);
+        }
+        println!("{}: fastest {:?} over {} runs", name, fastest, RUNS);
+    }
+    fs::remove_dir_all(&dir).ok();
+}
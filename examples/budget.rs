@@ -0,0 +1,23 @@
+//! A host running untrusted scripts with a budget, so one that loops
+//! forever is stopped with an error it can report. Run with
+//! `cargo run --example budget`.
+
+use std::time::Duration;
+
+use crafting_rust::budget::Budget;
+use crafting_rust::script_engine::{LoxEngine, ScriptEngine};
+
+fn main() {
+    let mut engine = LoxEngine::new();
+    engine.interpreter().set_budget(Budget {
+        max_steps: Some(10_000),
+        timeout: Some(Duration::from_secs(1)),
+    });
+
+    for source in ["var total = 0; while (true) total = total + 1;", "1 + 2;"] {
+        match engine.eval(source) {
+            Ok(value) => println!("evaluated: {}", value),
+            Err(e) => println!("stopped: {}", e),
+        }
+    }
+}
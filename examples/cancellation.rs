@@ -0,0 +1,27 @@
+//! A host cutting off a script that never finishes: a watchdog thread
+//! cancels it after a deadline, and the engine is used again afterwards.
+//! Run with `cargo run --example cancellation`.
+
+use std::{thread, time::Duration};
+
+use crafting_rust::script_engine::{LoxEngine, ScriptEngine};
+
+fn main() {
+    let mut engine = LoxEngine::new();
+    let token = engine.interpreter().cancellation_token();
+    let watchdog = thread::spawn(move || {
+        thread::sleep(Duration::from_millis(100));
+        token.cancel();
+    });
+
+    match engine.eval("fun spin() { while (true) {} } spin();") {
+        Ok(value) => println!("finished with {}", value),
+        Err(e) => println!("stopped: {}", e),
+    }
+    watchdog.join().unwrap();
+
+    match engine.eval("1 + 2;") {
+        Ok(value) => println!("then evaluated: {}", value),
+        Err(e) => println!("then failed: {}", e),
+    }
+}
@@ -0,0 +1,50 @@
+//! An application that runs plugins through `ScriptEngine` alone, so the
+//! same host code works for any language that implements it. Run with
+//! `cargo run --example script_engine`.
+
+use crafting_rust::script_engine::{LoxEngine, ScriptEngine};
+use crafting_rust::{NativeContext, Value};
+
+const PLUGIN: &str = r#"
+fun transform(title) {
+    return shout(title) + " (" + typeof(title) + ")";
+}
+"#;
+
+/// Loads `plugin` and has it transform `input`. Nothing here knows which
+/// language the plugin is written in.
+fn run_plugin<E: ScriptEngine>(
+    engine: &mut E,
+    plugin: &str,
+    input: E::Value,
+) -> Result<E::Value, E::Error> {
+    engine.load("plugin", plugin)?;
+    engine.call("transform", vec![input])
+}
+
+fn shout(_: &mut NativeContext, args: &[Value]) -> Result<Value, String> {
+    match &args[0] {
+        Value::String(text) => Ok(Value::String(text.to_uppercase().into())),
+        other => Err(format!(
+            "shout expects a string, not {}.",
+            other.type_name()
+        )),
+    }
+}
+
+fn main() {
+    let mut engine = LoxEngine::new();
+    engine.register("shout", 1, shout);
+
+    match run_plugin(&mut engine, PLUGIN, Value::String("hello".into())) {
+        Ok(value) => println!("plugin returned: {}", value),
+        Err(e) => println!("plugin failed: {}", e),
+    }
+    match engine.eval("1 + 2 * 3;") {
+        Ok(value) => println!("eval returned: {}", value),
+        Err(e) => println!("eval failed: {}", e),
+    }
+    if let Err(e) = engine.eval("print 1 +;") {
+        println!("eval failed: {}", e);
+    }
+}
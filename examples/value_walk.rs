@@ -0,0 +1,65 @@
+//! A host rendering values as an outline with `ValueVisitor`, without
+//! matching on `Value` itself. Run with `cargo run --example value_walk`.
+
+use std::{cell::RefCell, rc::Rc};
+
+use crafting_rust::script_engine::{LoxEngine, ScriptEngine};
+use crafting_rust::{Value, ValueVisitor};
+
+/// Prints one line per value, indented by how deep it is.
+#[derive(Default)]
+struct Outline {
+    depth: usize,
+}
+
+impl Outline {
+    fn line(&self, text: &str) {
+        println!("{}{}", "  ".repeat(self.depth), text);
+    }
+}
+
+impl ValueVisitor for Outline {
+    fn visit_nil(&mut self) {
+        self.line("nil");
+    }
+    fn visit_boolean(&mut self, value: bool) {
+        self.line(&value.to_string());
+    }
+    fn visit_number(&mut self, value: f64) {
+        self.line(&value.to_string());
+    }
+    fn visit_string(&mut self, value: &str) {
+        self.line(&format!("{:?}", value));
+    }
+    fn enter_list(&mut self, len: usize) -> bool {
+        self.line(&format!("list of {}", len));
+        self.depth += 1;
+        true
+    }
+    fn exit_list(&mut self) {
+        self.depth -= 1;
+    }
+    fn visit_cycle(&mut self, _value: &Value) {
+        self.line("(the enclosing list again)");
+    }
+    fn visit_other(&mut self, value: &Value) {
+        self.line(&format!("<{}>", value.type_name()));
+    }
+}
+
+fn main() {
+    let mut engine = LoxEngine::new();
+    let inner = Rc::new(RefCell::new(vec![
+        Value::Number(1.0),
+        Value::String("two".into()),
+    ]));
+    let outer = Value::List(Rc::new(RefCell::new(vec![
+        Value::List(Rc::clone(&inner)),
+        Value::Boolean(true),
+        engine.eval("clock;").unwrap_or(Value::None),
+    ])));
+    // A list that holds itself is walked once.
+    inner.borrow_mut().push(Value::List(Rc::clone(&inner)));
+
+    outer.walk(&mut Outline::default());
+}
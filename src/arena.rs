@@ -0,0 +1,263 @@
+//! Where a parsed program's nodes live. Nodes refer to their operands by
+//! `ExprId` and `StmtId`, indices into the arena the parser built, so a
+//! program is flat vectors rather than a tree of boxes. Each parse gets an
+//! arena of its own, freed with the last thing using it: the interpreter
+//! running the program, or a function declared in it.
+
+use std::{collections::HashSet, fmt, sync::OnceLock};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    expression::{Expr, ExprId},
+    source::Span,
+    statement::{ImportTarget, Stmt, StmtId},
+    token::Token,
+};
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct Arena {
+    exprs: Vec<Expr>,
+    stmts: Vec<Stmt>,
+    /// Where the name of each function whose body names `arguments` is,
+    /// worked out the first time it's asked for.
+    #[serde(skip)]
+    reading_arguments: OnceLock<HashSet<Span>>,
+}
+
+impl Arena {
+    pub fn new() -> Arena {
+        Arena::default()
+    }
+
+    pub fn expr(&self, id: ExprId) -> &Expr {
+        &self.exprs[id.index()]
+    }
+
+    pub fn stmt(&self, id: StmtId) -> &Stmt {
+        &self.stmts[id.index()]
+    }
+
+    pub fn alloc_expr(&mut self, expr: Expr) -> ExprId {
+        self.exprs.push(expr);
+        ExprId::new(self.exprs.len() - 1)
+    }
+
+    pub fn alloc_stmt(&mut self, stmt: Stmt) -> StmtId {
+        self.stmts.push(stmt);
+        StmtId::new(self.stmts.len() - 1)
+    }
+
+    /// How many nodes the arena holds, expressions and statements.
+    pub fn len(&self) -> usize {
+        self.exprs.len() + self.stmts.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Whether the function declared as `name` names `arguments` itself,
+    /// so its calls need the list. Functions declared inside it have their
+    /// own.
+    pub fn reads_arguments(&self, name: &Token) -> bool {
+        self.reading_arguments
+            .get_or_init(|| {
+                let functions = self.stmts.iter().filter_map(|stmt| match stmt {
+                    Stmt::Function(name, _, body) => Some((name, body)),
+                    _ => None,
+                });
+                functions
+                    .filter(|(_, body)| body.iter().any(|stmt| self.stmt_names_arguments(*stmt)))
+                    .map(|(name, _)| name.span)
+                    .collect()
+            })
+            .contains(&name.span)
+    }
+
+    fn stmt_names_arguments(&self, id: StmtId) -> bool {
+        let names = |expr: &ExprId| self.expr_names_arguments(*expr);
+        let stmts = |stmts: &[StmtId]| stmts.iter().any(|stmt| self.stmt_names_arguments(*stmt));
+        match self.stmt(id) {
+            Stmt::Expression(expr)
+            | Stmt::Print(_, expr)
+            | Stmt::Var(_, Some(expr))
+            | Stmt::Const(_, expr)
+            | Stmt::Destructure(_, expr)
+            | Stmt::Return(_, Some(expr)) => names(expr),
+            Stmt::Block(body) => stmts(body),
+            Stmt::If(condition, then_branch, else_branch) => {
+                names(condition)
+                    || stmts(&[*then_branch])
+                    || else_branch.is_some_and(|e| stmts(&[e]))
+            }
+            Stmt::While(condition, body) | Stmt::ForIn(_, condition, body) => {
+                names(condition) || stmts(&[*body])
+            }
+            Stmt::Export(_, declaration) => stmts(&[*declaration]),
+            Stmt::Var(_, None)
+            | Stmt::Return(_, None)
+            | Stmt::Function(..)
+            | Stmt::Class(..)
+            | Stmt::Import(..) => false,
+        }
+    }
+
+    fn expr_names_arguments(&self, id: ExprId) -> bool {
+        let names = |expr: &ExprId| self.expr_names_arguments(*expr);
+        match self.expr(id) {
+            Expr::Variable(name) | Expr::Assign(name, _) if name.lexeme() == "arguments" => true,
+            Expr::Grouping(expr)
+            | Expr::Unary(_, expr)
+            | Expr::Assign(_, expr)
+            | Expr::Get(expr, _) => names(expr),
+            Expr::Binary(left, _, right)
+            | Expr::Logical(left, _, right)
+            | Expr::Set(left, _, right) => names(left) || names(right),
+            Expr::Call(callee, _, arguments) => names(callee) || arguments.iter().any(names),
+            Expr::Tuple(_, values) => values.iter().any(names),
+            Expr::Variable(_) | Expr::Literal(_) => false,
+        }
+    }
+
+    /// `node` written out as Lox-like text, for debugging output.
+    pub fn show<T>(&self, node: T) -> Show<'_, T> {
+        Show { arena: self, node }
+    }
+}
+
+/// A node with the arena it's in, so it can be displayed.
+#[derive(Clone, Copy)]
+pub struct Show<'a, T> {
+    arena: &'a Arena,
+    node: T,
+}
+
+impl fmt::Display for Show<'_, ExprId> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let show = |id: &ExprId| self.arena.show(*id);
+        let list = |ids: &[ExprId]| itertools::join(ids.iter().map(show), ", ");
+        match self.arena.expr(self.node) {
+            Expr::Literal(crate::token::Literal::String(s)) => write!(f, "\"{s}\""),
+            Expr::Literal(t) => write!(f, "{t}"),
+            Expr::Grouping(e) => write!(f, "{}", show(e)),
+            Expr::Binary(l, op, r) => write!(f, "({} {op} {})", show(l), show(r)),
+            Expr::Variable(s) => write!(f, "{s}"),
+            Expr::Assign(l, r) => write!(f, "{l} = {}", show(r)),
+            Expr::Logical(l, op, r) => write!(f, "({} {op} {}", show(l), show(r)),
+            Expr::Call(c, _, args) => write!(f, "{}({})", show(c), list(args)),
+            Expr::Get(from, name) => write!(f, "{}.{name}", show(from)),
+            Expr::Set(get, name, value) => write!(f, "{}.{name} = {}", show(get), show(value)),
+            Expr::Unary(op, r) => write!(f, "({op}{})", show(r)),
+            Expr::Tuple(_, values) => write!(f, "{}", list(values)),
+        }
+    }
+}
+
+impl fmt::Display for Show<'_, StmtId> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let expr = |id: &ExprId| self.arena.show(*id);
+        let stmt = |id: &StmtId| self.arena.show(*id);
+        let block = |ids: &[StmtId]| itertools::join(ids.iter().map(stmt), " ");
+        match self.arena.stmt(self.node) {
+            Stmt::Expression(e) => write!(f, "{};", expr(e)),
+            Stmt::Print(_, e) => write!(f, "print {};", expr(e)),
+            Stmt::Var(name, Some(init)) => write!(f, "var {name} = {};", expr(init)),
+            Stmt::Var(name, None) => write!(f, "var {name};"),
+            Stmt::Const(name, init) => write!(f, "const {name} = {};", expr(init)),
+            Stmt::Destructure(names, init) => {
+                write!(
+                    f,
+                    "var ({}) = {};",
+                    itertools::join(names, ", "),
+                    expr(init)
+                )
+            }
+            Stmt::Block(stmts) => write!(f, "{{ {} }}", block(stmts)),
+            Stmt::If(condition, then_stmt, else_stmt) => match else_stmt {
+                Some(e) => write!(
+                    f,
+                    "if ({}) {} else {}",
+                    expr(condition),
+                    stmt(then_stmt),
+                    stmt(e)
+                ),
+                None => write!(f, "if ({}) {}", expr(condition), stmt(then_stmt)),
+            },
+            Stmt::While(condition, body) => {
+                write!(f, "while ({}) {}", expr(condition), stmt(body))
+            }
+            Stmt::ForIn(name, iterable, body) => {
+                write!(f, "for (var {name} in {}) {}", expr(iterable), stmt(body))
+            }
+            Stmt::Function(name, params, body) => write!(
+                f,
+                "fun {name}({}) {{ {} }}",
+                itertools::join(params, ", "),
+                block(body)
+            ),
+            Stmt::Return(_, Some(value)) => write!(f, "return {};", expr(value)),
+            Stmt::Return(_, None) => write!(f, "return;"),
+            Stmt::Class(name, methods) => write!(f, "class {name} {{ {} }}", block(methods)),
+            Stmt::Import(_, path, ImportTarget::All) => write!(f, "import \"{}\";", path.literal()),
+            Stmt::Import(_, path, ImportTarget::Alias(alias)) => {
+                write!(f, "import \"{}\" as {alias};", path.literal())
+            }
+            Stmt::Import(_, path, ImportTarget::Lazy(alias)) => {
+                write!(f, "import lazy \"{}\" as {alias};", path.literal())
+            }
+            Stmt::Import(_, path, ImportTarget::Names(names)) => write!(
+                f,
+                "import {{ {} }} from \"{}\";",
+                itertools::join(names, ", "),
+                path.literal()
+            ),
+            Stmt::Export(_, declaration) => write!(f, "export {}", stmt(declaration)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parser::Parser, scanner::Scanner, source::SourceId};
+
+    fn parse(source: &str) -> (Arena, Vec<StmtId>) {
+        let mut parser = Parser::new(Scanner::new(source, SourceId::ANONYMOUS).tokens());
+        let statements = parser.parse();
+        (parser.into_arena(), statements)
+    }
+
+    #[test]
+    fn each_parse_has_its_own_arena() {
+        let (first, a) = parse("print 1 + 2;");
+        let (second, b) = parse("print 1 + 2;");
+        // The same ids, each meaning a node in its own arena.
+        assert_eq!(a, b);
+        assert_eq!(first.show(a[0]).to_string(), "print (1 + 2);");
+        assert_eq!(second.show(b[0]).to_string(), "print (1 + 2);");
+        assert_eq!(first.len(), 4);
+    }
+
+    #[test]
+    fn operands_are_looked_up_by_id() {
+        let (arena, statements) = parse("print -x;");
+        let Stmt::Print(_, value) = arena.stmt(statements[0]) else {
+            panic!("not a print statement");
+        };
+        let Expr::Unary(op, operand) = arena.expr(*value) else {
+            panic!("not a unary expression");
+        };
+        assert_eq!(op.lexeme(), "-");
+        assert!(matches!(arena.expr(*operand), Expr::Variable(name) if name.lexeme() == "x"));
+    }
+
+    #[test]
+    fn shows_nodes_through_the_arena() {
+        let (arena, statements) = parse("if (a) print b.c(1, 2); else { var d = -e; }");
+        assert_eq!(
+            arena.show(statements[0]).to_string(),
+            "if (a) print b.c(1, 2); else { var d = (-e); }"
+        );
+    }
+}
@@ -0,0 +1,102 @@
+//! The syntax tree as JSON, for `jlox ast --json`, so linters, code
+//! generators and visualizers can read parsed programs without a Lox
+//! parser of their own, and so a parsed program can be read back in.
+//!
+//! A program is an object with its top-level `statements` and the `arena`
+//! they're in. Nodes are as serde derives them from `Expr` and `Stmt`: an
+//! object whose one key names the variant, with its fields in order.
+//! Operands are indices into the arena's `exprs` and `stmts` arrays.
+//! Tokens carry their type, lexeme, line, column, byte range and source,
+//! plus the literal for strings and numbers. A missing optional part, such
+//! as a `var` without an initializer, is `null`.
+
+use serde::{Deserialize, Serialize};
+
+use crate::{arena::Arena, statement::StmtId};
+
+#[derive(Serialize, Deserialize)]
+struct Program {
+    statements: Vec<StmtId>,
+    arena: Arena,
+}
+
+/// `statements`, parsed into `arena`, as JSON.
+pub fn to_json(arena: &Arena, statements: &[StmtId]) -> String {
+    let program = Program {
+        statements: statements.to_vec(),
+        arena: arena.clone(),
+    };
+    serde_json::to_string(&program).expect("a syntax tree has only JSON-representable parts")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parser::Parser, scanner::Scanner, source::SourceId};
+
+    fn parse(source: &str) -> (Arena, Vec<StmtId>) {
+        let mut parser = Parser::new(Scanner::new(source, SourceId::ANONYMOUS).tokens());
+        let statements = parser.parse();
+        (parser.into_arena(), statements)
+    }
+
+    fn show(arena: &Arena, statements: &[StmtId]) -> Vec<String> {
+        statements
+            .iter()
+            .map(|&statement| arena.show(statement).to_string())
+            .collect()
+    }
+
+    #[test]
+    fn round_trips_a_program() {
+        let source = "var a = 1;\n\
+                      const name = \"lox\";\n\
+                      fun add(x, y) { return x + y; }\n\
+                      class Point { sum() { return this.x + this.y; } }\n\
+                      if (a >= 1 and !false) print add(a, 2); else { a = nil; }\n\
+                      for (var i = 0; i < 3; i = i + 1) print i;\n\
+                      import \"lib.lox\" as lib;\n";
+        let (arena, statements) = parse(source);
+        let json = to_json(&arena, &statements);
+        let Program {
+            statements: read_statements,
+            arena: read,
+        } = serde_json::from_str(&json).unwrap();
+
+        assert_eq!(show(&read, &read_statements), show(&arena, &statements));
+        // The second trip changes nothing but the byte ranges, which now
+        // count from each lexeme.
+        let again = to_json(&read, &read_statements);
+        let strip = |json: &str| {
+            let mut value: serde_json::Value = serde_json::from_str(json).unwrap();
+            strip_ranges(&mut value);
+            value
+        };
+        assert_eq!(strip(&again), strip(&json));
+    }
+
+    fn strip_ranges(value: &mut serde_json::Value) {
+        match value {
+            serde_json::Value::Object(fields) => {
+                fields.remove("start");
+                fields.remove("end");
+                fields.values_mut().for_each(strip_ranges);
+            }
+            serde_json::Value::Array(values) => values.iter_mut().for_each(strip_ranges),
+            _ => {}
+        }
+    }
+
+    #[test]
+    fn writes_tokens_with_their_literals() {
+        let (arena, statements) = parse("print \"hi\";");
+        let json: serde_json::Value = serde_json::from_str(&to_json(&arena, &statements)).unwrap();
+        let print = &json["arena"]["stmts"][0]["Print"];
+        assert_eq!(print[0]["type"], "Print");
+        assert_eq!(print[0]["lexeme"], "print");
+        assert_eq!(print[0]["line"], 1);
+        assert_eq!(print[0].get("literal"), None);
+        let string = &json["arena"]["exprs"][print[1].as_u64().unwrap() as usize];
+        assert_eq!(string["Literal"], "hi");
+    }
+}
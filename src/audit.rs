@@ -0,0 +1,145 @@
+//! `jlox audit`: lists the natives a script and the modules it imports may
+//! call, without running anything, so that a script can be reviewed before
+//! it's given `--allow-fs` or run outside a sandbox.
+
+use std::{
+    collections::{BTreeMap, BTreeSet, HashSet},
+    fs,
+    path::{Path, PathBuf},
+    sync::atomic::Ordering,
+};
+
+use crate::{
+    arena::Arena,
+    dependencies, diagnostics,
+    natives::{self, Effect},
+    output::StdoutSink,
+    parser::Parser,
+    scanner::Scanner,
+    source::SourceId,
+    statement::{Stmt, StmtId},
+    token::Literal,
+};
+
+/// Prints the natives the script at `path` may call and returns the exit
+/// code: 1 if any has an effect that `allowed` doesn't name, either by the
+/// native's name or by its effect's.
+pub fn run(path: &str, allowed: &[String]) -> i32 {
+    let mut audit = Audit::default();
+    if let Err(code) = audit.file(Path::new(path), path.to_string()) {
+        return code;
+    }
+
+    let name_width = audit.uses.keys().map(String::len).max().unwrap_or(0);
+    let mut denied = 0;
+    println!("{} may call {} native(s):", path, audit.uses.len());
+    for (name, files) in &audit.uses {
+        let (effect, status) = match natives::effect(name) {
+            None => ("", ""),
+            Some(effect) if is_allowed(name, effect, allowed) => (effect.name(), "allowed"),
+            Some(effect) => {
+                denied += 1;
+                (effect.name(), "NOT ALLOWED")
+            }
+        };
+        let files: Vec<&str> = files.iter().map(String::as_str).collect();
+        println!(
+            "  {:<name_width$}  {:<7}  {:<11}  {}",
+            name,
+            effect,
+            status,
+            files.join(", ")
+        );
+    }
+    if denied > 0 {
+        println!("{} native(s) with effects are not allowed.", denied);
+        println!("Allow them with --allow <native or effect>.");
+        return 1;
+    }
+    0
+}
+
+fn is_allowed(name: &str, effect: Effect, allowed: &[String]) -> bool {
+    allowed
+        .iter()
+        .any(|allowed| allowed == name || allowed == effect.name())
+}
+
+#[derive(Default)]
+struct Audit {
+    // Each native used, with the files that use it.
+    uses: BTreeMap<String, BTreeSet<String>>,
+    visited: HashSet<PathBuf>,
+}
+
+impl Audit {
+    /// Records the natives `path` uses, then those of the modules it
+    /// imports. Errors are printed; the exit code is returned.
+    fn file(&mut self, path: &Path, name: String) -> Result<(), i32> {
+        let full_path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+        if !self.visited.insert(full_path) {
+            return Ok(());
+        }
+        let source = fs::read_to_string(path).map_err(|e| {
+            println!("Could not read {}: {}.", name, e);
+            66
+        })?;
+
+        crate::HAD_ERROR.store(false, Ordering::Relaxed);
+        diagnostics::reset();
+        let tokens = Scanner::new(&source, SourceId::register(&name)).tokens();
+        let mut parser = Parser::new(tokens);
+        let statements = parser.parse();
+        let arena = parser.into_arena();
+        diagnostics::flush(&StdoutSink);
+        if crate::HAD_ERROR.load(Ordering::Relaxed) {
+            return Err(65);
+        }
+
+        for read in dependencies::of_program(&arena, &statements).reads {
+            if natives::is_native(&read) {
+                self.uses.entry(read).or_default().insert(name.clone());
+            }
+        }
+
+        let dir = path.parent().unwrap_or(Path::new("."));
+        let mut modules = Vec::new();
+        imports(&arena, &statements, &mut modules);
+        for module in modules {
+            let module_path = dir.join(&module);
+            self.file(&module_path, module_path.display().to_string())?;
+        }
+        Ok(())
+    }
+}
+
+/// The paths of every module imported anywhere in `statements`.
+fn imports(arena: &Arena, statements: &[StmtId], paths: &mut Vec<String>) {
+    for statement in statements {
+        match arena.stmt(*statement) {
+            Stmt::Import(_, path, _) => {
+                if let Literal::String(path) = &path.literal() {
+                    paths.push(path.to_string());
+                }
+            }
+            Stmt::Block(body) | Stmt::Function(_, _, body) | Stmt::Class(_, body) => {
+                imports(arena, body, paths)
+            }
+            Stmt::If(_, then_stmt, else_stmt) => {
+                imports(arena, &[*then_stmt], paths);
+                if let Some(else_stmt) = else_stmt {
+                    imports(arena, &[*else_stmt], paths);
+                }
+            }
+            Stmt::While(_, body) | Stmt::ForIn(_, _, body) | Stmt::Export(_, body) => {
+                imports(arena, &[*body], paths)
+            }
+            Stmt::Expression(_)
+            | Stmt::Print(..)
+            | Stmt::Var(..)
+            | Stmt::Const(..)
+            | Stmt::Destructure(..)
+            | Stmt::Return(..) => {}
+        }
+    }
+}
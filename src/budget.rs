@@ -0,0 +1,44 @@
+//! Limits on how much work a run may do, for hosts running code they don't
+//! trust: a script that goes past them is stopped rather than left to spin.
+
+use std::{fmt, time::Duration};
+
+/// How many steps a run may take, and for how long. A step is a statement
+/// executed, so a loop takes at least one per iteration and a call one per
+/// statement in the function's body. Each run (a call to `interpret`, or a
+/// host calling a function) starts with the whole budget.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Budget {
+    pub max_steps: Option<u64>,
+    pub timeout: Option<Duration>,
+}
+
+impl Budget {
+    pub fn is_unlimited(&self) -> bool {
+        self.max_steps.is_none() && self.timeout.is_none()
+    }
+}
+
+/// What an interpreter that went past its budget unwinds with, as the
+/// panic payload, so a host catching the unwind can tell it from a runtime
+/// error. The panic hook isn't run for it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BudgetExceeded {
+    Steps(u64),
+    Timeout(Duration),
+}
+
+impl fmt::Display for BudgetExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BudgetExceeded::Steps(steps) => {
+                write!(f, "Execution took more than {} steps.", steps)
+            }
+            BudgetExceeded::Timeout(timeout) => {
+                write!(f, "Execution took more than {}ms.", timeout.as_millis())
+            }
+        }
+    }
+}
+
+impl std::error::Error for BudgetExceeded {}
@@ -0,0 +1,135 @@
+//! `jlox bundle`: a copy of the interpreter with a script appended, which
+//! runs the script when started, so a Lox tool can be handed to someone
+//! without jlox installed.
+//!
+//! The script's source and file name go after the executable's own bytes,
+//! then a trailer with their lengths, the capabilities the script was
+//! bundled with and a magic number. Executables ignore what's appended to
+//! them, and the copy finds its script from the end of the file without
+//! reading the rest. Modules the script imports aren't bundled: they're
+//! looked for relative to the directory the tool is run from.
+
+use std::{
+    env,
+    fs::{self, File},
+    io::{self, Read, Seek, SeekFrom},
+    path::Path,
+    sync::atomic::Ordering,
+};
+
+use crate::{
+    cli::Options, diagnostics, output::StdoutSink, parser::Parser, scanner::Scanner,
+    source::SourceId, HAD_ERROR,
+};
+
+const MAGIC: &[u8; 8] = b"jloxbndl";
+/// The source's length, the name's length, the capabilities, and `MAGIC`.
+const TRAILER_LEN: u64 = 8 + 8 + 1 + 8;
+
+const ALLOW_FS: u8 = 1;
+const ALLOW_ENV: u8 = 2;
+
+/// A script bundled into an executable.
+pub struct Bundled {
+    /// The file name it was bundled from, which errors are reported in.
+    pub name: String,
+    pub source: String,
+    pub allow_fs: bool,
+    pub allow_env: bool,
+}
+
+/// Writes a copy of this executable to `output` that runs the script at
+/// `path`, with `--allow-fs` and `--allow-env` as given, and returns the
+/// exit code.
+pub fn run(path: &str, output: &str, options: &Options) -> i32 {
+    let source = match fs::read_to_string(path) {
+        Ok(source) => source,
+        Err(e) => {
+            println!("Could not read {}: {}.", path, e);
+            return 66;
+        }
+    };
+    // A tool that can't parse would only fail once it's been handed out.
+    diagnostics::reset();
+    Parser::new(Scanner::new(&source, SourceId::register(path)).scan_tokens()).parse();
+    diagnostics::flush(&StdoutSink);
+    if HAD_ERROR.load(Ordering::Relaxed) {
+        return 65;
+    }
+
+    let name = Path::new(path)
+        .file_name()
+        .map_or(path.to_string(), |name| name.to_string_lossy().into_owned());
+    let mut capabilities = 0;
+    if options.allow_fs {
+        capabilities |= ALLOW_FS;
+    }
+    if options.allow_env {
+        capabilities |= ALLOW_ENV;
+    }
+    match write(output, &source, &name, capabilities) {
+        Ok(()) => 0,
+        Err(e) => {
+            println!("Could not write {}: {}.", output, e);
+            74
+        }
+    }
+}
+
+fn write(output: &str, source: &str, name: &str, capabilities: u8) -> io::Result<()> {
+    let executable = env::current_exe()?;
+    let mut bytes = fs::read(&executable)?;
+    bytes.extend_from_slice(source.as_bytes());
+    bytes.extend_from_slice(name.as_bytes());
+    bytes.extend_from_slice(&(source.len() as u64).to_le_bytes());
+    bytes.extend_from_slice(&(name.len() as u64).to_le_bytes());
+    bytes.push(capabilities);
+    bytes.extend_from_slice(MAGIC);
+    fs::write(output, bytes)?;
+    fs::set_permissions(output, fs::metadata(&executable)?.permissions())
+}
+
+/// The script bundled into this executable, if it's a bundle.
+pub fn embedded() -> Option<Bundled> {
+    let mut file = File::open(env::current_exe().ok()?).ok()?;
+    let (start, trailer) = trailer(&mut file)?;
+    file.seek(SeekFrom::Start(start)).ok()?;
+    let mut source = vec![0; trailer.source_len as usize];
+    let mut name = vec![0; trailer.name_len as usize];
+    file.read_exact(&mut source).ok()?;
+    file.read_exact(&mut name).ok()?;
+    Some(Bundled {
+        name: String::from_utf8(name).ok()?,
+        source: String::from_utf8(source).ok()?,
+        allow_fs: trailer.capabilities & ALLOW_FS != 0,
+        allow_env: trailer.capabilities & ALLOW_ENV != 0,
+    })
+}
+
+struct Trailer {
+    source_len: u64,
+    name_len: u64,
+    capabilities: u8,
+}
+
+/// The trailer at the end of `file`, if it has one, and where the bundled
+/// script starts, which is also how long the executable before it is.
+fn trailer(file: &mut File) -> Option<(u64, Trailer)> {
+    let length = file.seek(SeekFrom::End(0)).ok()?;
+    file.seek(SeekFrom::Start(length.checked_sub(TRAILER_LEN)?))
+        .ok()?;
+    let mut bytes = [0; TRAILER_LEN as usize];
+    file.read_exact(&mut bytes).ok()?;
+    if &bytes[17..] != MAGIC {
+        return None;
+    }
+    let trailer = Trailer {
+        source_len: u64::from_le_bytes(bytes[0..8].try_into().unwrap()),
+        name_len: u64::from_le_bytes(bytes[8..16].try_into().unwrap()),
+        capabilities: bytes[16],
+    };
+    let start = (length - TRAILER_LEN)
+        .checked_sub(trailer.source_len)?
+        .checked_sub(trailer.name_len)?;
+    Some((start, trailer))
+}
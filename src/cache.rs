@@ -0,0 +1,157 @@
+//! Parsed programs kept beside their scripts, so `jlox run` can skip
+//! scanning, parsing and folding a script that hasn't changed since it
+//! last ran.
+//!
+//! `script.lox` is cached in `script.loxc`: its folded syntax tree as
+//! JSON, under a header naming the cache format, the jlox build that wrote
+//! it, the script's name and a hash of its source. A cache that doesn't
+//! match on all of them is ignored, and rewritten once the script has been
+//! parsed again.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{arena::Arena, source::SourceId, statement::StmtId};
+
+/// Changed whenever the syntax tree's serialized form does.
+const FORMAT: u32 = 1;
+
+/// What a cache has to match before its program is read.
+#[derive(Serialize, Deserialize, PartialEq)]
+struct Header {
+    format: u32,
+    jlox: String,
+    script: String,
+    hash: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Cached {
+    #[serde(flatten)]
+    header: Header,
+    statements: Vec<StmtId>,
+    arena: Arena,
+}
+
+/// Where the program parsed from `script` is cached.
+pub fn path(script: &Path) -> PathBuf {
+    script.with_extension("loxc")
+}
+
+/// The program cached at `path`, if it was parsed from `source` as the
+/// script `source_id` by this build of jlox.
+pub fn load(path: &Path, source: &str, source_id: SourceId) -> Option<(Arena, Vec<StmtId>)> {
+    let text = fs::read_to_string(path).ok()?;
+    // Reading the tree registers its lexemes and literals, so check the
+    // header before that.
+    let header: Header = serde_json::from_str(&text).ok()?;
+    if header != Header::new(source, source_id) {
+        return None;
+    }
+    let cached: Cached = serde_json::from_str(&text).ok()?;
+    Some((cached.arena, cached.statements))
+}
+
+/// Caches the program parsed from `source` at `path`. A cache that can't
+/// be written is only a slower next run, so that isn't reported.
+pub fn save(path: &Path, source: &str, source_id: SourceId, arena: &Arena, statements: &[StmtId]) {
+    let cached = Cached {
+        header: Header::new(source, source_id),
+        statements: statements.to_vec(),
+        arena: arena.clone(),
+    };
+    // Folding never makes a NaN or an infinity, which JSON can't hold, but
+    // a program that doesn't serialize just goes uncached.
+    if let Ok(json) = serde_json::to_string(&cached) {
+        let _ = fs::write(path, json);
+    }
+}
+
+impl Header {
+    fn new(source: &str, source_id: SourceId) -> Header {
+        Header {
+            format: FORMAT,
+            jlox: build(),
+            script: source_id.name(),
+            hash: hash(source),
+        }
+    }
+}
+
+/// This build's version, and the features that change how source is
+/// scanned.
+fn build() -> String {
+    let mut build = env!("CARGO_PKG_VERSION").to_string();
+    if cfg!(feature = "units") {
+        build.push_str("+units");
+    }
+    build
+}
+
+/// 64-bit FNV-1a, which unlike `DefaultHasher` is the same in every
+/// build.
+fn hash(source: &str) -> u64 {
+    source.bytes().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parser::Parser, scanner::Scanner};
+
+    fn cache_file(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("jlox-cache-{}-{}.loxc", std::process::id(), name))
+    }
+
+    fn parse(source: &str, source_id: SourceId) -> (Arena, Vec<StmtId>) {
+        let mut parser = Parser::new(Scanner::new(source, source_id).tokens());
+        let statements = parser.parse();
+        (parser.into_arena(), statements)
+    }
+
+    #[test]
+    fn loads_what_was_saved_for_the_same_source() {
+        let path = cache_file("same");
+        let source = "var a = \"x\" + \"y\";\nprint a;\n";
+        let source_id = SourceId::register("same.lox");
+        let (arena, statements) = parse(source, source_id);
+        save(&path, source, source_id, &arena, &statements);
+
+        let (loaded, loaded_statements) = load(&path, source, source_id).unwrap();
+        let show = |arena: &Arena, statements: &[StmtId]| {
+            statements
+                .iter()
+                .map(|&statement| arena.show(statement).to_string())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(show(&loaded, &loaded_statements), show(&arena, &statements));
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn ignores_a_cache_of_other_source() {
+        let path = cache_file("changed");
+        let source_id = SourceId::register("changed.lox");
+        let (arena, statements) = parse("print 1;", source_id);
+        save(&path, "print 1;", source_id, &arena, &statements);
+
+        assert!(load(&path, "print 2;", source_id).is_none());
+        assert!(load(&path, "print 1;", SourceId::register("other.lox")).is_none());
+        fs::write(&path, "{\"format\":1").unwrap();
+        assert!(load(&path, "print 1;", source_id).is_none());
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn hashes_with_fnv_1a() {
+        assert_eq!(hash(""), 0xcbf29ce484222325);
+        assert_eq!(hash("a"), 0xaf63dc4c8601ec8c);
+        assert_ne!(hash("print 1;"), hash("print 2;"));
+    }
+}
@@ -0,0 +1,405 @@
+//! `jlox callgraph`: which functions call which, worked out from the source
+//! without running it.
+//!
+//! A call is resolved when its callee is a name that, following Lox's
+//! scoping, can only refer to a function or class declaration or a native.
+//! Anything else (a variable, a parameter, a method on some object, the
+//! result of another call) could be any function at runtime, so it's listed
+//! as a dynamic call instead.
+
+use std::{
+    collections::{BTreeSet, HashMap},
+    fmt::Write,
+};
+
+use crate::{
+    arena::Arena,
+    expression::{Expr, ExprId},
+    natives,
+    statement::{ImportTarget, Stmt, StmtId},
+    token::Token,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Script,
+    Function,
+    Class,
+    Method,
+    Native,
+}
+
+struct Node {
+    name: String,
+    kind: Kind,
+    line: Option<u32>,
+}
+
+/// A call that couldn't be resolved: who makes it, what the callee looks
+/// like in the source, and where.
+struct DynamicCall {
+    caller: usize,
+    callee: String,
+    line: u32,
+}
+
+pub struct CallGraph {
+    nodes: Vec<Node>,
+    edges: BTreeSet<(usize, usize)>,
+    dynamic: Vec<DynamicCall>,
+}
+
+#[derive(Clone, Copy)]
+enum Binding {
+    Callable(usize),
+    Value,
+}
+
+pub fn build(arena: &Arena, statements: &[StmtId]) -> CallGraph {
+    let mut builder = Builder {
+        arena,
+        graph: CallGraph {
+            nodes: vec![Node {
+                name: "<script>".to_string(),
+                kind: Kind::Script,
+                line: None,
+            }],
+            edges: BTreeSet::new(),
+            dynamic: Vec::new(),
+        },
+        scopes: vec![HashMap::new()],
+        current: 0,
+    };
+    // Globals are looked up when a call runs, so a function may call one
+    // declared after it.
+    for stmt in statements {
+        builder.hoist(*stmt);
+    }
+    builder.stmts(statements);
+    builder.graph
+}
+
+struct Builder<'a> {
+    arena: &'a Arena,
+    graph: CallGraph,
+    scopes: Vec<HashMap<String, Binding>>,
+    // The node whose body is being walked.
+    current: usize,
+}
+
+impl Builder<'_> {
+    fn add(&mut self, name: String, kind: Kind, line: Option<u32>) -> usize {
+        self.graph.nodes.push(Node { name, kind, line });
+        self.graph.nodes.len() - 1
+    }
+
+    /// The name a declaration inside the current node is shown with.
+    fn qualified(&self, name: &Token) -> String {
+        match self.graph.nodes[self.current].kind {
+            Kind::Script => name.lexeme().to_string(),
+            _ => format!("{}.{}", self.graph.nodes[self.current].name, name.lexeme()),
+        }
+    }
+
+    fn declare(&mut self, name: &Token, binding: Binding) {
+        self.scopes
+            .last_mut()
+            .unwrap()
+            .insert(name.lexeme().to_string(), binding);
+    }
+
+    fn hoist(&mut self, stmt: StmtId) {
+        match self.arena.stmt(stmt) {
+            Stmt::Function(name, _, _) => {
+                let node = self.add(name.lexeme().to_string(), Kind::Function, Some(name.line));
+                self.declare(name, Binding::Callable(node));
+            }
+            Stmt::Class(name, _) => {
+                let node = self.add(name.lexeme().to_string(), Kind::Class, Some(name.line));
+                self.declare(name, Binding::Callable(node));
+            }
+            Stmt::Export(_, declaration) => self.hoist(*declaration),
+            _ => {}
+        }
+    }
+
+    /// The node a hoisted global declaration was given.
+    fn hoisted(&self, name: &Token) -> Option<usize> {
+        match self.scopes[0].get(name.lexeme()) {
+            Some(Binding::Callable(node)) => Some(*node),
+            _ => None,
+        }
+    }
+
+    fn stmts(&mut self, statements: &[StmtId]) {
+        for stmt in statements {
+            self.stmt(*stmt);
+        }
+    }
+
+    fn stmt(&mut self, stmt: StmtId) {
+        match self.arena.stmt(stmt) {
+            Stmt::Expression(e) | Stmt::Print(_, e) => self.expr(*e),
+            Stmt::Var(name, init) => {
+                if let Some(init) = init {
+                    self.expr(*init);
+                }
+                self.declare(name, Binding::Value);
+            }
+            Stmt::Const(name, init) => {
+                self.expr(*init);
+                self.declare(name, Binding::Value);
+            }
+            Stmt::Destructure(names, init) => {
+                self.expr(*init);
+                for name in names {
+                    self.declare(name, Binding::Value);
+                }
+            }
+            Stmt::Block(body) => {
+                self.scopes.push(HashMap::new());
+                self.stmts(body);
+                self.scopes.pop();
+            }
+            Stmt::If(condition, then_stmt, else_stmt) => {
+                self.expr(*condition);
+                self.stmt(*then_stmt);
+                if let Some(else_stmt) = else_stmt {
+                    self.stmt(*else_stmt);
+                }
+            }
+            Stmt::While(condition, body) => {
+                self.expr(*condition);
+                self.stmt(*body);
+            }
+            Stmt::ForIn(name, iterable, body) => {
+                self.expr(*iterable);
+                self.scopes.push(HashMap::new());
+                self.declare(name, Binding::Value);
+                self.stmt(*body);
+                self.scopes.pop();
+            }
+            Stmt::Function(name, params, body) => {
+                let node = match (self.scopes.len(), self.hoisted(name)) {
+                    (1, Some(node)) => node,
+                    _ => {
+                        let name_text = self.qualified(name);
+                        let node = self.add(name_text, Kind::Function, Some(name.line));
+                        self.declare(name, Binding::Callable(node));
+                        node
+                    }
+                };
+                self.function(node, params, body);
+            }
+            Stmt::Class(name, methods) => {
+                let class = match (self.scopes.len(), self.hoisted(name)) {
+                    (1, Some(node)) => node,
+                    _ => {
+                        let name_text = self.qualified(name);
+                        let node = self.add(name_text, Kind::Class, Some(name.line));
+                        self.declare(name, Binding::Callable(node));
+                        node
+                    }
+                };
+                for method in methods {
+                    if let Stmt::Function(method_name, params, body) = self.arena.stmt(*method) {
+                        let name_text =
+                            format!("{}.{}", self.graph.nodes[class].name, method_name.lexeme());
+                        let node = self.add(name_text, Kind::Method, Some(method_name.line));
+                        // Calling the class runs its initializer.
+                        if method_name.lexeme() == "init" {
+                            self.graph.edges.insert((class, node));
+                        }
+                        self.function(node, params, body);
+                    }
+                }
+            }
+            Stmt::Return(_, value) => {
+                if let Some(value) = value {
+                    self.expr(*value);
+                }
+            }
+            // What a module exports isn't known without loading it, so
+            // imported names are plain values here.
+            Stmt::Import(_, _, target) => match target {
+                ImportTarget::All => {}
+                ImportTarget::Alias(alias) | ImportTarget::Lazy(alias) => {
+                    self.declare(alias, Binding::Value)
+                }
+                ImportTarget::Names(names) => {
+                    for name in names {
+                        self.declare(name, Binding::Value);
+                    }
+                }
+            },
+            Stmt::Export(_, declaration) => self.stmt(*declaration),
+        }
+    }
+
+    fn function(&mut self, node: usize, params: &[Token], body: &[StmtId]) {
+        let caller = self.current;
+        self.current = node;
+        self.scopes.push(HashMap::new());
+        for param in params {
+            self.declare(param, Binding::Value);
+        }
+        self.stmts(body);
+        self.scopes.pop();
+        self.current = caller;
+    }
+
+    fn expr(&mut self, expr: ExprId) {
+        match self.arena.expr(expr) {
+            Expr::Literal(_) | Expr::Variable(_) => {}
+            Expr::Assign(_, value) => self.expr(*value),
+            Expr::Grouping(e) | Expr::Unary(_, e) | Expr::Get(e, _) => self.expr(*e),
+            Expr::Binary(l, _, r) | Expr::Logical(l, _, r) | Expr::Set(l, _, r) => {
+                self.expr(*l);
+                self.expr(*r);
+            }
+            Expr::Call(callee, paren, args) => {
+                self.call(*callee, paren);
+                self.expr(*callee);
+                for arg in args {
+                    self.expr(*arg);
+                }
+            }
+            Expr::Tuple(_, values) => {
+                for value in values {
+                    self.expr(*value);
+                }
+            }
+        }
+    }
+
+    fn call(&mut self, callee: ExprId, paren: &Token) {
+        let target = match self.arena.expr(callee) {
+            Expr::Variable(name) => self.resolve(name),
+            _ => None,
+        };
+        match target {
+            Some(node) => {
+                self.graph.edges.insert((self.current, node));
+            }
+            None => self.graph.dynamic.push(DynamicCall {
+                caller: self.current,
+                callee: self.arena.show(callee).to_string(),
+                line: paren.line,
+            }),
+        }
+    }
+
+    /// The declaration a called name must refer to, if there's only one.
+    fn resolve(&mut self, name: &Token) -> Option<usize> {
+        for scope in self.scopes.iter().rev() {
+            match scope.get(name.lexeme()) {
+                Some(Binding::Callable(node)) => return Some(*node),
+                Some(Binding::Value) => return None,
+                None => {}
+            }
+        }
+        if !natives::is_native(name.lexeme()) {
+            return None;
+        }
+        let existing = self
+            .graph
+            .nodes
+            .iter()
+            .position(|node| node.kind == Kind::Native && node.name == name.lexeme());
+        Some(existing.unwrap_or_else(|| self.add(name.lexeme().to_string(), Kind::Native, None)))
+    }
+}
+
+impl CallGraph {
+    /// Each node with the calls it makes, dynamic ones included, then the
+    /// functions and classes nothing else calls.
+    pub fn to_text(&self) -> String {
+        let mut out = String::new();
+        for (index, node) in self.nodes.iter().enumerate() {
+            if node.kind == Kind::Native {
+                continue;
+            }
+            let _ = writeln!(out, "{}", self.describe(index));
+            for &(_, callee) in self.edges.iter().filter(|(caller, _)| *caller == index) {
+                let _ = writeln!(out, "  -> {}", self.describe(callee));
+            }
+            for call in self.dynamic.iter().filter(|call| call.caller == index) {
+                let _ = writeln!(out, "  -> ? {} (dynamic, line {})", call.callee, call.line);
+            }
+        }
+
+        let uncalled: Vec<&str> = self
+            .nodes
+            .iter()
+            .enumerate()
+            .filter(|(index, node)| {
+                // Methods are only ever called through an instance, which
+                // this can't follow.
+                matches!(node.kind, Kind::Function | Kind::Class)
+                    && !self
+                        .edges
+                        .iter()
+                        .any(|(caller, callee)| callee == index && caller != callee)
+            })
+            .map(|(_, node)| node.name.as_str())
+            .collect();
+        if !uncalled.is_empty() {
+            let _ = writeln!(out, "\nNever called directly: {}", uncalled.join(", "));
+        }
+        out
+    }
+
+    /// The graph in Graphviz's DOT language. Natives are dashed boxes, and
+    /// dynamic calls are dashed red edges to a `?` node each.
+    pub fn to_dot(&self) -> String {
+        let mut out = String::from("digraph calls {\n  node [shape=box];\n");
+        for (index, node) in self.nodes.iter().enumerate() {
+            let style = match node.kind {
+                Kind::Script => ", shape=ellipse",
+                Kind::Class => ", shape=component",
+                Kind::Native => ", style=dashed",
+                Kind::Function | Kind::Method => "",
+            };
+            let label = match node.line {
+                Some(line) => format!("{}\\nline {}", node.name, line),
+                None => node.name.clone(),
+            };
+            let _ = writeln!(out, "  n{} [label={}{}];", index, dot_string(&label), style);
+        }
+        for (caller, callee) in &self.edges {
+            let _ = writeln!(out, "  n{} -> n{};", caller, callee);
+        }
+        for (index, call) in self.dynamic.iter().enumerate() {
+            let label = format!("{} ?", call.callee);
+            let _ = writeln!(
+                out,
+                "  d{} [label={}, shape=plaintext, fontcolor=red];",
+                index,
+                dot_string(&label)
+            );
+            let _ = writeln!(
+                out,
+                "  n{} -> d{} [style=dashed, color=red, label=\"line {}\"];",
+                call.caller, index, call.line
+            );
+        }
+        out.push_str("}\n");
+        out
+    }
+
+    fn describe(&self, index: usize) -> String {
+        let node = &self.nodes[index];
+        match (node.kind, node.line) {
+            (Kind::Native, _) => format!("{} (native)", node.name),
+            (Kind::Class, Some(line)) => format!("{} (class, line {})", node.name, line),
+            (_, Some(line)) => format!("{} (line {})", node.name, line),
+            (_, None) => node.name.clone(),
+        }
+    }
+}
+
+/// A DOT string literal. Backslashes are left alone so `\n` still breaks a
+/// label's line.
+fn dot_string(text: &str) -> String {
+    format!("\"{}\"", text.replace('"', "\\\""))
+}
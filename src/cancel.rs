@@ -0,0 +1,56 @@
+//! Stopping a running script from outside, for hosts that must cut off a
+//! runaway evaluation without killing the thread running it.
+
+use std::{
+    fmt,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+/// A handle that asks an interpreter to stop. Clones share one flag, so
+/// one can be sent to another thread and triggered from there.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Stops the interpreter at its next statement or call. If it's not
+    /// running anything, whatever it runs next stops there instead.
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    /// The flag itself, for native code to check.
+    #[cfg(all(feature = "jit", not(target_arch = "wasm32")))]
+    pub(crate) fn flag(&self) -> &AtomicBool {
+        &self.0
+    }
+
+    /// Clears the request, returning whether there was one.
+    pub(crate) fn take(&self) -> bool {
+        self.0.swap(false, Ordering::Relaxed)
+    }
+}
+
+/// What a cancelled interpreter unwinds with, as the panic payload, so a
+/// host catching the unwind can tell it from a runtime error. The panic
+/// hook isn't run for it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cancelled;
+
+impl fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Execution was cancelled.")
+    }
+}
+
+impl std::error::Error for Cancelled {}
@@ -0,0 +1,129 @@
+//! `run_and_capture`: runs a program the way `jlox run` does and returns
+//! everything it did as data, so a corpus of programs can be checked
+//! against recorded snapshots whenever the scanner, parser or interpreter
+//! changes. And `check`, which does the same for `jlox check`.
+
+use std::{
+    cell::RefCell,
+    fmt,
+    panic::{self, AssertUnwindSafe},
+    rc::Rc,
+    sync::atomic::Ordering,
+};
+
+use crate::{
+    arena::Arena,
+    diagnostics::{self, Diagnostic},
+    folder,
+    interpreter::{Interpreter, Value},
+    natives,
+    output::{Callback, OutputEvent},
+    parser::Parser,
+    resolver,
+    scanner::Scanner,
+    source::SourceId,
+    statement::StmtId,
+};
+
+/// What running a program produced.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProgramResult {
+    /// One line per `print`.
+    pub output: Vec<String>,
+    /// Errors and warnings, static or from running, as the command line
+    /// prints them.
+    pub diagnostics: Vec<String>,
+    /// The program's globals once it stopped, sorted by name. Natives
+    /// aren't included.
+    pub final_globals: Vec<(String, Value)>,
+}
+
+/// Runs `source` in a fresh interpreter. Like `jlox run`, statements that
+/// parsed still run after a syntax error, and a runtime error stops the
+/// program but is only reported, here as its last diagnostic.
+pub fn run_and_capture(source: &str) -> ProgramResult {
+    let output = Rc::new(RefCell::new(Vec::new()));
+    let reported = Rc::new(RefCell::new(Vec::new()));
+    let sink = {
+        let output = Rc::clone(&output);
+        let reported = Rc::clone(&reported);
+        Callback(move |event| match event {
+            OutputEvent::PrintValue(value) => output.borrow_mut().push(value.to_string()),
+            OutputEvent::Diagnostic(diagnostic) => {
+                reported.borrow_mut().push(diagnostic.to_string())
+            }
+            OutputEvent::DiagnosticsSuppressed(count) => reported
+                .borrow_mut()
+                .push(format!("... {} additional errors suppressed.", count)),
+            OutputEvent::Result(_) | OutputEvent::TraceLine(_) => {}
+        })
+    };
+    let sink = Rc::new(sink);
+
+    crate::HAD_ERROR.store(false, Ordering::Relaxed);
+    diagnostics::reset();
+    let mut interpreter = Interpreter::new();
+    interpreter.set_output(sink.clone());
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        let tokens = Scanner::new(source, SourceId::ANONYMOUS).tokens();
+        let (ast, statements) = folder::parse_and_fold(Parser::new(tokens));
+        diagnostics::flush(&*sink);
+        interpreter.interpret(&ast, statements)
+    }));
+    diagnostics::flush(&*sink);
+    crate::HAD_ERROR.store(false, Ordering::Relaxed);
+    natives::take_exit_request();
+    if let Err(payload) = result {
+        reported.borrow_mut().push(crate::panic_message(&*payload));
+    }
+
+    ProgramResult {
+        output: output.take(),
+        diagnostics: reported.take(),
+        final_globals: interpreter.globals_iter().collect(),
+    }
+}
+
+/// The static errors and warnings in `source`, found without running it as
+/// `jlox check` does, in source order.
+pub fn check(source: &str) -> Vec<Diagnostic> {
+    diagnostics::reset();
+    analyze(source, SourceId::ANONYMOUS);
+    crate::HAD_ERROR.store(false, Ordering::Relaxed);
+    diagnostics::ordered(diagnostics::take())
+}
+
+/// Scans, parses and resolves `source`, queueing what it finds, and
+/// returns the statements that parsed with the arena they're in.
+pub(crate) fn analyze(source: &str, source_id: SourceId) -> (Arena, Vec<StmtId>) {
+    let mut parser = Parser::new(Scanner::new(source, source_id).tokens());
+    let statements = parser.parse();
+    // Statements that failed to parse are missing, so the variables they
+    // used would be reported as unused.
+    let parsed = parser.errors().is_empty();
+    let arena = parser.into_arena();
+    if parsed {
+        resolver::resolve(&arena, &statements);
+    }
+    (arena, statements)
+}
+
+/// The snapshot format: a section for each field, with each global shown
+/// with its type so `1` and `"1"` differ.
+impl fmt::Display for ProgramResult {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "-- output")?;
+        for line in &self.output {
+            writeln!(f, "{}", line)?;
+        }
+        writeln!(f, "-- diagnostics")?;
+        for diagnostic in &self.diagnostics {
+            writeln!(f, "{}", diagnostic)?;
+        }
+        writeln!(f, "-- globals")?;
+        for (name, value) in &self.final_globals {
+            writeln!(f, "{}: {} = {}", name, value.type_name(), value)?;
+        }
+        Ok(())
+    }
+}
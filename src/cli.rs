@@ -0,0 +1,846 @@
+//! The command line. clap reads the arguments into `Cli`; `parse` then
+//! turns that into the `Command` to run and the `Options` it runs with,
+//! which is all the rest of the crate sees.
+
+use std::{iter, num::NonZeroUsize, path::Path, sync::Arc, time::Duration};
+
+use clap::{
+    error::ErrorKind, ArgMatches, Args, CommandFactory, FromArgMatches, Parser, Subcommand,
+    ValueEnum,
+};
+
+use crate::{
+    diagnostics::{self, Severity},
+    environment::edit_distance,
+    highlight::HighlightFormat,
+    map_rows::RowFormat,
+    number::{FloatBackend, NumberBackend},
+    preview::PrintLimits,
+};
+
+/// What the command line asked for.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    /// Run a script; `-` means stdin.
+    Run(String),
+    Eval(String),
+    Repl,
+    Ast(String),
+    /// Report static errors in each script without running it.
+    Check(Vec<String>),
+    /// List the natives a script may call.
+    Audit(String),
+    /// Print the static call graph of a script.
+    Callgraph(String),
+    /// Evaluate `Options::expr` against each row of a CSV or JSON file.
+    MapRows(String),
+    Format(Vec<String>),
+    /// Print a script with its syntax colored.
+    Highlight(String),
+    /// Print a script compiled to JavaScript.
+    Compile(String),
+    /// Write an executable that runs a script to a file.
+    Bundle(String, String),
+    /// Run every test under a directory.
+    Test(String),
+    /// Run a script, checking its output against a recorded trace.
+    ReplayCompare(String, String),
+    Minify(String),
+    RunAll(String),
+    /// Serve Jupyter clients described by a connection file.
+    Kernel(String),
+    /// Debug scripts for an editor over the Debug Adapter Protocol.
+    Dap,
+    /// Serve an editor over the Language Server Protocol.
+    Lsp,
+    /// Time runs of a script.
+    Bench(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum DiagnosticsFormat {
+    Text,
+    Json,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Backend {
+    Float,
+    #[cfg(feature = "decimal")]
+    Decimal,
+    #[cfg(feature = "rational")]
+    Rational,
+}
+
+/// The languages `compile` can write.
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+enum Target {
+    Js,
+}
+
+pub struct Options {
+    pub dump_folded: bool,
+    pub seed: Option<u64>,
+    pub flatten: bool,
+    pub dot: bool,
+    pub output: Option<String>,
+    pub json: bool,
+    pub keep_going: bool,
+    pub runs: usize,
+    pub allow_fs: bool,
+    pub allow_env: bool,
+    pub allow: Vec<String>,
+    pub error_values: bool,
+    pub max_depth: Option<usize>,
+    pub max_steps: Option<u64>,
+    pub timeout: Option<Duration>,
+    pub script_args: Vec<String>,
+    pub max_errors: Option<usize>,
+    /// Severity overrides from `--deny` and `--warn`, in order.
+    pub severities: Vec<(String, Severity)>,
+    pub diagnostics_format: DiagnosticsFormat,
+    pub print_limits: PrintLimits,
+    pub expr: Option<String>,
+    pub row_format: RowFormat,
+    pub highlight_format: HighlightFormat,
+    pub verbose: bool,
+    pub write: bool,
+    pub watch: bool,
+    pub record: Option<String>,
+    pub profile: bool,
+    pub coverage: Option<String>,
+    pub backend: Backend,
+    /// Whether to run the script from its `.loxc` cache when that's
+    /// current, and save one when it isn't.
+    pub cache: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            dump_folded: false,
+            seed: None,
+            flatten: false,
+            dot: false,
+            output: None,
+            json: false,
+            keep_going: false,
+            runs: 10,
+            allow_fs: false,
+            allow_env: false,
+            allow: Vec::new(),
+            error_values: false,
+            max_depth: None,
+            max_steps: None,
+            timeout: None,
+            script_args: Vec::new(),
+            max_errors: None,
+            severities: Vec::new(),
+            diagnostics_format: DiagnosticsFormat::Text,
+            print_limits: PrintLimits::default(),
+            expr: None,
+            row_format: RowFormat::Csv,
+            highlight_format: HighlightFormat::Ansi,
+            verbose: false,
+            write: false,
+            watch: false,
+            record: None,
+            profile: false,
+            coverage: None,
+            backend: Backend::Float,
+            cache: false,
+        }
+    }
+}
+
+impl Options {
+    pub fn numbers(&self) -> Arc<dyn NumberBackend> {
+        match self.backend {
+            Backend::Float => Arc::new(FloatBackend),
+            #[cfg(feature = "decimal")]
+            Backend::Decimal => Arc::new(crate::number::DecimalBackend),
+            #[cfg(feature = "rational")]
+            Backend::Rational => Arc::new(crate::number::RationalBackend),
+        }
+    }
+}
+
+/// Runs a script, or with no script the REPL.
+#[derive(Parser)]
+#[command(
+    name = "jlox",
+    version,
+    about = "A tree-walking interpreter for Lox.",
+    args_conflicts_with_subcommands = true
+)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Sub>,
+    #[command(flatten)]
+    run: RunOptions,
+    /// run this code instead of a script; every other argument is passed
+    /// to it
+    #[arg(short = 'e', value_name = "code")]
+    eval: Option<String>,
+    #[command(flatten)]
+    script: Script,
+}
+
+/// A script to run and the arguments it's given. They're one argument to
+/// clap so that everything after the script, flags included, is the
+/// script's.
+#[derive(Args)]
+struct Script {
+    /// the script, or - to read it from stdin (with neither this nor -e,
+    /// start the REPL), then arguments for it to read with args()
+    #[arg(
+        value_names = ["script", "args"],
+        num_args = 1..,
+        trailing_var_arg = true,
+        allow_hyphen_values = true
+    )]
+    words: Vec<String>,
+}
+
+impl Script {
+    /// The script's path, and its arguments.
+    fn split(self) -> (Option<String>, Vec<String>) {
+        let mut words = self.words.into_iter();
+        (words.next(), words.collect())
+    }
+}
+
+#[derive(Subcommand)]
+enum Sub {
+    /// run a script (the default when one is named)
+    Run {
+        #[command(flatten)]
+        run: RunOptions,
+        #[command(flatten)]
+        script: Script,
+    },
+    /// start the interactive prompt (the default)
+    Repl {
+        #[command(flatten)]
+        running: Running,
+    },
+    /// print the parsed program
+    Ast {
+        /// print the syntax tree as JSON
+        #[arg(long)]
+        json: bool,
+        script: String,
+    },
+    /// report static errors without running anything
+    Check {
+        /// print at most n errors
+        #[arg(long, value_name = "n")]
+        max_errors: Option<usize>,
+        /// report these comma-separated diagnostics as errors:
+        /// unused-variable, shadowing, or warnings for every warning
+        #[arg(long, value_name = "rules", value_delimiter = ',', value_parser = rule)]
+        deny: Vec<String>,
+        /// report these diagnostics as warnings, which don't fail the
+        /// check; both override the [diagnostics] section of lox.toml
+        #[arg(long, value_name = "rules", value_delimiter = ',', value_parser = rule)]
+        warn: Vec<String>,
+        /// print diagnostics as text, or as one JSON document covering
+        /// every file
+        #[arg(long, value_name = "name", value_enum, default_value_t = DiagnosticsFormat::Text)]
+        diagnostics_format: DiagnosticsFormat,
+        #[arg(value_name = "script", required = true)]
+        scripts: Vec<String>,
+    },
+    /// list the natives a script may call, failing on effectful ones that
+    /// aren't allowed
+    Audit {
+        /// comma-separated natives or effects (io, process) to allow
+        #[arg(long, value_name = "names", value_delimiter = ',')]
+        allow: Vec<String>,
+        script: String,
+    },
+    /// print which functions call which
+    Callgraph {
+        /// print the graph in Graphviz's DOT language
+        #[arg(long)]
+        dot: bool,
+        script: String,
+    },
+    /// print the script with its syntax colored, for a terminal or as HTML
+    Highlight {
+        /// color for a terminal with ansi escapes, or write html
+        #[arg(long, value_name = "name", value_enum, default_value_t = HighlightFormat::Ansi)]
+        format: HighlightFormat,
+        script: String,
+    },
+    /// print each script with canonical layout
+    Fmt {
+        /// rewrite the files in place instead of printing them
+        #[arg(short, long)]
+        write: bool,
+        #[arg(value_name = "script", required = true)]
+        scripts: Vec<String>,
+    },
+    /// print the script compiled to another language
+    Compile {
+        /// the language to compile to
+        #[arg(long, value_name = "name", value_enum)]
+        target: Target,
+        script: String,
+    },
+    /// write a copy of jlox that runs the script, to hand to people
+    /// without jlox installed
+    Bundle {
+        /// where to write the executable
+        #[arg(short, long, value_name = "file")]
+        output: String,
+        /// let the bundled script use readFile, writeFile and appendFile
+        #[arg(long)]
+        allow_fs: bool,
+        /// let the bundled script use getenv
+        #[arg(long)]
+        allow_env: bool,
+        script: String,
+    },
+    /// run the golden tests under a directory
+    Test {
+        #[command(flatten)]
+        running: Running,
+        /// save which lines ran: an lcov tracefile if the name ends in
+        /// .info or .lcov, otherwise the annotated source
+        #[arg(long, value_name = "file")]
+        coverage: Option<String>,
+        #[arg(default_value = "tests")]
+        dir: String,
+    },
+    /// time runs of a script, with its output discarded
+    Bench {
+        #[command(flatten)]
+        running: Running,
+        /// how many times to run the script
+        #[arg(long, value_name = "n", default_value = "10")]
+        runs: NonZeroUsize,
+        script: String,
+    },
+    /// evaluate code for each row of a CSV or JSON file, with the row
+    /// bound to `row`
+    MapRows {
+        #[command(flatten)]
+        running: Running,
+        /// the expression to evaluate for each row
+        #[arg(long, value_name = "code")]
+        expr: String,
+        /// write results as csv, or as json, a value per line
+        #[arg(long, value_name = "name", value_enum, default_value_t = RowFormat::Csv)]
+        format: RowFormat,
+        file: String,
+    },
+    /// check a run against one saved with --record
+    ReplayCompare {
+        #[command(flatten)]
+        running: Running,
+        trace: String,
+        script: String,
+    },
+    /// print the script with as few characters as it needs
+    Minify {
+        /// inline blocks that declare nothing
+        #[arg(long)]
+        flatten: bool,
+        script: String,
+    },
+    /// run every script in a directory, printing a JSON summary
+    RunAll {
+        #[command(flatten)]
+        running: Running,
+        /// don't stop at the first failing file
+        #[arg(long)]
+        keep_going: bool,
+        dir: String,
+    },
+    /// serve a Jupyter notebook (see kernelspec/)
+    Kernel {
+        #[command(flatten)]
+        running: Running,
+        connection_file: String,
+    },
+    /// serve the Debug Adapter Protocol on stdin/stdout, for editors'
+    /// debuggers
+    Dap {
+        #[command(flatten)]
+        running: Running,
+    },
+    /// serve the Language Server Protocol on stdin/stdout, for editors
+    Lsp,
+}
+
+/// The options of every subcommand that runs Lox code.
+#[derive(Args)]
+struct Running {
+    /// report each phase's timing on stderr
+    #[arg(short, long)]
+    verbose: bool,
+    /// number semantics: float, decimal or rational
+    #[arg(long, value_name = "name", default_value = "float", value_parser = backend)]
+    backend: Backend,
+    /// seed random() and randomInt()
+    #[arg(long, value_name = "n")]
+    seed: Option<u64>,
+    /// print at most n errors
+    #[arg(long, value_name = "n")]
+    max_errors: Option<usize>,
+    /// show at most n characters of a printed string
+    #[arg(long, value_name = "n", value_parser = limit)]
+    max_string: Option<Limit>,
+    /// show at most n elements of a printed list
+    #[arg(long, value_name = "n", value_parser = limit)]
+    max_items: Option<Limit>,
+    /// show lists at most n deep when printing (the REPL defaults to 1000,
+    /// 100 and 6)
+    #[arg(long, value_name = "n", value_parser = limit)]
+    max_nesting: Option<Limit>,
+    /// enable readFile, writeFile and appendFile
+    #[arg(long)]
+    allow_fs: bool,
+    /// enable getenv
+    #[arg(long)]
+    allow_env: bool,
+    /// let a failing expression evaluate to an error value instead of
+    /// stopping the program
+    #[arg(long)]
+    error_values: bool,
+    /// stop with a stack overflow error when calls nest more than n deep
+    /// (default 1000)
+    #[arg(long, value_name = "n")]
+    max_depth: Option<NonZeroUsize>,
+    /// stop a run after it executes n statements
+    #[arg(long, value_name = "n")]
+    max_steps: Option<u64>,
+    /// stop a run after n milliseconds
+    #[arg(long, value_name = "n")]
+    timeout_ms: Option<u64>,
+}
+
+/// The options of running a script, on top of `Running`.
+#[derive(Args)]
+struct RunOptions {
+    #[command(flatten)]
+    running: Running,
+    /// save a trace of the program's output as JSON
+    #[arg(long, value_name = "file")]
+    record: Option<String>,
+    /// report each function's call count and time on stderr at exit
+    #[arg(long)]
+    profile: bool,
+    /// save which lines ran: an lcov tracefile if the name ends in .info
+    /// or .lcov, otherwise the annotated source
+    #[arg(long, value_name = "file")]
+    coverage: Option<String>,
+    /// print the program after constant folding instead of running it
+    #[arg(long)]
+    dump_folded: bool,
+    /// run again whenever the script or a module it imports changes
+    #[arg(long)]
+    watch: bool,
+    /// parse the script even if its .loxc cache is current, and don't
+    /// save one
+    #[arg(long)]
+    no_cache: bool,
+}
+
+/// A `--max-*` print limit: a count, or no limit at all.
+#[derive(Debug, Clone, Copy)]
+struct Limit(Option<usize>);
+
+fn limit(value: &str) -> Result<Limit, String> {
+    match value {
+        "none" => Ok(Limit(None)),
+        _ => value
+            .parse()
+            .map(|limit| Limit(Some(limit)))
+            .map_err(|_| "expected a non-negative integer or 'none'".to_string()),
+    }
+}
+
+fn backend(name: &str) -> Result<Backend, String> {
+    match name {
+        "float" => Ok(Backend::Float),
+        #[cfg(feature = "decimal")]
+        "decimal" => Ok(Backend::Decimal),
+        #[cfg(not(feature = "decimal"))]
+        "decimal" => Err("this build doesn't include the decimal backend".to_string()),
+        #[cfg(feature = "rational")]
+        "rational" => Ok(Backend::Rational),
+        #[cfg(not(feature = "rational"))]
+        "rational" => Err("this build doesn't include the rational backend".to_string()),
+        _ => Err("expected 'float', 'decimal' or 'rational'".to_string()),
+    }
+}
+
+fn rule(name: &str) -> Result<String, String> {
+    diagnostics::check_rule(name.trim())?;
+    Ok(name.trim().to_string())
+}
+
+impl Running {
+    fn apply(self, options: &mut Options) {
+        options.verbose = self.verbose;
+        options.backend = self.backend;
+        options.seed = self.seed;
+        options.max_errors = self.max_errors;
+        let limits = &mut options.print_limits;
+        for (limit, given) in [
+            (&mut limits.max_string, self.max_string),
+            (&mut limits.max_items, self.max_items),
+            (&mut limits.max_nesting, self.max_nesting),
+        ] {
+            if let Some(Limit(given)) = given {
+                *limit = given;
+            }
+        }
+        options.allow_fs = self.allow_fs;
+        options.allow_env = self.allow_env;
+        options.error_values = self.error_values;
+        options.max_depth = self.max_depth.map(NonZeroUsize::get);
+        options.max_steps = self.max_steps;
+        options.timeout = self.timeout_ms.map(Duration::from_millis);
+    }
+}
+
+impl RunOptions {
+    fn apply(self, options: &mut Options) {
+        self.running.apply(options);
+        options.record = self.record;
+        options.profile = self.profile;
+        options.coverage = self.coverage;
+        options.dump_folded = self.dump_folded;
+        options.watch = self.watch;
+        options.cache = !self.no_cache;
+    }
+}
+
+/// The `--deny` and `--warn` overrides in `matches`, in the order they
+/// were given, so a later one wins.
+fn severities(matches: &ArgMatches) -> Vec<(String, Severity)> {
+    let mut severities = Vec::new();
+    for (id, severity) in [("deny", Severity::Deny), ("warn", Severity::Warn)] {
+        let (Some(indices), Some(rules)) = (matches.indices_of(id), matches.get_many::<String>(id))
+        else {
+            continue;
+        };
+        severities.extend(
+            indices
+                .zip(rules)
+                .map(|(i, rule)| (i, rule.clone(), severity)),
+        );
+    }
+    severities.sort_by_key(|(i, _, _)| *i);
+    severities
+        .into_iter()
+        .map(|(_, rule, severity)| (rule, severity))
+        .collect()
+}
+
+/// Parses the arguments after the program name. Everything after the
+/// script belongs to the script. `--help` and `--version` come back as
+/// errors too, which print themselves.
+pub fn parse(args: impl IntoIterator<Item = String>) -> Result<(Command, Options), clap::Error> {
+    let matches =
+        Cli::command().try_get_matches_from(iter::once("jlox".to_string()).chain(args))?;
+    let cli = Cli::from_arg_matches(&matches)?;
+    let mut options = Options::default();
+    let command = match cli.command {
+        None => {
+            cli.run.apply(&mut options);
+            let (path, args) = cli.script.split();
+            match (cli.eval, path) {
+                // `-e` stands in for the script, so positional arguments
+                // are passed through to the code.
+                (Some(source), path) => {
+                    options.script_args = path.into_iter().chain(args).collect();
+                    Command::Eval(source)
+                }
+                (None, None) => Command::Repl,
+                (None, Some(script)) => {
+                    mistyped(&Cli::command(), &script)?;
+                    options.script_args = args;
+                    Command::Run(script)
+                }
+            }
+        }
+        Some(Sub::Run { run, script }) => {
+            run.apply(&mut options);
+            let (path, args) = script.split();
+            if let Some(path) = &path {
+                let command = Cli::command();
+                mistyped(command.find_subcommand("run").unwrap(), path)?;
+            }
+            let Some(path) = path else {
+                return Err(Cli::command().error(
+                    ErrorKind::MissingRequiredArgument,
+                    "'run' expects a script.",
+                ));
+            };
+            options.script_args = args;
+            Command::Run(path)
+        }
+        Some(Sub::Repl { running }) => {
+            running.apply(&mut options);
+            Command::Repl
+        }
+        Some(Sub::Ast { json, script }) => {
+            options.json = json;
+            Command::Ast(script)
+        }
+        Some(Sub::Check {
+            max_errors,
+            diagnostics_format,
+            scripts,
+            ..
+        }) => {
+            options.max_errors = max_errors;
+            options.diagnostics_format = diagnostics_format;
+            if let Some(("check", matches)) = matches.subcommand() {
+                options.severities = severities(matches);
+            }
+            Command::Check(scripts)
+        }
+        Some(Sub::Audit { allow, script }) => {
+            options.allow = allow.iter().map(|name| name.trim().to_string()).collect();
+            Command::Audit(script)
+        }
+        Some(Sub::Callgraph { dot, script }) => {
+            options.dot = dot;
+            Command::Callgraph(script)
+        }
+        Some(Sub::Highlight { format, script }) => {
+            options.highlight_format = format;
+            Command::Highlight(script)
+        }
+        Some(Sub::Fmt { write, scripts }) => {
+            options.write = write;
+            Command::Format(scripts)
+        }
+        Some(Sub::Compile {
+            target: Target::Js,
+            script,
+        }) => Command::Compile(script),
+        Some(Sub::Bundle {
+            output,
+            allow_fs,
+            allow_env,
+            script,
+        }) => {
+            options.output = Some(output.clone());
+            options.allow_fs = allow_fs;
+            options.allow_env = allow_env;
+            Command::Bundle(script, output)
+        }
+        Some(Sub::Test {
+            running,
+            coverage,
+            dir,
+        }) => {
+            running.apply(&mut options);
+            options.coverage = coverage;
+            Command::Test(dir)
+        }
+        Some(Sub::Bench {
+            running,
+            runs,
+            script,
+        }) => {
+            running.apply(&mut options);
+            options.runs = runs.get();
+            Command::Bench(script)
+        }
+        Some(Sub::MapRows {
+            running,
+            expr,
+            format,
+            file,
+        }) => {
+            running.apply(&mut options);
+            options.expr = Some(expr);
+            options.row_format = format;
+            Command::MapRows(file)
+        }
+        Some(Sub::ReplayCompare {
+            running,
+            trace,
+            script,
+        }) => {
+            running.apply(&mut options);
+            Command::ReplayCompare(trace, script)
+        }
+        Some(Sub::Minify { flatten, script }) => {
+            options.flatten = flatten;
+            Command::Minify(script)
+        }
+        Some(Sub::RunAll {
+            running,
+            keep_going,
+            dir,
+        }) => {
+            running.apply(&mut options);
+            options.keep_going = keep_going;
+            Command::RunAll(dir)
+        }
+        Some(Sub::Kernel {
+            running,
+            connection_file,
+        }) => {
+            running.apply(&mut options);
+            Command::Kernel(connection_file)
+        }
+        Some(Sub::Dap { running }) => {
+            running.apply(&mut options);
+            Command::Dap
+        }
+        Some(Sub::Lsp) => Command::Lsp,
+    };
+    let runs_file = matches!(&command, Command::Run(script) if script != "-");
+    // Only a script file has somewhere to keep its cache.
+    options.cache &= runs_file;
+    if options.watch && !runs_file {
+        return Err(Cli::command().error(
+            ErrorKind::ArgumentConflict,
+            "--watch only works when running a script file.",
+        ));
+    }
+    Ok((command, options))
+}
+
+/// Fails if `script` is most likely a mistyped subcommand or option
+/// rather than a script. clap takes any word in the script's place as the
+/// script, since the script's arguments may look like anything.
+fn mistyped(command: &clap::Command, script: &str) -> Result<(), clap::Error> {
+    let (kind, what, known): (_, _, Vec<String>) = if script.starts_with('-') && script != "-" {
+        let flags = command.get_arguments().flat_map(|arg| {
+            let short = arg.get_short().map(|short| format!("-{}", short));
+            let long = arg.get_long().map(|long| format!("--{}", long));
+            short.into_iter().chain(long)
+        });
+        (ErrorKind::UnknownArgument, "option", flags.collect())
+    } else if !script.contains(['.', '/', '\\']) && !Path::new(script).exists() {
+        let names = command
+            .get_subcommands()
+            .map(|subcommand| subcommand.get_name());
+        (
+            ErrorKind::InvalidSubcommand,
+            "command",
+            names.map(str::to_string).collect(),
+        )
+    } else {
+        return Ok(());
+    };
+    let flag = script.split('=').next().unwrap_or(script);
+    let closest = known
+        .iter()
+        .map(|known| (edit_distance(flag, known), known))
+        .min()
+        .filter(|(distance, _)| *distance <= (flag.len() / 3).clamp(1, 2));
+    match (closest, kind) {
+        (Some((_, known)), _) => Err(command.clone().error(
+            kind,
+            format!("Unknown {} '{}'. Did you mean '{}'?", what, flag, known),
+        )),
+        (None, ErrorKind::UnknownArgument) => Err(command
+            .clone()
+            .error(kind, format!("Unknown option '{}'.", flag))),
+        (None, _) => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<(Command, Options), clap::Error> {
+        super::parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn the_cli_is_consistent() {
+        Cli::command().debug_assert();
+    }
+
+    #[test]
+    fn runs_a_script_with_its_arguments() {
+        let (command, options) = parse(&["--seed", "3", "main.lox", "a", "--seed"]).unwrap();
+        assert_eq!(command, Command::Run("main.lox".to_string()));
+        assert_eq!(options.seed, Some(3));
+        assert_eq!(options.script_args, ["a", "--seed"]);
+
+        let (command, options) = parse(&["run", "--max-depth=5", "main.lox", "-v"]).unwrap();
+        assert_eq!(command, Command::Run("main.lox".to_string()));
+        assert_eq!(options.max_depth, Some(5));
+        assert_eq!(options.script_args, ["-v"]);
+        assert!(!options.verbose);
+    }
+
+    #[test]
+    fn with_no_script_starts_the_repl() {
+        assert_eq!(parse(&[]).unwrap().0, Command::Repl);
+        assert_eq!(parse(&["repl"]).unwrap().0, Command::Repl);
+        let (command, options) = parse(&["-e", "print 1;", "x"]).unwrap();
+        assert_eq!(command, Command::Eval("print 1;".to_string()));
+        assert_eq!(options.script_args, ["x"]);
+    }
+
+    #[test]
+    fn keeps_deny_and_warn_in_order() {
+        let (command, options) = parse(&[
+            "check",
+            "--warn",
+            "shadowing",
+            "--deny=unused-variable,shadowing",
+            "a.lox",
+            "b.lox",
+        ])
+        .unwrap();
+        assert_eq!(
+            command,
+            Command::Check(vec!["a.lox".to_string(), "b.lox".to_string()])
+        );
+        assert_eq!(
+            options.severities,
+            [
+                ("shadowing".to_string(), Severity::Warn),
+                ("unused-variable".to_string(), Severity::Deny),
+                ("shadowing".to_string(), Severity::Deny),
+            ]
+        );
+        assert!(parse(&["check", "--deny", "nonsense", "a.lox"]).is_err());
+    }
+
+    #[test]
+    fn options_belong_to_their_subcommands() {
+        let (command, options) = parse(&[
+            "map-rows", "--expr", "row.a", "--format", "json", "rows.csv",
+        ])
+        .unwrap();
+        assert_eq!(command, Command::MapRows("rows.csv".to_string()));
+        assert_eq!(options.row_format, RowFormat::Json);
+        let (_, options) = parse(&["highlight", "--format=html", "main.lox"]).unwrap();
+        assert_eq!(options.highlight_format, HighlightFormat::Html);
+
+        assert!(parse(&["fmt", "--seed", "1", "main.lox"]).is_err());
+        assert!(parse(&["compile", "main.lox"]).is_err());
+        assert!(parse(&["bench", "--runs", "0", "main.lox"]).is_err());
+        assert!(parse(&["--watch", "-"]).is_err());
+    }
+
+    #[test]
+    fn suggests_subcommands_for_typos() {
+        let error = parse(&["chek"]).err().unwrap();
+        assert!(error.to_string().contains("Did you mean 'check'?"));
+        assert_eq!(
+            parse(&["main"]).unwrap().0,
+            Command::Run("main".to_string())
+        );
+        let error = parse(&["--sede", "1", "main.lox"]).err().unwrap();
+        assert!(error.to_string().contains("Did you mean '--seed'?"));
+        assert!(parse(&["run", "--nonsense", "main.lox"]).is_err());
+    }
+}
@@ -0,0 +1,220 @@
+//! `--coverage`: which lines of each file ran, and how often, written as an
+//! lcov tracefile or as the source annotated with counts.
+//!
+//! A line counts as run when a statement starting on it is executed. Lines
+//! with no statement starting on them (blank lines, comments, closing
+//! braces) aren't counted either way.
+
+use std::{
+    cell::RefCell,
+    collections::{BTreeMap, BTreeSet},
+    fmt::Write as _,
+    fs, io,
+    path::Path,
+};
+
+use crate::{
+    arena::Arena,
+    expression::{Expr, ExprId},
+    parser::Parser,
+    scanner::Scanner,
+    source::SourceId,
+    statement::{Stmt, StmtId},
+    token::Token,
+};
+
+type Hits = BTreeMap<SourceId, BTreeMap<u32, u64>>;
+
+thread_local! {
+    static HITS: RefCell<Option<Hits>> = const { RefCell::new(None) };
+}
+
+/// Starts recording, forgetting anything recorded before.
+pub fn start() {
+    HITS.with(|hits| *hits.borrow_mut() = Some(BTreeMap::new()));
+}
+
+/// Whether lines run are being recorded.
+#[cfg(all(feature = "jit", not(target_arch = "wasm32")))]
+pub fn enabled() -> bool {
+    HITS.with(|hits| hits.borrow().is_some())
+}
+
+/// Notes that `stmt` is about to run, if recording.
+pub fn hit(arena: &Arena, stmt: &Stmt) {
+    HITS.with(|hits| {
+        if let (Some(hits), Some(token)) = (hits.borrow_mut().as_mut(), first_token(arena, stmt)) {
+            *hits
+                .entry(token.source)
+                .or_default()
+                .entry(token.line)
+                .or_default() += 1;
+        }
+    });
+}
+
+/// Stops recording and writes what was recorded to `path`: an lcov
+/// tracefile if it ends in `.info` or `.lcov`, otherwise each file's source
+/// with the times each line ran in front of it.
+pub fn write(path: &str) -> io::Result<()> {
+    let hits = HITS
+        .with(|hits| hits.borrow_mut().take())
+        .unwrap_or_default();
+    let mut files = Vec::new();
+    for (source, counts) in hits {
+        let name = source.name();
+        // Code typed at the prompt or passed with -e has no file to report.
+        let Ok(contents) = fs::read_to_string(&name) else {
+            continue;
+        };
+        let lines = executable_lines(&contents);
+        files.push((name, contents, lines, counts));
+    }
+
+    let lcov = matches!(
+        Path::new(path).extension().and_then(|ext| ext.to_str()),
+        Some("info" | "lcov")
+    );
+    let mut report = String::new();
+    for (name, contents, lines, counts) in &files {
+        if lcov {
+            write_lcov(&mut report, name, lines, counts);
+        } else {
+            write_annotated(&mut report, name, contents, lines, counts);
+        }
+    }
+    fs::write(path, report)
+}
+
+fn write_lcov(report: &mut String, name: &str, lines: &BTreeSet<u32>, counts: &BTreeMap<u32, u64>) {
+    let full_path = Path::new(name)
+        .canonicalize()
+        .map_or_else(|_| name.to_string(), |path| path.display().to_string());
+    let _ = writeln!(report, "TN:");
+    let _ = writeln!(report, "SF:{}", full_path);
+    for line in lines {
+        let _ = writeln!(report, "DA:{},{}", line, counts.get(line).unwrap_or(&0));
+    }
+    let _ = writeln!(report, "LF:{}", lines.len());
+    let _ = writeln!(report, "LH:{}", hit_count(lines, counts));
+    let _ = writeln!(report, "end_of_record");
+}
+
+fn write_annotated(
+    report: &mut String,
+    name: &str,
+    contents: &str,
+    lines: &BTreeSet<u32>,
+    counts: &BTreeMap<u32, u64>,
+) {
+    let hit = hit_count(lines, counts);
+    let percent = if lines.is_empty() {
+        100.0
+    } else {
+        hit as f64 * 100.0 / lines.len() as f64
+    };
+    let _ = writeln!(
+        report,
+        "==> {} ({} of {} lines run, {:.1}%)",
+        name,
+        hit,
+        lines.len(),
+        percent
+    );
+    for (index, text) in contents.lines().enumerate() {
+        let line = index as u32 + 1;
+        let count = match counts.get(&line) {
+            Some(count) => count.to_string(),
+            None if lines.contains(&line) => "#####".to_string(),
+            None => String::new(),
+        };
+        let _ = writeln!(report, "{:>8} | {}", count, text);
+    }
+    report.push('\n');
+}
+
+fn hit_count(lines: &BTreeSet<u32>, counts: &BTreeMap<u32, u64>) -> usize {
+    lines
+        .iter()
+        .filter(|line| counts.contains_key(line))
+        .count()
+}
+
+/// The lines of `source` on which a statement starts.
+fn executable_lines(source: &str) -> BTreeSet<u32> {
+    let tokens = Scanner::new(source, SourceId::ANONYMOUS).tokens();
+    let mut parser = Parser::new(tokens);
+    let statements = parser.parse();
+    let mut lines = BTreeSet::new();
+    collect_lines(&parser.into_arena(), &statements, &mut lines);
+    lines
+}
+
+fn collect_lines(arena: &Arena, statements: &[StmtId], lines: &mut BTreeSet<u32>) {
+    for stmt in statements {
+        let stmt = arena.stmt(*stmt);
+        if let Some(token) = first_token(arena, stmt) {
+            lines.insert(token.line);
+        }
+        match stmt {
+            Stmt::Block(body) | Stmt::Function(_, _, body) => collect_lines(arena, body, lines),
+            // Methods aren't executed as statements when their class is,
+            // so only their bodies count.
+            Stmt::Class(_, methods) => {
+                for method in methods {
+                    if let Stmt::Function(_, _, body) = arena.stmt(*method) {
+                        collect_lines(arena, body, lines);
+                    }
+                }
+            }
+            Stmt::If(_, then_stmt, else_stmt) => {
+                collect_lines(arena, &[*then_stmt], lines);
+                if let Some(else_stmt) = else_stmt {
+                    collect_lines(arena, &[*else_stmt], lines);
+                }
+            }
+            Stmt::While(_, body) | Stmt::ForIn(_, _, body) | Stmt::Export(_, body) => {
+                collect_lines(arena, &[*body], lines)
+            }
+            _ => {}
+        }
+    }
+}
+
+/// The token a statement starts with, if it kept one. Blocks don't count
+/// as statements of their own, and nor do statements made only of literals.
+pub(crate) fn first_token(arena: &Arena, stmt: &Stmt) -> Option<Token> {
+    match stmt {
+        Stmt::Print(keyword, _)
+        | Stmt::Var(keyword, _)
+        | Stmt::Const(keyword, _)
+        | Stmt::Return(keyword, _)
+        | Stmt::Import(keyword, ..)
+        | Stmt::Export(keyword, _)
+        | Stmt::Function(keyword, ..)
+        | Stmt::Class(keyword, _)
+        | Stmt::ForIn(keyword, ..) => Some(keyword.clone()),
+        Stmt::Destructure(names, _) => names.first().cloned(),
+        Stmt::Expression(expr) | Stmt::If(expr, ..) | Stmt::While(expr, _) => {
+            leftmost_token(arena, *expr)
+        }
+        Stmt::Block(_) => None,
+    }
+}
+
+fn leftmost_token(arena: &Arena, expr: ExprId) -> Option<Token> {
+    let leftmost = |expr: &ExprId| leftmost_token(arena, *expr);
+    match arena.expr(expr) {
+        Expr::Binary(l, op, _) | Expr::Logical(l, op, _) => leftmost(l).or(Some(op.clone())),
+        Expr::Call(callee, paren, _) => leftmost(callee).or(Some(paren.clone())),
+        Expr::Get(object, name) | Expr::Set(object, name, _) => {
+            leftmost(object).or(Some(name.clone()))
+        }
+        Expr::Grouping(inner) => leftmost(inner),
+        Expr::Unary(token, _) | Expr::Assign(token, _) | Expr::Variable(token) => {
+            Some(token.clone())
+        }
+        Expr::Tuple(comma, values) => leftmost(&values[0]).or(Some(comma.clone())),
+        Expr::Literal(_) => None,
+    }
+}
@@ -0,0 +1,698 @@
+//! `jlox dap`: a debug adapter, so editors can run a script with
+//! breakpoints, step through it and look at its variables.
+//!
+//! The client speaks the Debug Adapter Protocol on stdin and stdout, framed
+//! the same way as the language server's messages. Requests are read on a
+//! thread of their own and handed to the interpreter's thread, which looks
+//! at them before each statement while the program runs and waits for them
+//! while it's stopped.
+
+use std::{
+    cell::{Cell, RefCell},
+    collections::{BTreeSet, HashMap},
+    fs,
+    io::{self, Write},
+    panic::{self, AssertUnwindSafe},
+    path::{Path, PathBuf},
+    process,
+    rc::Rc,
+    sync::mpsc::{self, Receiver},
+    thread,
+};
+
+use crate::{
+    cli::Options,
+    diagnostics,
+    environment::Environment,
+    interpreter::{Interpreter, Value},
+    json::Json,
+    lox_class::LoxInstance,
+    lsp, preview,
+    source::SourceId,
+    statement::Stmt,
+    token::Token,
+};
+
+thread_local! {
+    static SESSION: RefCell<Option<Session>> = const { RefCell::new(None) };
+    // Whether a program is running under the debugger, checked before every
+    // statement, so kept apart from the session to be cheap to read.
+    static ACTIVE: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Serves one client until it disconnects.
+pub fn run(options: &Options) -> io::Result<()> {
+    let (sender, requests) = mpsc::channel();
+    thread::Builder::new()
+        .name("dap-reader".to_string())
+        .spawn(move || {
+            let mut input = io::stdin().lock();
+            while let Ok(Some(body)) = lsp::read_message(&mut input) {
+                match Json::parse(&body) {
+                    Ok(request) => {
+                        if sender.send(request).is_err() {
+                            break;
+                        }
+                    }
+                    Err(e) => eprintln!("jlox dap: ignoring a message: {}.", e),
+                }
+            }
+        })?;
+    let session = Session {
+        client: Rc::new(Client { seq: Cell::new(0) }),
+        requests,
+        program: None,
+        breakpoints: HashMap::new(),
+        paths: HashMap::new(),
+        frames: Vec::new(),
+        mode: Mode::Run,
+        last: None,
+        globals: None,
+        containers: Vec::new(),
+    };
+    SESSION.with(|cell| *cell.borrow_mut() = Some(session));
+    loop {
+        let Some(flow) = with_session(|session| {
+            let request = session.requests.recv().ok()?;
+            Some(session.handle(&request))
+        }) else {
+            return Ok(());
+        };
+        match flow {
+            Flow::Launch => launch(options),
+            Flow::Disconnect => return Ok(()),
+            Flow::Wait | Flow::Resume => {}
+        }
+    }
+}
+
+fn with_session<T>(f: impl FnOnce(&mut Session) -> T) -> T {
+    SESSION.with(|cell| f(cell.borrow_mut().as_mut().expect("no debug session")))
+}
+
+/// Runs the program named by `launch`, then tells the client how it ended.
+fn launch(options: &Options) {
+    let (client, program) =
+        with_session(|session| (session.client.clone(), session.program.take()));
+    let Some(Program {
+        path,
+        stop_on_entry,
+    }) = program
+    else {
+        client.output(
+            "stderr",
+            "Nothing to run: 'launch' didn't name a program.\n",
+        );
+        client.event("terminated", Json::object::<&str>([]));
+        return;
+    };
+    let source = match fs::read_to_string(&path) {
+        Ok(source) => source,
+        Err(e) => {
+            client.output("stderr", &format!("Could not read {}: {}.\n", path, e));
+            client.event("terminated", Json::object::<&str>([]));
+            return;
+        }
+    };
+    let mut interpreter = crate::new_interpreter(crate::script_dir(Path::new(&path)), options);
+    interpreter.set_output(Rc::new(Console(client.clone())));
+    with_session(|session| {
+        session.globals = Some(Rc::clone(&interpreter.globals));
+        session.frames = vec![Frame::new("<script>".to_string())];
+        session.mode = if stop_on_entry {
+            Mode::Entry
+        } else {
+            Mode::Run
+        };
+        session.last = None;
+    });
+
+    ACTIVE.with(|active| active.set(true));
+    let source_id = SourceId::register(&path);
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        crate::run_in(&mut interpreter, &source, source_id, options)
+    }));
+    ACTIVE.with(|active| active.set(false));
+    diagnostics::flush(&*interpreter.output());
+
+    let exit_code = match result {
+        Ok(code) => code.unwrap_or(0),
+        Err(payload) => {
+            client.output("stderr", &format!("{}\n", crate::panic_message(&*payload)));
+            70
+        }
+    };
+    with_session(|session| {
+        session.frames.clear();
+        session.containers.clear();
+    });
+    client.event(
+        "exited",
+        Json::object([("exitCode", Json::from(exit_code as f64))]),
+    );
+    client.event("terminated", Json::object::<&str>([]));
+}
+
+/// Whether a program is running under the debugger.
+#[cfg(all(feature = "jit", not(target_arch = "wasm32")))]
+pub(crate) fn active() -> bool {
+    ACTIVE.with(Cell::get)
+}
+
+/// Stops before `stmt` if a breakpoint or a step asks for it, and waits for
+/// the client to say how to go on. Does nothing unless a program is
+/// running under the debugger.
+pub(crate) fn statement(interpreter: &Interpreter, stmt: &Stmt) {
+    if !ACTIVE.with(Cell::get) {
+        return;
+    }
+    let Some(token) = crate::coverage::first_token(interpreter.ast(), stmt) else {
+        return;
+    };
+    with_session(|session| session.statement(interpreter, token));
+}
+
+/// Notes that a call to the function `name` began. The call's frame is
+/// dropped again with what this returns.
+pub(crate) fn enter(name: &Token) -> Option<FrameGuard> {
+    if !ACTIVE.with(Cell::get) {
+        return None;
+    }
+    let depth = with_session(|session| {
+        session.frames.push(Frame::new(name.lexeme().to_string()));
+        session.frames.len() - 1
+    });
+    Some(FrameGuard(depth))
+}
+
+/// Drops a call's frame when the call returns, or unwinds.
+pub(crate) struct FrameGuard(usize);
+
+impl Drop for FrameGuard {
+    fn drop(&mut self) {
+        SESSION.with(|cell| {
+            if let Some(session) = cell.borrow_mut().as_mut() {
+                session.frames.truncate(self.0);
+            }
+        });
+    }
+}
+
+/// Writes messages to the client, numbering them as it goes.
+struct Client {
+    seq: Cell<u64>,
+}
+
+impl Client {
+    fn send(&self, kind: &str, fields: Vec<(&str, Json)>) {
+        self.seq.set(self.seq.get() + 1);
+        let mut message = vec![
+            ("seq", Json::from(self.seq.get() as f64)),
+            ("type", Json::from(kind)),
+        ];
+        message.extend(fields);
+        let body = Json::object(message).to_string();
+        let mut stdout = io::stdout().lock();
+        let _ = write!(stdout, "Content-Length: {}\r\n\r\n{}", body.len(), body);
+        let _ = stdout.flush();
+    }
+
+    fn respond(&self, request: &Json, body: Json) {
+        self.send(
+            "response",
+            vec![
+                (
+                    "request_seq",
+                    request.get("seq").cloned().unwrap_or(Json::Null),
+                ),
+                ("success", Json::from(true)),
+                (
+                    "command",
+                    request.get("command").cloned().unwrap_or(Json::Null),
+                ),
+                ("body", body),
+            ],
+        );
+    }
+
+    fn fail(&self, request: &Json, message: &str) {
+        self.send(
+            "response",
+            vec![
+                (
+                    "request_seq",
+                    request.get("seq").cloned().unwrap_or(Json::Null),
+                ),
+                ("success", Json::from(false)),
+                (
+                    "command",
+                    request.get("command").cloned().unwrap_or(Json::Null),
+                ),
+                ("message", Json::from(message)),
+            ],
+        );
+    }
+
+    fn event(&self, event: &str, body: Json) {
+        self.send("event", vec![("event", Json::from(event)), ("body", body)]);
+    }
+
+    fn output(&self, category: &str, text: &str) {
+        self.event(
+            "output",
+            Json::object([
+                ("category", Json::from(category)),
+                ("output", Json::from(text)),
+            ]),
+        );
+    }
+}
+
+/// Sends what the program prints to the client's debug console, since
+/// stdout is taken by the protocol.
+struct Console(Rc<Client>);
+
+impl std::fmt::Debug for Console {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Console")
+    }
+}
+
+impl crate::output::OutputSink for Console {
+    fn emit(&self, event: crate::output::OutputEvent) {
+        use crate::output::OutputEvent;
+        match event {
+            OutputEvent::PrintValue(value) => self
+                .0
+                .output("stdout", &format!("{}\n", preview::format(&value))),
+            OutputEvent::Diagnostic(diagnostic) => {
+                self.0.output("stderr", &format!("{}\n", diagnostic))
+            }
+            OutputEvent::DiagnosticsSuppressed(count) => self.0.output(
+                "stderr",
+                &format!("... {} additional errors suppressed.\n", count),
+            ),
+            OutputEvent::Result(_) => {}
+            OutputEvent::TraceLine(line) => self.0.output("console", &format!("{}\n", line)),
+        }
+    }
+}
+
+struct Program {
+    path: String,
+    stop_on_entry: bool,
+}
+
+/// A call in progress, and where it has got to.
+struct Frame {
+    name: String,
+    /// Where the statement running in this frame starts.
+    position: Option<Token>,
+    /// The innermost scope of that statement.
+    scope: Option<Rc<RefCell<Environment>>>,
+}
+
+impl Frame {
+    fn new(name: String) -> Self {
+        Frame {
+            name,
+            position: None,
+            scope: None,
+        }
+    }
+}
+
+/// When to stop next.
+#[derive(Clone, Copy, PartialEq)]
+enum Mode {
+    /// Only at breakpoints.
+    Run,
+    /// Before the first statement.
+    Entry,
+    /// As soon as possible, because the client asked to pause.
+    Pause,
+    /// At the next statement, in whatever frame.
+    StepIn,
+    /// At the next statement in the frame at this depth or one outside it.
+    Over(usize),
+    /// At the next statement in a frame outside the one at this depth.
+    Out(usize),
+}
+
+/// Something the client can expand to see the variables inside.
+enum Container {
+    /// A frame's local scopes, up to the globals.
+    Locals(Rc<RefCell<Environment>>),
+    Globals,
+    List(Rc<RefCell<Vec<Value>>>),
+    Instance(Rc<RefCell<LoxInstance>>),
+}
+
+/// What handling a request means for the program.
+enum Flow {
+    Wait,
+    Launch,
+    Resume,
+    Disconnect,
+}
+
+struct Session {
+    client: Rc<Client>,
+    requests: Receiver<Json>,
+    program: Option<Program>,
+    /// The lines with breakpoints, by file.
+    breakpoints: HashMap<PathBuf, BTreeSet<u32>>,
+    /// Each source's canonical path, which breakpoints are set by.
+    paths: HashMap<SourceId, PathBuf>,
+    /// The calls in progress, outermost first.
+    frames: Vec<Frame>,
+    mode: Mode,
+    /// Where the last statement started, and in how deep a frame, so a line
+    /// with a breakpoint stops once rather than for each statement on it.
+    last: Option<(SourceId, u32, usize)>,
+    globals: Option<Rc<RefCell<Environment>>>,
+    /// What the variable references handed out since the program last
+    /// stopped stand for: reference `n` is `containers[n - 1]`.
+    containers: Vec<Container>,
+}
+
+impl Session {
+    fn statement(&mut self, interpreter: &Interpreter, token: Token) {
+        let depth = self.frames.len();
+        if let Some(frame) = self.frames.last_mut() {
+            frame.position = Some(token.clone());
+            frame.scope = Some(interpreter.environment());
+        }
+        // Requests that arrived while the program ran, like `pause`.
+        while let Ok(request) = self.requests.try_recv() {
+            if let Flow::Disconnect = self.handle(&request) {
+                process::exit(0);
+            }
+        }
+
+        let here = (token.source, token.line, depth);
+        let at_breakpoint = self.last != Some(here) && self.has_breakpoint(&token);
+        let reason = match self.mode {
+            Mode::Entry => Some("entry"),
+            Mode::Pause => Some("pause"),
+            Mode::StepIn => Some("step"),
+            Mode::Over(from) if depth <= from => Some("step"),
+            Mode::Out(from) if depth < from => Some("step"),
+            _ if at_breakpoint => Some("breakpoint"),
+            _ => None,
+        };
+        self.last = Some(here);
+        let Some(reason) = reason else {
+            return;
+        };
+
+        self.mode = Mode::Run;
+        self.client.event(
+            "stopped",
+            Json::object([
+                ("reason", Json::from(reason)),
+                ("threadId", Json::from(1.0)),
+                ("allThreadsStopped", Json::from(true)),
+            ]),
+        );
+        loop {
+            let Ok(request) = self.requests.recv() else {
+                // The client has gone, so there's nobody to debug for.
+                process::exit(0);
+            };
+            match self.handle(&request) {
+                Flow::Resume => break,
+                Flow::Disconnect => process::exit(0),
+                Flow::Wait | Flow::Launch => {}
+            }
+        }
+        self.containers.clear();
+    }
+
+    fn has_breakpoint(&mut self, token: &Token) -> bool {
+        if self.breakpoints.is_empty() {
+            return false;
+        }
+        let path = self.paths.entry(token.source).or_insert_with(|| {
+            let name = token.source.name();
+            fs::canonicalize(&name).unwrap_or_else(|_| PathBuf::from(name))
+        });
+        self.breakpoints
+            .get(path)
+            .is_some_and(|lines| lines.contains(&token.line))
+    }
+
+    fn handle(&mut self, request: &Json) -> Flow {
+        let command = request.get("command").and_then(Json::as_str).unwrap_or("");
+        let arguments = request.get("arguments").unwrap_or(&Json::Null);
+        let client = Rc::clone(&self.client);
+        let mut flow = Flow::Wait;
+        let body = match command {
+            "initialize" => {
+                client.respond(
+                    request,
+                    Json::object([("supportsConfigurationDoneRequest", Json::from(true))]),
+                );
+                client.event("initialized", Json::object::<&str>([]));
+                return flow;
+            }
+            "launch" => {
+                let Some(path) = arguments.get("program").and_then(Json::as_str) else {
+                    client.fail(request, "'launch' needs the path of a program.");
+                    return flow;
+                };
+                let stop_on_entry = arguments.get("stopOnEntry").and_then(Json::as_bool);
+                self.program = Some(Program {
+                    path: path.to_string(),
+                    stop_on_entry: stop_on_entry.unwrap_or(false),
+                });
+                Json::Null
+            }
+            "setBreakpoints" => self.set_breakpoints(arguments),
+            "configurationDone" => {
+                flow = Flow::Launch;
+                Json::Null
+            }
+            "threads" => Json::object([(
+                "threads",
+                Json::Array(vec![Json::object([
+                    ("id", Json::from(1.0)),
+                    ("name", Json::from("main")),
+                ])]),
+            )]),
+            "stackTrace" => self.stack_trace(),
+            "scopes" => {
+                let frame = arguments.get("frameId").and_then(Json::as_f64);
+                self.scopes(frame.unwrap_or(0.0) as usize)
+            }
+            "variables" => {
+                let reference = arguments.get("variablesReference").and_then(Json::as_f64);
+                self.variables(reference.unwrap_or(0.0) as usize)
+            }
+            "continue" | "next" | "stepIn" | "stepOut" => {
+                let depth = self.frames.len();
+                self.mode = match command {
+                    "next" => Mode::Over(depth),
+                    "stepIn" => Mode::StepIn,
+                    "stepOut" => Mode::Out(depth),
+                    _ => Mode::Run,
+                };
+                flow = Flow::Resume;
+                Json::object([("allThreadsContinued", Json::from(true))])
+            }
+            "pause" => {
+                self.mode = Mode::Pause;
+                Json::Null
+            }
+            "disconnect" | "terminate" => {
+                flow = Flow::Disconnect;
+                Json::Null
+            }
+            _ => {
+                client.fail(request, &format!("Unsupported request '{}'.", command));
+                return flow;
+            }
+        };
+        client.respond(request, body);
+        flow
+    }
+
+    fn set_breakpoints(&mut self, arguments: &Json) -> Json {
+        let path = arguments
+            .get("source")
+            .and_then(|source| source.get("path"))
+            .and_then(Json::as_str)
+            .unwrap_or("");
+        let lines: BTreeSet<u32> = match arguments.get("breakpoints") {
+            Some(Json::Array(breakpoints)) => breakpoints
+                .iter()
+                .filter_map(|breakpoint| breakpoint.get("line")?.as_f64())
+                .map(|line| line as u32)
+                .collect(),
+            _ => BTreeSet::new(),
+        };
+        let verified = lines
+            .iter()
+            .map(|line| {
+                Json::object([
+                    ("verified", Json::from(true)),
+                    ("line", Json::from(*line as f64)),
+                ])
+            })
+            .collect();
+        let path = fs::canonicalize(path).unwrap_or_else(|_| PathBuf::from(path));
+        self.breakpoints.insert(path, lines);
+        Json::object([("breakpoints", Json::Array(verified))])
+    }
+
+    /// The frames in progress, innermost first. A frame's id is its depth
+    /// plus one.
+    fn stack_trace(&self) -> Json {
+        let frames: Vec<Json> = self
+            .frames
+            .iter()
+            .enumerate()
+            .rev()
+            .filter_map(|(depth, frame)| {
+                let position = frame.position.as_ref()?;
+                let path = position.source.name();
+                Json::object([
+                    ("id", Json::from(depth + 1)),
+                    ("name", Json::from(frame.name.as_str())),
+                    (
+                        "source",
+                        Json::object([
+                            ("name", Json::from(file_name(&path))),
+                            ("path", Json::from(path)),
+                        ]),
+                    ),
+                    ("line", Json::from(position.line as f64)),
+                    ("column", Json::from(position.column as f64)),
+                ])
+                .into()
+            })
+            .collect();
+        Json::object([
+            ("totalFrames", Json::from(frames.len())),
+            ("stackFrames", Json::Array(frames)),
+        ])
+    }
+
+    fn scopes(&mut self, frame: usize) -> Json {
+        let mut scopes = Vec::new();
+        let scope = frame
+            .checked_sub(1)
+            .and_then(|depth| self.frames.get(depth))
+            .and_then(|frame| frame.scope.clone());
+        if let Some(scope) = scope {
+            let reference = self.reference(Container::Locals(scope));
+            scopes.push(scope_json("Locals", reference, false));
+        }
+        let reference = self.reference(Container::Globals);
+        scopes.push(scope_json("Globals", reference, true));
+        Json::object([("scopes", Json::Array(scopes))])
+    }
+
+    fn variables(&mut self, reference: usize) -> Json {
+        let bindings: Vec<(String, Value)> = match reference
+            .checked_sub(1)
+            .and_then(|index| self.containers.get(index))
+        {
+            Some(Container::Locals(scope)) => {
+                let mut bindings: Vec<(String, Value)> = Vec::new();
+                let mut scope = Some(Rc::clone(scope));
+                while let Some(current) = scope {
+                    if self
+                        .globals
+                        .as_ref()
+                        .is_some_and(|globals| Rc::ptr_eq(globals, &current))
+                    {
+                        break;
+                    }
+                    // A name in an inner scope hides the same name further out.
+                    let mut found: Vec<_> = current
+                        .borrow()
+                        .iter()
+                        .filter(|(name, _)| bindings.iter().all(|(seen, _)| seen != name))
+                        .map(|(name, value)| (name.to_string(), value.clone()))
+                        .collect();
+                    found.sort_by(|(a, _), (b, _)| a.cmp(b));
+                    bindings.extend(found);
+                    scope = current.borrow().enclosing();
+                }
+                bindings
+            }
+            Some(Container::Globals) => {
+                let mut bindings: Vec<_> = self
+                    .globals
+                    .iter()
+                    .flat_map(|globals| {
+                        globals
+                            .borrow()
+                            .iter()
+                            .map(|(name, value)| (name.to_string(), value.clone()))
+                            .collect::<Vec<_>>()
+                    })
+                    .collect();
+                bindings.sort_by(|(a, _), (b, _)| a.cmp(b));
+                bindings
+            }
+            Some(Container::List(items)) => items
+                .borrow()
+                .iter()
+                .enumerate()
+                .map(|(index, item)| (index.to_string(), item.clone()))
+                .collect(),
+            Some(Container::Instance(instance)) => {
+                let mut bindings: Vec<_> = instance
+                    .borrow()
+                    .fields
+                    .iter()
+                    .map(|(name, value)| (name.to_string(), value.clone()))
+                    .collect();
+                bindings.sort_by(|(a, _), (b, _)| a.cmp(b));
+                bindings
+            }
+            None => Vec::new(),
+        };
+        let variables = bindings
+            .into_iter()
+            .map(|(name, value)| {
+                let reference = match &value {
+                    Value::List(items) => self.reference(Container::List(Rc::clone(items))),
+                    Value::Instance(instance) => {
+                        self.reference(Container::Instance(Rc::clone(instance)))
+                    }
+                    _ => 0,
+                };
+                Json::object([
+                    ("name", Json::from(name)),
+                    ("value", Json::from(preview::format(&value))),
+                    ("type", Json::from(value.type_name())),
+                    ("variablesReference", Json::from(reference)),
+                ])
+            })
+            .collect();
+        Json::object([("variables", Json::Array(variables))])
+    }
+
+    fn reference(&mut self, container: Container) -> usize {
+        self.containers.push(container);
+        self.containers.len()
+    }
+}
+
+fn scope_json(name: &str, reference: usize, expensive: bool) -> Json {
+    Json::object([
+        ("name", Json::from(name)),
+        ("variablesReference", Json::from(reference)),
+        ("expensive", Json::from(expensive)),
+    ])
+}
+
+fn file_name(path: &str) -> &str {
+    Path::new(path)
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or(path)
+}
@@ -0,0 +1,137 @@
+//! Which variables a piece of code reads and writes that it doesn't declare
+//! itself, for embedders building dependency graphs, such as a spreadsheet
+//! working out which formulas to recompute when a global changes. The names
+//! are bound the way the resolver binds them.
+
+use std::collections::BTreeSet;
+
+use crate::{
+    arena::Arena,
+    diagnostics,
+    expression::ExprId,
+    function::Function,
+    parser::Parser,
+    resolver::{self, Resolution},
+    scanner::Scanner,
+    source::SourceId,
+    statement::{Stmt, StmtId},
+};
+
+/// The free variables a piece of code reads and writes, i.e. the names it
+/// doesn't declare itself and so must find in an enclosing scope. Embedders
+/// use these to work out what to recompute when a global changes.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Dependencies {
+    pub reads: BTreeSet<String>,
+    pub writes: BTreeSet<String>,
+}
+
+impl From<Resolution> for Dependencies {
+    fn from(resolution: Resolution) -> Self {
+        let mut dependencies = Dependencies::default();
+        for (name, read) in resolution.free {
+            let names = if read {
+                &mut dependencies.reads
+            } else {
+                &mut dependencies.writes
+            };
+            names.insert(name.lexeme().to_string());
+        }
+        dependencies
+    }
+}
+
+/// Free variables of `source`: a formula such as `price * quantity`, with
+/// or without its `;`, or a whole program. Static errors come back as the
+/// command line would print them.
+pub fn of_source(source: &str) -> Result<Dependencies, Vec<String>> {
+    diagnostics::reset();
+    let source = source.trim_end();
+    let source = if source.ends_with([';', '}']) {
+        source.to_string()
+    } else {
+        format!("{};", source)
+    };
+    let mut parser = Parser::new(Scanner::new(&source, SourceId::ANONYMOUS).tokens());
+    let statements = parser.parse();
+    let errors = diagnostics::take();
+    crate::HAD_ERROR.store(false, std::sync::atomic::Ordering::Relaxed);
+    if !errors.is_empty() {
+        return Err(errors.iter().map(ToString::to_string).collect());
+    }
+    let arena = parser.into_arena();
+    match statements[..] {
+        [statement] => match arena.stmt(statement) {
+            Stmt::Expression(expr) => Ok(of_expr(&arena, *expr)),
+            _ => Ok(of_program(&arena, &statements)),
+        },
+        _ => Ok(of_program(&arena, &statements)),
+    }
+}
+
+/// Free variables of a whole program: the globals it expects to be given,
+/// mostly natives. Its own top-level declarations don't count.
+pub fn of_program(arena: &Arena, statements: &[StmtId]) -> Dependencies {
+    resolver::scopes(arena, statements).into()
+}
+
+/// Free variables of an expression: every variable in it, since an
+/// expression can't declare one.
+pub fn of_expr(arena: &Arena, expr: ExprId) -> Dependencies {
+    resolver::expression_scopes(arena, expr).into()
+}
+
+/// Free variables of a function's body, not counting its parameters. Natives
+/// have none.
+pub fn of_function(function: &Function) -> Dependencies {
+    match function {
+        Function::User {
+            params, body, ast, ..
+        } => resolver::function_scopes(ast, params, body).into(),
+        Function::Native { .. } => Dependencies::default(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(names: &[&str]) -> BTreeSet<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn formulas_depend_on_every_variable_in_them() {
+        let dependencies = of_source("price * (1 + tax) - discount").unwrap();
+        assert_eq!(dependencies.reads, names(&["discount", "price", "tax"]));
+        assert!(dependencies.writes.is_empty());
+    }
+
+    #[test]
+    fn programs_depend_on_globals_they_dont_declare() {
+        let source = "var total = 0;
+            fun add(n) { var doubled = n * 2; total = total + doubled; count = count + 1; }
+            add(rate);";
+        let dependencies = of_source(source).unwrap();
+        assert_eq!(dependencies.reads, names(&["count", "rate"]));
+        assert_eq!(dependencies.writes, names(&["count"]));
+    }
+
+    #[test]
+    fn blocks_scope_their_declarations() {
+        let dependencies = of_source("{ var a = 1; print a; } print a;").unwrap();
+        assert_eq!(dependencies.reads, names(&["a"]));
+    }
+
+    #[test]
+    fn functions_declare_their_arguments() {
+        let source = "fun count(x) { return len(arguments) + x; } print arguments;";
+        let dependencies = of_source(source).unwrap();
+        assert_eq!(dependencies.reads, names(&["arguments", "len"]));
+    }
+
+    #[test]
+    fn static_errors_are_returned() {
+        assert!(of_source("1 +").is_err());
+    }
+}
@@ -0,0 +1,238 @@
+use std::{
+    cell::{Cell, RefCell},
+    collections::{HashMap, HashSet},
+    fs,
+    path::Path,
+};
+
+use crate::{
+    json::Json,
+    output::{OutputEvent, OutputSink},
+    source::SourceId,
+};
+
+/// How a rule's diagnostics are reported: not at all, as warnings that
+/// don't fail the run, or as errors.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Allow,
+    Warn,
+    Deny,
+}
+
+impl Severity {
+    fn parse(name: &str) -> Option<Severity> {
+        match name {
+            "allow" => Some(Severity::Allow),
+            "warn" => Some(Severity::Warn),
+            "deny" => Some(Severity::Deny),
+            _ => None,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Severity::Allow => "allow",
+            Severity::Warn => "warning",
+            Severity::Deny => "error",
+        }
+    }
+}
+
+/// The rules whose severity can be configured, with their defaults.
+const RULES: &[(&str, Severity)] = &[
+    ("unused-variable", Severity::Warn),
+    ("shadowing", Severity::Allow),
+];
+
+/// Not a rule: setting it changes every rule that is otherwise a warning.
+const WARNINGS: &str = "warnings";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    /// `Deny` for errors, `Warn` for warnings.
+    pub severity: Severity,
+    pub source: SourceId,
+    pub line: u32,
+    /// 0 when only the line is known.
+    pub column: u32,
+    /// How many characters from `column` it's about, 0 when that isn't
+    /// known.
+    pub length: u32,
+    pub location: String,
+    pub message: String,
+    /// The rule it came from, for warnings and errors that can be
+    /// configured.
+    pub code: Option<&'static str>,
+}
+
+impl Diagnostic {
+    /// The diagnostic as `--diagnostics-format json` writes it.
+    pub(crate) fn to_json(&self) -> Json {
+        Json::object([
+            ("file", Json::from(self.source.name())),
+            ("line", Json::from(self.line as usize)),
+            ("column", Json::from(self.column as usize)),
+            ("length", Json::from(self.length as usize)),
+            ("severity", Json::from(self.severity.name())),
+            ("code", self.code.map_or(Json::Null, Json::from)),
+            ("message", Json::from(self.message.as_str())),
+        ])
+    }
+}
+
+impl std::fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}:{}", self.source.name(), self.line)?;
+        if self.column > 0 {
+            write!(f, ":{}", self.column)?;
+        }
+        let kind = match self.severity {
+            Severity::Warn => "Warning",
+            _ => "Error",
+        };
+        write!(f, ": {}{}: {}", kind, self.location, self.message)?;
+        if let Some(code) = self.code {
+            write!(f, " ({})", code)?;
+        }
+        Ok(())
+    }
+}
+
+thread_local! {
+    static PENDING: RefCell<Vec<Diagnostic>> = const { RefCell::new(Vec::new()) };
+    static EMITTED: Cell<usize> = const { Cell::new(0) };
+    static MAX_ERRORS: Cell<Option<usize>> = const { Cell::new(None) };
+    static SEVERITIES: RefCell<HashMap<String, Severity>> = RefCell::new(HashMap::new());
+}
+
+/// Caps how many diagnostics a single run prints (`--max-errors`).
+pub fn set_max_errors(max: Option<usize>) {
+    MAX_ERRORS.with(|m| m.set(max));
+}
+
+/// Fails unless `rule` is a rule's name or `warnings`.
+pub fn check_rule(rule: &str) -> Result<(), String> {
+    if rule == WARNINGS || RULES.iter().any(|(name, _)| *name == rule) {
+        return Ok(());
+    }
+    let names: Vec<_> = RULES.iter().map(|(name, _)| *name).collect();
+    Err(format!(
+        "Unknown diagnostic '{}'. Expected one of {} or {}.",
+        rule,
+        names.join(", "),
+        WARNINGS
+    ))
+}
+
+/// Overrides the severity of `rule`, or of every warning if it's
+/// `warnings` (`--deny`, `--warn`).
+pub fn set_severity(rule: &str, severity: Severity) -> Result<(), String> {
+    check_rule(rule)?;
+    SEVERITIES.with(|severities| severities.borrow_mut().insert(rule.to_string(), severity));
+    Ok(())
+}
+
+/// How diagnostics from `rule` should be reported.
+pub fn severity(rule: &str) -> Severity {
+    let configured = |name: &str| SEVERITIES.with(|s| s.borrow().get(name).copied());
+    let default = RULES
+        .iter()
+        .find(|(name, _)| *name == rule)
+        .map_or(Severity::Warn, |(_, severity)| *severity);
+    match configured(rule).unwrap_or(default) {
+        Severity::Warn => configured(WARNINGS).unwrap_or(Severity::Warn),
+        severity => severity,
+    }
+}
+
+/// Applies the `[diagnostics]` section of the nearest `lox.toml` in `dir` or
+/// one of its parents, where each line sets a rule to "allow", "warn" or
+/// "deny". Only that subset of TOML is understood.
+pub fn load_config(dir: &Path) -> Result<(), String> {
+    let Some(path) = dir
+        .ancestors()
+        .map(|dir| dir.join("lox.toml"))
+        .find(|path| path.is_file())
+    else {
+        return Ok(());
+    };
+    let contents = fs::read_to_string(&path)
+        .map_err(|e| format!("Could not read {}: {}.", path.display(), e))?;
+    let mut in_section = false;
+    for (index, line) in contents.lines().enumerate() {
+        let error = |message: String| format!("{}:{}: {}", path.display(), index + 1, message);
+        let line = line.split('#').next().unwrap_or("").trim();
+        if line.starts_with('[') {
+            in_section = line == "[diagnostics]";
+            continue;
+        }
+        if !in_section || line.is_empty() {
+            continue;
+        }
+        let Some((rule, value)) = line.split_once('=') else {
+            return Err(error("Expected 'rule = \"level\"'.".to_string()));
+        };
+        let value = value.trim().trim_matches('"');
+        let severity = Severity::parse(value).ok_or_else(|| {
+            error(format!(
+                "Expected \"allow\", \"warn\" or \"deny\", not '{}'.",
+                value
+            ))
+        })?;
+        set_severity(rule.trim().trim_matches('"'), severity).map_err(error)?;
+    }
+    Ok(())
+}
+
+/// Queues a diagnostic; nothing is emitted until `flush`.
+pub fn push(diagnostic: Diagnostic) {
+    PENDING.with(|pending| pending.borrow_mut().push(diagnostic));
+}
+
+/// Removes and returns everything queued on this thread, so worker threads
+/// can hand their diagnostics back to the main one.
+pub fn take() -> Vec<Diagnostic> {
+    PENDING.with(|pending| std::mem::take(&mut *pending.borrow_mut()))
+}
+
+/// Whether anything is queued on this thread.
+pub fn any_pending() -> bool {
+    PENDING.with(|pending| !pending.borrow().is_empty())
+}
+
+/// Forgets queued diagnostics and the count emitted so far, at the start of
+/// a new run.
+pub fn reset() {
+    PENDING.with(|pending| pending.borrow_mut().clear());
+    EMITTED.with(|emitted| emitted.set(0));
+}
+
+/// `diagnostics` ordered by file and position, without repeats of the same
+/// (file, line, message).
+pub fn ordered(mut diagnostics: Vec<Diagnostic>) -> Vec<Diagnostic> {
+    diagnostics.sort_by_key(|d| (d.source, d.line, d.column));
+    let mut seen = HashSet::new();
+    diagnostics.retain(|d| seen.insert((d.source, d.line, d.message.clone())));
+    diagnostics
+}
+
+/// Sends queued diagnostics to `sink` `ordered`, dropping anything past the
+/// error limit.
+pub fn flush(sink: &dyn OutputSink) {
+    let diagnostics = ordered(take());
+    let max = MAX_ERRORS.with(Cell::get).unwrap_or(usize::MAX);
+    let mut suppressed = 0;
+    for diagnostic in diagnostics {
+        let emitted = EMITTED.with(Cell::get);
+        if emitted >= max {
+            suppressed += 1;
+            continue;
+        }
+        sink.emit(OutputEvent::Diagnostic(diagnostic));
+        EMITTED.with(|e| e.set(emitted + 1));
+    }
+    if suppressed > 0 {
+        sink.emit(OutputEvent::DiagnosticsSuppressed(suppressed));
+    }
+}
@@ -0,0 +1,121 @@
+//! `Engine`: natives and settings registered once, from which a host such
+//! as a server spawns a fresh interpreter for each script it runs.
+
+use std::sync::Arc;
+
+use crate::{
+    budget::Budget,
+    environment::Environment,
+    interpreter::{Interpreter, DEFAULT_MAX_DEPTH},
+    natives::{self, NativeFn},
+    number::{FloatBackend, NumberBackend},
+    operators::{Handler, Operators, Precedence},
+};
+
+/// Natives and settings shared by the interpreters it spawns.
+///
+/// Each spawned interpreter is an isolate: its globals, module cache and
+/// cancellation token are its own, and a script assigning over a native
+/// only changes it in that interpreter. Natives registered after spawning
+/// reach only interpreters spawned later.
+///
+/// An engine holds nothing tied to one thread, so it can be handed to
+/// another thread or shared between several that each spawn interpreters.
+#[derive(Debug, Clone)]
+pub struct Engine {
+    /// The natives registered on top of the standard ones, which are
+    /// looked up as scripts use them and cost nothing to share.
+    natives: Vec<(&'static str, usize, NativeFn)>,
+    fs: bool,
+    env: bool,
+    numbers: Arc<dyn NumberBackend>,
+    operators: Arc<Operators>,
+    error_values: bool,
+    max_depth: usize,
+    budget: Budget,
+}
+
+impl Default for Engine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Engine {
+    pub fn new() -> Self {
+        Self::with_numbers(Arc::new(FloatBackend))
+    }
+
+    pub fn with_numbers(numbers: Arc<dyn NumberBackend>) -> Self {
+        Engine {
+            natives: Vec::new(),
+            fs: false,
+            env: false,
+            numbers,
+            operators: Arc::default(),
+            error_values: false,
+            max_depth: DEFAULT_MAX_DEPTH,
+            budget: Budget::default(),
+        }
+    }
+
+    /// Makes `body` callable as `name` in every interpreter spawned from now
+    /// on. It takes the place of any standard native of the same name.
+    pub fn register_native(&mut self, name: &'static str, arity: usize, body: NativeFn) {
+        self.natives.push((name, arity, body));
+    }
+
+    /// As `Interpreter::register_operator`, for every interpreter spawned
+    /// from now on.
+    pub fn register_operator(
+        &mut self,
+        symbol: &str,
+        precedence: Precedence,
+        handler: Handler,
+    ) -> Result<(), String> {
+        Arc::make_mut(&mut self.operators).register(symbol, precedence, handler)
+    }
+
+    pub fn allow_fs(&mut self) {
+        self.fs = true;
+    }
+
+    pub fn allow_env(&mut self) {
+        self.env = true;
+    }
+
+    /// As `Interpreter::error_values`, for every interpreter spawned.
+    pub fn error_values(&mut self, on: bool) {
+        self.error_values = on;
+    }
+
+    /// As `Interpreter::set_max_depth`, for every interpreter spawned.
+    pub fn set_max_depth(&mut self, max_depth: usize) {
+        self.max_depth = max_depth;
+    }
+
+    /// As `Interpreter::set_budget`, for every interpreter spawned.
+    pub fn set_budget(&mut self, budget: Budget) {
+        self.budget = budget;
+    }
+
+    /// A fresh interpreter with this engine's natives and settings.
+    pub fn spawn(&self) -> Interpreter {
+        let mut builtins = Environment::with_fallback(natives::lookup, natives::names);
+        if self.fs {
+            natives::define_fs(&mut builtins);
+        }
+        if self.env {
+            natives::define_env(&mut builtins);
+        }
+        for &(name, arity, body) in &self.natives {
+            builtins.define(name, natives::native(name, arity, body));
+        }
+        let mut interpreter = Interpreter::with_builtins(builtins, Arc::clone(&self.numbers));
+        interpreter.set_operators(Arc::clone(&self.operators));
+        interpreter.error_values(self.error_values);
+        interpreter.set_max_depth(self.max_depth);
+        interpreter.set_budget(self.budget);
+        interpreter
+    }
+}
@@ -1,12 +1,23 @@
 use itertools::Itertools;
-use std::{cell::RefCell, collections::HashMap, rc::Rc};
+use std::{
+    cell::RefCell,
+    collections::{HashMap, HashSet},
+    rc::Rc,
+};
 
-use crate::{interpreter::Value, token::Token};
+use crate::{interpreter::Value, symbol::Symbol, token::Token};
 
-#[derive(Debug, Clone, PartialEq, Default)]
+#[derive(Debug, Clone, Default)]
 pub struct Environment {
     enclosing: Option<Rc<RefCell<Environment>>>,
-    values: HashMap<String, Value>,
+    values: HashMap<Symbol, Value>,
+    // The names in `values` declared with `const`.
+    constants: HashSet<Symbol>,
+    // Consulted for names found nowhere else, so a scope can offer bindings
+    // without storing them up front.
+    fallback: Option<fn(&str) -> Option<Value>>,
+    // Every name `fallback` knows, for suggesting one after a typo.
+    fallback_names: Option<fn() -> Vec<&'static str>>,
 }
 
 impl std::fmt::Display for Environment {
@@ -19,7 +30,7 @@ impl std::fmt::Display for Environment {
                     .iter()
                     .map(|(k, v)| format!("{}={}", k, v))
                     .join(", "),
-                env.borrow().to_string(),
+                env.borrow(),
             )
         } else {
             write!(
@@ -39,6 +50,9 @@ impl Environment {
         Self {
             enclosing: None,
             values: HashMap::new(),
+            constants: HashSet::new(),
+            fallback: None,
+            fallback_names: None,
         }
     }
 
@@ -46,32 +60,234 @@ impl Environment {
         Self {
             enclosing: Some(Rc::clone(enclosing)),
             values: HashMap::new(),
+            constants: HashSet::new(),
+            fallback: None,
+            fallback_names: None,
         }
     }
 
-    pub fn define(&mut self, key: String, value: Value) {
+    /// An outermost scope that asks `fallback` for names it hasn't defined.
+    /// `names` lists the names it can answer for.
+    pub fn with_fallback(
+        fallback: fn(&str) -> Option<Value>,
+        names: fn() -> Vec<&'static str>,
+    ) -> Self {
+        Self {
+            enclosing: None,
+            values: HashMap::new(),
+            constants: HashSet::new(),
+            fallback: Some(fallback),
+            fallback_names: Some(names),
+        }
+    }
+
+    fn fallback_for(&self, key: &str) -> Option<Value> {
+        self.fallback.and_then(|fallback| fallback(key))
+    }
+
+    pub fn define(&mut self, key: impl Into<Symbol>, value: Value) {
+        let key = key.into();
+        self.constants.remove(&key);
+        self.values.insert(key, value);
+    }
+
+    /// Defines `key` as `define` does, but so that assigning to it fails.
+    pub fn define_constant(&mut self, key: impl Into<Symbol>, value: Value) {
+        let key = key.into();
+        self.constants.insert(key);
         self.values.insert(key, value);
     }
 
     pub fn get(&self, key: &Token) -> Result<Value, String> {
-        if let Some(value) = self.values.get(&key.lexeme) {
-            Ok(value.clone())
+        self.lookup_symbol(key.symbol)
+            .ok_or_else(|| self.undefined(key.lexeme()))
+    }
+
+    /// The error for reading or assigning `key` where it isn't defined.
+    fn undefined(&self, key: &str) -> String {
+        match self.suggest(key) {
+            Some(name) => format!("Undefined variable '{}'. Did you mean '{}'?", key, name),
+            None => format!("Undefined variable '{}'.", key),
+        }
+    }
+
+    /// Looks `key` up here and in the enclosing scopes.
+    pub fn lookup(&self, key: &str) -> Option<Value> {
+        self.lookup_symbol(Symbol::intern(key))
+    }
+
+    fn lookup_symbol(&self, key: Symbol) -> Option<Value> {
+        if let Some(value) = self.values.get(&key) {
+            Some(value.clone())
         } else if let Some(env) = &self.enclosing {
-            env.borrow().get(key)
+            env.borrow().lookup_symbol(key)
         } else {
-            Err(format!("Undefined variable '{}'.", key.lexeme))
+            self.fallback_for(key.as_str())
+        }
+    }
+
+    /// The name visible from this scope closest to `key`, if one is close
+    /// enough to be a likely typo: within a third of the name's length, or
+    /// an edit for names of three to five characters. Names shorter than
+    /// that are close to too much to suggest anything.
+    fn suggest(&self, key: &str) -> Option<String> {
+        let length = key.chars().count();
+        if length < 3 {
+            return None;
         }
+        let mut names = Vec::new();
+        self.visible_names(&mut names);
+        let limit = (length / 3).max(1);
+        names
+            .into_iter()
+            .map(|name| (edit_distance(key, &name), name))
+            .filter(|(distance, _)| *distance <= limit)
+            .min()
+            .map(|(_, name)| name)
+    }
+
+    fn visible_names(&self, names: &mut Vec<String>) {
+        names.extend(self.values.keys().map(Symbol::to_string));
+        if let Some(env) = &self.enclosing {
+            env.borrow().visible_names(names);
+        } else if let Some(fallback_names) = self.fallback_names {
+            names.extend(fallback_names().into_iter().map(str::to_string));
+        }
+    }
+
+    /// Looks `key` up in this scope only, ignoring enclosing ones.
+    pub fn get_local(&self, key: &str) -> Option<Value> {
+        self.values.get(&Symbol::intern(key)).cloned()
+    }
+
+    /// Removes `key` from this scope only, returning its value.
+    pub fn remove(&mut self, key: &str) -> Option<Value> {
+        self.values.remove(&Symbol::intern(key))
+    }
+
+    /// The scope this one is nested in, if any.
+    pub fn enclosing(&self) -> Option<Rc<RefCell<Environment>>> {
+        self.enclosing.clone()
+    }
+
+    /// Bindings in this scope only, in no particular order.
+    pub fn iter(&self) -> impl Iterator<Item = (&'static str, &Value)> {
+        self.values.iter().map(|(key, value)| (key.as_str(), value))
     }
 
     pub fn assign(&mut self, name: Token, value: Value) -> Result<(), String> {
-        if self.values.contains_key(&name.lexeme) {
-            self.values.insert(name.lexeme.clone(), value);
-            Ok(())
+        match self.assign_defined(&name, value) {
+            Some(assigned) => assigned,
+            // Suggested from here, so the innermost scope's names count.
+            None => Err(self.undefined(name.lexeme())),
+        }
+    }
+
+    /// Assigns to `name` where it's defined, or returns `None` if it isn't.
+    fn assign_defined(&mut self, name: &Token, value: Value) -> Option<Result<(), String>> {
+        if self.constants.contains(&name.symbol) {
+            Some(Err(format!(
+                "Can't assign to constant '{}'.",
+                name.lexeme()
+            )))
+        } else if let Some(slot) = self.values.get_mut(&name.symbol) {
+            *slot = value;
+            Some(Ok(()))
         } else if let Some(env) = &self.enclosing {
-            env.borrow_mut().assign(name, value)?;
-            Ok(())
+            env.borrow_mut().assign_defined(name, value)
+        } else if self.fallback_for(name.lexeme()).is_some() {
+            // Assigning to a native replaces it from now on.
+            self.values.insert(name.symbol, value);
+            Some(Ok(()))
         } else {
-            Err(format!("Undefined variable {}.", name.lexeme))
+            None
+        }
+    }
+}
+
+/// How many edits turn `a` into `b`, where an edit inserts, removes or
+/// replaces a character or swaps two adjacent ones. Swapping counts once,
+/// since `coutn` is as likely a typo for `count` as `cont` is.
+pub fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    // `rows[i][j]` is the distance between the first i characters of `a`
+    // and the first j of `b`.
+    let mut rows = vec![(0..=b.len()).collect::<Vec<usize>>()];
+    for i in 1..=a.len() {
+        let mut row = vec![i];
+        for j in 1..=b.len() {
+            let replace = rows[i - 1][j - 1] + usize::from(a[i - 1] != b[j - 1]);
+            let mut distance = replace.min(rows[i - 1][j] + 1).min(row[j - 1] + 1);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                distance = distance.min(rows[i - 2][j - 2] + 1);
+            }
+            row.push(distance);
         }
+        rows.push(row);
+    }
+    rows[a.len()][b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{source::SourceId, token::TokenType};
+
+    fn name(lexeme: &str) -> Token {
+        let token = crate::scanner::Scanner::new(lexeme, SourceId::ANONYMOUS)
+            .tokens()
+            .next()
+            .unwrap();
+        assert_eq!(token.token_type, TokenType::Identifier);
+        token
+    }
+
+    fn scope_with(names: &[&str]) -> Environment {
+        let mut environment = Environment::new();
+        for name in names {
+            environment.define(*name, Value::None);
+        }
+        environment
+    }
+
+    #[test]
+    fn edit_distance_counts_each_kind_of_edit_once() {
+        assert_eq!(edit_distance("count", "count"), 0);
+        assert_eq!(edit_distance("count", "cont"), 1);
+        assert_eq!(edit_distance("count", "counts"), 1);
+        assert_eq!(edit_distance("count", "mount"), 1);
+        assert_eq!(edit_distance("coutn", "count"), 1);
+        assert_eq!(edit_distance("", "abc"), 3);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+    }
+
+    #[test]
+    fn reading_a_typo_suggests_the_name() {
+        let environment = scope_with(&["count"]);
+        assert_eq!(
+            environment.get(&name("coutn")),
+            Err("Undefined variable 'coutn'. Did you mean 'count'?".to_string())
+        );
+        assert_eq!(
+            environment.get(&name("total")),
+            Err("Undefined variable 'total'.".to_string())
+        );
+    }
+
+    #[test]
+    fn assigning_a_typo_suggests_names_from_inner_scopes() {
+        let globals = Rc::new(RefCell::new(scope_with(&["total"])));
+        let mut local = Environment::new_from(&globals);
+        local.define("count", Value::None);
+        assert_eq!(
+            local.assign(name("cout"), Value::None),
+            Err("Undefined variable 'cout'. Did you mean 'count'?".to_string())
+        );
+        assert_eq!(
+            local.assign(name("totl"), Value::None),
+            Err("Undefined variable 'totl'. Did you mean 'total'?".to_string())
+        );
+        assert_eq!(local.assign(name("total"), Value::None), Ok(()));
     }
 }
@@ -0,0 +1,210 @@
+//! `:explore <expr>` in the REPL: shows how an expression parsed as a tree
+//! whose nodes can be opened and closed, and evaluated one at a time in the
+//! session's environment.
+
+use std::{
+    panic::{self, AssertUnwindSafe},
+    sync::{atomic::Ordering, Arc},
+};
+
+use crate::{
+    arena::Arena,
+    diagnostics,
+    expression::{Expr, ExprId},
+    interpreter::Interpreter,
+    line_editor::{LineEditor, ReadLine},
+    natives,
+    parser::Parser,
+    scanner::Scanner,
+    source::SourceId,
+    statement::Stmt,
+    token::{Literal, Token},
+};
+
+const HELP: &str = "\
+<n>       open or close node n
+eval <n>  evaluate node n in the session
+all       open every node
+none      close every node
+q         go back to the REPL (Ctrl-D works too)";
+
+struct Node {
+    expr: ExprId,
+    depth: usize,
+    children: Vec<usize>,
+    open: bool,
+}
+
+pub fn explore(source: &str, interpreter: &mut Interpreter, editor: &mut LineEditor) {
+    let Some((ast, expr)) = parse(source, interpreter) else {
+        return;
+    };
+    let mut nodes = Vec::new();
+    flatten(&ast, expr, 0, &mut nodes);
+    nodes[0].open = true;
+    print_tree(&ast, &nodes);
+
+    loop {
+        let input = match editor.read_line("explore> ") {
+            Ok(ReadLine::Line(input)) => input,
+            Ok(ReadLine::Interrupted) => continue,
+            Ok(ReadLine::Eof) | Err(_) => break,
+        };
+        let (command, argument) = input.trim().split_once(' ').unwrap_or((input.trim(), ""));
+        match (command, argument.trim()) {
+            ("", _) => print_tree(&ast, &nodes),
+            ("q" | "quit", _) => break,
+            ("help", _) => println!("{}", HELP),
+            ("all" | "none", _) => {
+                for node in &mut nodes {
+                    node.open = command == "all";
+                }
+                print_tree(&ast, &nodes);
+            }
+            ("eval", n) => match node_index(n, &nodes) {
+                Some(index) => evaluate(&ast, nodes[index].expr, interpreter),
+                None => println!("Usage: eval <n>, where n is a node number."),
+            },
+            (n, "") if node_index(n, &nodes).is_some() => {
+                let index = node_index(n, &nodes).unwrap();
+                nodes[index].open = !nodes[index].open;
+                print_tree(&ast, &nodes);
+            }
+            _ => println!("Unknown command '{}'. Type help for a list.", command),
+        }
+    }
+}
+
+/// Parses `source` as a single expression, reporting any errors.
+fn parse(source: &str, interpreter: &Interpreter) -> Option<(Arc<Arena>, ExprId)> {
+    diagnostics::reset();
+    let source = format!("{};", source.trim().trim_end_matches(';'));
+    let tokens = Scanner::new(&source, SourceId::ANONYMOUS).tokens();
+    let mut parser = Parser::new(tokens);
+    let mut statements = parser.parse();
+    let arena = parser.into_arena();
+    diagnostics::flush(&*interpreter.output());
+    if crate::HAD_ERROR.swap(false, Ordering::Relaxed) {
+        return None;
+    }
+    match (
+        statements.pop().map(|stmt| arena.stmt(stmt)),
+        statements.is_empty(),
+    ) {
+        (Some(&Stmt::Expression(expr)), true) => Some((Arc::new(arena), expr)),
+        _ => {
+            println!("Usage: :explore <expression>");
+            None
+        }
+    }
+}
+
+/// Adds `expr` and everything under it to `nodes`, parents first.
+fn flatten(arena: &Arena, expr: ExprId, depth: usize, nodes: &mut Vec<Node>) -> usize {
+    let index = nodes.len();
+    nodes.push(Node {
+        expr,
+        depth,
+        children: Vec::new(),
+        open: false,
+    });
+    let children: Vec<ExprId> = match arena.expr(expr) {
+        Expr::Grouping(inner) => vec![*inner],
+        Expr::Binary(l, _, r) | Expr::Logical(l, _, r) => vec![*l, *r],
+        Expr::Unary(_, r) | Expr::Assign(_, r) => vec![*r],
+        Expr::Call(callee, _, args) => std::iter::once(callee).chain(args).copied().collect(),
+        Expr::Get(object, _) => vec![*object],
+        Expr::Set(object, _, value) => vec![*object, *value],
+        Expr::Tuple(_, values) => values.clone(),
+        Expr::Variable(_) | Expr::Literal(_) => vec![],
+    };
+    for child in children {
+        let child = flatten(arena, child, depth + 1, nodes);
+        nodes[index].children.push(child);
+    }
+    index
+}
+
+fn print_tree(arena: &Arena, nodes: &[Node]) {
+    print_node(arena, nodes, 0);
+}
+
+fn print_node(arena: &Arena, nodes: &[Node], index: usize) {
+    let node = &nodes[index];
+    let expr = arena.expr(node.expr);
+    let marker = match (node.children.is_empty(), node.open) {
+        (true, _) => ' ',
+        (false, true) => '-',
+        (false, false) => '+',
+    };
+    let mut line = format!(
+        "{}{} [{}] {}",
+        "  ".repeat(node.depth),
+        marker,
+        index + 1,
+        label(expr)
+    );
+    if !node.children.is_empty() {
+        line.push_str(&format!("  {}", arena.show(node.expr)));
+    }
+    if let Some(token) = position(expr) {
+        line.push_str(&format!("  at {}:{}", token.line, token.column));
+    }
+    println!("{}", line);
+    if node.open {
+        for &child in &node.children {
+            print_node(arena, nodes, child);
+        }
+    }
+}
+
+fn label(expr: &Expr) -> String {
+    match expr {
+        Expr::Grouping(_) => "Grouping".to_string(),
+        Expr::Binary(_, op, _) => format!("Binary {}", op.lexeme()),
+        Expr::Logical(_, op, _) => format!("Logical {}", op.lexeme()),
+        Expr::Unary(op, _) => format!("Unary {}", op.lexeme()),
+        Expr::Assign(name, _) => format!("Assign {}", name.lexeme()),
+        Expr::Call(_, _, args) => format!("Call with {} argument(s)", args.len()),
+        Expr::Get(_, name) => format!("Get .{}", name.lexeme()),
+        Expr::Set(_, name, _) => format!("Set .{}", name.lexeme()),
+        Expr::Variable(name) => format!("Variable {}", name.lexeme()),
+        Expr::Literal(Literal::String(s)) => format!("Literal \"{}\"", s),
+        Expr::Literal(literal) => format!("Literal {}", literal),
+        Expr::Tuple(_, values) => format!("Tuple of {} value(s)", values.len()),
+    }
+}
+
+/// The token that places a node in the source, for nodes that keep one.
+fn position(expr: &Expr) -> Option<&Token> {
+    match expr {
+        Expr::Binary(_, token, _)
+        | Expr::Logical(_, token, _)
+        | Expr::Unary(token, _)
+        | Expr::Assign(token, _)
+        | Expr::Call(_, token, _)
+        | Expr::Get(_, token)
+        | Expr::Set(_, token, _)
+        | Expr::Variable(token)
+        | Expr::Tuple(token, _) => Some(token),
+        Expr::Grouping(_) | Expr::Literal(_) => None,
+    }
+}
+
+fn node_index(text: &str, nodes: &[Node]) -> Option<usize> {
+    let n: usize = text.parse().ok()?;
+    (1..=nodes.len()).contains(&n).then(|| n - 1)
+}
+
+/// Evaluates a subtree, printing its value or the error it stopped with.
+fn evaluate(ast: &Arc<Arena>, expr: ExprId, interpreter: &mut Interpreter) {
+    let result = panic::catch_unwind(AssertUnwindSafe(|| interpreter.evaluate_in(ast, expr)));
+    diagnostics::flush(&*interpreter.output());
+    crate::HAD_ERROR.store(false, Ordering::Relaxed);
+    // `exit()` has no session to end here.
+    natives::take_exit_request();
+    match result {
+        Ok(value) => println!("{} => {}", ast.show(expr), value),
+        Err(payload) => println!("Runtime error: {}", crate::panic_message(&*payload)),
+    }
+}
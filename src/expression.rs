@@ -1,111 +1,115 @@
-use crate::token::*;
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug, PartialEq, Clone)]
+use crate::{arena::Arena, token::*};
+
+/// An expression, as an index into the `Arena` it was parsed into. Copying
+/// one is cheap, and it identifies the node within its arena.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct ExprId(u32);
+
+impl ExprId {
+    pub(crate) fn new(index: usize) -> ExprId {
+        ExprId(index as u32)
+    }
+
+    pub(crate) fn index(self) -> usize {
+        self.0 as usize
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
 pub enum Expr {
-    Grouping(Box<Expr>),
-    Binary(Box<Expr>, Token, Box<Expr>),
-    Unary(Token, Box<Expr>),
-    Assign(Token, Box<Expr>),
-    Logical(Box<Expr>, Token, Box<Expr>),
-    Call(Box<Expr>, Token, Vec<Expr>),
-    Get(Box<Expr>, Token),
-    Set(Box<Expr>, Token, Box<Expr>),
+    Grouping(ExprId),
+    Binary(ExprId, Token, ExprId),
+    Unary(Token, ExprId),
+    Assign(Token, ExprId),
+    Logical(ExprId, Token, ExprId),
+    Call(ExprId, Token, Vec<ExprId>),
+    Get(ExprId, Token),
+    Set(ExprId, Token, ExprId),
     Variable(Token),
     Literal(Literal),
+    /// `a, b` after `return`, which gives back a list of the values. The
+    /// token is the first comma.
+    Tuple(Token, Vec<ExprId>),
     // This,
     // Super,
 }
 
-impl std::fmt::Display for Expr {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            Expr::Literal(t) => write!(f, "{t}"),
-            Expr::Grouping(e) => write!(f, "{e}"),
-            Expr::Binary(l, op, r) => write!(f, "({l} {op} {r})"),
-            Expr::Variable(s) => write!(f, "{s}"),
-            Expr::Assign(l, r) => write!(f, "{l} = {r}"),
-            Expr::Logical(l, op, r) => write!(f, "({l} {op} {r}"),
-            Expr::Call(c, _, args) => write!(f, "{c}({})", itertools::join(args, ", ")),
-            Expr::Get(from, name) => write!(f, "{from}.{name}"),
-            Expr::Set(get, name, value) => write!(f, "{get}.{name} = {value}"),
-            Expr::Unary(op, r) => write!(f, "({op}{r})"),
-            // Expr::This => write!(f, "this"),
-            // Expr::Super => write!(f, "super"),
-        }
-    }
-}
-
 impl Expr {
     pub fn accept<T>(&self, visitor: &mut dyn ExprVisitor<T>) -> T {
         match self {
-            Expr::Grouping(g) => visitor.visit_grouping_expr(g.clone()),
+            Expr::Grouping(g) => visitor.visit_grouping_expr(*g),
             Expr::Literal(l) => visitor.visit_literal_expr(l.clone()),
-            Expr::Unary(op, r) => visitor.visit_unary_expr(op.clone(), r.clone()),
-            Expr::Binary(l, op, r) => visitor.visit_binary_expr(l.clone(), op.clone(), r.clone()),
+            Expr::Unary(op, r) => visitor.visit_unary_expr(op.clone(), *r),
+            Expr::Binary(l, op, r) => visitor.visit_binary_expr(*l, op.clone(), *r),
             Expr::Variable(t) => visitor.visit_var_expr(t.clone()),
-            Expr::Assign(l, r) => visitor.visit_assign_expr(l.clone(), r.clone()),
-            Expr::Logical(l, op, r) => visitor.visit_logical_expr(l.clone(), op.clone(), r.clone()),
-            Expr::Call(c, paren, args) => {
-                visitor.visit_call_expr(c.clone(), paren.clone(), args.clone())
-            }
-            Expr::Get(ex, name) => {
-                visitor.visit_get_expr(ex.clone(), name.clone()).unwrap()
-            }
-            Expr::Set(get, name, value) => {
-                visitor.visit_set_expr(get.clone(), name.clone(), value.clone()).unwrap()
-            }
+            Expr::Assign(l, r) => visitor.visit_assign_expr(l.clone(), *r),
+            Expr::Logical(l, op, r) => visitor.visit_logical_expr(*l, op.clone(), *r),
+            Expr::Call(c, paren, args) => visitor.visit_call_expr(*c, paren.clone(), args.clone()),
+            Expr::Get(ex, name) => visitor.visit_get_expr(*ex, name.clone()),
+            Expr::Set(get, name, value) => visitor.visit_set_expr(*get, name.clone(), *value),
+            Expr::Tuple(comma, values) => visitor.visit_tuple_expr(comma.clone(), values.clone()),
         }
     }
-    pub fn assign(lvalue: Token, rvalue: Expr) -> Self {
-        Self::Assign(lvalue, Box::new(rvalue))
+
+    // The constructors below add their operands to `arena`.
+
+    pub fn assign(arena: &mut Arena, lvalue: Token, rvalue: Expr) -> Self {
+        Self::Assign(lvalue, arena.alloc_expr(rvalue))
     }
 
-    pub fn binary(left: Expr, operator: Token, right: Expr) -> Self {
-        Self::Binary(Box::new(left), operator, Box::new(right))
+    pub fn binary(arena: &mut Arena, left: Expr, operator: Token, right: Expr) -> Self {
+        Self::Binary(arena.alloc_expr(left), operator, arena.alloc_expr(right))
     }
 
-    pub fn unary(operator: Token, right: Expr) -> Self {
-        Self::Unary(operator, Box::new(right))
+    pub fn unary(arena: &mut Arena, operator: Token, right: Expr) -> Self {
+        Self::Unary(operator, arena.alloc_expr(right))
     }
 
-    pub fn group(self) -> Self {
-        Self::Grouping(Box::new(self))
+    pub fn group(arena: &mut Arena, expr: Expr) -> Self {
+        Self::Grouping(arena.alloc_expr(expr))
     }
 
     pub fn literal(value: Literal) -> Self {
         Self::Literal(value)
     }
 
-    pub fn logical(left: Expr, operator: Token, right: Expr) -> Self {
-        Self::Logical(Box::new(left), operator, Box::new(right))
+    pub fn logical(arena: &mut Arena, left: Expr, operator: Token, right: Expr) -> Self {
+        Self::Logical(arena.alloc_expr(left), operator, arena.alloc_expr(right))
     }
 
     pub fn variable(t: Token) -> Self {
         Self::Variable(t)
     }
 
-    pub fn call(callee: Expr, paren: Token, args: Vec<Expr>) -> Self {
-        Self::Call(Box::new(callee), paren, args)
+    pub fn call(arena: &mut Arena, callee: Expr, paren: Token, args: Vec<Expr>) -> Self {
+        Self::Call(
+            arena.alloc_expr(callee),
+            paren,
+            args.into_iter().map(|arg| arena.alloc_expr(arg)).collect(),
+        )
     }
 
-    pub fn get(expr: Expr, name: Token) -> Self {
-        Self::Get(Box::new(expr), name)
+    pub fn get(arena: &mut Arena, expr: Expr, name: Token) -> Self {
+        Self::Get(arena.alloc_expr(expr), name)
     }
 
-    pub fn set(get: Expr, name: Token, value: Expr) -> Self {
-        Self::Set(Box::new(get), name, Box::new(value))
+    pub fn set(arena: &mut Arena, get: Expr, name: Token, value: Expr) -> Self {
+        Self::Set(arena.alloc_expr(get), name, arena.alloc_expr(value))
     }
 }
 
 pub trait ExprVisitor<T> {
-    fn visit_grouping_expr(&mut self, expr: Box<Expr>) -> T;
+    fn visit_grouping_expr(&mut self, expr: ExprId) -> T;
     fn visit_literal_expr(&self, literal: Literal) -> T;
-    fn visit_unary_expr(&mut self, op: Token, r: Box<Expr>) -> T;
-    fn visit_binary_expr(&mut self, l: Box<Expr>, op: Token, r: Box<Expr>) -> T;
-    fn visit_var_expr(&self, t: Token) -> T;
-    fn visit_assign_expr(&mut self, name: Token, value: Box<Expr>) -> T;
-    fn visit_logical_expr(&mut self, l: Box<Expr>, op: Token, r: Box<Expr>) -> T;
-    fn visit_call_expr(&mut self, c: Box<Expr>, paren: Token, args: Vec<Expr>) -> T;
-    fn visit_get_expr(&mut self, expr: Box<Expr>, name: Token) -> Result<T, String>;
-    fn visit_set_expr(&mut self, get: Box<Expr>, name: Token, value: Box<Expr>) -> Result<T, String>;
+    fn visit_unary_expr(&mut self, op: Token, r: ExprId) -> T;
+    fn visit_binary_expr(&mut self, l: ExprId, op: Token, r: ExprId) -> T;
+    fn visit_var_expr(&mut self, t: Token) -> T;
+    fn visit_assign_expr(&mut self, name: Token, value: ExprId) -> T;
+    fn visit_logical_expr(&mut self, l: ExprId, op: Token, r: ExprId) -> T;
+    fn visit_call_expr(&mut self, c: ExprId, paren: Token, args: Vec<ExprId>) -> T;
+    fn visit_get_expr(&mut self, expr: ExprId, name: Token) -> T;
+    fn visit_set_expr(&mut self, object: ExprId, name: Token, value: ExprId) -> T;
+    fn visit_tuple_expr(&mut self, comma: Token, values: Vec<ExprId>) -> T;
 }
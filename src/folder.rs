@@ -0,0 +1,205 @@
+use std::sync::Arc;
+
+use crate::{
+    arena::Arena,
+    expression::{Expr, ExprId, ExprVisitor},
+    literals,
+    parser::Parser,
+    statement::{Stmt, StmtId},
+    token::{Literal, Token, TokenType},
+};
+
+/// Parses the rest of `parser`'s tokens and folds them, giving a program
+/// ready to run.
+pub fn parse_and_fold(mut parser: Parser) -> (Arc<Arena>, Vec<StmtId>) {
+    let statements = parser.parse();
+    let mut arena = parser.into_arena();
+    let statements = Folder::new(&mut arena).fold(statements);
+    (Arc::new(arena), statements)
+}
+
+/// Pre-computes expressions whose operands are all literals. Numeric
+/// arithmetic is left alone since its result depends on the interpreter's
+/// number backend. Folded nodes are added to the arena the program was
+/// parsed into.
+pub struct Folder<'a> {
+    arena: &'a mut Arena,
+}
+
+impl<'a> Folder<'a> {
+    pub fn new(arena: &'a mut Arena) -> Self {
+        Folder { arena }
+    }
+
+    pub fn fold(&mut self, statements: Vec<StmtId>) -> Vec<StmtId> {
+        statements
+            .into_iter()
+            .map(|stmt| self.fold_stmt(stmt))
+            .collect()
+    }
+
+    fn fold_stmt(&mut self, stmt: StmtId) -> StmtId {
+        let folded = match self.arena.stmt(stmt).clone() {
+            Stmt::Expression(e) => Stmt::Expression(self.fold_id(e)),
+            Stmt::Print(keyword, e) => Stmt::Print(keyword, self.fold_id(e)),
+            Stmt::Var(name, init) => Stmt::Var(name, init.map(|e| self.fold_id(e))),
+            Stmt::Const(name, init) => Stmt::Const(name, self.fold_id(init)),
+            Stmt::Destructure(names, init) => Stmt::Destructure(names, self.fold_id(init)),
+            Stmt::Block(stmts) => Stmt::Block(self.fold(stmts)),
+            Stmt::If(condition, then_stmt, else_stmt) => Stmt::If(
+                self.fold_id(condition),
+                self.fold_stmt(then_stmt),
+                else_stmt.map(|s| self.fold_stmt(s)),
+            ),
+            Stmt::While(condition, body) => {
+                Stmt::While(self.fold_id(condition), self.fold_stmt(body))
+            }
+            Stmt::ForIn(name, iterable, body) => {
+                Stmt::ForIn(name, self.fold_id(iterable), self.fold_stmt(body))
+            }
+            Stmt::Function(name, params, body) => Stmt::Function(name, params, self.fold(body)),
+            Stmt::Return(keyword, value) => Stmt::Return(keyword, value.map(|e| self.fold_id(e))),
+            Stmt::Class(name, methods) => Stmt::Class(name, self.fold(methods)),
+            Stmt::Import(..) => return stmt,
+            Stmt::Export(keyword, declaration) => {
+                Stmt::Export(keyword, self.fold_stmt(declaration))
+            }
+        };
+        self.arena.alloc_stmt(folded)
+    }
+
+    fn fold_expr(&mut self, expr: ExprId) -> Expr {
+        self.arena.expr(expr).clone().accept(self)
+    }
+
+    fn fold_id(&mut self, expr: ExprId) -> ExprId {
+        let folded = self.fold_expr(expr);
+        self.arena.alloc_expr(folded)
+    }
+}
+
+impl ExprVisitor<Expr> for Folder<'_> {
+    fn visit_grouping_expr(&mut self, expr: ExprId) -> Expr {
+        match self.fold_expr(expr) {
+            Expr::Literal(l) => Expr::Literal(l),
+            e => Expr::group(self.arena, e),
+        }
+    }
+
+    fn visit_literal_expr(&self, literal: Literal) -> Expr {
+        Expr::literal(literal)
+    }
+
+    fn visit_unary_expr(&mut self, op: Token, r: ExprId) -> Expr {
+        let right = self.fold_expr(r);
+
+        match (op.token_type, &right) {
+            (TokenType::Bang, Expr::Literal(l)) => Expr::literal(Literal::Boolean(!is_truthy(l))),
+            (TokenType::Minus, Expr::Literal(Literal::Number(n))) => {
+                Expr::literal(Literal::Number(-n))
+            }
+            _ => Expr::unary(self.arena, op, right),
+        }
+    }
+
+    fn visit_binary_expr(&mut self, l: ExprId, op: Token, r: ExprId) -> Expr {
+        let left = self.fold_expr(l);
+        let right = self.fold_expr(r);
+
+        match (&left, op.token_type, &right) {
+            (
+                Expr::Literal(Literal::String(ls)),
+                TokenType::Plus,
+                Expr::Literal(Literal::String(rs)),
+            ) => Expr::literal(Literal::String(literals::intern(&format!("{}{}", ls, rs)))),
+            // `x + "a" + "b"` parses as `(x + "a") + "b"`; since `+` on strings
+            // is associative the two literals can be joined into `x + "ab"`.
+            (
+                Expr::Binary(inner, inner_op, tail),
+                TokenType::Plus,
+                Expr::Literal(Literal::String(rs)),
+            ) if inner_op.token_type == TokenType::Plus => {
+                if let Expr::Literal(Literal::String(ls)) = self.arena.expr(*tail) {
+                    let joined = Literal::String(literals::intern(&format!("{}{}", ls, rs)));
+                    let joined = self.arena.alloc_expr(Expr::literal(joined));
+                    Expr::Binary(*inner, inner_op.clone(), joined)
+                } else {
+                    Expr::binary(self.arena, left, op, right)
+                }
+            }
+            (Expr::Literal(Literal::Number(ln)), _, Expr::Literal(Literal::Number(rn)))
+                if is_comparison(op.token_type) =>
+            {
+                let result = match op.token_type {
+                    TokenType::Greater => ln > rn,
+                    TokenType::GreaterEqual => ln >= rn,
+                    TokenType::Less => ln < rn,
+                    _ => ln <= rn,
+                };
+                Expr::literal(Literal::Boolean(result))
+            }
+            // `1km == 1000m`, so quantities are left for the interpreter.
+            #[cfg(feature = "units")]
+            (Expr::Literal(Literal::Quantity(..)), _, _)
+            | (_, _, Expr::Literal(Literal::Quantity(..))) => {
+                Expr::binary(self.arena, left, op, right)
+            }
+            (Expr::Literal(ll), TokenType::EqualEqual, Expr::Literal(rl)) => {
+                Expr::literal(Literal::Boolean(ll == rl))
+            }
+            (Expr::Literal(ll), TokenType::BangEqual, Expr::Literal(rl)) => {
+                Expr::literal(Literal::Boolean(ll != rl))
+            }
+            _ => Expr::binary(self.arena, left, op, right),
+        }
+    }
+
+    fn visit_var_expr(&mut self, t: Token) -> Expr {
+        Expr::variable(t)
+    }
+
+    fn visit_assign_expr(&mut self, name: Token, value: ExprId) -> Expr {
+        let value = self.fold_expr(value);
+        Expr::assign(self.arena, name, value)
+    }
+
+    fn visit_logical_expr(&mut self, l: ExprId, op: Token, r: ExprId) -> Expr {
+        let (left, right) = (self.fold_expr(l), self.fold_expr(r));
+        Expr::logical(self.arena, left, op, right)
+    }
+
+    fn visit_call_expr(&mut self, c: ExprId, paren: Token, args: Vec<ExprId>) -> Expr {
+        let callee = self.fold_expr(c);
+        let args = args.into_iter().map(|a| self.fold_expr(a)).collect();
+        Expr::call(self.arena, callee, paren, args)
+    }
+
+    fn visit_get_expr(&mut self, expr: ExprId, name: Token) -> Expr {
+        let expr = self.fold_expr(expr);
+        Expr::get(self.arena, expr, name)
+    }
+
+    fn visit_set_expr(&mut self, object: ExprId, name: Token, value: ExprId) -> Expr {
+        let (object, value) = (self.fold_expr(object), self.fold_expr(value));
+        Expr::set(self.arena, object, name, value)
+    }
+
+    fn visit_tuple_expr(&mut self, comma: Token, values: Vec<ExprId>) -> Expr {
+        Expr::Tuple(comma, values.into_iter().map(|v| self.fold_id(v)).collect())
+    }
+}
+
+fn is_comparison(token_type: TokenType) -> bool {
+    matches!(
+        token_type,
+        TokenType::Greater | TokenType::GreaterEqual | TokenType::Less | TokenType::LessEqual
+    )
+}
+
+fn is_truthy(literal: &Literal) -> bool {
+    match literal {
+        Literal::None => false,
+        Literal::Boolean(b) => *b,
+        _ => true,
+    }
+}
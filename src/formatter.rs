@@ -0,0 +1,199 @@
+use crate::token::{Token, TokenType};
+
+const INDENT: &str = "    ";
+
+/// Re-emits a program with canonical layout: four-space indentation, one
+/// statement per line, opening braces on the same line and single spaces
+/// around binary operators. Blank lines between statements are kept (at
+/// most one in a row), as are comments.
+///
+/// This works on the token stream rather than the tree because the tree has
+/// already lost things a formatter must keep: comments, redundant
+/// parentheses, and `for` loops, which the parser turns into `while`. The
+/// caller should parse `tokens` first and only format programs that parse.
+pub fn format(source: &str, tokens: &[Token]) -> String {
+    let mut formatter = Formatter {
+        out: String::new(),
+        indent: 0,
+        paren_depth: 0,
+        previous: None,
+        unary_minus: false,
+        last_line: 0,
+        line_open: false,
+    };
+    let mut comments = comments(source).into_iter().peekable();
+    for (i, token) in tokens.iter().enumerate() {
+        if token.token_type == TokenType::Eof {
+            break;
+        }
+        // A comment runs to the end of its line, so it comes after every
+        // token that ends on that line.
+        while let Some(comment) = comments.next_if(|c| c.line < token.line) {
+            formatter.comment(comment);
+        }
+        formatter.token(token, tokens.get(i + 1));
+    }
+    for comment in comments {
+        formatter.comment(comment);
+    }
+    formatter.end_line();
+    formatter.out
+}
+
+struct Comment {
+    line: u32,
+    text: String,
+}
+
+/// Finds the `//` comments the scanner skipped, with the line each is on.
+fn comments(source: &str) -> Vec<Comment> {
+    let mut comments = Vec::new();
+    let mut line = 1;
+    let mut in_string = false;
+    let mut chars = source.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\n' => line += 1,
+            '"' => in_string = !in_string,
+            '/' if !in_string && chars.peek() == Some(&'/') => {
+                let mut text = String::from("/");
+                while let Some(c) = chars.next_if(|&c| c != '\n') {
+                    text.push(c);
+                }
+                comments.push(Comment {
+                    line,
+                    text: text.trim_end().to_string(),
+                });
+            }
+            _ => {}
+        }
+    }
+    comments
+}
+
+struct Formatter {
+    out: String,
+    indent: usize,
+    // `;` only ends a line outside parentheses, i.e. not in a `for` header.
+    paren_depth: usize,
+    previous: Option<TokenType>,
+    // Whether the last `-` negates rather than subtracts.
+    unary_minus: bool,
+    // The source line of the last thing written, to carry blank lines over.
+    last_line: u32,
+    // Whether the current output line has anything on it yet.
+    line_open: bool,
+}
+
+impl Formatter {
+    fn token(&mut self, token: &Token, next: Option<&Token>) {
+        if token.token_type == TokenType::RightBrace {
+            self.indent = self.indent.saturating_sub(1);
+            if self.previous != Some(TokenType::LeftBrace) {
+                self.end_line();
+            }
+        }
+        if self.line_open {
+            if self.needs_space(token.token_type) {
+                self.out.push(' ');
+            }
+        } else {
+            self.start_line(token.line, token.token_type == TokenType::RightBrace);
+        }
+        self.out.push_str(token.lexeme());
+        self.line_open = true;
+        self.last_line = token.line;
+
+        let next_type = next.map(|t| t.token_type);
+        match token.token_type {
+            TokenType::Minus => self.unary_minus = !ends_operand(self.previous),
+            TokenType::LeftParen => self.paren_depth += 1,
+            TokenType::RightParen => self.paren_depth = self.paren_depth.saturating_sub(1),
+            TokenType::Semicolon if self.paren_depth == 0 => self.end_line(),
+            TokenType::LeftBrace => {
+                self.indent += 1;
+                if next_type != Some(TokenType::RightBrace) {
+                    self.end_line();
+                }
+            }
+            TokenType::RightBrace if next_type != Some(TokenType::Else) => self.end_line(),
+            _ => {}
+        }
+        self.previous = Some(token.token_type);
+    }
+
+    fn comment(&mut self, comment: Comment) {
+        // Pull a trailing comment back up onto the line it followed.
+        if self.last_line == comment.line && !self.line_open && self.out.ends_with('\n') {
+            self.out.pop();
+            self.line_open = true;
+        }
+        if self.line_open && self.last_line == comment.line {
+            self.out.push_str("  ");
+        } else {
+            self.end_line();
+            self.start_line(comment.line, false);
+        }
+        self.out.push_str(&comment.text);
+        self.last_line = comment.line;
+        self.line_open = true;
+        self.end_line();
+    }
+
+    /// Indents a new line, first keeping one blank line if the source had
+    /// any here. Blank lines just inside braces are dropped.
+    fn start_line(&mut self, line: u32, closing: bool) {
+        let after_open = matches!(self.out.trim_end().chars().last(), None | Some('{'));
+        if line > self.last_line + 1 && !after_open && !closing {
+            self.out.push('\n');
+        }
+        self.out.push_str(&INDENT.repeat(self.indent));
+    }
+
+    fn end_line(&mut self) {
+        if self.line_open {
+            self.out.push('\n');
+            self.line_open = false;
+        }
+    }
+
+    fn needs_space(&self, current: TokenType) -> bool {
+        let Some(previous) = self.previous else {
+            return false;
+        };
+        match (previous, current) {
+            (
+                _,
+                TokenType::Semicolon | TokenType::Comma | TokenType::Dot | TokenType::RightParen,
+            ) => false,
+            (TokenType::LeftParen | TokenType::Dot, _) => false,
+            // Ranges: `0..n`.
+            (_, TokenType::DotDot | TokenType::DotDotEqual) => false,
+            (TokenType::DotDot | TokenType::DotDotEqual, _) => false,
+            (TokenType::LeftBrace, TokenType::RightBrace) => false,
+            // Calls and declarations: `f(x)`, `fun f(x)`, `f(a)(b)`.
+            (TokenType::Identifier | TokenType::RightParen, TokenType::LeftParen) => false,
+            (TokenType::Bang, _) => false,
+            (TokenType::Minus, _) => !self.unary_minus,
+            _ => true,
+        }
+    }
+}
+
+/// Whether a token of this type can end an operand, so that a `-` after it
+/// must be subtraction.
+fn ends_operand(token_type: Option<TokenType>) -> bool {
+    matches!(
+        token_type,
+        Some(
+            TokenType::Identifier
+                | TokenType::Number
+                | TokenType::String
+                | TokenType::RightParen
+                | TokenType::True
+                | TokenType::False
+                | TokenType::Nil
+                | TokenType::This
+        )
+    )
+}
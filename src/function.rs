@@ -1,22 +1,29 @@
-use std::{cell::RefCell, rc::Rc};
+use std::{cell::RefCell, rc::Rc, sync::Arc};
 
 use crate::{
+    arena::Arena,
+    dap,
     environment::Environment,
     interpreter::{Interpreter, Value},
+    natives::{NativeContext, NativeFn},
+    profiler,
     returns::Return,
-    statement::{Stmt, StmtVisitor},
+    statement::StmtId,
     Token,
 };
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone)]
 pub enum Function {
     Native {
+        name: &'static str,
         arity: usize,
-        body: Box<fn(&Vec<Value>) -> Value>,
+        body: Box<NativeFn>,
     },
     User {
         // arity: usize,
-        body: Vec<Stmt>,
+        body: Vec<StmtId>,
+        /// The arena `body` was parsed into.
+        ast: Arc<Arena>,
         params: Vec<Token>,
         name: Token,
         enclosing: Rc<RefCell<Environment>>,
@@ -27,35 +34,148 @@ impl std::fmt::Display for Function {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         match self {
             Function::Native { .. } => write!(f, "<native fn>"),
-            Function::User { name, .. } => write!(f, "<fn {}>", name.lexeme),
+            Function::User { name, .. } => write!(f, "<fn {}>", name.lexeme()),
         }
     }
 }
 
+/// How much of the thread's stack must be left for a call to run on it.
+/// Each Lox call nests many Rust calls, and a debug build's frames are big.
+#[cfg(not(target_arch = "wasm32"))]
+const RED_ZONE: usize = 256 * 1024;
+
+/// How much stack to allocate at a time once the thread's runs low.
+#[cfg(not(target_arch = "wasm32"))]
+const STACK_SEGMENT: usize = 4 * 1024 * 1024;
+
+/// Runs `call` where it has room, moving to a stack allocated from the heap
+/// when the thread's is nearly used up, so deep recursion stops at the
+/// interpreter's depth limit rather than aborting the process.
+#[cfg(not(target_arch = "wasm32"))]
+fn with_stack<T>(call: impl FnOnce() -> T) -> T {
+    stacker::maybe_grow(RED_ZONE, STACK_SEGMENT, call)
+}
+
+/// A browser's wasm stack can't be grown from here.
+#[cfg(target_arch = "wasm32")]
+fn with_stack<T>(call: impl FnOnce() -> T) -> T {
+    call()
+}
+
 impl Function {
     pub fn call(
         &self,
         interpreter: &mut Interpreter,
         arguments: Vec<Value>,
+    ) -> Result<Value, String> {
+        interpreter.check_cancelled();
+        if let Err(overflow) = interpreter.enter_call() {
+            return Ok(overflow);
+        }
+        #[cfg(all(feature = "jit", not(target_arch = "wasm32")))]
+        if let Some(value) = crate::jit::call(self, interpreter, &arguments) {
+            interpreter.exit_call();
+            return Ok(value);
+        }
+        let result = with_stack(|| {
+            if profiler::enabled() {
+                let name = self.profile_name();
+                let started = profiler::enter(&name);
+                let result = self.invoke(interpreter, arguments);
+                profiler::exit(&name, started);
+                result
+            } else {
+                self.invoke(interpreter, arguments)
+            }
+        });
+        interpreter.exit_call();
+        result
+    }
+
+    fn invoke(
+        &self,
+        interpreter: &mut Interpreter,
+        arguments: Vec<Value>,
     ) -> Result<Value, String> {
         match self {
-            Function::Native { body, .. } => Ok(body(&arguments)),
-            Function::User { params, body, enclosing, .. } => {
+            Function::Native { body, .. } => body(&mut NativeContext::new(interpreter), &arguments),
+            Function::User {
+                params,
+                body,
+                ast,
+                enclosing,
+                name,
+            } => {
+                let _frame = dap::enter(name);
                 let mut env = Environment::new_from(enclosing);
-                for i in 0..params.len() {
-                    env.define(params[i].lexeme.clone(), arguments[i].clone());
+                // Every argument passed, for helpers that don't name them
+                // all, made only for a function that uses it. A parameter
+                // called `arguments` hides it.
+                if ast.reads_arguments(name) {
+                    let all = Value::List(Rc::new(RefCell::new(arguments.clone())));
+                    env.define("arguments", all);
+                }
+                for (param, argument) in params.iter().zip(arguments) {
+                    env.define(param.symbol, argument);
                 }
 
-                match interpreter.execute_block(body.clone(), Rc::new(RefCell::new(env))) {
-                    Err(Return { value }) => {
-                        Ok(value)
-                    }
+                let outer = interpreter.swap_ast(Arc::clone(ast));
+                let result = interpreter.execute_block(body, Rc::new(RefCell::new(env)));
+                interpreter.swap_ast(outer);
+                match result {
+                    Err(Return::Value(value)) => Ok(value),
+                    // The exit request is still pending, so the caller keeps
+                    // unwinding once its current statement finishes.
+                    Err(Return::Exit(_)) => Ok(Value::None),
                     Ok(..) => Ok(Value::None),
                 }
             }
         }
     }
 
+    /// How `--profile` reports this function: user functions by where
+    /// they're declared, since two can share a name.
+    fn profile_name(&self) -> String {
+        match self {
+            Function::Native { name, .. } => format!("{} (native)", name),
+            Function::User { name, .. } => {
+                format!("{} ({}:{})", name.lexeme(), name.source.name(), name.line)
+            }
+        }
+    }
+
+    /// Whether two function values are the same function. Natives are made
+    /// afresh each time one is looked up, so they're compared by name.
+    pub fn is_same(this: &Rc<Function>, other: &Rc<Function>) -> bool {
+        match (&**this, &**other) {
+            (Function::Native { name: l, .. }, Function::Native { name: r, .. }) => l == r,
+            _ => Rc::ptr_eq(this, other),
+        }
+    }
+
+    /// The method `self` with `this` bound to `instance`.
+    pub fn bind(&self, instance: Value) -> Function {
+        let Function::User {
+            body,
+            ast,
+            params,
+            name,
+            enclosing,
+        } = self
+        else {
+            return self.clone();
+        };
+        let mut environment = Environment::new_from(enclosing);
+        environment.define("this", instance);
+        Function::User {
+            body: body.clone(),
+            ast: Arc::clone(ast),
+            params: params.clone(),
+            name: name.clone(),
+            enclosing: Rc::new(RefCell::new(environment)),
+        }
+    }
+
     pub fn arity(&self) -> usize {
         match self {
             Function::Native { arity, .. } => *arity,
@@ -0,0 +1,197 @@
+//! `jlox test`: runs every `.lox` file under a directory and checks what it
+//! printed against what the file says it should.
+//!
+//! Expectations are written as comments in the test itself:
+//!
+//! ```text
+//! print 1 + 2; // expect: 3
+//! print 1 + ;  // expect error: Expect expression
+//! ```
+//!
+//! `expect:` lines must match the program's output exactly and in order.
+//! `expect error:` lines match any diagnostic or runtime error that
+//! contains the text. A test with a `.expected` file next to it is instead
+//! compared with that file, line for line, as it would appear in a terminal.
+
+use std::{
+    cell::RefCell,
+    fmt, fs,
+    panic::{self, AssertUnwindSafe},
+    path::{Path, PathBuf},
+    rc::Rc,
+    sync::atomic::Ordering,
+};
+
+use crate::{
+    cli::Options,
+    diagnostics,
+    output::{Callback, OutputEvent},
+    source::SourceId,
+};
+
+#[derive(Debug, Clone, PartialEq)]
+enum Line {
+    Output(String),
+    Error(String),
+}
+
+impl Line {
+    fn text(&self) -> &str {
+        match self {
+            Line::Output(text) | Line::Error(text) => text,
+        }
+    }
+
+    /// Whether this actual line satisfies the `expected` one.
+    fn satisfies(&self, expected: &Line) -> bool {
+        match (self, expected) {
+            (Line::Output(actual), Line::Output(expected)) => actual == expected,
+            (Line::Error(actual), Line::Error(expected)) => actual.contains(expected.as_str()),
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for Line {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Line::Output(text) => write!(f, "output {:?}", text),
+            Line::Error(text) => write!(f, "error {:?}", text),
+        }
+    }
+}
+
+/// Runs the tests under `dir`, printing a line for each and a summary.
+/// Returns whether they all passed.
+pub fn run(dir: &str, options: &Options) -> bool {
+    let mut paths = Vec::new();
+    if let Err(e) = find_tests(Path::new(dir), &mut paths) {
+        println!("Could not read directory {}: {}.", dir, e);
+        return false;
+    }
+    paths.sort();
+
+    let mut failed = 0;
+    for path in &paths {
+        match test(path, options) {
+            Ok(()) => println!("PASS {}", path.display()),
+            Err(reason) => {
+                failed += 1;
+                println!("FAIL {}", path.display());
+                println!("  {}", reason);
+            }
+        }
+    }
+
+    println!("{} passed, {} failed.", paths.len() - failed, failed);
+    failed == 0
+}
+
+fn find_tests(dir: &Path, paths: &mut Vec<PathBuf>) -> std::io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            find_tests(&path, paths)?;
+        } else if path.extension().is_some_and(|ext| ext == "lox") {
+            paths.push(path);
+        }
+    }
+    Ok(())
+}
+
+fn test(path: &Path, options: &Options) -> Result<(), String> {
+    let source = fs::read_to_string(path).map_err(|e| format!("Could not read file: {}.", e))?;
+    let actual = execute(path, &source, options);
+
+    let expected_file = path.with_extension("expected");
+    if expected_file.exists() {
+        let expected = fs::read_to_string(&expected_file)
+            .map_err(|e| format!("Could not read {}: {}.", expected_file.display(), e))?;
+        let expected: Vec<String> = expected.lines().map(|line| format!("{:?}", line)).collect();
+        let actual: Vec<String> = actual
+            .iter()
+            .map(|line| format!("{:?}", line.text()))
+            .collect();
+        return compare(&expected, &actual, |expected, actual| expected == actual)
+            .map_err(|(_, reason)| reason);
+    }
+
+    let expected: Vec<(usize, Line)> = source.lines().enumerate().filter_map(expectation).collect();
+    let lines: Vec<&Line> = expected.iter().map(|(_, line)| line).collect();
+    let actual: Vec<&Line> = actual.iter().collect();
+    compare(&lines, &actual, |expected, actual| {
+        actual.satisfies(expected)
+    })
+    .map_err(|(index, reason)| match expected.get(index) {
+        Some((line, _)) => format!("{} (expected on line {})", reason, line + 1),
+        None => reason,
+    })
+}
+
+/// Reads an `// expect: ` or `// expect error: ` comment.
+fn expectation((index, line): (usize, &str)) -> Option<(usize, Line)> {
+    let (_, comment) = line.split_once("// expect")?;
+    if let Some(text) = comment.strip_prefix(": ") {
+        Some((index, Line::Output(text.to_string())))
+    } else {
+        let text = comment.strip_prefix(" error: ")?;
+        Some((index, Line::Error(text.to_string())))
+    }
+}
+
+/// Finds the first place `actual` doesn't match `expected`, returning its
+/// index and a description.
+fn compare<T: fmt::Display>(
+    expected: &[T],
+    actual: &[T],
+    matches: impl Fn(&T, &T) -> bool,
+) -> Result<(), (usize, String)> {
+    for (index, (expected, actual)) in expected.iter().zip(actual).enumerate() {
+        if !matches(expected, actual) {
+            return Err((index, format!("Expected {}, got {}.", expected, actual)));
+        }
+    }
+    match (expected.get(actual.len()), actual.get(expected.len())) {
+        (Some(missing), _) => Err((
+            actual.len(),
+            format!("Expected {}, but nothing more was printed.", missing),
+        )),
+        (_, Some(extra)) => Err((expected.len(), format!("Unexpected {}.", extra))),
+        _ => Ok(()),
+    }
+}
+
+/// Runs a test in a fresh interpreter, collecting what it printed.
+fn execute(path: &Path, source: &str, options: &Options) -> Vec<Line> {
+    let lines = Rc::new(RefCell::new(Vec::new()));
+    let sink = {
+        let lines = Rc::clone(&lines);
+        Callback(move |event| {
+            let line = match event {
+                OutputEvent::PrintValue(value) => Line::Output(value.to_string()),
+                OutputEvent::Diagnostic(diagnostic) => Line::Error(diagnostic.to_string()),
+                OutputEvent::DiagnosticsSuppressed(count) => {
+                    Line::Error(format!("... {} additional errors suppressed.", count))
+                }
+                OutputEvent::Result(_) | OutputEvent::TraceLine(_) => return,
+            };
+            lines.borrow_mut().push(line);
+        })
+    };
+    let sink = Rc::new(sink);
+
+    crate::HAD_ERROR.store(false, Ordering::Relaxed);
+    let mut interpreter = crate::new_interpreter(crate::script_dir(path), options);
+    interpreter.set_output(sink.clone());
+    let source_id = SourceId::register(&path.display().to_string());
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        crate::run_in(&mut interpreter, source, source_id, options)
+    }));
+    diagnostics::flush(&*sink);
+    if let Err(payload) = result {
+        lines
+            .borrow_mut()
+            .push(Line::Error(crate::panic_message(&*payload)));
+    }
+    lines.take()
+}
@@ -0,0 +1,178 @@
+//! `jlox highlight`: prints a script with its syntax colored, as HTML for
+//! docs and blog posts or with ANSI escapes for a terminal.
+//!
+//! Tokens are classified by the scanner, so highlighting agrees with what
+//! the interpreter reads. Everything between tokens (spacing, comments and
+//! characters the scanner rejected) is copied through unchanged, with
+//! comments colored too.
+
+use std::fs;
+
+use crate::{
+    cli::Options,
+    scanner::Scanner,
+    source::SourceId,
+    token::{Token, TokenType},
+};
+
+/// How highlighted code is written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum HighlightFormat {
+    /// A `<pre class="lox">` block with a `<span>` around each colored
+    /// piece, classed `lox-keyword`, `lox-literal`, `lox-string`,
+    /// `lox-number` or `lox-comment` for a stylesheet to color.
+    Html,
+    /// Text with ANSI color escapes.
+    Ansi,
+}
+
+/// How a piece of a script is colored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Style {
+    Keyword,
+    /// `true`, `false`, `nil`, `this` and `super`.
+    Literal,
+    String,
+    Number,
+    Comment,
+}
+
+impl Style {
+    fn of(token: &Token) -> Option<Style> {
+        use TokenType::*;
+        match token.token_type {
+            And | Class | Const | Else | Export | Fun | For | If | Import | Or | Print | Return
+            | Var | While => Some(Style::Keyword),
+            False | Nil | Super | This | True => Some(Style::Literal),
+            String => Some(Style::String),
+            Number => Some(Style::Number),
+            _ => None,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Style::Keyword => "keyword",
+            Style::Literal => "literal",
+            Style::String => "string",
+            Style::Number => "number",
+            Style::Comment => "comment",
+        }
+    }
+
+    fn ansi(self) -> &'static str {
+        match self {
+            Style::Keyword => "\x1b[1;35m",
+            Style::Literal => "\x1b[33m",
+            Style::String => "\x1b[32m",
+            Style::Number => "\x1b[36m",
+            Style::Comment => "\x1b[90m",
+        }
+    }
+}
+
+/// Prints the script at `path` highlighted as `options.highlight_format`
+/// asks, returning the exit code: 66 if it can't be read.
+pub fn run(path: &str, options: &Options) -> i32 {
+    let source = match fs::read_to_string(path) {
+        Ok(source) => source,
+        Err(e) => {
+            println!("Could not read {}: {}.", path, e);
+            return 66;
+        }
+    };
+    print!(
+        "{}",
+        highlight(&source, SourceId::register(path), options.highlight_format)
+    );
+    0
+}
+
+/// `source` with its syntax colored as `format`.
+pub fn highlight(source: &str, source_id: SourceId, format: HighlightFormat) -> String {
+    let mut out = Output {
+        text: String::new(),
+        format,
+    };
+    if format == HighlightFormat::Html {
+        out.text.push_str("<pre class=\"lox\"><code>");
+    }
+    let mut end = 0;
+    // Characters the scanner rejects are left where they are, between
+    // tokens, rather than reported.
+    for token in Scanner::new(source, source_id).filter_map(Result::ok) {
+        let range = token.span.range();
+        out.between(&source[end..range.start]);
+        out.piece(token.lexeme(), Style::of(&token));
+        end = range.end;
+    }
+    out.between(&source[end..]);
+    if format == HighlightFormat::Html {
+        out.text.push_str("</code></pre>\n");
+    }
+    out.text
+}
+
+struct Output {
+    text: String,
+    format: HighlightFormat,
+}
+
+impl Output {
+    /// Text the scanner skipped, where the only thing to color is `//`
+    /// comments. Strings are tokens, so a `//` here always starts one.
+    fn between(&mut self, mut text: &str) {
+        while let Some(start) = text.find("//") {
+            self.piece(&text[..start], None);
+            let end = text[start..]
+                .find('\n')
+                .map_or(text.len(), |end| start + end);
+            self.piece(&text[start..end], Some(Style::Comment));
+            text = &text[end..];
+        }
+        self.piece(text, None);
+    }
+
+    fn piece(&mut self, text: &str, style: Option<Style>) {
+        if text.is_empty() {
+            return;
+        }
+        match (self.format, style) {
+            (HighlightFormat::Html, None) => escape_html(&mut self.text, text),
+            (HighlightFormat::Html, Some(style)) => {
+                self.text.push_str("<span class=\"lox-");
+                self.text.push_str(style.name());
+                self.text.push_str("\">");
+                escape_html(&mut self.text, text);
+                self.text.push_str("</span>");
+            }
+            (HighlightFormat::Ansi, None) => self.text.push_str(text),
+            (HighlightFormat::Ansi, Some(style)) => {
+                // A string can span lines, so the color is set again on
+                // each, for pagers that reset it at line ends.
+                for (i, line) in text.split('\n').enumerate() {
+                    if i > 0 {
+                        self.text.push('\n');
+                    }
+                    if !line.is_empty() {
+                        self.text.push_str(style.ansi());
+                        self.text.push_str(line);
+                        self.text.push_str("\x1b[0m");
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn escape_html(out: &mut String, text: &str) {
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            c => out.push(c),
+        }
+    }
+}
@@ -0,0 +1,102 @@
+//! HMAC-SHA256, which the Jupyter protocol uses to sign messages. A
+//! signature is the lowercase hex of the MAC of a message's header, parent
+//! header, metadata and content, in that order.
+
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+type HmacSha256 = Hmac<Sha256>;
+
+fn mac(key: &[u8], parts: &[&[u8]]) -> HmacSha256 {
+    let mut mac = HmacSha256::new_from_slice(key).expect("HMAC takes keys of any length");
+    for part in parts {
+        mac.update(part);
+    }
+    mac
+}
+
+/// The signature of the concatenation of `parts`, as lowercase hex.
+pub fn sign(key: &[u8], parts: &[&[u8]]) -> String {
+    hex::encode(mac(key, parts).finalize().into_bytes())
+}
+
+/// Whether `signature`, in hex, is the signature of `parts`. The comparison
+/// takes the same time wherever the signatures differ, so a client can't
+/// forge one a byte at a time.
+pub fn verify(key: &[u8], parts: &[&[u8]], signature: &[u8]) -> bool {
+    match hex::decode(signature) {
+        Ok(signature) => mac(key, parts).verify_slice(&signature).is_ok(),
+        Err(_) => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Test cases from RFC 4231, section 4, but for the truncated one.
+    const RFC_4231: &[(&[u8], &[u8], &str)] = &[
+        (
+            &[0x0b; 20],
+            b"Hi There",
+            "b0344c61d8db38535ca8afceaf0bf12b881dc200c9833da726e9376c2e32cff7",
+        ),
+        (
+            b"Jefe",
+            b"what do ya want for nothing?",
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843",
+        ),
+        (
+            &[0xaa; 20],
+            &[0xdd; 50],
+            "773ea91e36800e46854db8ebd09181a72959098b3ef8c122d9635514ced565fe",
+        ),
+        (
+            &[
+                0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0a, 0x0b, 0x0c, 0x0d, 0x0e,
+                0x0f, 0x10, 0x11, 0x12, 0x13, 0x14, 0x15, 0x16, 0x17, 0x18, 0x19,
+            ],
+            &[0xcd; 50],
+            "82558a389a443c0ea4cc819899f2083a85f0faa3e578f8077a2e3ff46729665b",
+        ),
+        (
+            &[0xaa; 131],
+            b"Test Using Larger Than Block-Size Key - Hash Key First",
+            "60e431591ee0b67f0d8a26aacbf5b77f8e0bc6213728c5140546040f0ee37f54",
+        ),
+        (
+            &[0xaa; 131],
+            b"This is a test using a larger than block-size key and a larger than block-size \
+              data. The key needs to be hashed before being used by the HMAC algorithm.",
+            "9b09ffa71b942fcb27635fbcd5b0e944bfdc63644f0713938a7f51535c3a35e2",
+        ),
+    ];
+
+    #[test]
+    fn signs_the_rfc_4231_test_cases() {
+        for (key, data, expected) in RFC_4231 {
+            assert_eq!(sign(key, &[data]), *expected);
+            assert!(verify(key, &[data], expected.as_bytes()));
+        }
+    }
+
+    #[test]
+    fn signs_the_parts_as_one_message() {
+        let (key, _, expected) = RFC_4231[1];
+        assert_eq!(
+            sign(key, &[b"what do ya", b" want ", b"for nothing?"]),
+            expected
+        );
+    }
+
+    #[test]
+    fn rejects_other_signatures() {
+        let (key, data, expected) = RFC_4231[0];
+        let mut forged = expected.as_bytes().to_vec();
+        forged[63] = b'0';
+        assert!(!verify(key, &[data], &forged));
+        assert!(!verify(key, &[data], &expected.as_bytes()[..62]));
+        assert!(!verify(key, &[data], b"not hex"));
+        assert!(!verify(b"another key", &[data], expected.as_bytes()));
+    }
+}
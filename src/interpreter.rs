@@ -1,24 +1,53 @@
+use crate::arena::Arena;
+use crate::budget::{Budget, BudgetExceeded};
+use crate::cancel::{CancellationToken, Cancelled};
+use crate::coverage;
+use crate::dap;
+use crate::diagnostics;
 use crate::environment::Environment;
-use crate::expression::{Expr, ExprVisitor};
+use crate::expression::{ExprId, ExprVisitor};
 use crate::function::Function;
+use crate::iterate;
 use crate::lox_class::{LoxClass, LoxInstance};
+use crate::module::{self, LazyModule, Module, ModuleCache};
+use crate::natives::{self, NativeFn};
+use crate::number::{FloatBackend, NumberBackend};
+use crate::operators::{Handler, Operators, Precedence};
+use crate::output::{OutputEvent, OutputSink, StdoutSink};
+use crate::range::Range;
 use crate::returns::Return;
-use crate::statement::{Stmt, StmtVisitor};
+use crate::source::SourceId;
+use crate::statement::{ImportTarget, Stmt, StmtId, StmtVisitor};
+use crate::symbol::Symbol;
 use crate::token::{Literal, Token, TokenType};
 use std::cell::RefCell;
 use std::collections::HashMap;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
 use std::rc::Rc;
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::sync::Arc;
+use std::time::Instant;
 
 #[derive(Debug, Clone)]
 pub enum Value {
     None,
     Boolean(bool),
     Number(f64),
-    String(String),
-    Callable(Function),
-    Class(LoxClass),
-    Instance(LoxInstance),
+    String(Arc<str>),
+    Callable(Rc<Function>),
+    Class(Rc<LoxClass>),
+    Instance(Rc<RefCell<LoxInstance>>),
+    List(Rc<RefCell<Vec<Value>>>),
+    Range(Range),
+    Module(Rc<Module>),
+    LazyModule(Rc<LazyModule>),
+    /// A runtime error carried as a value, when error values are on (see
+    /// `Interpreter::error_values`).
+    Error(Rc<str>),
+    /// What `var a;` gives `a` until it's assigned. Reading it is an error.
+    Uninitialized,
+    #[cfg(feature = "units")]
+    Quantity(f64, &'static crate::units::Unit),
 }
 
 impl std::fmt::Display for Value {
@@ -31,10 +60,96 @@ impl std::fmt::Display for Value {
             Self::Callable(_) => write!(f, "function"),
             Self::Class(_) => write!(f, "class"),
             Self::Instance(_) => write!(f, "instance"),
+            Self::List(items) => write!(f, "[{}]", itertools::join(items.borrow().iter(), ", ")),
+            Self::Range(range) => write!(f, "{}", range),
+            Self::Module(module) => write!(f, "<module {}>", module.name()),
+            Self::LazyModule(_) => write!(f, "<lazy module>"),
+            Self::Error(message) => write!(f, "<error: {}>", message),
+            Self::Uninitialized => write!(f, "<uninitialized>"),
+            #[cfg(feature = "units")]
+            Self::Quantity(n, unit) => write!(f, "{} {}", n, unit.symbol),
         }
     }
 }
 
+impl Value {
+    /// The name `typeof` reports for this value.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::None => "nil",
+            Value::Boolean(_) => "boolean",
+            Value::Number(_) => "number",
+            Value::String(_) => "string",
+            Value::Callable(_) => "function",
+            Value::Class(_) => "class",
+            Value::Instance(_) => "instance",
+            Value::List(_) => "list",
+            Value::Range(_) => "range",
+            Value::Module(_) | Value::LazyModule(_) => "module",
+            Value::Error(_) => "error",
+            Value::Uninitialized => "uninitialized",
+            #[cfg(feature = "units")]
+            Value::Quantity(..) => "quantity",
+        }
+    }
+
+    /// Where a function or class was declared, as `path:line:column`. Natives
+    /// have no declaration to point to.
+    pub fn definition(&self) -> Option<String> {
+        let name = match self {
+            Value::Callable(function) => match &**function {
+                Function::User { name, .. } => name,
+                Function::Native { .. } => return None,
+            },
+            Value::Class(class) => class.declaration.as_ref()?,
+            _ => return None,
+        };
+        Some(format!(
+            "{}:{}:{}",
+            name.source.name(),
+            name.line,
+            name.column
+        ))
+    }
+}
+
+/// What a program stopped by a runtime error unwinds with, as the panic
+/// payload, so a host catching the unwind can report it and carry on. The
+/// panic hook isn't run for it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RuntimeError {
+    pub message: String,
+    /// The file, line and column it was raised at, when the interpreter
+    /// knows them.
+    pub location: Option<(SourceId, u32, u32)>,
+}
+
+impl RuntimeError {
+    fn at(token: &Token, message: String) -> RuntimeError {
+        RuntimeError {
+            message,
+            location: Some((token.source, token.line, token.column)),
+        }
+    }
+}
+
+/// Shown as `path:line:col: message`, the way static diagnostics name
+/// where they are.
+impl std::fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        if let Some((source, line, column)) = self.location {
+            write!(f, "{}:{}", source.name(), line)?;
+            if column > 0 {
+                write!(f, ":{}", column)?;
+            }
+            write!(f, ": ")?;
+        }
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for RuntimeError {}
+
 impl PartialEq for Value {
     fn eq(&self, other: &Value) -> bool {
         match (self, other) {
@@ -44,69 +159,542 @@ impl PartialEq for Value {
             (Value::Boolean(left), Value::Boolean(right)) => left == right,
             (Value::Number(left), Value::Number(right)) => left == right,
             (Value::String(left), Value::String(right)) => left.eq(right),
+            (Value::Callable(left), Value::Callable(right)) => Function::is_same(left, right),
+            (Value::Class(left), Value::Class(right)) => left.id == right.id,
+            (Value::Instance(left), Value::Instance(right)) => Rc::ptr_eq(left, right),
+            (Value::Range(left), Value::Range(right)) => left == right,
+            #[cfg(feature = "units")]
+            (Value::Quantity(l, lu), Value::Quantity(r, ru)) => {
+                crate::units::equal((*l, lu), (*r, ru))
+            }
             _ => false, // TODO: this should be defined or all.
         }
     }
 }
 
-#[derive(Debug, PartialEq, Clone)]
+#[derive(Debug, Clone)]
 pub struct Interpreter {
     environment: Rc<RefCell<Environment>>,
     // locals: HashMap<Expr, usize>,
-    pub globals: Environment,
+    /// The nodes of the code running now: the program's, or those of the
+    /// program the function being called was declared in.
+    ast: Arc<Arena>,
+    pub globals: Rc<RefCell<Environment>>,
+    builtins: Rc<RefCell<Environment>>,
+    numbers: Arc<dyn NumberBackend>,
+    operators: Arc<Operators>,
+    base_dir: PathBuf,
+    modules: Rc<RefCell<ModuleCache>>,
+    exports: Vec<String>,
+    output: Rc<dyn OutputSink>,
+    echo_results: bool,
+    error_values: bool,
+    cancellation: CancellationToken,
+    /// Where each call in progress was made, innermost last.
+    calls: Vec<Token>,
+    /// How many calls are in progress, natives included.
+    depth: usize,
+    max_depth: usize,
+    budget: Budget,
+    /// Steps taken since the current run started, and when it started. The
+    /// clock is only read for a budget with a timeout, since there's none
+    /// to read in a browser.
+    steps: u64,
+    started: Option<Instant>,
+}
+
+/// How deep calls may nest before a program stops with "Stack overflow.",
+/// unless the host sets another limit.
+pub const DEFAULT_MAX_DEPTH: usize = 1000;
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Interpreter {
     pub fn new() -> Self {
-        let mut globals = Environment::new();
-        let clock = Value::Callable(Function::Native {
-            arity: 0,
-            body: Box::new(|_args: &Vec<Value>| {
-                Value::Number(
-                    SystemTime::now()
-                        .duration_since(UNIX_EPOCH)
-                        .expect("Could not retrieve time.")
-                        .as_millis() as f64,
-                )
-            }),
-        });
-
-        globals.define("clock".to_string(), clock);
+        Self::with_numbers(Arc::new(FloatBackend))
+    }
+
+    pub fn with_numbers(numbers: Arc<dyn NumberBackend>) -> Self {
+        Self::with_builtins(
+            Environment::with_fallback(natives::lookup, natives::names),
+            numbers,
+        )
+    }
+
+    /// An interpreter whose natives are `builtins`, an outermost scope.
+    pub(crate) fn with_builtins(builtins: Environment, numbers: Arc<dyn NumberBackend>) -> Self {
+        let builtins = Rc::new(RefCell::new(builtins));
+        let globals = Rc::new(RefCell::new(Environment::new_from(&builtins)));
         Interpreter {
-            environment: Rc::new(RefCell::new(Environment::new())),
+            environment: Rc::clone(&globals),
+            ast: Arc::default(),
             globals,
+            builtins,
+            numbers,
+            operators: Arc::default(),
+            base_dir: PathBuf::from("."),
+            modules: Rc::new(RefCell::new(ModuleCache::default())),
+            exports: Vec::new(),
+            output: Rc::new(StdoutSink),
+            echo_results: false,
+            error_values: false,
+            cancellation: CancellationToken::new(),
+            calls: Vec::new(),
+            depth: 0,
+            max_depth: DEFAULT_MAX_DEPTH,
+            budget: Budget::default(),
+            steps: 0,
+            started: None,
         }
     }
 
-    pub fn interpret(&mut self, statements: Vec<Stmt>) {
-        for statement in statements {
-            self.execute(statement);
+    /// An interpreter for an imported module: it shares natives, number
+    /// semantics and the module cache, but gets globals of its own.
+    pub fn for_module(&self, base_dir: PathBuf) -> Self {
+        let globals = Rc::new(RefCell::new(Environment::new_from(&self.builtins)));
+        Interpreter {
+            environment: Rc::clone(&globals),
+            ast: Arc::default(),
+            globals,
+            builtins: Rc::clone(&self.builtins),
+            numbers: Arc::clone(&self.numbers),
+            operators: Arc::clone(&self.operators),
+            base_dir,
+            modules: Rc::clone(&self.modules),
+            exports: Vec::new(),
+            output: Rc::clone(&self.output),
+            echo_results: false,
+            error_values: self.error_values,
+            cancellation: self.cancellation.clone(),
+            calls: Vec::new(),
+            depth: 0,
+            max_depth: self.max_depth,
+            budget: self.budget,
+            steps: 0,
+            started: None,
         }
     }
 
-    fn evaluate(&mut self, expr: Expr) -> Value {
-        expr.accept(self)
+    /// Makes `body` callable from scripts as `name`, in this interpreter and
+    /// the modules it loads.
+    pub fn register_native(&mut self, name: &'static str, arity: usize, body: NativeFn) {
+        self.builtins
+            .borrow_mut()
+            .define(name, natives::native(name, arity, body));
     }
 
-    fn execute(&mut self, stmt: Stmt) -> Result<(), Return> {
-        stmt.accept(self)?;
+    /// Adds an infix operator to the ones programs run here are scanned
+    /// with, in this interpreter and the modules it loads from now on. See
+    /// `Operators::register`.
+    pub fn register_operator(
+        &mut self,
+        symbol: &str,
+        precedence: Precedence,
+        handler: Handler,
+    ) -> Result<(), String> {
+        Arc::make_mut(&mut self.operators).register(symbol, precedence, handler)
+    }
+
+    /// The operators programs run here are scanned with.
+    pub fn operators(&self) -> &Arc<Operators> {
+        &self.operators
+    }
+
+    /// Replaces the operators programs run here are scanned with, as an
+    /// `Engine` does for the interpreters it spawns.
+    pub fn set_operators(&mut self, operators: Arc<Operators>) {
+        self.operators = operators;
+    }
+
+    pub fn allow_fs(&mut self) {
+        natives::define_fs(&mut self.builtins.borrow_mut());
+    }
+
+    pub fn allow_env(&mut self) {
+        natives::define_env(&mut self.builtins.borrow_mut());
+    }
+
+    /// Directory that `import` paths are resolved against.
+    pub fn base_dir(&self) -> &Path {
+        &self.base_dir
+    }
+
+    pub fn set_base_dir(&mut self, base_dir: PathBuf) {
+        self.base_dir = base_dir;
+    }
+
+    pub fn modules(&self) -> Rc<RefCell<ModuleCache>> {
+        Rc::clone(&self.modules)
+    }
+
+    /// The script's global bindings, sorted by name. Natives aren't
+    /// included.
+    pub fn globals_iter(&self) -> impl Iterator<Item = (String, Value)> {
+        let mut bindings: Vec<_> = self
+            .globals
+            .borrow()
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.clone()))
+            .collect();
+        bindings.sort_by(|(a, _), (b, _)| a.cmp(b));
+        bindings.into_iter()
+    }
+
+    /// The value `name` has at the top level, natives included.
+    pub fn global(&self, name: &str) -> Option<Value> {
+        self.globals
+            .borrow()
+            .get_local(name)
+            .or_else(|| self.builtins.borrow().get_local(name))
+            .or_else(|| natives::lookup(name))
+    }
+
+    /// Unsets a global defined by the script, returning its last value.
+    pub fn remove_global(&mut self, name: &str) -> Option<Value> {
+        self.exports.retain(|export| export != name);
+        self.globals.borrow_mut().remove(name)
+    }
+
+    /// Unsets every global the script defined. With `keep_natives` false the
+    /// natives are dropped as well, leaving a completely empty global scope.
+    /// Closures that captured the globals see the cleared scope too.
+    pub fn clear_globals(&mut self, keep_natives: bool) {
+        if !keep_natives {
+            // Builtins are shared with loaded modules, so swap in a fresh
+            // scope rather than emptying the shared one.
+            self.builtins = Rc::new(RefCell::new(Environment::new()));
+        }
+        *self.globals.borrow_mut() = Environment::new_from(&self.builtins);
+        self.environment = Rc::clone(&self.globals);
+        self.exports.clear();
+    }
+
+    pub fn exported_values(&self) -> HashMap<String, Value> {
+        let globals = self.globals.borrow();
+        self.exports
+            .iter()
+            .filter_map(|name| Some((name.clone(), globals.get_local(name)?)))
+            .collect()
+    }
+
+    /// Runs `statements`, whose nodes are in `ast`, stopping early if the
+    /// script calls `exit`. The requested exit code is returned so the host
+    /// decides what to do with it.
+    pub fn interpret(&mut self, ast: &Arc<Arena>, statements: Vec<StmtId>) -> Option<i32> {
+        // A run that unwound, with a runtime error or by being cancelled,
+        // may have left a function's scope current.
+        self.environment = Rc::clone(&self.globals);
+        self.calls.clear();
+        self.depth = 0;
+        self.ast = Arc::clone(ast);
+        self.start_budget();
+        let count = statements.len();
+        for (i, statement) in statements.into_iter().enumerate() {
+            let result = match ast.stmt(statement) {
+                Stmt::Expression(expr) if self.echo_results && i + 1 == count => self.echo(*expr),
+                _ => self.execute(statement),
+            };
+            if let Err(Return::Exit(code)) = result {
+                natives::take_exit_request();
+                return Some(code);
+            }
+        }
+        None
+    }
+
+    /// Evaluates a trailing expression statement and reports its value.
+    fn echo(&mut self, expr: ExprId) -> Result<(), Return> {
+        let value = self.evaluate(expr);
+        if let Some(code) = natives::exit_requested() {
+            return Err(Return::Exit(code));
+        }
+        self.output.emit(OutputEvent::Result(value));
+        Ok(())
+    }
+
+    /// Where `print` output, and the diagnostics flushed by the host, go.
+    /// Shared with modules loaded from now on.
+    pub fn set_output(&mut self, output: Rc<dyn OutputSink>) {
+        self.output = output;
+    }
+
+    pub fn output(&self) -> Rc<dyn OutputSink> {
+        Rc::clone(&self.output)
+    }
+
+    /// With `echo` set, `interpret` emits the value of a final expression
+    /// statement as an `OutputEvent::Result`, the way a REPL shows it.
+    pub fn echo_results(&mut self, echo: bool) {
+        self.echo_results = echo;
+    }
+
+    /// With `on` set, a runtime error in an expression makes it evaluate to
+    /// a `Value::Error`, which operators and calls pass along, instead of
+    /// stopping the program. Spreadsheets and rule engines use this so one
+    /// bad cell doesn't fail the whole batch.
+    pub fn error_values(&mut self, on: bool) {
+        self.error_values = on;
+    }
+
+    /// How deep calls may nest before the program stops with a "Stack
+    /// overflow." runtime error. Calls that would run out of the host
+    /// thread's stack carry on in more allocated from the heap, so any
+    /// thread can run to the limit.
+    pub fn set_max_depth(&mut self, max_depth: usize) {
+        self.max_depth = max_depth;
+    }
+
+    /// Counts a call starting. One more than `max_depth` calls deep it's a
+    /// runtime error instead, raised at the line of the call, whose value is
+    /// returned if error values are on.
+    pub(crate) fn enter_call(&mut self) -> Result<(), Value> {
+        if self.depth >= self.max_depth {
+            let message = "Stack overflow.".to_string();
+            return Err(self.raise(match self.calls.last() {
+                Some(call) => RuntimeError::at(call, message),
+                None => RuntimeError {
+                    message,
+                    location: None,
+                },
+            }));
+        }
+        self.depth += 1;
         Ok(())
     }
 
+    pub(crate) fn exit_call(&mut self) {
+        self.depth -= 1;
+    }
+
+    /// Calls running, and how many may be.
+    #[cfg(all(feature = "jit", not(target_arch = "wasm32")))]
+    pub(crate) fn call_depth(&self) -> (usize, usize) {
+        (self.depth, self.max_depth)
+    }
+
+    /// Whether calls may run as compiled code, which takes no steps, stops
+    /// at no breakpoints, goes uncounted by `--profile` and `--coverage`,
+    /// and does its arithmetic in floats.
+    #[cfg(all(feature = "jit", not(target_arch = "wasm32")))]
+    pub(crate) fn allows_native_code(&self) -> bool {
+        self.budget.is_unlimited()
+            && self.numbers.is_float()
+            && !crate::profiler::enabled()
+            && !crate::coverage::enabled()
+            && !crate::dap::active()
+    }
+
+    /// Limits each run to `budget`. A run that goes past it unwinds with
+    /// `BudgetExceeded` as the panic payload.
+    pub fn set_budget(&mut self, budget: Budget) {
+        self.budget = budget;
+    }
+
+    /// Starts a run with the whole budget.
+    pub(crate) fn start_budget(&mut self) {
+        self.steps = 0;
+        self.started = self.budget.timeout.map(|_| Instant::now());
+    }
+
+    fn take_step(&mut self) {
+        self.steps += 1;
+        if let Some(max_steps) = self.budget.max_steps {
+            if self.steps > max_steps {
+                panic::resume_unwind(Box::new(BudgetExceeded::Steps(max_steps)));
+            }
+        }
+        // Reading the clock on every step would slow every script down.
+        if let Some(timeout) = self.budget.timeout {
+            let started = *self.started.get_or_insert_with(Instant::now);
+            if self.steps.is_multiple_of(1024) && started.elapsed() > timeout {
+                panic::resume_unwind(Box::new(BudgetExceeded::Timeout(timeout)));
+            }
+        }
+    }
+
+    /// A handle another thread can use to stop this interpreter. It's
+    /// checked before every statement and call, and a cancelled run
+    /// unwinds with `Cancelled` as the panic payload. The request is
+    /// cleared as the run stops, so the interpreter can be used again.
+    pub fn cancellation_token(&self) -> CancellationToken {
+        self.cancellation.clone()
+    }
+
+    /// Unwinds with `Cancelled` if the host has asked this interpreter to
+    /// stop.
+    pub fn check_cancelled(&self) {
+        // A plain load first: this runs for every statement, and is almost
+        // always false.
+        if self.cancellation.is_cancelled() && self.cancellation.take() {
+            panic::resume_unwind(Box::new(Cancelled));
+        }
+    }
+
+    /// A runtime error that stops the program, or becomes its value.
+    fn runtime_error(&self, message: String) -> Value {
+        self.raise(RuntimeError {
+            message,
+            location: None,
+        })
+    }
+
+    fn raise(&self, error: RuntimeError) -> Value {
+        if self.error_values {
+            Value::Error(error.message.into())
+        } else if cfg!(target_arch = "wasm32") {
+            // Nothing unwinds there, so panic with the error: the panic
+            // hook is the one place left that can report it.
+            panic::panic_any(error)
+        } else {
+            panic::resume_unwind(Box::new(error))
+        }
+    }
+
+    /// A runtime error raised at `token`, which stops the program or
+    /// becomes the expression's value.
+    fn error_at(&self, token: &Token, message: &str) -> Value {
+        self.raise(RuntimeError::at(token, message.to_string()))
+    }
+
+    /// Reports an error at `token` that the program goes on from, such as a
+    /// failed import. Unlike static errors it's shown straight away, so it
+    /// lands between the output printed before and after it.
+    fn report_at(&self, token: &Token, message: &str) {
+        crate::error_at_token(token, message);
+        diagnostics::flush(&*self.output);
+    }
+
+    fn module_export(&self, module: &Module, name: &Token) -> Value {
+        if let Some(export) = module.export(name.lexeme()) {
+            export
+        } else {
+            let message = format!(
+                "Module '{}' has no export '{}'.",
+                module.name(),
+                name.lexeme()
+            );
+            self.report_at(name, &message);
+            Value::None
+        }
+    }
+
+    /// The innermost scope of the code running now.
+    pub(crate) fn environment(&self) -> Rc<RefCell<Environment>> {
+        Rc::clone(&self.environment)
+    }
+
+    /// Evaluates `expr`, whose nodes are in `ast`, in the current scope.
+    pub fn evaluate_in(&mut self, ast: &Arc<Arena>, expr: ExprId) -> Value {
+        self.ast = Arc::clone(ast);
+        self.evaluate(expr)
+    }
+
+    /// Makes `ast` the arena ids are looked up in, as a call to a function
+    /// declared in another program does, and returns the one it replaces.
+    pub(crate) fn swap_ast(&mut self, ast: Arc<Arena>) -> Arc<Arena> {
+        std::mem::replace(&mut self.ast, ast)
+    }
+
+    /// The arena of the code running now.
+    pub(crate) fn ast(&self) -> &Arc<Arena> {
+        &self.ast
+    }
+
+    fn evaluate(&mut self, expr: ExprId) -> Value {
+        // The arena is held here, not borrowed from `self`, for the
+        // visitor to have `self` mutably.
+        let ast = Arc::clone(&self.ast);
+        ast.expr(expr).accept(self)
+    }
+
+    fn execute(&mut self, stmt: StmtId) -> Result<(), Return> {
+        self.check_cancelled();
+        if !self.budget.is_unlimited() {
+            self.take_step();
+        }
+        let ast = Arc::clone(&self.ast);
+        let stmt = ast.stmt(stmt);
+        coverage::hit(&ast, stmt);
+        dap::statement(self, stmt);
+        stmt.accept(self)?;
+        match natives::exit_requested() {
+            Some(code) => Err(Return::Exit(code)),
+            None => Ok(()),
+        }
+    }
+
     // fn resolve(&mut self, expr: Expr, depth: int) {
     //     self.locals.put
     // }
 
+    /// Calls `callee` from outside the tree walk, as natives calling back
+    /// into Lox do. The current scope is restored even if the call stops
+    /// with a runtime error, so a caller that catches it can carry on.
+    pub fn call_value(&mut self, callee: &Value, arguments: Vec<Value>) -> Result<Value, String> {
+        // Once `exit` has been called nothing else runs.
+        if natives::exit_requested().is_some() {
+            return Ok(Value::None);
+        }
+        let Value::Callable(function) = callee else {
+            return Err(format!(
+                "Can only call functions, got {}.",
+                callee.type_name()
+            ));
+        };
+        if arguments.len() != function.arity() {
+            return Err(format!(
+                "Expected {} argument(s) but got {}.",
+                function.arity(),
+                arguments.len()
+            ));
+        }
+        let environment = Rc::clone(&self.environment);
+        let ast = Arc::clone(&self.ast);
+        let (calls, depth) = (self.calls.len(), self.depth);
+        let result = panic::catch_unwind(AssertUnwindSafe(|| function.call(self, arguments)));
+        self.environment = environment;
+        self.ast = ast;
+        self.calls.truncate(calls);
+        self.depth = depth;
+        result.unwrap_or_else(|payload| panic::resume_unwind(payload))
+    }
+
+    /// How `value` shows when printed or joined to a string: what an
+    /// instance's `toString` field gives back when called, if it holds a
+    /// function of no arguments, and otherwise the value itself.
+    fn display_value(&mut self, value: Value) -> Value {
+        let Value::Instance(instance) = &value else {
+            return value;
+        };
+        match LoxInstance::get(instance, Symbol::intern("toString")) {
+            Value::Callable(function) if function.arity() == 0 => self
+                .call_value(&Value::Callable(function), Vec::new())
+                .unwrap_or_else(|message| self.runtime_error(message)),
+            _ => value,
+        }
+    }
+
+    /// The line the running function was called from, if it was called
+    /// from Lox code. Asked by a native, it's where the native's caller was
+    /// called.
+    pub(crate) fn caller_line(&self) -> Option<u32> {
+        self.calls.iter().rev().nth(1).map(|call| call.line)
+    }
+
     pub fn execute_block(
         &mut self,
-        statements: Vec<Stmt>,
+        statements: &[StmtId],
         environment: Rc<RefCell<Environment>>,
     ) -> Result<(), Return> {
         let previous = self.environment.clone();
         let steps = || -> Result<(), Return> {
             self.environment = environment;
             for statement in statements {
-                self.execute(statement)?
+                self.execute(*statement)?
             }
             Ok(())
         };
@@ -122,12 +710,17 @@ impl ExprVisitor<Value> for Interpreter {
             Literal::None => Value::None,
             Literal::Boolean(b) => Value::Boolean(b),
             Literal::Number(n) => Value::Number(n),
-            Literal::String(s) => Value::String(s.clone()),
+            Literal::String(s) => Value::String(s),
+            #[cfg(feature = "units")]
+            Literal::Quantity(n, unit) => Value::Quantity(n, unit),
         }
     }
 
-    fn visit_logical_expr(&mut self, l: Box<Expr>, op: Token, r: Box<Expr>) -> Value {
-        let left = self.evaluate(*l);
+    fn visit_logical_expr(&mut self, l: ExprId, op: Token, r: ExprId) -> Value {
+        let left = self.evaluate(l);
+        if let Value::Error(_) = left {
+            return left;
+        }
 
         if op.token_type == TokenType::Or {
             if is_truthy(&left) {
@@ -137,113 +730,186 @@ impl ExprVisitor<Value> for Interpreter {
             return left;
         }
 
-        self.evaluate(*r)
+        self.evaluate(r)
     }
 
-    fn visit_grouping_expr(&mut self, expr: Box<Expr>) -> Value {
-        self.evaluate(*expr)
+    fn visit_grouping_expr(&mut self, expr: ExprId) -> Value {
+        self.evaluate(expr)
     }
 
-    fn visit_unary_expr(&mut self, op: Token, r: Box<Expr>) -> Value {
-        let right = self.evaluate(*r);
+    fn visit_unary_expr(&mut self, op: Token, r: ExprId) -> Value {
+        let right = self.evaluate(r);
+        if natives::exit_requested().is_some() {
+            return Value::None;
+        }
+        if let Value::Error(_) = right {
+            return right;
+        }
 
         match op.token_type {
             TokenType::Bang => Value::Boolean(!is_truthy(&right)),
             TokenType::Minus => {
                 if let Value::Number(n) = right {
                     Value::Number(-n)
+                } else if let Some(negated) = negate_quantity(&right) {
+                    negated
                 } else {
-                    panic!("{:?} must be a number", right);
+                    self.runtime_error(format!("{:?} must be a number", right))
                 }
             }
             _ => Value::None,
         }
     }
 
-    fn visit_binary_expr(&mut self, l: Box<Expr>, op: Token, r: Box<Expr>) -> Value {
-        let left = self.evaluate(*l);
-        let right = self.evaluate(*r);
+    fn visit_binary_expr(&mut self, l: ExprId, op: Token, r: ExprId) -> Value {
+        let left = self.evaluate(l);
+        let right = self.evaluate(r);
+        // An operand called `exit`, so its value is a placeholder.
+        if natives::exit_requested().is_some() {
+            return Value::None;
+        }
+        // The first error wins, as in a spreadsheet.
+        if let Value::Error(_) = left {
+            return left;
+        }
+        if let Value::Error(_) = right {
+            return right;
+        }
+        #[cfg(feature = "units")]
+        if let Some(result) = crate::units::binary(&*self.numbers, op.token_type, &left, &right) {
+            return result.unwrap_or_else(|message| self.error_at(&op, &message));
+        }
 
         match op.token_type {
+            TokenType::Operator(_) => {
+                let result = match self.operators.handler(op.lexeme()) {
+                    Some(handler) => handler(&left, &right),
+                    None => Err(format!("Unknown operator '{}'.", op.lexeme())),
+                };
+                result.unwrap_or_else(|message| self.error_at(&op, &message))
+            }
             TokenType::Minus => {
                 if let (Value::Number(ln), Value::Number(rn)) = (left.clone(), right.clone()) {
-                    Value::Number(ln - rn)
+                    Value::Number(self.numbers.sub(ln, rn))
                 } else {
-                    panic!("{:?} and {:?} must be numbers", left, right);
+                    self.runtime_error(format!("{:?} and {:?} must be numbers", left, right))
                 }
             }
-            TokenType::Slash => {
-                if let (Value::Number(ln), Value::Number(rn)) = (left.clone(), right.clone()) {
-                    Value::Number(ln / rn)
-                } else {
-                    panic!("{:?} and {:?} must be numbers", left, right);
+            TokenType::Slash => match (&left, &right) {
+                (Value::Number(_), Value::Number(rn)) if *rn == 0.0 => {
+                    self.error_at(&op, "Division by zero.")
                 }
-            }
+                (Value::Number(ln), Value::Number(rn)) => Value::Number(self.numbers.div(*ln, *rn)),
+                _ => self.runtime_error(format!("{:?} and {:?} must be numbers", left, right)),
+            },
             TokenType::Star => {
                 if let (Value::Number(ln), Value::Number(rn)) = (left.clone(), right.clone()) {
-                    Value::Number(ln * rn)
+                    Value::Number(self.numbers.mul(ln, rn))
                 } else {
-                    panic!("{:?} and {:?} must be numbers", left, right);
+                    self.runtime_error(format!("{:?} and {:?} must be numbers", left, right))
                 }
             }
-            TokenType::Plus => match (left.clone(), right.clone()) {
-                (Value::String(ls), Value::String(rs)) => Value::String(ls + &rs),
-                (Value::Number(ln), Value::Number(rn)) => Value::Number(ln + rn),
-                _ => panic!(
+            TokenType::Plus => {
+                // An instance joined to a string shows as its `toString`.
+                let (left, right) = match (&left, &right) {
+                    (Value::String(_), Value::Instance(_)) => {
+                        (left.clone(), self.display_value(right))
+                    }
+                    (Value::Instance(_), Value::String(_)) => {
+                        (self.display_value(left), right.clone())
+                    }
+                    _ => (left, right),
+                };
+                match (left.clone(), right.clone()) {
+                    (Value::String(ls), Value::String(rs)) => {
+                        Value::String([ls, rs].concat().into())
+                    }
+                    (Value::Number(ln), Value::Number(rn)) => {
+                        Value::Number(self.numbers.add(ln, rn))
+                    }
+                    _ => self.runtime_error(format!(
+                        "{:?} and {:?} must both be strings or both be numbers",
+                        left, right
+                    )),
+                }
+            }
+            // Strings compare by code point, so "Z" < "a".
+            TokenType::Greater => match (&left, &right) {
+                (Value::Number(ln), Value::Number(rn)) => Value::Boolean(ln > rn),
+                (Value::String(ls), Value::String(rs)) => Value::Boolean(ls > rs),
+                _ => self.runtime_error(format!(
                     "{:?} and {:?} must both be strings or both be numbers",
                     left, right
-                ),
+                )),
+            },
+            TokenType::GreaterEqual => match (&left, &right) {
+                (Value::Number(ln), Value::Number(rn)) => Value::Boolean(ln >= rn),
+                (Value::String(ls), Value::String(rs)) => Value::Boolean(ls >= rs),
+                _ => self.runtime_error(format!(
+                    "{:?} and {:?} must both be strings or both be numbers",
+                    left, right
+                )),
+            },
+            TokenType::Less => match (&left, &right) {
+                (Value::Number(ln), Value::Number(rn)) => Value::Boolean(ln < rn),
+                (Value::String(ls), Value::String(rs)) => Value::Boolean(ls < rs),
+                _ => self.runtime_error(format!(
+                    "{:?} and {:?} must both be strings or both be numbers",
+                    left, right
+                )),
+            },
+            TokenType::LessEqual => match (&left, &right) {
+                (Value::Number(ln), Value::Number(rn)) => Value::Boolean(ln <= rn),
+                (Value::String(ls), Value::String(rs)) => Value::Boolean(ls <= rs),
+                _ => self.runtime_error(format!(
+                    "{:?} and {:?} must both be strings or both be numbers",
+                    left, right
+                )),
+            },
+            TokenType::DotDot | TokenType::DotDotEqual => match (&left, &right) {
+                (Value::Number(start), Value::Number(end)) => Value::Range(Range {
+                    start: *start,
+                    end: *end,
+                    inclusive: op.token_type == TokenType::DotDotEqual,
+                }),
+                _ => self.runtime_error(format!("{:?} and {:?} must be numbers", left, right)),
             },
-            TokenType::Greater => {
-                if let (Value::Number(ln), Value::Number(rn)) = (left.clone(), right.clone()) {
-                    Value::Boolean(ln > rn)
-                } else {
-                    panic!("{:?} and {:?} must be numbers", left, right);
-                }
-            }
-            TokenType::GreaterEqual => {
-                if let (Value::Number(ln), Value::Number(rn)) = (left.clone(), right.clone()) {
-                    Value::Boolean(ln >= rn)
-                } else {
-                    panic!("{:?} and {:?} must be numbers", left, right);
-                }
-            }
-            TokenType::Less => {
-                if let (Value::Number(ln), Value::Number(rn)) = (left.clone(), right.clone()) {
-                    Value::Boolean(ln < rn)
-                } else {
-                    panic!("{:?} and {:?} must be numbers", left, right);
-                }
-            }
-            TokenType::LessEqual => {
-                if let (Value::Number(ln), Value::Number(rn)) = (left.clone(), right.clone()) {
-                    Value::Boolean(ln <= rn)
-                } else {
-                    panic!("{:?} and {:?} must be numbers", left, right);
-                }
-            }
             TokenType::BangEqual => Value::Boolean(!is_equal(left, right)),
             TokenType::EqualEqual => Value::Boolean(is_equal(left, right)),
             _ => Value::None,
         }
     }
 
-    fn visit_var_expr(&self, name: Token) -> Value {
-        let value = self.environment.borrow().get(&name).unwrap();
-        value
+    fn visit_var_expr(&mut self, name: Token) -> Value {
+        let value = self.environment.borrow().get(&name);
+        if let Ok(Value::Uninitialized) = value {
+            let message = format!(
+                "Variable '{}' is read before it's assigned a value.",
+                name.lexeme()
+            );
+            return self.error_at(&name, &message);
+        }
+        value.unwrap_or_else(|message| self.error_at(&name, &message))
     }
 
-    fn visit_assign_expr(&mut self, name: Token, value: Box<Expr>) -> Value {
-        let value = self.evaluate(*value);
-        self.environment
+    fn visit_assign_expr(&mut self, name: Token, value: ExprId) -> Value {
+        let value = self.evaluate(value);
+        let assigned = self
+            .environment
             .borrow_mut()
-            .assign(name, value.clone())
-            .unwrap();
+            .assign(name.clone(), value.clone());
+        if let Err(message) = assigned {
+            return self.error_at(&name, &message);
+        }
         value
     }
 
-    fn visit_call_expr(&mut self, c: Box<Expr>, paren: Token, args: Vec<Expr>) -> Value {
-        let callee = self.evaluate(*c);
+    fn visit_call_expr(&mut self, c: ExprId, paren: Token, args: Vec<ExprId>) -> Value {
+        // Once `exit` has been called nothing else in the statement runs.
+        if natives::exit_requested().is_some() {
+            return Value::None;
+        }
+        let callee = self.evaluate(c);
 
         let mut arguments = Vec::new();
         for arg in args {
@@ -253,105 +919,182 @@ impl ExprVisitor<Value> for Interpreter {
         match callee {
             Value::Callable(function) => {
                 if arguments.len() != function.arity() {
-                    crate::error_at_token(
-                        &paren,
-                        &format!(
-                            "Expected {} argument but got {}.",
-                            function.arity(),
-                            arguments.len()
-                        ),
+                    let message = format!(
+                        "Expected {} argument but got {}.",
+                        function.arity(),
+                        arguments.len()
                     );
+                    return self.error_at(&paren, &message);
                 }
-                if let Ok(res) = function.call(self, arguments) {
-                    res
-                } else {
-                    Value::None
+                self.calls.push(paren.clone());
+                let result = function.call(self, arguments);
+                self.calls.pop();
+                match result {
+                    Ok(res) => res,
+                    Err(message) => self.error_at(&paren, &message),
                 }
             }
             Value::Class(class) => {
-                let args_size = args.len();
                 let instance = LoxInstance { klass: class, fields: HashMap::new() };
-                Value::None
-            }
-            _ => {
-                crate::error_at_token(&paren, "Can only call functions and classes.");
-                Value::None
+                Value::Instance(Rc::new(RefCell::new(instance)))
             }
+            Value::Error(_) => callee,
+            _ => self.error_at(&paren, "Can only call functions and classes."),
         }
     }
 
-    fn visit_get_expr(&mut self, expr: Box<Expr>, name: Token) -> Result<Value, String> {
-        let value = self.evaluate(*expr);
+    fn visit_get_expr(&mut self, expr: ExprId, name: Token) -> Value {
+        let value = self.evaluate(expr);
         if let Value::Instance(instance) = value {
-            Ok(instance.get(name))
+            LoxInstance::get(&instance, name.symbol)
+        } else if let Value::Module(module) = value {
+            self.module_export(&module, &name)
+        } else if let Value::LazyModule(lazy) = value {
+            match lazy.force() {
+                Ok(module) => self.module_export(&module, &name),
+                Err(message) => self.error_at(&name, &message),
+            }
+        } else if let Value::Error(_) = value {
+            value
         } else {
-            Err("Only instances have properties".to_string())
+            self.runtime_error("Only instances have properties.".to_string())
         }
     }
 
-    fn visit_set_expr(&mut self, object: Box<Expr>, name: Token, value: Box<Expr>) -> Result<Value, String> {
-        let object_value = self.evaluate(*object);
+    fn visit_set_expr(&mut self, object: ExprId, name: Token, value: ExprId) -> Value {
+        let object_value = self.evaluate(object);
 
-        if let Value::Instance(mut instance) = object_value {
-            let value_value = self.evaluate(*value);
-            instance.set(name, value_value);
-            Ok(value_value)
+        if let Value::Instance(instance) = object_value {
+            let value_value = self.evaluate(value);
+            instance.borrow_mut().set(name, value_value.clone());
+            value_value
+        } else if let Value::Error(_) = object_value {
+            object_value
         } else {
-            Err("Only instances have fields.".to_string())
+            self.runtime_error("Only instances have fields.".to_string())
         }
     }
+
+    fn visit_tuple_expr(&mut self, _: Token, values: Vec<ExprId>) -> Value {
+        let values = values
+            .into_iter()
+            .map(|value| self.evaluate(value))
+            .collect();
+        Value::List(Rc::new(RefCell::new(values)))
+    }
 }
 
-impl StmtVisitor<()> for Interpreter {
-    fn visit_expression_stmt(&mut self, stmt: Expr) -> Result<(), Return> {
+impl StmtVisitor<Result<(), Return>> for Interpreter {
+    fn visit_expression_stmt(&mut self, stmt: ExprId) -> Result<(), Return> {
         self.evaluate(stmt);
         Ok(())
     }
 
-    fn visit_print_stmt(&mut self, stmt: Expr) -> Result<(), Return> {
+    fn visit_print_stmt(&mut self, stmt: ExprId) -> Result<(), Return> {
         let value = self.evaluate(stmt);
-        println!("{}", value);
+        let value = self.display_value(value);
+        self.output.emit(OutputEvent::PrintValue(value));
         Ok(())
     }
 
-    fn visit_return_stmt(&mut self, _: Token, value: Option<Expr>) -> Result<(), Return> {
+    fn visit_return_stmt(&mut self, _: Token, value: Option<ExprId>) -> Result<(), Return> {
         let value_value = if let Some(v) = value {
             self.evaluate(v)
         } else {
             Value::None
         };
 
-        Err(Return { value: value_value })
+        Err(Return::Value(value_value))
+    }
+
+    fn visit_var_stmt(&mut self, name: Token, initializer: Option<ExprId>) -> Result<(), Return> {
+        let value = initializer.map_or_else(|| Value::Uninitialized, |expr| self.evaluate(expr));
+        self.environment.borrow_mut().define(name.symbol, value);
+        Ok(())
+    }
+
+    fn visit_const_stmt(&mut self, name: Token, initializer: ExprId) -> Result<(), Return> {
+        let value = self.evaluate(initializer);
+        self.environment
+            .borrow_mut()
+            .define_constant(name.symbol, value);
+        Ok(())
+    }
+
+    fn visit_destructure_stmt(
+        &mut self,
+        names: Vec<Token>,
+        initializer: ExprId,
+    ) -> Result<(), Return> {
+        let value = self.evaluate(initializer);
+        let values = match &value {
+            Value::List(items) if items.borrow().len() == names.len() => items.borrow().clone(),
+            Value::List(items) => {
+                let message = format!(
+                    "Expected {} values to unpack but got {}.",
+                    names.len(),
+                    items.borrow().len()
+                );
+                vec![self.runtime_error(message); names.len()]
+            }
+            Value::Error(_) => vec![value; names.len()],
+            _ => {
+                let message = format!("Only lists can be unpacked, not a {}.", value.type_name());
+                vec![self.runtime_error(message); names.len()]
+            }
+        };
+        let mut environment = self.environment.borrow_mut();
+        for (name, value) in names.into_iter().zip(values) {
+            environment.define(name.symbol, value);
+        }
+        Ok(())
     }
 
-    fn visit_var_stmt(&mut self, name: Token, initializer: Option<Expr>) -> Result<(), Return> {
-        let value = initializer.map_or_else(|| Value::None, |expr| self.evaluate(expr));
-        self.environment.borrow_mut().define(name.lexeme, value);
+    fn visit_while_stmt(&mut self, condition: ExprId, body: StmtId) -> Result<(), Return> {
+        while is_truthy(&self.evaluate(condition)) {
+            self.execute(body)?;
+        }
         Ok(())
     }
 
-    fn visit_while_stmt(&mut self, condition: Expr, body: Box<Stmt>) -> Result<(), Return> {
-        while is_truthy(&self.evaluate(condition.clone())) {
-            self.execute(*body.clone())?;
+    fn visit_for_in_stmt(
+        &mut self,
+        name: Token,
+        iterable: ExprId,
+        body: StmtId,
+    ) -> Result<(), Return> {
+        let collection = self.evaluate(iterable);
+        let Some(items) = iterate::items(&collection) else {
+            self.runtime_error(format!(
+                "Only lists, ranges and instances can be iterated, not a {}.",
+                collection.type_name()
+            ));
+            return Ok(());
+        };
+        for item in items {
+            // A fresh scope each time, so closures capture that item.
+            let mut environment = Environment::new_from(&self.environment);
+            environment.define(name.symbol, item);
+            self.execute_block(&[body], Rc::new(RefCell::new(environment)))?;
         }
         Ok(())
     }
 
-    fn visit_block_stmt(&mut self, statements: Vec<Stmt>) -> Result<(), Return> {
+    fn visit_block_stmt(&mut self, statements: Vec<StmtId>) -> Result<(), Return> {
         let environment = Environment::new_from(&self.environment);
-        self.execute_block(statements, Rc::new(RefCell::new(environment)))?;
+        self.execute_block(&statements, Rc::new(RefCell::new(environment)))?;
         Ok(())
     }
 
     fn visit_if_stmt(
         &mut self,
-        condition: Expr,
-        then_branch: Box<Stmt>,
-        else_branch: Box<Option<Stmt>>,
+        condition: ExprId,
+        then_branch: StmtId,
+        else_branch: Option<StmtId>,
     ) -> Result<(), Return> {
         if is_truthy(&self.evaluate(condition)) {
-            self.execute(*then_branch)?;
-        } else if let Some(e) = *else_branch {
+            self.execute(then_branch)?;
+        } else if let Some(e) = else_branch {
             self.execute(e)?;
         }
         Ok(())
@@ -361,34 +1104,130 @@ impl StmtVisitor<()> for Interpreter {
         &mut self,
         name: Token,
         params: Vec<Token>,
-        body: Vec<Stmt>,
+        body: Vec<StmtId>,
     ) -> Result<(), Return> {
-        let key = name.lexeme.clone();
-        let function = Value::Callable(Function::User {
+        let key = name.symbol;
+        let function = Value::Callable(Rc::new(Function::User {
             body,
+            ast: Arc::clone(&self.ast),
             params,
             name,
             enclosing: Rc::clone(&self.environment),
-        });
+        }));
         self.environment.borrow_mut().define(key, function);
         Ok(())
     }
 
-    fn visit_class_stmt(&self, name: Token, methods: Vec<Stmt>) -> Result<(), Return> {
-        self.environment
-            .borrow_mut()
-            .define(name.lexeme, Value::None);
-        let klass = LoxClass { name: name.lexeme };
+    fn visit_import_stmt(
+        &mut self,
+        _: Token,
+        path: Token,
+        target: ImportTarget,
+    ) -> Result<(), Return> {
+        let Literal::String(module_path) = &path.literal() else {
+            return Ok(());
+        };
+
+        if let ImportTarget::Lazy(alias) = target {
+            let loader = self.for_module(self.base_dir.clone());
+            let lazy = LazyModule::new(module_path.to_string(), loader);
+            self.environment
+                .borrow_mut()
+                .define(alias.symbol, Value::LazyModule(Rc::new(lazy)));
+            return Ok(());
+        }
+
+        let module = match module::load(self, module_path) {
+            Ok(module) => module,
+            Err(message) => {
+                self.report_at(&path, &message);
+                return Ok(());
+            }
+        };
+
+        let mut environment = self.environment.borrow_mut();
+        match target {
+            ImportTarget::All => {
+                for (name, value) in module.exports.borrow().iter() {
+                    environment.define(name.clone(), value.clone());
+                }
+            }
+            ImportTarget::Alias(alias) => {
+                environment.define(alias.symbol, Value::Module(Rc::clone(&module)));
+            }
+            ImportTarget::Names(names) => {
+                for name in names {
+                    match module.export(name.lexeme()) {
+                        Some(value) => environment.define(name.symbol, value),
+                        None => self.report_at(
+                            &name,
+                            &format!(
+                                "Module '{}' has no export '{}'.",
+                                module.name(),
+                                name.lexeme()
+                            ),
+                        ),
+                    }
+                }
+            }
+            ImportTarget::Lazy(_) => {}
+        }
+        Ok(())
+    }
+
+    fn visit_export_stmt(&mut self, _: Token, declaration: StmtId) -> Result<(), Return> {
+        let names = match self.ast.stmt(declaration) {
+            Stmt::Var(name, _)
+            | Stmt::Const(name, _)
+            | Stmt::Function(name, ..)
+            | Stmt::Class(name, _) => vec![name.clone()],
+            Stmt::Destructure(names, _) => names.clone(),
+            _ => Vec::new(),
+        };
+        self.execute(declaration)?;
+        self.exports
+            .extend(names.iter().map(|name| name.lexeme().to_string()));
+        Ok(())
+    }
+
+    fn visit_class_stmt(&mut self, name: Token, methods: Vec<StmtId>) -> Result<(), Return> {
+        let mut functions = HashMap::new();
+        for method in methods {
+            if let Stmt::Function(method, params, body) = self.ast.stmt(method) {
+                let function = Function::User {
+                    body: body.clone(),
+                    ast: Arc::clone(&self.ast),
+                    params: params.clone(),
+                    name: method.clone(),
+                    enclosing: Rc::clone(&self.environment),
+                };
+                functions.insert(method.symbol, Rc::new(function));
+            }
+        }
+        let klass = LoxClass::declared(name.clone(), functions);
         self.environment
             .borrow_mut()
-            .assign(name, Value::Class(klass));
+            .define(name.symbol, Value::Class(Rc::new(klass)));
         Ok(())
     }
 }
 
+#[cfg(feature = "units")]
+fn negate_quantity(value: &Value) -> Option<Value> {
+    match value {
+        Value::Quantity(n, unit) => Some(Value::Quantity(-n, unit)),
+        _ => None,
+    }
+}
+
+#[cfg(not(feature = "units"))]
+fn negate_quantity(_: &Value) -> Option<Value> {
+    None
+}
+
 fn is_truthy(object: &Value) -> bool {
     match object {
-        Value::None => false,
+        Value::None | Value::Error(_) => false,
         Value::Boolean(b) => *b,
         _ => true,
     }
@@ -400,6 +1239,13 @@ fn is_equal(left: Value, right: Value) -> bool {
         (Value::Boolean(l), Value::Boolean(r)) => l == r,
         (Value::Number(l), Value::Number(r)) => l == r,
         (Value::String(l), Value::String(r)) => l == r,
+        // Functions, classes and instances are equal only to themselves.
+        (Value::Callable(l), Value::Callable(r)) => Function::is_same(&l, &r),
+        (Value::Class(l), Value::Class(r)) => l.id == r.id,
+        (Value::Instance(l), Value::Instance(r)) => Rc::ptr_eq(&l, &r),
+        (Value::Range(l), Value::Range(r)) => l == r,
+        #[cfg(feature = "units")]
+        (Value::Quantity(l, lu), Value::Quantity(r, ru)) => crate::units::equal((l, lu), (r, ru)),
         _ => false,
     }
 }
@@ -0,0 +1,31 @@
+//! Ctrl-C: rather than killing the process, it stops the statement that's
+//! running through the interpreter's `CancellationToken`, so the REPL can
+//! go back to its prompt.
+
+use std::sync::Once;
+
+use crate::cancel::CancellationToken;
+
+/// Makes Ctrl-C cancel whatever the interpreter `token` belongs to is
+/// running. A second Ctrl-C before it stops ends the process, for a script
+/// stuck waiting in a native. Only the first call has any effect.
+pub fn install(token: CancellationToken) {
+    static INSTALLED: Once = Once::new();
+    INSTALLED.call_once(|| handle_interrupts(token));
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn handle_interrupts(token: CancellationToken) {
+    let handler = move || {
+        if token.is_cancelled() {
+            std::process::exit(130);
+        }
+        token.cancel();
+    };
+    // Without a handler Ctrl-C just ends the process, as it always has.
+    let _ = ctrlc::set_handler(handler);
+}
+
+/// A browser has no Ctrl-C to handle.
+#[cfg(target_arch = "wasm32")]
+fn handle_interrupts(_token: CancellationToken) {}
@@ -0,0 +1,70 @@
+//! What `for (var x in collection)` steps through: a list's elements in
+//! order, a range's numbers, or the field names of an instance used as a
+//! map, such as a `map-rows` row, in sorted order.
+
+use std::{cell::RefCell, rc::Rc};
+
+use crate::{interpreter::Value, range::Range};
+
+pub(crate) enum Items {
+    /// Read an element at a time, so the loop sees elements its body
+    /// appends and holds no borrow while the body runs.
+    List {
+        items: Rc<RefCell<Vec<Value>>>,
+        next: usize,
+    },
+    Range {
+        range: Range,
+        next: usize,
+    },
+    Keys(std::vec::IntoIter<Value>),
+}
+
+/// The items of `value`, or `None` if it can't be iterated.
+pub(crate) fn items(value: &Value) -> Option<Items> {
+    match value {
+        Value::List(items) => Some(Items::List {
+            items: Rc::clone(items),
+            next: 0,
+        }),
+        Value::Range(range) => Some(Items::Range {
+            range: *range,
+            next: 0,
+        }),
+        Value::Instance(instance) => {
+            let mut keys: Vec<&str> = instance
+                .borrow()
+                .fields
+                .keys()
+                .map(|key| key.as_str())
+                .collect();
+            keys.sort_unstable();
+            let keys: Vec<Value> = keys
+                .into_iter()
+                .map(|key| Value::String(key.into()))
+                .collect();
+            Some(Items::Keys(keys.into_iter()))
+        }
+        _ => None,
+    }
+}
+
+impl Iterator for Items {
+    type Item = Value;
+
+    fn next(&mut self) -> Option<Value> {
+        match self {
+            Items::List { items, next } => {
+                let item = items.borrow().get(*next).cloned();
+                *next += 1;
+                item
+            }
+            Items::Range { range, next } => {
+                let item = range.get(*next)?;
+                *next += 1;
+                Some(Value::Number(item))
+            }
+            Items::Keys(keys) => keys.next(),
+        }
+    }
+}
@@ -0,0 +1,918 @@
+//! The `jit` feature: functions called often enough are compiled to native
+//! code with Cranelift, and later calls to them run that instead of walking
+//! their syntax tree.
+//!
+//! Only a pure, numeric subset of Lox is compiled: top-level functions
+//! whose bodies use number and boolean literals, parameters and local
+//! variables, arithmetic, comparisons, `and`, `or`, `!`, `if`, `while`,
+//! `return` of a number and calls to other such functions. A function
+//! using anything else is left to the tree-walker for good.
+//!
+//! Compiled code can't report errors. Where the tree-walker would raise one
+//! (dividing by zero, going past the depth limit, returning nothing) or
+//! would stop (a cancelled run), the native call gives up and the
+//! tree-walker runs the whole call again. That's only safe because the
+//! subset has no side effects to repeat.
+
+use std::{
+    cell::RefCell,
+    collections::{HashMap, HashSet},
+    mem::offset_of,
+    rc::Rc,
+    sync::{atomic::AtomicBool, Arc, Weak},
+};
+
+use cranelift_codegen::{
+    ir::{
+        condcodes::{FloatCC, IntCC},
+        types, AbiParam, Block, InstBuilder, MemFlags, Signature, StackSlotData, StackSlotKind,
+        Type, Value as Ir,
+    },
+    settings::{self, Configurable},
+};
+use cranelift_frontend::{FunctionBuilder, FunctionBuilderContext, Variable};
+use cranelift_jit::{JITBuilder, JITModule};
+use cranelift_module::{default_libcall_names, FuncId, Module};
+
+use crate::{
+    arena::Arena,
+    expression::{Expr, ExprId},
+    function::Function,
+    interpreter::{Interpreter, Value},
+    source::Span,
+    statement::{Stmt, StmtId},
+    symbol::Symbol,
+    token::{Literal, Token, TokenType},
+};
+
+/// Calls to a function before it's compiled.
+const HOT_CALLS: u32 = 50;
+
+/// Nested native calls allowed, on top of the calls already running. Past
+/// this the call is handed back to the tree-walker, which grows its stack
+/// as it goes.
+const NATIVE_DEPTH: u64 = 4096;
+
+/// Stack kept free for native code, enough for `NATIVE_DEPTH` frames.
+const NATIVE_STACK: usize = 4 * 1024 * 1024;
+
+/// A function declaration: the arena it was parsed into and where its name
+/// is.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+struct Key {
+    arena: usize,
+    name: Span,
+}
+
+impl Key {
+    fn of(ast: &Arc<Arena>, name: &Token) -> Key {
+        Key {
+            arena: Arc::as_ptr(ast) as usize,
+            name: name.span,
+        }
+    }
+}
+
+/// What a compiled function is handed: its arguments, and the state its
+/// calls share.
+type Code = unsafe extern "C" fn(*const f64, *mut Context) -> f64;
+
+#[repr(C)]
+struct Context {
+    depth: u64,
+    max_depth: u64,
+    cancelled: *const AtomicBool,
+    /// Set when the call has to be run by the tree-walker instead.
+    failed: u8,
+}
+
+struct Compiled {
+    code: Code,
+    /// The global every function it calls, directly or not, was found under.
+    /// Redefining one of them makes the code out of date.
+    callees: Vec<(Symbol, Key)>,
+    /// Keeps the arena alive, so the keys above can't come to name another
+    /// function.
+    _ast: Arc<Arena>,
+}
+
+enum State {
+    Counting(u32),
+    Compiled(Rc<Compiled>),
+    Unsupported,
+}
+
+struct Entry {
+    /// The arena the function was parsed into. While it's referred to, even
+    /// weakly, its address isn't reused for another.
+    ast: Weak<Arena>,
+    state: State,
+}
+
+#[derive(Default)]
+struct Jit {
+    entries: HashMap<Key, Entry>,
+    /// How many entries were left after dropping those of freed arenas.
+    swept: usize,
+    /// Where compiled code lives. It's never freed.
+    modules: Vec<JITModule>,
+}
+
+thread_local! {
+    static JIT: RefCell<Jit> = RefCell::new(Jit::default());
+}
+
+/// Runs `function` natively if it's hot and compiles, giving what it
+/// returned. `None` leaves the call to the tree-walker.
+pub(crate) fn call(
+    function: &Function,
+    interpreter: &Interpreter,
+    arguments: &[Value],
+) -> Option<Value> {
+    let Function::User {
+        ast,
+        name,
+        enclosing,
+        ..
+    } = function
+    else {
+        return None;
+    };
+    if !Rc::ptr_eq(enclosing, &interpreter.globals) || !interpreter.allows_native_code() {
+        return None;
+    }
+    let arguments = arguments
+        .iter()
+        .map(|argument| match argument {
+            Value::Number(n) => Some(*n),
+            _ => None,
+        })
+        .collect::<Option<Vec<f64>>>()?;
+    let key = Key::of(ast, name);
+    let compiled = JIT.with(|jit| jit.borrow_mut().hot(key, function, interpreter))?;
+    let current = compiled
+        .callees
+        .iter()
+        .all(|&(name, key)| global(interpreter, name).is_some_and(|(bound, _)| bound == key));
+    if !current {
+        return None;
+    }
+
+    let (depth, max_depth) = interpreter.call_depth();
+    // The call has been counted already, and the code counts it again.
+    let depth = depth as u64 - 1;
+    let cancellation = interpreter.cancellation_token();
+    let mut context = Context {
+        depth,
+        max_depth: (max_depth as u64).min(depth + NATIVE_DEPTH),
+        cancelled: cancellation.flag(),
+        failed: 0,
+    };
+    let result = stacker::maybe_grow(NATIVE_STACK, NATIVE_STACK, || unsafe {
+        (compiled.code)(arguments.as_ptr(), &mut context)
+    });
+    (context.failed == 0).then_some(Value::Number(result))
+}
+
+/// The top-level user function bound to `name` in the globals.
+fn global(interpreter: &Interpreter, name: Symbol) -> Option<(Key, Rc<Function>)> {
+    let Some(Value::Callable(function)) = interpreter.globals.borrow().get_local(name.as_str())
+    else {
+        return None;
+    };
+    match &*function {
+        Function::User {
+            ast,
+            name,
+            enclosing,
+            ..
+        } if Rc::ptr_eq(enclosing, &interpreter.globals) => {
+            Some((Key::of(ast, name), Rc::clone(&function)))
+        }
+        _ => None,
+    }
+}
+
+impl Jit {
+    /// Counts a call to `function`, compiling it once it's hot, and gives
+    /// its code if it has some.
+    fn hot(
+        &mut self,
+        key: Key,
+        function: &Function,
+        interpreter: &Interpreter,
+    ) -> Option<Rc<Compiled>> {
+        let Function::User { ast, .. } = function else {
+            return None;
+        };
+        match &mut self.entry(key, ast).state {
+            State::Compiled(compiled) => return Some(Rc::clone(compiled)),
+            State::Unsupported => return None,
+            State::Counting(calls) => {
+                *calls += 1;
+                if *calls < HOT_CALLS {
+                    return None;
+                }
+            }
+        }
+        self.compile(key, function, interpreter);
+        match &self.entries[&key].state {
+            State::Compiled(compiled) => Some(Rc::clone(compiled)),
+            _ => None,
+        }
+    }
+
+    /// Compiles `function` and the functions it calls that aren't compiled
+    /// yet. If any of them can't be, `function` is marked as unsupported,
+    /// along with the one that couldn't.
+    fn compile(&mut self, key: Key, function: &Function, interpreter: &Interpreter) {
+        let compiled = module().and_then(|module| {
+            let mut batch = Batch::new(module, &self.entries, interpreter);
+            batch.declare(key, Rc::new(function.clone()))?;
+            while let Some((key, function, id)) = batch.queue.pop() {
+                if batch.define(&function, key, id).is_none() {
+                    return Some(Err((key, function)));
+                }
+            }
+            let Batch {
+                mut module,
+                functions,
+                direct,
+                ..
+            } = batch;
+            module.finalize_definitions().ok()?;
+            Some(Ok((module, functions, direct)))
+        });
+        let (module, functions, direct) = match compiled {
+            Some(Ok(compiled)) => compiled,
+            Some(Err((failed, callee))) => {
+                self.mark(failed, &callee, State::Unsupported);
+                self.mark(key, function, State::Unsupported);
+                return;
+            }
+            None => {
+                self.mark(key, function, State::Unsupported);
+                return;
+            }
+        };
+        for (key, (function, id)) in &functions {
+            let Function::User { ast, .. } = &**function else {
+                continue;
+            };
+            let pointer = module.get_finalized_function(*id);
+            let code = unsafe { std::mem::transmute::<*const u8, Code>(pointer) };
+            let callees = self.callees(*key, &direct);
+            let compiled = Compiled {
+                code,
+                callees,
+                _ast: Arc::clone(ast),
+            };
+            self.mark(*key, function, State::Compiled(Rc::new(compiled)));
+        }
+        self.modules.push(module);
+    }
+
+    fn mark(&mut self, key: Key, function: &Function, state: State) {
+        if let Function::User { ast, .. } = function {
+            self.entry(key, ast).state = state;
+        }
+    }
+
+    /// The entry for the function at `key` in `ast`, starting its count if
+    /// it has none. Every so often, entries whose arena has been freed are
+    /// dropped: only compiled functions keep theirs.
+    fn entry(&mut self, key: Key, ast: &Arc<Arena>) -> &mut Entry {
+        if !self.entries.contains_key(&key) && self.entries.len() >= 2 * self.swept.max(32) {
+            self.entries.retain(|_, entry| entry.ast.strong_count() > 0);
+            self.swept = self.entries.len();
+        }
+        self.entries.entry(key).or_insert_with(|| Entry {
+            ast: Arc::downgrade(ast),
+            state: State::Counting(0),
+        })
+    }
+
+    /// Every function `key` calls, directly or through others, by the
+    /// global it was found under.
+    fn callees(&self, key: Key, direct: &HashMap<Key, Vec<(Symbol, Key)>>) -> Vec<(Symbol, Key)> {
+        let mut seen = HashSet::from([key]);
+        let mut pending = vec![key];
+        let mut callees = Vec::new();
+        while let Some(caller) = pending.pop() {
+            let calls = match (
+                direct.get(&caller),
+                self.entries.get(&caller).map(|entry| &entry.state),
+            ) {
+                (Some(calls), _) => calls.clone(),
+                (None, Some(State::Compiled(compiled))) => compiled.callees.clone(),
+                _ => Vec::new(),
+            };
+            for (name, callee) in calls {
+                if !callees.contains(&(name, callee)) {
+                    callees.push((name, callee));
+                }
+                if seen.insert(callee) {
+                    pending.push(callee);
+                }
+            }
+        }
+        callees
+    }
+}
+
+/// A module for native code of this machine, or `None` if Cranelift can't
+/// target it.
+fn module() -> Option<JITModule> {
+    let mut flags = settings::builder();
+    flags.set("use_colocated_libcalls", "false").ok()?;
+    flags.set("is_pic", "false").ok()?;
+    flags.set("opt_level", "speed").ok()?;
+    let isa = cranelift_native::builder()
+        .ok()?
+        .finish(settings::Flags::new(flags))
+        .ok()?;
+    Some(JITModule::new(JITBuilder::with_isa(
+        isa,
+        default_libcall_names(),
+    )))
+}
+
+/// Functions being compiled into one module.
+struct Batch<'a> {
+    module: JITModule,
+    signature: Signature,
+    /// Functions already compiled, which are called at their address.
+    entries: &'a HashMap<Key, Entry>,
+    interpreter: &'a Interpreter,
+    functions: HashMap<Key, (Rc<Function>, FuncId)>,
+    queue: Vec<(Key, Rc<Function>, FuncId)>,
+    /// The functions each one calls, by the global they were found under.
+    direct: HashMap<Key, Vec<(Symbol, Key)>>,
+}
+
+impl<'a> Batch<'a> {
+    fn new(
+        module: JITModule,
+        entries: &'a HashMap<Key, Entry>,
+        interpreter: &'a Interpreter,
+    ) -> Self {
+        let pointer = module.target_config().pointer_type();
+        let mut signature = module.make_signature();
+        signature.params.push(AbiParam::new(pointer));
+        signature.params.push(AbiParam::new(pointer));
+        signature.returns.push(AbiParam::new(types::F64));
+        Batch {
+            module,
+            signature,
+            entries,
+            interpreter,
+            functions: HashMap::new(),
+            queue: Vec::new(),
+            direct: HashMap::new(),
+        }
+    }
+
+    /// Adds `function` to the batch, to be defined in turn.
+    fn declare(&mut self, key: Key, function: Rc<Function>) -> Option<FuncId> {
+        if let Some((_, id)) = self.functions.get(&key) {
+            return Some(*id);
+        }
+        let id = self
+            .module
+            .declare_anonymous_function(&self.signature)
+            .ok()?;
+        self.functions.insert(key, (Rc::clone(&function), id));
+        self.queue.push((key, function, id));
+        Some(id)
+    }
+
+    /// Compiles `function`'s body, or gives `None` if it uses something
+    /// that can't be.
+    fn define(&mut self, function: &Function, key: Key, id: FuncId) -> Option<()> {
+        let Function::User {
+            params, body, ast, ..
+        } = function
+        else {
+            return None;
+        };
+        let mut context = self.module.make_context();
+        context.func.signature = self.signature.clone();
+        let mut builder_context = FunctionBuilderContext::new();
+        let builder = FunctionBuilder::new(&mut context.func, &mut builder_context);
+        let mut translator = Translator::new(builder, self, ast);
+        translator.function(params, body)?;
+        let callees = translator.callees;
+        self.module.define_function(id, &mut context).ok()?;
+        self.direct.insert(key, callees);
+        Some(())
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Kind {
+    Number,
+    Boolean,
+}
+
+impl Kind {
+    fn ir(self) -> Type {
+        match self {
+            Kind::Number => types::F64,
+            Kind::Boolean => types::I8,
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Local {
+    variable: Variable,
+    kind: Kind,
+    constant: bool,
+}
+
+struct Translator<'a, 'b> {
+    builder: FunctionBuilder<'a>,
+    batch: &'a mut Batch<'b>,
+    ast: &'a Arena,
+    /// Names in scope, innermost last. `None` is a name that's in scope but
+    /// can't be compiled, such as `arguments`.
+    scopes: Vec<HashMap<Symbol, Option<Local>>>,
+    variables: u32,
+    /// The `Context` pointer.
+    context: Ir,
+    /// Where a call that has to give up goes.
+    fail: Block,
+    callees: Vec<(Symbol, Key)>,
+}
+
+impl<'a, 'b> Translator<'a, 'b> {
+    fn new(mut builder: FunctionBuilder<'a>, batch: &'a mut Batch<'b>, ast: &'a Arena) -> Self {
+        let entry = builder.create_block();
+        builder.append_block_params_for_function_params(entry);
+        builder.switch_to_block(entry);
+        let context = builder.block_params(entry)[1];
+        let fail = builder.create_block();
+        Translator {
+            builder,
+            batch,
+            ast,
+            scopes: Vec::new(),
+            variables: 0,
+            context,
+            fail,
+            callees: Vec::new(),
+        }
+    }
+
+    fn function(&mut self, params: &[Token], body: &[StmtId]) -> Option<()> {
+        let entry = self.builder.current_block().expect("the entry block");
+        let arguments = self.builder.block_params(entry)[0];
+        let depth = self.load(types::I64, offset_of!(Context, depth));
+        let max_depth = self.load(types::I64, offset_of!(Context, max_depth));
+        let overflow = self
+            .builder
+            .ins()
+            .icmp(IntCC::UnsignedGreaterThanOrEqual, depth, max_depth);
+        self.fail_if(overflow);
+        let depth = self.builder.ins().iadd_imm(depth, 1);
+        self.store(depth, offset_of!(Context, depth));
+
+        let mut scope = HashMap::new();
+        scope.insert(Symbol::intern("arguments"), None);
+        self.scopes.push(scope);
+        for (i, param) in params.iter().enumerate() {
+            let value =
+                self.builder
+                    .ins()
+                    .load(types::F64, MemFlags::trusted(), arguments, (i * 8) as i32);
+            self.declare(param.symbol, value, Kind::Number, false);
+        }
+        for &stmt in body {
+            self.statement(stmt)?;
+        }
+        // Falling off the end returns nil.
+        self.builder.ins().jump(self.fail, &[]);
+
+        self.builder.switch_to_block(self.fail);
+        let failed = self.builder.ins().iconst(types::I8, 1);
+        self.store(failed, offset_of!(Context, failed));
+        let zero = self.builder.ins().f64const(0.0);
+        self.builder.ins().return_(&[zero]);
+        self.builder.seal_all_blocks();
+        Some(())
+    }
+
+    fn statement(&mut self, stmt: StmtId) -> Option<()> {
+        match self.ast.stmt(stmt) {
+            Stmt::Expression(expr) => {
+                self.expression(*expr)?;
+            }
+            Stmt::Var(name, Some(initializer)) => {
+                let (value, kind) = self.expression(*initializer)?;
+                self.declare(name.symbol, value, kind, false);
+            }
+            Stmt::Const(name, initializer) => {
+                let (value, kind) = self.expression(*initializer)?;
+                self.declare(name.symbol, value, kind, true);
+            }
+            Stmt::Block(statements) => {
+                self.scopes.push(HashMap::new());
+                for &stmt in statements {
+                    self.statement(stmt)?;
+                }
+                self.scopes.pop();
+            }
+            Stmt::If(condition, then_branch, else_branch) => {
+                let then_block = self.builder.create_block();
+                let else_block = self.builder.create_block();
+                let after = self.builder.create_block();
+                self.branch(*condition, then_block, else_block)?;
+                self.builder.switch_to_block(then_block);
+                self.statement(*then_branch)?;
+                self.builder.ins().jump(after, &[]);
+                self.builder.switch_to_block(else_block);
+                if let Some(else_branch) = else_branch {
+                    self.statement(*else_branch)?;
+                }
+                self.builder.ins().jump(after, &[]);
+                self.builder.switch_to_block(after);
+            }
+            Stmt::While(condition, body) => {
+                let header = self.builder.create_block();
+                let body_block = self.builder.create_block();
+                let after = self.builder.create_block();
+                self.builder.ins().jump(header, &[]);
+                self.builder.switch_to_block(header);
+                self.branch(*condition, body_block, after)?;
+                self.builder.switch_to_block(body_block);
+                self.statement(*body)?;
+                // A loop is where a cancelled run would otherwise never stop.
+                let flag = self.load(self.pointer(), offset_of!(Context, cancelled));
+                let cancelled =
+                    self.builder
+                        .ins()
+                        .atomic_load(types::I8, MemFlags::trusted(), flag);
+                self.fail_if(cancelled);
+                self.builder.ins().jump(header, &[]);
+                self.builder.switch_to_block(after);
+            }
+            Stmt::Return(_, Some(value)) => {
+                let value = self.number(*value)?;
+                let depth = self.load(types::I64, offset_of!(Context, depth));
+                let depth = self.builder.ins().iadd_imm(depth, -1);
+                self.store(depth, offset_of!(Context, depth));
+                self.builder.ins().return_(&[value]);
+                // Anything after the return is unreachable, but still has
+                // to go somewhere.
+                let rest = self.builder.create_block();
+                self.builder.switch_to_block(rest);
+            }
+            _ => return None,
+        }
+        Some(())
+    }
+
+    /// Jumps to `then_block` if `condition` is truthy, else to
+    /// `else_block`.
+    fn branch(&mut self, condition: ExprId, then_block: Block, else_block: Block) -> Option<()> {
+        match self.expression(condition)? {
+            (value, Kind::Boolean) => {
+                self.builder
+                    .ins()
+                    .brif(value, then_block, &[], else_block, &[]);
+            }
+            // Every number is truthy.
+            (_, Kind::Number) => {
+                self.builder.ins().jump(then_block, &[]);
+            }
+        }
+        Some(())
+    }
+
+    fn number(&mut self, expr: ExprId) -> Option<Ir> {
+        match self.expression(expr)? {
+            (value, Kind::Number) => Some(value),
+            _ => None,
+        }
+    }
+
+    fn expression(&mut self, expr: ExprId) -> Option<(Ir, Kind)> {
+        match self.ast.expr(expr) {
+            Expr::Literal(Literal::Number(n)) => {
+                Some((self.builder.ins().f64const(*n), Kind::Number))
+            }
+            Expr::Literal(Literal::Boolean(b)) => Some((
+                self.builder.ins().iconst(types::I8, *b as i64),
+                Kind::Boolean,
+            )),
+            Expr::Grouping(inner) => self.expression(*inner),
+            Expr::Variable(name) => {
+                let local = self.local(name.symbol)??;
+                Some((self.builder.use_var(local.variable), local.kind))
+            }
+            Expr::Assign(name, value) => {
+                let local = self.local(name.symbol)??;
+                let (value, kind) = self.expression(*value)?;
+                if local.constant || kind != local.kind {
+                    return None;
+                }
+                self.builder.def_var(local.variable, value);
+                Some((value, kind))
+            }
+            Expr::Unary(op, right) => {
+                let (right, kind) = self.expression(*right)?;
+                match (op.token_type, kind) {
+                    (TokenType::Minus, Kind::Number) => {
+                        Some((self.builder.ins().fneg(right), kind))
+                    }
+                    (TokenType::Bang, Kind::Boolean) => {
+                        Some((self.builder.ins().icmp_imm(IntCC::Equal, right, 0), kind))
+                    }
+                    _ => None,
+                }
+            }
+            Expr::Binary(left, op, right) => self.binary(*left, op.token_type, *right),
+            Expr::Logical(left, op, right) => {
+                let left = self.boolean(*left)?;
+                let right_block = self.builder.create_block();
+                let after = self.builder.create_block();
+                let result = self.builder.append_block_param(after, types::I8);
+                if op.token_type == TokenType::Or {
+                    self.builder
+                        .ins()
+                        .brif(left, after, &[left], right_block, &[]);
+                } else {
+                    self.builder
+                        .ins()
+                        .brif(left, right_block, &[], after, &[left]);
+                }
+                self.builder.switch_to_block(right_block);
+                let right = self.boolean(*right)?;
+                self.builder.ins().jump(after, &[right]);
+                self.builder.switch_to_block(after);
+                Some((result, Kind::Boolean))
+            }
+            Expr::Call(callee, _, arguments) => self.call(*callee, arguments),
+            _ => None,
+        }
+    }
+
+    fn boolean(&mut self, expr: ExprId) -> Option<Ir> {
+        match self.expression(expr)? {
+            (value, Kind::Boolean) => Some(value),
+            _ => None,
+        }
+    }
+
+    fn binary(&mut self, left: ExprId, op: TokenType, right: ExprId) -> Option<(Ir, Kind)> {
+        let (left, kind) = self.expression(left)?;
+        let (right, right_kind) = self.expression(right)?;
+        if kind != right_kind {
+            return None;
+        }
+        let ins = self.builder.ins();
+        let number = match (op, kind) {
+            (TokenType::Plus, Kind::Number) => ins.fadd(left, right),
+            (TokenType::Minus, Kind::Number) => ins.fsub(left, right),
+            (TokenType::Star, Kind::Number) => ins.fmul(left, right),
+            (TokenType::Slash, Kind::Number) => {
+                let zero = ins.f64const(0.0);
+                let by_zero = self.builder.ins().fcmp(FloatCC::Equal, right, zero);
+                self.fail_if(by_zero);
+                self.builder.ins().fdiv(left, right)
+            }
+            (TokenType::EqualEqual, Kind::Boolean) => {
+                return Some((ins.icmp(IntCC::Equal, left, right), Kind::Boolean));
+            }
+            (TokenType::BangEqual, Kind::Boolean) => {
+                return Some((ins.icmp(IntCC::NotEqual, left, right), Kind::Boolean));
+            }
+            (_, Kind::Number) => {
+                let condition = match op {
+                    TokenType::EqualEqual => FloatCC::Equal,
+                    TokenType::BangEqual => FloatCC::NotEqual,
+                    TokenType::Greater => FloatCC::GreaterThan,
+                    TokenType::GreaterEqual => FloatCC::GreaterThanOrEqual,
+                    TokenType::Less => FloatCC::LessThan,
+                    TokenType::LessEqual => FloatCC::LessThanOrEqual,
+                    _ => return None,
+                };
+                return Some((ins.fcmp(condition, left, right), Kind::Boolean));
+            }
+            _ => return None,
+        };
+        Some((number, Kind::Number))
+    }
+
+    /// A call to a global function that can be compiled too.
+    fn call(&mut self, callee: ExprId, arguments: &[ExprId]) -> Option<(Ir, Kind)> {
+        let Expr::Variable(name) = self.ast.expr(callee) else {
+            return None;
+        };
+        if self.local(name.symbol).is_some() {
+            return None;
+        }
+        let (key, function) = global(self.batch.interpreter, name.symbol)?;
+        if function.arity() != arguments.len() {
+            return None;
+        }
+        let mut values = Vec::new();
+        for &argument in arguments {
+            values.push(self.number(argument)?);
+        }
+        let slot = self.builder.create_sized_stack_slot(StackSlotData::new(
+            StackSlotKind::ExplicitSlot,
+            (values.len().max(1) * 8) as u32,
+            3,
+        ));
+        for (i, value) in values.into_iter().enumerate() {
+            self.builder.ins().stack_store(value, slot, (i * 8) as i32);
+        }
+        let pointer = self.pointer();
+        let arguments = self.builder.ins().stack_addr(pointer, slot, 0);
+        let call = match self.batch.entries.get(&key).map(|entry| &entry.state) {
+            Some(State::Unsupported) => return None,
+            Some(State::Compiled(compiled)) => {
+                let signature = self.builder.import_signature(self.batch.signature.clone());
+                let address = self
+                    .builder
+                    .ins()
+                    .iconst(pointer, compiled.code as usize as i64);
+                self.builder
+                    .ins()
+                    .call_indirect(signature, address, &[arguments, self.context])
+            }
+            _ => {
+                let id = self.batch.declare(key, function)?;
+                let reference = self
+                    .batch
+                    .module
+                    .declare_func_in_func(id, self.builder.func);
+                self.builder
+                    .ins()
+                    .call(reference, &[arguments, self.context])
+            }
+        };
+        let result = self.builder.inst_results(call)[0];
+        if !self.callees.contains(&(name.symbol, key)) {
+            self.callees.push((name.symbol, key));
+        }
+        let failed = self.load(types::I8, offset_of!(Context, failed));
+        self.fail_if(failed);
+        Some((result, Kind::Number))
+    }
+
+    /// The local `name`, if it's in scope: `Some(None)` if it is but can't
+    /// be compiled.
+    fn local(&self, name: Symbol) -> Option<Option<Local>> {
+        self.scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(&name).copied())
+    }
+
+    fn declare(&mut self, name: Symbol, value: Ir, kind: Kind, constant: bool) {
+        let variable = Variable::from_u32(self.variables);
+        self.variables += 1;
+        self.builder.declare_var(variable, kind.ir());
+        self.builder.def_var(variable, value);
+        let local = Local {
+            variable,
+            kind,
+            constant,
+        };
+        self.scopes
+            .last_mut()
+            .expect("a function has a scope")
+            .insert(name, Some(local));
+    }
+
+    /// Gives up on the call if `condition` is nonzero.
+    fn fail_if(&mut self, condition: Ir) {
+        let next = self.builder.create_block();
+        self.builder
+            .ins()
+            .brif(condition, self.fail, &[], next, &[]);
+        self.builder.switch_to_block(next);
+    }
+
+    fn load(&mut self, ty: Type, offset: usize) -> Ir {
+        self.builder
+            .ins()
+            .load(ty, MemFlags::trusted(), self.context, offset as i32)
+    }
+
+    fn store(&mut self, value: Ir, offset: usize) {
+        self.builder
+            .ins()
+            .store(MemFlags::trusted(), value, self.context, offset as i32);
+    }
+
+    fn pointer(&self) -> Type {
+        self.batch.module.target_config().pointer_type()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        folder::parse_and_fold, parser::Parser, playground::eval, scanner::Scanner,
+        source::SourceId,
+    };
+
+    /// How many functions have been compiled on this thread.
+    fn compiled() -> usize {
+        JIT.with(|jit| {
+            jit.borrow()
+                .entries
+                .values()
+                .filter(|entry| matches!(entry.state, State::Compiled(_)))
+                .count()
+        })
+    }
+
+    #[test]
+    fn compiles_a_hot_recursive_function() {
+        let source = "fun fib(n) { if (n < 2) return n; return fib(n - 1) + fib(n - 2); }\n\
+                      print fib(20);\n";
+        assert_eq!(eval(source), "6765\n");
+        assert_eq!(compiled(), 1);
+    }
+
+    #[test]
+    fn compiles_loops_locals_and_callees() {
+        let source = "fun square(x) { return x * x; }\n\
+                      fun sum(n) {\n\
+                        var total = 0;\n\
+                        var i = 1;\n\
+                        while (i <= n and !(total > 1000000)) {\n\
+                          total = total + square(i) / 2;\n\
+                          i = i + 1;\n\
+                        }\n\
+                        return total;\n\
+                      }\n\
+                      var result = 0;\n\
+                      for (var k = 0; k < 100; k = k + 1) result = sum(10);\n\
+                      print result;\n";
+        assert_eq!(eval(source), "192.5\n");
+        assert_eq!(compiled(), 2);
+    }
+
+    #[test]
+    fn leaves_errors_to_the_tree_walker() {
+        let source = "fun inverse(x) { return 1 / x; }\n\
+                      for (var i = 0; i < 100; i = i + 1) inverse(i + 1);\n\
+                      print inverse(0);\n";
+        assert!(eval(source).contains("Division by zero."));
+        assert_eq!(compiled(), 1);
+
+        let source = "fun deep(n) { return deep(n + 1); }\n\
+                      print deep(0);\n";
+        assert!(eval(source).contains("Stack overflow."));
+    }
+
+    #[test]
+    fn leaves_other_values_to_the_tree_walker() {
+        let source = "fun twice(x) { return x + x; }\n\
+                      for (var i = 0; i < 100; i = i + 1) twice(i);\n\
+                      print twice(\"ab\");\n\
+                      fun greet(x) { print x; return 0; }\n\
+                      for (var i = 0; i < 100; i = i + 1) greet(i);\n";
+        let output = eval(source);
+        assert!(output.starts_with("abab\n0\n1\n"));
+        assert!(output.ends_with("99\n"));
+        assert_eq!(compiled(), 1);
+    }
+
+    #[test]
+    fn notices_a_callee_being_redefined() {
+        let source = "fun step(x) { return x + 1; }\n\
+                      fun walk(x) { return step(x) * 2; }\n\
+                      for (var i = 0; i < 100; i = i + 1) walk(i);\n\
+                      fun step(x) { return x + 10; }\n\
+                      print walk(1);\n";
+        assert_eq!(eval(source), "22\n");
+    }
+
+    #[test]
+    fn lets_go_of_scripts_it_has_not_compiled() {
+        // Each script redefines `id`, so only the last one's syntax tree is
+        // still in use.
+        let mut interpreter = Interpreter::new();
+        for _ in 0..100 {
+            let tokens = Scanner::new("fun id(x) { return x; } id(1);", SourceId::ANONYMOUS);
+            let (ast, statements) = parse_and_fold(Parser::new(tokens.tokens()));
+            interpreter.interpret(&ast, statements);
+        }
+        JIT.with(|jit| {
+            let jit = jit.borrow();
+            assert!(jit.entries.len() < 100);
+            let live = jit
+                .entries
+                .values()
+                .filter(|entry| entry.ast.strong_count() > 0);
+            assert_eq!(live.count(), 1);
+        });
+    }
+}
@@ -0,0 +1,990 @@
+//! `jlox compile --target=js`: lowers a script to JavaScript that reads
+//! much like the Lox it came from, for running programs where only a JS
+//! runtime is available.
+//!
+//! Declarations map one to one: `fun` to `function`, `class` to `class`,
+//! `var` to `var` at the top level and `let` in blocks, so closures
+//! capture the same variables. What JavaScript does differently goes
+//! through small helpers defined ahead of the program: `print`, which
+//! shows values as Lox does; `+`, which in Lox only joins two strings or
+//! adds two numbers; and the places Lox's truthiness differs (`0` and `""`
+//! are true in Lox). So do the natives that have a JavaScript equivalent.
+//! Calling a class declared in the script constructs it with `new`.
+//!
+//! Imports become ES module imports of the `.js` file compiled from the
+//! imported script. What has no counterpart, such as an import of every
+//! export or a native like `sleep`, is reported as an error rather
+//! than compiled into something that behaves differently. Quantities
+//! with units compile to their plain number.
+
+use std::{
+    collections::{BTreeSet, HashSet},
+    fs,
+    sync::atomic::Ordering,
+};
+
+use crate::{
+    arena::Arena,
+    diagnostics,
+    expression::{Expr, ExprId},
+    json, natives,
+    output::StdoutSink,
+    parser::Parser,
+    scanner::Scanner,
+    source::SourceId,
+    statement::{ImportTarget, Stmt, StmtId},
+    token::{Literal, Token, TokenType},
+};
+
+const INDENT: &str = "    ";
+
+/// Definitions the output may need, with the others each one uses,
+/// written ahead of it in this order when it does. Helpers start with
+/// `__lox_`, which a Lox name gets a `_` after (see `name_of`).
+const PRELUDE: &[(&str, &[&str], &str)] = &[
+    // Lox's instances are JavaScript objects that aren't arrays or ranges.
+    (
+        "__lox_instance",
+        &[],
+        "function __lox_instance(value) {\n    \
+         return value instanceof Object && typeof value !== \"function\" && \
+         !Array.isArray(value) &&\n        \
+         value.constructor?.name !== \"__lox_Range\";\n}",
+    ),
+    // How `print` shows a value, as `Value`'s `Display` does.
+    (
+        "__lox_str",
+        &["__lox_instance"],
+        "function __lox_str(value) {\n    \
+         if (value == null) return \"none\";\n    \
+         if (Array.isArray(value)) return `[${value.map(__lox_str).join(\", \")}]`;\n    \
+         if (typeof value === \"function\") {\n        \
+         return /^class\\b/.test(Function.prototype.toString.call(value)) ? \"class\" : \"function\";\n    \
+         }\n    \
+         return __lox_instance(value) ? \"instance\" : String(value);\n}",
+    ),
+    // An instance with a `toString` method shows as what it returns.
+    (
+        "__lox_display",
+        &["__lox_instance"],
+        "function __lox_display(value) {\n    \
+         if (__lox_instance(value) && typeof value.toString === \"function\" &&\n        \
+         value.toString !== Object.prototype.toString && value.toString.length === 0) {\n        \
+         return value.toString();\n    \
+         }\n    \
+         return value;\n}",
+    ),
+    (
+        "__lox_print",
+        &["__lox_str", "__lox_display"],
+        "function __lox_print(value) {\n    console.log(__lox_str(__lox_display(value)));\n}",
+    ),
+    // `+` only joins two strings or adds two numbers.
+    (
+        "__lox_add",
+        &["__lox_str", "__lox_display"],
+        "function __lox_add(left, right) {\n    \
+         if (typeof left === \"string\") right = __lox_display(right);\n    \
+         if (typeof right === \"string\") left = __lox_display(left);\n    \
+         if (typeof left === typeof right && (typeof left === \"string\" || typeof left === \"number\")) {\n        \
+         return left + right;\n    \
+         }\n    \
+         throw new TypeError(`${__lox_str(left)} and ${__lox_str(right)} must both be strings or both be numbers`);\n}",
+    ),
+    (
+        "__lox_truthy",
+        &[],
+        "function __lox_truthy(value) {\n    return value != null && value !== false;\n}",
+    ),
+    (
+        "__lox_items",
+        &[],
+        "function __lox_items(value) {\n    \
+         if (value instanceof Object && Symbol.iterator in value) return value;\n    \
+         if (value instanceof Object) return Object.keys(value).sort();\n    \
+         throw new TypeError(\"Only lists, ranges and instances can be iterated.\");\n}",
+    ),
+    (
+        "__lox_Range",
+        &[],
+        "class __lox_Range {\n    constructor(start, end, inclusive) {\n        \
+         Object.assign(this, { start, end, inclusive });\n    }\n    \
+         *[Symbol.iterator]() {\n        \
+         for (let n = this.start; this.inclusive ? n <= this.end : n < this.end; n++) \
+         yield n;\n    }\n    \
+         toString() {\n        \
+         return `${this.start}..${this.inclusive ? \"=\" : \"\"}${this.end}`;\n    }\n}",
+    ),
+    (
+        "__lox_and",
+        &["__lox_truthy"],
+        "function __lox_and(left, right) {\n    return __lox_truthy(left) ? right() : left;\n}",
+    ),
+    (
+        "__lox_or",
+        &["__lox_truthy"],
+        "function __lox_or(left, right) {\n    return __lox_truthy(left) ? left : right();\n}",
+    ),
+    ("clock", &[], "const clock = () => Date.now() / 1000;"),
+    ("clockMillis", &[], "const clockMillis = () => Date.now();"),
+    // Counts characters rather than UTF-16 units, as Lox does.
+    ("len", &[], "const len = (value) => [...value].length;"),
+    ("sqrt", &[], "const sqrt = Math.sqrt;"),
+    ("abs", &[], "const abs = Math.abs;"),
+    ("floor", &[], "const floor = Math.floor;"),
+    ("ceil", &[], "const ceil = Math.ceil;"),
+    ("pow", &[], "const pow = Math.pow;"),
+    ("min", &[], "const min = Math.min;"),
+    ("max", &[], "const max = Math.max;"),
+    ("sin", &[], "const sin = Math.sin;"),
+    ("cos", &[], "const cos = Math.cos;"),
+    ("PI", &[], "const PI = Math.PI;"),
+    ("E", &[], "const E = Math.E;"),
+    // `typeof` is reserved in JavaScript, so a Lox call to it names
+    // `typeof_`.
+    (
+        "typeof",
+        &["__lox_instance"],
+        "function typeof_(value) {\n    \
+         if (value == null) return \"nil\";\n    \
+         if (Array.isArray(value)) return \"list\";\n    \
+         if (typeof value === \"function\") {\n        \
+         return /^class\\b/.test(Function.prototype.toString.call(value)) ? \"class\" : \"function\";\n    \
+         }\n    \
+         if (value.constructor?.name === \"__lox_Range\") return \"range\";\n    \
+         return __lox_instance(value) ? \"instance\" : typeof value;\n}",
+    ),
+    // Nil for anything that isn't a finite number, where `Number` would
+    // take "" and hex too.
+    (
+        "num",
+        &["__lox_str"],
+        "function num(value) {\n    \
+         if (typeof value === \"number\") return value;\n    \
+         if (typeof value !== \"string\") {\n        \
+         throw new TypeError(`Argument 1 to 'num' must be a string, got ${__lox_str(value)}.`);\n    \
+         }\n    \
+         const text = value.trim();\n    \
+         if (!/^[+-]?(\\d+\\.?\\d*|\\.\\d+)(e[+-]?\\d+)?$/i.test(text)) return null;\n    \
+         const n = Number(text);\n    \
+         return Number.isFinite(n) ? n : null;\n}",
+    ),
+    (
+        "toFixed",
+        &["__lox_str"],
+        "function toFixed(n, digits) {\n    \
+         for (const [i, arg] of [n, digits].entries()) {\n        \
+         if (typeof arg !== \"number\") {\n            \
+         throw new TypeError(`Argument ${i + 1} to 'toFixed' must be a number, got ${__lox_str(arg)}.`);\n        \
+         }\n    \
+         }\n    \
+         if (!Number.isInteger(digits) || digits < 0 || digits > 100) {\n        \
+         throw new RangeError(\"Digits for 'toFixed' must be an integer between 0 and 100.\");\n    \
+         }\n    \
+         return n.toFixed(digits);\n}",
+    ),
+];
+
+/// Words JavaScript reserves that are ordinary names in Lox. A Lox name
+/// that is one gets a trailing `_`.
+const RESERVED: &[&str] = &[
+    "arguments",
+    "await",
+    "break",
+    "case",
+    "catch",
+    "const",
+    "continue",
+    "debugger",
+    "default",
+    "delete",
+    "do",
+    "enum",
+    "eval",
+    "extends",
+    "finally",
+    "function",
+    "implements",
+    "in",
+    "instanceof",
+    "interface",
+    "let",
+    "new",
+    "null",
+    "package",
+    "private",
+    "protected",
+    "public",
+    "static",
+    "switch",
+    "throw",
+    "try",
+    "typeof",
+    "undefined",
+    "void",
+    "with",
+    "yield",
+];
+
+/// Prints the JavaScript compiled from the script at `path`, returning the
+/// exit code: 66 if it can't be read and 65 if it has errors or uses
+/// something JavaScript can't express.
+pub fn run(path: &str) -> i32 {
+    let source = match fs::read_to_string(path) {
+        Ok(source) => source,
+        Err(e) => {
+            println!("Could not read {}: {}.", path, e);
+            return 66;
+        }
+    };
+    diagnostics::reset();
+    let tokens = Scanner::new(&source, SourceId::register(path)).tokens();
+    let mut parser = Parser::new(tokens);
+    let statements = parser.parse();
+    let output = if crate::HAD_ERROR.load(Ordering::Relaxed) {
+        None
+    } else {
+        Some(compile(&parser.into_arena(), &statements))
+    };
+    diagnostics::flush(&StdoutSink);
+    match output {
+        Some(output) if !crate::HAD_ERROR.load(Ordering::Relaxed) => {
+            print!("{}", output);
+            0
+        }
+        _ => 65,
+    }
+}
+
+/// The program as JavaScript. Constructs it can't express are reported as
+/// errors.
+pub fn compile(arena: &Arena, statements: &[StmtId]) -> String {
+    let mut compiler = Compiler {
+        arena,
+        out: String::new(),
+        indent: 0,
+        prefix: "",
+        declared: HashSet::new(),
+        classes: HashSet::new(),
+        prelude: BTreeSet::new(),
+        in_method: false,
+    };
+    for &statement in statements {
+        compiler.declare(statement);
+    }
+    for &statement in statements {
+        compiler.stmt(statement);
+    }
+    let mut text = String::new();
+    for &index in &compiler.prelude {
+        text.push_str(PRELUDE[index].2);
+        text.push('\n');
+    }
+    if !compiler.prelude.is_empty() {
+        text.push('\n');
+    }
+    text.push_str(&compiler.out);
+    text
+}
+
+struct Compiler<'a> {
+    arena: &'a Arena,
+    out: String,
+    indent: usize,
+    /// Written at the start of the next line, for `export`.
+    prefix: &'static str,
+    /// Every name the program declares, which hides a native of the same
+    /// name.
+    declared: HashSet<&'static str>,
+    /// Every class the program declares, which calls construct with `new`.
+    classes: HashSet<&'static str>,
+    /// Indices into `PRELUDE` of what the output uses.
+    prelude: BTreeSet<usize>,
+    /// Whether a method's body is being written, where `this` is the
+    /// method's.
+    in_method: bool,
+}
+
+impl Compiler<'_> {
+    /// Records the names `id` declares, in it and anywhere inside it.
+    fn declare(&mut self, id: StmtId) {
+        match self.arena.stmt(id) {
+            Stmt::Var(name, _) => {
+                self.declared.insert(name.lexeme());
+            }
+            Stmt::Const(name, _) => {
+                self.declared.insert(name.lexeme());
+            }
+            Stmt::Destructure(names, _) => {
+                self.declared.extend(names.iter().map(Token::lexeme));
+            }
+            Stmt::Block(statements) => statements.iter().for_each(|s| self.declare(*s)),
+            Stmt::If(_, then_branch, else_branch) => {
+                self.declare(*then_branch);
+                if let Some(else_branch) = else_branch {
+                    self.declare(*else_branch);
+                }
+            }
+            Stmt::While(_, body) => self.declare(*body),
+            Stmt::ForIn(name, _, body) => {
+                self.declared.insert(name.lexeme());
+                self.declare(*body);
+            }
+            Stmt::Function(name, params, body) => {
+                self.declared.insert(name.lexeme());
+                self.declared.extend(params.iter().map(Token::lexeme));
+                body.iter().for_each(|s| self.declare(*s));
+            }
+            Stmt::Class(name, methods) => {
+                self.declared.insert(name.lexeme());
+                self.classes.insert(name.lexeme());
+                methods.iter().for_each(|s| self.declare(*s));
+            }
+            Stmt::Import(_, _, ImportTarget::Alias(alias) | ImportTarget::Lazy(alias)) => {
+                self.declared.insert(alias.lexeme());
+            }
+            Stmt::Import(_, _, ImportTarget::Names(names)) => {
+                self.declared.extend(names.iter().map(Token::lexeme));
+            }
+            Stmt::Export(_, declaration) => self.declare(*declaration),
+            Stmt::Expression(_)
+            | Stmt::Print(..)
+            | Stmt::Return(..)
+            | Stmt::Import(_, _, ImportTarget::All) => {}
+        }
+    }
+
+    fn line(&mut self, text: &str) {
+        for _ in 0..self.indent {
+            self.out.push_str(INDENT);
+        }
+        self.out.push_str(self.prefix);
+        self.prefix = "";
+        self.out.push_str(text);
+        self.out.push('\n');
+    }
+
+    /// Defines `name` ahead of the output, with what it uses, if it's in
+    /// `PRELUDE`.
+    fn use_prelude(&mut self, name: &str) -> bool {
+        match PRELUDE.iter().position(|(defined, ..)| *defined == name) {
+            Some(index) => {
+                if self.prelude.insert(index) {
+                    for needed in PRELUDE[index].1 {
+                        self.use_prelude(needed);
+                    }
+                }
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn stmt(&mut self, id: StmtId) {
+        match self.arena.stmt(id) {
+            Stmt::Expression(expression) => {
+                let expression = self.expr(*expression);
+                self.line(&format!("{};", expression));
+            }
+            Stmt::Print(_, expression) => {
+                self.use_prelude("__lox_print");
+                let expression = self.expr(*expression);
+                self.line(&format!("__lox_print({});", expression));
+            }
+            Stmt::Var(name, initializer) => {
+                let keyword = if self.indent == 0 { "var" } else { "let" };
+                match initializer {
+                    Some(initializer) => {
+                        let initializer = self.expr(*initializer);
+                        self.line(&format!("{} {} = {};", keyword, name_of(name), initializer));
+                    }
+                    None => self.line(&format!("{} {};", keyword, name_of(name))),
+                }
+            }
+            Stmt::Const(name, initializer) => {
+                let initializer = self.expr(*initializer);
+                self.line(&format!("const {} = {};", name_of(name), initializer));
+            }
+            Stmt::Destructure(names, initializer) => {
+                let keyword = if self.indent == 0 { "var" } else { "let" };
+                let names: Vec<String> = names.iter().map(name_of).collect();
+                let initializer = self.expr(*initializer);
+                self.line(&format!(
+                    "{} [{}] = {};",
+                    keyword,
+                    names.join(", "),
+                    initializer
+                ));
+            }
+            Stmt::Block(statements) => {
+                self.line("{");
+                self.body(statements);
+                self.line("}");
+            }
+            Stmt::If(..) => {
+                self.if_chain(id, "");
+                self.line("}");
+            }
+            Stmt::While(condition, body) => {
+                let condition = self.condition(*condition);
+                self.line(&format!("while ({}) {{", condition));
+                self.branch(*body);
+                self.line("}");
+            }
+            Stmt::ForIn(name, iterable, body) => {
+                self.use_prelude("__lox_items");
+                let iterable = self.expr(*iterable);
+                self.line(&format!(
+                    "for (let {} of __lox_items({})) {{",
+                    name_of(name),
+                    iterable
+                ));
+                self.branch(*body);
+                self.line("}");
+            }
+            // A `function` would get a `this` of its own, so one declared
+            // in a method is an arrow function, which sees the method's.
+            Stmt::Function(name, params, body) if self.in_method => {
+                self.function(&format!("const {} = ", name_of(name)), params, body, true)
+            }
+            Stmt::Function(name, params, body) => {
+                self.function(&format!("function {}", name_of(name)), params, body, false)
+            }
+            Stmt::Return(_, Some(value)) => {
+                let value = self.expr(*value);
+                self.line(&format!("return {};", value));
+            }
+            Stmt::Return(_, None) => self.line("return;"),
+            Stmt::Class(name, methods) => {
+                if methods.is_empty() {
+                    self.line(&format!("class {} {{}}", name_of(name)));
+                    return;
+                }
+                self.line(&format!("class {} {{", name_of(name)));
+                self.indent += 1;
+                let in_method = std::mem::replace(&mut self.in_method, true);
+                for method in methods {
+                    if let Stmt::Function(name, params, body) = self.arena.stmt(*method) {
+                        // JavaScript would take a method by this name for
+                        // the constructor.
+                        let name = match name.lexeme() {
+                            "constructor" => "constructor_",
+                            name => name,
+                        };
+                        self.function(name, params, body, false);
+                    }
+                }
+                self.in_method = in_method;
+                self.indent -= 1;
+                self.line("}");
+            }
+            Stmt::Import(keyword, path, target) => self.import(keyword, path, target),
+            Stmt::Export(_, declaration) => {
+                self.prefix = "export ";
+                self.stmt(*declaration);
+            }
+        }
+    }
+
+    /// Statements inside braces that have already been written.
+    fn body(&mut self, statements: &[StmtId]) {
+        self.indent += 1;
+        for &statement in statements {
+            self.stmt(statement);
+        }
+        self.indent -= 1;
+    }
+
+    /// The branch of an `if` or body of a `while`, whose braces are written
+    /// by the caller. A block's statements go inside them directly.
+    fn branch(&mut self, id: StmtId) {
+        match self.arena.stmt(id) {
+            Stmt::Block(statements) => self.body(statements),
+            _ => self.body(&[id]),
+        }
+    }
+
+    /// Writes `if` and any `else if` following it up to, but not
+    /// including, the closing brace.
+    fn if_chain(&mut self, id: StmtId, before: &str) {
+        let Stmt::If(condition, then_branch, else_branch) = self.arena.stmt(id) else {
+            unreachable!("if_chain is only called with an if");
+        };
+        let condition = self.condition(*condition);
+        self.line(&format!("{}if ({}) {{", before, condition));
+        self.branch(*then_branch);
+        match else_branch.map(|else_branch| (else_branch, self.arena.stmt(else_branch))) {
+            Some((else_branch, Stmt::If(..))) => self.if_chain(else_branch, "} else "),
+            Some((else_branch, _)) => {
+                self.line("} else {");
+                self.branch(else_branch);
+            }
+            None => {}
+        }
+    }
+
+    /// Writes `head(params) { body }`, or `head(params) => { body };` as an
+    /// arrow function.
+    fn function(&mut self, head: &str, params: &[Token], body: &[StmtId], arrow: bool) {
+        let params: Vec<String> = params.iter().map(name_of).collect();
+        let (arrow, end) = if arrow { (" =>", ";") } else { ("", "") };
+        let head = format!("{}({}){}", head, params.join(", "), arrow);
+        if body.is_empty() {
+            self.line(&format!("{} {{}}{}", head, end));
+            return;
+        }
+        self.line(&format!("{} {{", head));
+        self.body(body);
+        self.line(&format!("}}{}", end));
+    }
+
+    fn import(&mut self, keyword: &Token, path: &Token, target: &ImportTarget) {
+        if self.indent > 0 {
+            crate::error_at_token(keyword, "Only top-level imports compile to JavaScript.");
+            return;
+        }
+        let from = json::quote(&module_path(&path.literal().to_string()));
+        match target {
+            ImportTarget::Alias(alias) | ImportTarget::Lazy(alias) => {
+                self.line(&format!("import * as {} from {};", name_of(alias), from))
+            }
+            ImportTarget::Names(names) => {
+                // Exports are renamed as they're declared, so the names
+                // match.
+                let names: Vec<String> = names.iter().map(name_of).collect();
+                self.line(&format!("import {{ {} }} from {};", names.join(", "), from))
+            }
+            ImportTarget::All => crate::error_at_token(
+                keyword,
+                "Can't import every export in JavaScript; list the names or use 'as'.",
+            ),
+        }
+    }
+
+    /// `id` as the condition of an `if` or `while`.
+    fn condition(&mut self, id: ExprId) -> String {
+        let condition = self.expr(id);
+        if self.is_boolean(id) {
+            condition
+        } else {
+            self.use_prelude("__lox_truthy");
+            format!("__lox_truthy({})", condition)
+        }
+    }
+
+    fn expr(&mut self, id: ExprId) -> String {
+        match self.arena.expr(id) {
+            Expr::Grouping(expression) => format!("({})", self.expr(*expression)),
+            Expr::Binary(left, operator, right) => {
+                if let TokenType::Operator(_) = operator.token_type {
+                    crate::error_at_token(
+                        operator,
+                        "Registered operators have no JavaScript equivalent.",
+                    );
+                }
+                if let TokenType::DotDot | TokenType::DotDotEqual = operator.token_type {
+                    self.use_prelude("__lox_Range");
+                    let inclusive = operator.token_type == TokenType::DotDotEqual;
+                    return format!(
+                        "new __lox_Range({}, {}, {})",
+                        self.expr(*left),
+                        self.expr(*right),
+                        inclusive
+                    );
+                }
+                if operator.token_type == TokenType::Plus && !self.same_operands(*left, *right) {
+                    self.use_prelude("__lox_add");
+                    return format!("__lox_add({}, {})", self.expr(*left), self.expr(*right));
+                }
+                // `nil` is compared loosely so that it also equals what a
+                // function that returns nothing gives back.
+                let nil = self.is_nil(*left) || self.is_nil(*right);
+                let operator = match operator.lexeme() {
+                    "==" if nil => "==",
+                    "!=" if nil => "!=",
+                    "==" => "===",
+                    "!=" => "!==",
+                    operator => operator,
+                };
+                format!("{} {} {}", self.expr(*left), operator, self.expr(*right))
+            }
+            Expr::Unary(operator, right) => {
+                let operand = self.expr(*right);
+                match operator.lexeme() {
+                    "!" if self.is_boolean(*right) => format!("!{}", operand),
+                    "!" => {
+                        self.use_prelude("__lox_truthy");
+                        format!("!__lox_truthy({})", operand)
+                    }
+                    // Keeps `- -x` from becoming a decrement.
+                    "-" if operand.starts_with('-') => format!("-({})", operand),
+                    operator => format!("{}{}", operator, operand),
+                }
+            }
+            Expr::Assign(name, value) => format!("{} = {}", name_of(name), self.expr(*value)),
+            Expr::Logical(left, operator, right) => {
+                let is_and = operator.token_type == TokenType::And;
+                if self.is_boolean(*left) && self.is_boolean(*right) {
+                    let operator = if is_and { "&&" } else { "||" };
+                    return format!("{} {} {}", self.expr(*left), operator, self.expr(*right));
+                }
+                let helper = if is_and { "__lox_and" } else { "__lox_or" };
+                self.use_prelude(helper);
+                format!(
+                    "{}({}, () => {})",
+                    helper,
+                    self.expr(*left),
+                    self.expr(*right)
+                )
+            }
+            Expr::Call(callee, _, arguments) => {
+                let new = match self.arena.expr(*callee) {
+                    Expr::Variable(name) if self.classes.contains(name.lexeme()) => "new ",
+                    _ => "",
+                };
+                let callee = self.expr(*callee);
+                let arguments: Vec<String> = arguments
+                    .iter()
+                    .map(|argument| self.expr(*argument))
+                    .collect();
+                format!("{}{}({})", new, callee, arguments.join(", "))
+            }
+            Expr::Get(object, name) => format!("{}.{}", self.expr(*object), name.lexeme()),
+            Expr::Set(object, name, value) => format!(
+                "{}.{} = {}",
+                self.expr(*object),
+                name.lexeme(),
+                self.expr(*value)
+            ),
+            // A method's, as methods are written as methods of a class.
+            Expr::Variable(name) if name.token_type == TokenType::This => "this".to_string(),
+            Expr::Variable(name) => {
+                let lexeme = name.lexeme();
+                if !self.declared.contains(lexeme)
+                    && natives::lookup(lexeme).is_some()
+                    && !self.use_prelude(lexeme)
+                {
+                    crate::error_at_token(
+                        name,
+                        &format!("The native '{}' has no JavaScript equivalent.", lexeme),
+                    );
+                }
+                name_of(name)
+            }
+            Expr::Literal(value) => literal(value),
+            Expr::Tuple(_, values) => {
+                let values: Vec<String> = values.iter().map(|value| self.expr(*value)).collect();
+                format!("[{}]", values.join(", "))
+            }
+        }
+    }
+}
+
+/// The name a Lox variable gets in JavaScript.
+fn name_of(name: &Token) -> String {
+    let lexeme = name.lexeme();
+    if RESERVED.contains(&lexeme) || lexeme.starts_with("__lox_") {
+        format!("{}_", lexeme)
+    } else {
+        lexeme.to_string()
+    }
+}
+
+fn literal(value: &Literal) -> String {
+    match value {
+        Literal::String(s) => json::quote(s),
+        Literal::Number(n) => n.to_string(),
+        Literal::Boolean(b) => b.to_string(),
+        Literal::None => "null".to_string(),
+        #[cfg(feature = "units")]
+        Literal::Quantity(n, unit) => format!("{} /* {} */", n, unit.symbol),
+    }
+}
+
+impl Compiler<'_> {
+    /// Whether `id` always evaluates to a boolean, so JavaScript's
+    /// truthiness agrees with Lox's and it needs no helper.
+    fn is_boolean(&self, id: ExprId) -> bool {
+        match self.arena.expr(id) {
+            Expr::Literal(Literal::Boolean(_)) => true,
+            Expr::Grouping(expression) => self.is_boolean(*expression),
+            Expr::Unary(operator, _) => operator.token_type == TokenType::Bang,
+            Expr::Binary(_, operator, _) => matches!(
+                operator.token_type,
+                TokenType::EqualEqual
+                    | TokenType::BangEqual
+                    | TokenType::Less
+                    | TokenType::LessEqual
+                    | TokenType::Greater
+                    | TokenType::GreaterEqual
+            ),
+            Expr::Logical(left, _, right) => self.is_boolean(*left) && self.is_boolean(*right),
+            _ => false,
+        }
+    }
+
+    /// Whether `left` and `right` are both numbers or both strings
+    /// whatever they evaluate to, so JavaScript's `+` does what Lox's
+    /// does with them.
+    fn same_operands(&self, left: ExprId, right: ExprId) -> bool {
+        matches!(
+            (self.operand(left), self.operand(right)),
+            (Some(left), Some(right)) if left == right
+        )
+    }
+
+    /// Whether `id` always evaluates to a number (`Some(true)`) or a
+    /// string (`Some(false)`).
+    fn operand(&self, id: ExprId) -> Option<bool> {
+        match self.arena.expr(id) {
+            Expr::Literal(Literal::Number(_)) => Some(true),
+            Expr::Literal(Literal::String(_)) => Some(false),
+            Expr::Grouping(expression) => self.operand(*expression),
+            Expr::Unary(operator, _) if operator.token_type == TokenType::Minus => Some(true),
+            Expr::Binary(_, operator, _)
+                if matches!(
+                    operator.token_type,
+                    TokenType::Minus | TokenType::Star | TokenType::Slash
+                ) =>
+            {
+                Some(true)
+            }
+            Expr::Binary(left, operator, right) if operator.token_type == TokenType::Plus => self
+                .operand(*left)
+                .filter(|_| self.same_operands(*left, *right)),
+            // Natives that either give back a string or fail.
+            Expr::Call(callee, ..) => match self.arena.expr(*callee) {
+                Expr::Variable(name) if !self.declared.contains(name.lexeme()) => {
+                    match name.lexeme() {
+                        "typeof" | "toFixed" => Some(false),
+                        _ => None,
+                    }
+                }
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    fn is_nil(&self, id: ExprId) -> bool {
+        matches!(self.arena.expr(id), Expr::Literal(Literal::None))
+    }
+}
+
+/// Where the compiled module for the Lox import `path` is: the same path
+/// ending in `.js` rather than `.lox`, relative to the importing file.
+fn module_path(path: &str) -> String {
+    let path = path.strip_suffix(".lox").unwrap_or(path);
+    if path.starts_with('.') || path.starts_with('/') {
+        format!("{}.js", path)
+    } else {
+        format!("./{}.js", path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::Write,
+        process::{Command, Output, Stdio},
+    };
+
+    use super::*;
+
+    fn compile_source(source: &str) -> (String, Vec<String>) {
+        let mut parser = Parser::new(Scanner::new(source, SourceId::ANONYMOUS).tokens());
+        let statements = parser.parse();
+        let js = compile(&parser.into_arena(), &statements);
+        let errors = diagnostics::take().iter().map(|d| d.to_string()).collect();
+        (js, errors)
+    }
+
+    /// What node does with `js`, or `None` if node isn't installed.
+    fn node(js: &str) -> Option<Output> {
+        let mut child = match Command::new("node")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+        {
+            Ok(child) => child,
+            Err(_) => {
+                eprintln!("node isn't installed; skipping");
+                return None;
+            }
+        };
+        child
+            .stdin
+            .take()
+            .unwrap()
+            .write_all(js.as_bytes())
+            .unwrap();
+        Some(child.wait_with_output().unwrap())
+    }
+
+    /// Checks that the compiled `source` prints what the interpreter does.
+    fn assert_runs_alike(source: &str) {
+        let (js, errors) = compile_source(source);
+        assert_eq!(errors, Vec::<String>::new());
+        let Some(output) = node(&js) else {
+            return;
+        };
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(output.status.success(), "{}\n{}", js, stderr);
+        assert_eq!(
+            String::from_utf8(output.stdout).unwrap(),
+            crate::playground::eval(source),
+            "{}",
+            js
+        );
+    }
+
+    #[test]
+    fn runs_statements_and_closures_like_the_interpreter() {
+        assert_runs_alike(
+            "fun counter() {\n\
+               var n = 0;\n\
+               fun next() { n = n + 1; return n; }\n\
+               return next;\n\
+             }\n\
+             var next = counter();\n\
+             next();\n\
+             print next();\n\
+             for (var i = 0; i < 3; i = i + 1) { if (i == 1) print \"one\"; else print i; }\n\
+             for (var k in 1..=2) print k * 10;\n\
+             print 0 and \"zero is true\";\n\
+             print nil or \"fallback\";\n\
+             var s = \"a\";\n\
+             print s + \"b\" + \"c\";\n",
+        );
+    }
+
+    #[test]
+    fn prints_values_as_lox_does() {
+        assert_runs_alike(
+            "class Point {}\n\
+             fun f() {}\n\
+             var p = Point();\n\
+             print p;\n\
+             print f;\n\
+             print Point;\n\
+             print nil;\n\
+             print 1..3;\n\
+             fun show() { return \"a point\"; }\n\
+             p.toString = show;\n\
+             print p;\n\
+             print \"it's \" + p;\n",
+        );
+    }
+
+    #[test]
+    fn calls_methods_with_this() {
+        assert_runs_alike(
+            "class Point {\n\
+               toString() { return \"(\" + this.label + \")\"; }\n\
+               sum(dx) { return this.x + this.y + dx; }\n\
+             }\n\
+             var p = Point();\n\
+             p.label = \"a\";\n\
+             p.x = 1;\n\
+             p.y = 2;\n\
+             print p;\n\
+             print p.sum(3);\n\
+             print \"at \" + p;\n",
+        );
+    }
+
+    #[test]
+    fn keeps_this_in_functions_declared_in_methods() {
+        assert_runs_alike(
+            "class A {\n\
+               m() {\n\
+                 fun f() { return this.x; }\n\
+                 fun g() { fun h() { return this.x + 1; } return h(); }\n\
+                 return f() + g();\n\
+               }\n\
+             }\n\
+             var a = A();\n\
+             a.x = 2;\n\
+             print a.m();\n",
+        );
+    }
+
+    #[test]
+    fn maps_typeof_num_and_to_fixed() {
+        assert_runs_alike(
+            "class Point {}\n\
+             fun f() {}\n\
+             print typeof(Point()) + \" \" + typeof(f) + \" \" + typeof(Point);\n\
+             print typeof(1) + \" \" + typeof(\"s\") + \" \" + typeof(nil) + \" \" + typeof(true);\n\
+             print typeof(1..2);\n\
+             print num(\" 42 \") + 1;\n\
+             print num(\"1.5e2\");\n\
+             print num(\"0x10\");\n\
+             print num(\"\");\n\
+             print toFixed(3.14159, 2);\n\
+             print toFixed(2, 0);\n",
+        );
+    }
+
+    #[test]
+    fn adding_a_string_and_a_number_fails() {
+        let (js, errors) = compile_source("var n = 1;\nprint \"a\" + n;\nprint \"unreached\";\n");
+        assert_eq!(errors, Vec::<String>::new());
+        assert!(js.contains("__lox_add(\"a\", n)"), "{}", js);
+        let Some(output) = node(&js) else {
+            return;
+        };
+        assert!(!output.status.success());
+        assert_eq!(output.stdout, b"");
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(
+            stderr.contains("TypeError: a and 1 must both be strings or both be numbers"),
+            "{}",
+            stderr
+        );
+    }
+
+    #[test]
+    fn adds_known_numbers_and_strings_directly() {
+        let (js, _) = compile_source("print 1 + 2 * 3;\nprint \"a\" + typeof(1);\n");
+        assert!(
+            js.ends_with("__lox_print(1 + 2 * 3);\n__lox_print(\"a\" + typeof_(1));\n"),
+            "{}",
+            js
+        );
+        assert!(!js.contains("function __lox_add"), "{}", js);
+    }
+
+    #[test]
+    fn renames_names_javascript_reserves() {
+        let (js, _) =
+            compile_source("var new = 1;\nvar __lox_print = 2;\nprint new + __lox_print;\n");
+        assert!(
+            js.contains("var new_ = 1;\nvar __lox_print_ = 2;\n"),
+            "{}",
+            js
+        );
+        assert!(
+            js.contains("__lox_print(__lox_add(new_, __lox_print_));"),
+            "{}",
+            js
+        );
+    }
+
+    #[test]
+    fn reports_what_javascript_cant_express() {
+        let (_, errors) = compile_source("import \"lib.lox\";\nsleep(1);\n");
+        assert_eq!(errors.len(), 2, "{:?}", errors);
+        assert!(
+            errors[0].contains("Can't import every export"),
+            "{:?}",
+            errors
+        );
+        assert!(
+            errors[1].contains("The native 'sleep' has no JavaScript equivalent."),
+            "{:?}",
+            errors
+        );
+    }
+}
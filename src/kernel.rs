@@ -0,0 +1,521 @@
+//! `jlox kernel`: a Jupyter kernel, so Lox can be used from notebooks.
+//!
+//! Jupyter starts the kernel with a connection file naming five TCP ports,
+//! each a ZeroMQ socket. Shell and control carry requests, iopub broadcasts
+//! output to every frontend, stdin is for input prompts (which Lox doesn't
+//! have) and the heartbeat socket echoes pings. Requests are run one at a
+//! time on the main thread against a single interpreter, so definitions
+//! persist between cells just as they do in the REPL.
+
+use std::{
+    cell::{Cell, RefCell},
+    fs, io,
+    panic::{self, AssertUnwindSafe},
+    path::Path,
+    process,
+    rc::Rc,
+    sync::atomic::Ordering,
+    thread,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    cli::Options,
+    diagnostics, hmac,
+    interpreter::{Interpreter, Value},
+    json::Json,
+    output::{OutputEvent, OutputSink},
+    preview,
+    source::SourceId,
+};
+
+const DELIMITER: &[u8] = b"<IDS|MSG>";
+const PROTOCOL_VERSION: &str = "5.3";
+
+/// Runs the kernel described by the connection file at `path` until a
+/// client asks it to shut down.
+pub fn run(path: &str, options: &Options) -> io::Result<()> {
+    let contents = fs::read_to_string(path)?;
+    let config = Json::parse(&contents).map_err(invalid)?;
+    let ip = config
+        .get("ip")
+        .and_then(Json::as_str)
+        .unwrap_or("127.0.0.1")
+        .to_string();
+    let port = |name: &str| {
+        config
+            .get(name)
+            .and_then(Json::as_f64)
+            .map(|port| port as u16)
+            .ok_or_else(|| invalid(format!("connection file has no {}", name)))
+    };
+    match config.get("signature_scheme").and_then(Json::as_str) {
+        None | Some("hmac-sha256") => {}
+        Some(scheme) => return Err(invalid(format!("unsupported signature scheme {}", scheme))),
+    }
+    let key = config
+        .get("key")
+        .and_then(Json::as_str)
+        .unwrap_or("")
+        .as_bytes()
+        .to_vec();
+
+    let mut context = zmq::Context::new();
+    let bind = |name: &str, socket_type| -> io::Result<zmq::Socket> {
+        let socket = context.socket(socket_type)?;
+        // Give up on undelivered messages a second after shutting down,
+        // rather than waiting for a frontend that's gone.
+        socket.set_linger(1000)?;
+        socket.bind(&format!("tcp://{}:{}", ip, port(name)?))?;
+        Ok(socket)
+    };
+    let shell = bind("shell_port", zmq::ROUTER)?;
+    let control = bind("control_port", zmq::ROUTER)?;
+    let iopub = bind("iopub_port", zmq::PUB)?;
+    // Bound so frontends can connect, but never read: Lox has no input().
+    let stdin = bind("stdin_port", zmq::ROUTER)?;
+    let heartbeat = bind("hb_port", zmq::REP)?;
+    // Answered on a thread of its own, so a long cell doesn't look like a
+    // dead kernel. Terminating the context ends it.
+    thread::spawn(move || {
+        while let Ok(ping) = heartbeat.recv_multipart(0) {
+            if heartbeat.send_multipart(ping, 0).is_err() {
+                return;
+            }
+        }
+    });
+
+    let mut kernel = Kernel::new(Session::new(key), Publisher { socket: iopub }, options);
+    while !kernel.shutdown {
+        // Control first, so a shutdown isn't stuck behind queued cells.
+        let mut ready = [
+            control.as_poll_item(zmq::POLLIN),
+            shell.as_poll_item(zmq::POLLIN),
+        ];
+        zmq::poll(&mut ready, -1)?;
+        let socket = if ready[0].is_readable() {
+            &control
+        } else {
+            &shell
+        };
+        let frames = socket.recv_multipart(0)?;
+        if let Some(reply) = kernel.handle(frames) {
+            socket.send_multipart(reply, 0)?;
+        }
+    }
+
+    // Every socket has to be closed before the context can be, which
+    // flushes the shutdown reply.
+    drop((kernel, shell, control, stdin));
+    context.destroy()?;
+    Ok(())
+}
+
+/// A decoded, verified Jupyter message.
+struct Message {
+    identities: Vec<Vec<u8>>,
+    header: Json,
+    content: Json,
+}
+
+impl Message {
+    fn msg_type(&self) -> &str {
+        self.header
+            .get("msg_type")
+            .and_then(Json::as_str)
+            .unwrap_or("")
+    }
+}
+
+/// Signs and numbers the messages the kernel sends.
+struct Session {
+    key: Vec<u8>,
+    id: String,
+    sent: Cell<u64>,
+}
+
+impl Session {
+    fn new(key: Vec<u8>) -> Self {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_nanos());
+        Session {
+            key,
+            id: format!("{:x}-{:x}", process::id(), nanos),
+            sent: Cell::new(0),
+        }
+    }
+
+    fn decode(&self, frames: Vec<Vec<u8>>) -> Result<Message, String> {
+        let split = frames
+            .iter()
+            .position(|frame| frame == DELIMITER)
+            .ok_or("message has no delimiter")?;
+        let mut parts = frames[split + 1..].iter();
+        let (Some(signature), Some(header), Some(parent), Some(metadata), Some(content)) = (
+            parts.next(),
+            parts.next(),
+            parts.next(),
+            parts.next(),
+            parts.next(),
+        ) else {
+            return Err("message is missing parts".to_string());
+        };
+        if !self.key.is_empty()
+            && !hmac::verify(&self.key, &[header, parent, metadata, content], signature)
+        {
+            return Err("message has a bad signature".to_string());
+        }
+        let parse = |bytes: &[u8]| Json::parse(&String::from_utf8_lossy(bytes));
+        Ok(Message {
+            identities: frames[..split].to_vec(),
+            header: parse(header)?,
+            content: parse(content)?,
+        })
+    }
+
+    fn encode(
+        &self,
+        identities: Vec<Vec<u8>>,
+        msg_type: &str,
+        parent: &Json,
+        content: Json,
+    ) -> Vec<Vec<u8>> {
+        let sent = self.sent.get() + 1;
+        self.sent.set(sent);
+        let header = Json::object([
+            ("msg_id", Json::from(format!("{}-{}", self.id, sent))),
+            ("session", Json::from(self.id.as_str())),
+            ("username", Json::from("kernel")),
+            ("date", Json::from(timestamp())),
+            ("msg_type", Json::from(msg_type)),
+            ("version", Json::from(PROTOCOL_VERSION)),
+        ]);
+        let parts = [
+            header.to_string().into_bytes(),
+            parent.to_string().into_bytes(),
+            b"{}".to_vec(),
+            content.to_string().into_bytes(),
+        ];
+        let signature = match self.key.is_empty() {
+            true => String::new(),
+            false => hmac::sign(&self.key, &parts.each_ref().map(Vec::as_slice)),
+        };
+        let mut frames = identities;
+        frames.push(DELIMITER.to_vec());
+        frames.push(signature.into_bytes());
+        frames.extend(parts);
+        frames
+    }
+}
+
+/// The iopub socket, which every frontend subscribes to.
+struct Publisher {
+    socket: zmq::Socket,
+}
+
+impl Publisher {
+    /// Sends to every subscriber. Output nobody is listening for is lost,
+    /// as with any PUB socket.
+    fn publish(&self, frames: Vec<Vec<u8>>) {
+        let _ = self.socket.send_multipart(frames, 0);
+    }
+}
+
+/// Turns the interpreter's output into iopub messages for the cell being
+/// run, as it happens.
+struct CellOutput {
+    session: Rc<Session>,
+    publisher: Publisher,
+    parent: RefCell<Json>,
+    execution_count: Cell<usize>,
+    errors: RefCell<Vec<String>>,
+}
+
+impl CellOutput {
+    fn publish(&self, msg_type: &str, content: Json) {
+        let topic = vec![msg_type.as_bytes().to_vec()];
+        let frames = self
+            .session
+            .encode(topic, msg_type, &self.parent.borrow(), content);
+        self.publisher.publish(frames);
+    }
+
+    fn stream(&self, name: &str, text: String) {
+        self.publish(
+            "stream",
+            Json::object([("name", Json::from(name)), ("text", Json::from(text))]),
+        );
+    }
+}
+
+impl std::fmt::Debug for CellOutput {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "CellOutput")
+    }
+}
+
+impl OutputSink for CellOutput {
+    fn emit(&self, event: OutputEvent) {
+        match event {
+            OutputEvent::PrintValue(value) => {
+                self.stream("stdout", format!("{}\n", preview::format(&value)))
+            }
+            OutputEvent::Diagnostic(diagnostic) => {
+                self.errors.borrow_mut().push(diagnostic.to_string())
+            }
+            OutputEvent::DiagnosticsSuppressed(count) => self
+                .errors
+                .borrow_mut()
+                .push(format!("... {} additional errors suppressed.", count)),
+            OutputEvent::Result(Value::None) => {}
+            OutputEvent::Result(value) => self.publish(
+                "execute_result",
+                Json::object([
+                    ("execution_count", Json::from(self.execution_count.get())),
+                    (
+                        "data",
+                        Json::object([("text/plain", Json::from(preview::format(&value)))]),
+                    ),
+                    ("metadata", Json::object::<&str>([])),
+                ]),
+            ),
+            OutputEvent::TraceLine(line) => self.stream("stderr", format!("{}\n", line)),
+        }
+    }
+}
+
+struct Kernel<'a> {
+    session: Rc<Session>,
+    output: Rc<CellOutput>,
+    interpreter: Interpreter,
+    options: &'a Options,
+    /// Set once a client has asked the kernel to shut down.
+    shutdown: bool,
+}
+
+impl<'a> Kernel<'a> {
+    fn new(session: Session, publisher: Publisher, options: &'a Options) -> Self {
+        let session = Rc::new(session);
+        let output = Rc::new(CellOutput {
+            session: Rc::clone(&session),
+            publisher,
+            parent: RefCell::new(Json::object::<&str>([])),
+            execution_count: Cell::new(0),
+            errors: RefCell::new(Vec::new()),
+        });
+        let mut interpreter = crate::new_interpreter(Path::new("."), options);
+        interpreter.set_output(output.clone());
+        interpreter.echo_results(true);
+        Kernel {
+            session,
+            output,
+            interpreter,
+            options,
+            shutdown: false,
+        }
+    }
+
+    /// Handles the request in `frames`, returning the reply to send back
+    /// on the socket it came in on.
+    fn handle(&mut self, frames: Vec<Vec<u8>>) -> Option<Vec<Vec<u8>>> {
+        let message = match self.session.decode(frames) {
+            Ok(message) => message,
+            Err(error) => {
+                eprintln!("jlox kernel: ignoring request: {}.", error);
+                return None;
+            }
+        };
+        *self.output.parent.borrow_mut() = message.header.clone();
+        self.output.publish(
+            "status",
+            Json::object([("execution_state", Json::from("busy"))]),
+        );
+
+        self.shutdown = message.msg_type() == "shutdown_request";
+        let reply = match message.msg_type() {
+            "kernel_info_request" => Some(("kernel_info_reply", kernel_info())),
+            "execute_request" => Some(("execute_reply", self.execute(&message.content))),
+            "is_complete_request" => {
+                let code = message
+                    .content
+                    .get("code")
+                    .and_then(Json::as_str)
+                    .unwrap_or("");
+                let status = if crate::needs_more_input(code) {
+                    "incomplete"
+                } else {
+                    "complete"
+                };
+                Some((
+                    "is_complete_reply",
+                    Json::object([("status", Json::from(status)), ("indent", Json::from(""))]),
+                ))
+            }
+            "comm_info_request" => Some((
+                "comm_info_reply",
+                Json::object([
+                    ("status", Json::from("ok")),
+                    ("comms", Json::object::<&str>([])),
+                ]),
+            )),
+            // Cells can't be stopped midway yet; by the time this is read the
+            // one being interrupted has finished.
+            "interrupt_request" => Some(("interrupt_reply", ok())),
+            "shutdown_request" => {
+                let restart = message
+                    .content
+                    .get("restart")
+                    .cloned()
+                    .unwrap_or(Json::Bool(false));
+                Some((
+                    "shutdown_reply",
+                    Json::object([("status", Json::from("ok")), ("restart", restart)]),
+                ))
+            }
+            _ => None,
+        };
+        let reply = reply.map(|(msg_type, content)| {
+            self.session
+                .encode(message.identities, msg_type, &message.header, content)
+        });
+
+        self.output.publish(
+            "status",
+            Json::object([("execution_state", Json::from("idle"))]),
+        );
+        reply
+    }
+
+    /// Runs a cell and returns the content of its `execute_reply`.
+    fn execute(&mut self, content: &Json) -> Json {
+        let code = content.get("code").and_then(Json::as_str).unwrap_or("");
+        let silent = content
+            .get("silent")
+            .and_then(Json::as_bool)
+            .unwrap_or(false);
+        if !silent {
+            self.output
+                .execution_count
+                .set(self.output.execution_count.get() + 1);
+        }
+        let count = self.output.execution_count.get();
+        self.output.publish(
+            "execute_input",
+            Json::object([
+                ("code", Json::from(code)),
+                ("execution_count", Json::from(count)),
+            ]),
+        );
+
+        crate::HAD_ERROR.store(false, Ordering::Relaxed);
+        self.output.errors.borrow_mut().clear();
+        let source_id = SourceId::register(&format!("[{}]", count));
+        let interpreter = &mut self.interpreter;
+        let options = self.options;
+        // A notebook has nowhere to exit to, so `exit()` just ends the cell.
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            crate::run_in(interpreter, code, source_id, options)
+        }));
+        diagnostics::flush(&*self.output);
+
+        let (ename, evalue, traceback) = match result {
+            Err(payload) => {
+                let message = crate::panic_message(&*payload);
+                ("RuntimeError", message.clone(), vec![message])
+            }
+            Ok(_) if crate::HAD_ERROR.load(Ordering::Relaxed) => {
+                let errors = self.output.errors.take();
+                let first = errors.first().cloned().unwrap_or_default();
+                ("Error", first, errors)
+            }
+            Ok(_) => {
+                return Json::object([
+                    ("status", Json::from("ok")),
+                    ("execution_count", Json::from(count)),
+                    ("user_expressions", Json::object::<&str>([])),
+                    ("payload", Json::Array(Vec::new())),
+                ]);
+            }
+        };
+        let error = [
+            ("ename", Json::from(ename)),
+            ("evalue", Json::from(evalue)),
+            (
+                "traceback",
+                Json::Array(traceback.into_iter().map(Json::from).collect()),
+            ),
+        ];
+        self.output.publish("error", Json::object(error.clone()));
+        let mut reply = vec![
+            ("status", Json::from("error")),
+            ("execution_count", Json::from(count)),
+        ];
+        reply.extend(error);
+        Json::object(reply)
+    }
+}
+
+fn kernel_info() -> Json {
+    Json::object([
+        ("status", Json::from("ok")),
+        ("protocol_version", Json::from(PROTOCOL_VERSION)),
+        ("implementation", Json::from("jlox")),
+        (
+            "implementation_version",
+            Json::from(env!("CARGO_PKG_VERSION")),
+        ),
+        (
+            "language_info",
+            Json::object([
+                ("name", Json::from("lox")),
+                ("version", Json::from(env!("CARGO_PKG_VERSION"))),
+                ("mimetype", Json::from("text/x-lox")),
+                ("file_extension", Json::from(".lox")),
+            ]),
+        ),
+        (
+            "banner",
+            Json::from(format!("jlox {}", env!("CARGO_PKG_VERSION"))),
+        ),
+        ("help_links", Json::Array(Vec::new())),
+    ])
+}
+
+fn ok() -> Json {
+    Json::object([("status", Json::from("ok"))])
+}
+
+/// The current time in ISO 8601, as message headers want it.
+fn timestamp() -> String {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    let secs = now.as_secs();
+    let (hour, minute, second) = (secs / 3600 % 24, secs / 60 % 60, secs % 60);
+    // Howard Hinnant's days-to-civil conversion.
+    let days = (secs / 86400) as i64 + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:06}Z",
+        year,
+        month,
+        day,
+        hour,
+        minute,
+        second,
+        now.subsec_micros()
+    )
+}
+
+fn invalid(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
@@ -0,0 +1,1067 @@
+//! jlox, a tree-walking interpreter for Lox. The `jlox` binary is a thin
+//! wrapper around `main`; applications embedding Lox start from
+//! `script_engine`.
+
+mod arena;
+mod ast_json;
+mod audit;
+pub mod budget;
+mod bundle;
+mod cache;
+mod callgraph;
+pub mod cancel;
+mod capture;
+mod cli;
+mod coverage;
+mod dap;
+pub mod dependencies;
+mod diagnostics;
+mod engine;
+mod environment;
+mod explorer;
+mod expression;
+mod folder;
+mod formatter;
+mod function;
+mod golden;
+mod highlight;
+#[cfg(all(feature = "kernel", not(target_arch = "wasm32")))]
+mod hmac;
+mod interpreter;
+mod interrupt;
+mod iterate;
+#[cfg(all(feature = "jit", not(target_arch = "wasm32")))]
+mod jit;
+mod js;
+mod json;
+#[cfg(all(feature = "kernel", not(target_arch = "wasm32")))]
+mod kernel;
+mod line_editor;
+pub mod literals;
+mod lox_class;
+mod lsp;
+mod map_rows;
+mod minify;
+mod module;
+mod natives;
+mod number;
+pub mod operators;
+mod output;
+mod parser;
+mod playground;
+mod preview;
+mod profiler;
+mod range;
+mod replay;
+mod resolver;
+mod returns;
+mod scanner;
+pub mod script_engine;
+mod source;
+mod statement;
+mod symbol;
+mod token;
+#[cfg(feature = "units")]
+mod units;
+mod walk;
+
+use budget::{Budget, BudgetExceeded};
+use cancel::Cancelled;
+pub use capture::{check, run_and_capture, ProgramResult};
+use cli::{Command, DiagnosticsFormat, Options};
+pub use diagnostics::{Diagnostic, Severity};
+pub use engine::Engine;
+pub use interpreter::{Interpreter, RuntimeError, Value};
+pub use natives::NativeContext;
+pub use playground::{eval, eval_with_print};
+pub use walk::ValueVisitor;
+/// The scanner on its own, for tools like syntax highlighters.
+#[cfg(feature = "tokens")]
+pub mod tokens {
+    pub use crate::scanner::{ScanError, Scanner};
+    pub use crate::source::SourceId;
+    pub use crate::token::{Literal, Token, TokenType};
+}
+use json::Json;
+use line_editor::{LineEditor, ReadLine};
+use minify::Minifier;
+use operators::Operators;
+use output::{Callback, OutputEvent, OutputSink, StdoutSink};
+use preview::PrintLimits;
+use replay::{Comparer, Outcome, Recorder};
+
+use crate::parser::*;
+use crate::scanner::Scanner;
+use crate::source::SourceId;
+use crate::token::*;
+use std::any::Any;
+use std::cell::{Cell, RefCell};
+use std::io::prelude::*;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+use std::{env, fs, io, process, thread};
+
+// Atomic because `parallelMap` workers can report errors too.
+static HAD_ERROR: AtomicBool = AtomicBool::new(false);
+
+/// Runs the `jlox` command line with the process's arguments.
+pub fn main() {
+    if let Err(payload) = panic::catch_unwind(run_cli) {
+        // Only a bug in the interpreter has been reported by the panic hook:
+        // runtime errors and stopped runs unwind without it.
+        if let Some(error) = payload.downcast_ref::<RuntimeError>() {
+            eprintln!("{}", error);
+            process::exit(70);
+        }
+        if let Some(exceeded) = payload.downcast_ref::<BudgetExceeded>() {
+            eprintln!("{}", exceeded);
+            process::exit(70);
+        }
+        if payload.is::<Cancelled>() {
+            eprintln!("{}", Cancelled);
+            process::exit(130);
+        }
+        process::exit(101);
+    }
+}
+
+fn run_cli() {
+    if let Some(bundled) = bundle::embedded() {
+        run_bundled(bundled);
+        return;
+    }
+    let (command, options) = match cli::parse(env::args().skip(1)) {
+        Ok(parsed) => parsed,
+        // Including --help and --version, which aren't failures.
+        Err(error) => {
+            let _ = error.print();
+            process::exit(if error.use_stderr() { 64 } else { 0 });
+        }
+    };
+
+    if let Some(seed) = options.seed {
+        natives::seed_random(seed);
+    }
+    natives::set_script_args(options.script_args.clone());
+    diagnostics::set_max_errors(options.max_errors);
+    let configured = env::current_dir()
+        .map_err(|e| format!("Could not find the current directory: {}.", e))
+        .and_then(|dir| diagnostics::load_config(&dir));
+    if let Err(message) = configured {
+        println!("{}", message);
+        process::exit(78);
+    }
+    for (rule, severity) in &options.severities {
+        // Already checked while parsing the arguments.
+        diagnostics::set_severity(rule, *severity).unwrap();
+    }
+    preview::set_limits(options.print_limits);
+
+    match command {
+        Command::Eval(source) => run_eval(&source, &options),
+        Command::Ast(path) => print_ast(&path, &options),
+        Command::Check(paths) => check_files(&paths, &options),
+        Command::Audit(path) => process::exit(audit::run(&path, &options.allow)),
+        Command::Callgraph(path) => print_callgraph(&path, &options),
+        Command::MapRows(path) => process::exit(map_rows::run(&path, &options)),
+        Command::Format(paths) => format_files(&paths, &options),
+        Command::Highlight(path) => process::exit(highlight::run(&path, &options)),
+        Command::Compile(path) => process::exit(js::run(&path)),
+        Command::Bundle(path, output) => process::exit(bundle::run(&path, &output, &options)),
+        Command::Test(dir) => {
+            if options.coverage.is_some() {
+                coverage::start();
+            }
+            let passed = golden::run(&dir, &options);
+            if let Some(path) = &options.coverage {
+                write_coverage(path);
+            }
+            if !passed {
+                process::exit(70);
+            }
+        }
+        Command::ReplayCompare(trace, script) => replay_compare(&trace, &script, &options),
+        Command::Minify(path) => minify_file(&path, &options),
+        Command::RunAll(dir) => run_all(&dir, &options),
+        #[cfg(all(feature = "kernel", not(target_arch = "wasm32")))]
+        Command::Kernel(path) => {
+            if let Err(e) = kernel::run(&path, &options) {
+                println!("Could not start the kernel: {}.", e);
+                process::exit(74);
+            }
+        }
+        #[cfg(not(all(feature = "kernel", not(target_arch = "wasm32"))))]
+        Command::Kernel(_) => {
+            println!("This build doesn't include the Jupyter kernel.");
+            process::exit(64);
+        }
+        Command::Dap => {
+            if let Err(e) = dap::run(&options) {
+                eprintln!("jlox dap: {}.", e);
+                process::exit(74);
+            }
+        }
+        Command::Lsp => {
+            if let Err(e) = lsp::run() {
+                eprintln!("jlox lsp: {}.", e);
+                process::exit(74);
+            }
+        }
+        Command::Run(path) if path == "-" => run_stdin(&options),
+        Command::Run(path) if options.watch => watch_file(&path, &options),
+        Command::Run(path) => run_file(&path, &options),
+        Command::Bench(path) => bench_file(&path, &options),
+        Command::Repl => run_prompt(&options),
+    }
+}
+
+fn run_file(path: &str, options: &Options) {
+    let contents = read_script(path);
+    let interpreter = new_interpreter(script_dir(Path::new(path)), options);
+    interrupt::install(interpreter.cancellation_token());
+    let source_id = SourceId::register(path);
+    if let Some(code) = run_program(interpreter, &contents, source_id, options) {
+        process::exit(code);
+    }
+    if HAD_ERROR.load(Ordering::Relaxed) {
+        process::exit(65);
+    }
+}
+
+/// The contents of the script at `path`, exiting if it can't be read.
+fn read_script(path: &str) -> String {
+    fs::read_to_string(path).unwrap_or_else(|e| {
+        println!("Could not read {}: {}.", path, e);
+        process::exit(66);
+    })
+}
+
+/// Runs the script `options.runs` times, each in a fresh interpreter with
+/// its output discarded, and reports how long the runs took.
+fn bench_file(path: &str, options: &Options) {
+    let contents = read_script(path);
+    let source_id = SourceId::register(path);
+    // Static errors would be discarded with the rest of the output.
+    diagnostics::reset();
+    Parser::new(Scanner::new(&contents, source_id).tokens()).parse();
+    diagnostics::flush(&StdoutSink);
+    if HAD_ERROR.load(Ordering::Relaxed) {
+        process::exit(65);
+    }
+
+    let mut times = Vec::with_capacity(options.runs);
+    for _ in 0..options.runs {
+        let mut interpreter = new_interpreter(script_dir(Path::new(path)), options);
+        interpreter.set_output(Rc::new(Callback(|_| {})));
+        let started = Instant::now();
+        if let Some(code) = run_in(&mut interpreter, &contents, source_id, options) {
+            if code != 0 {
+                println!("The script exited with code {}.", code);
+                process::exit(code);
+            }
+        }
+        times.push(started.elapsed());
+    }
+    times.sort();
+    let ms = |time: Duration| time.as_secs_f64() * 1000.0;
+    println!(
+        "{} runs: min {:.3}ms, median {:.3}ms, max {:.3}ms",
+        times.len(),
+        ms(times[0]),
+        ms(times[times.len() / 2]),
+        ms(times[times.len() - 1])
+    );
+}
+
+/// Runs the script, then again with a fresh interpreter each time it or a
+/// module it imported is saved. Runs until interrupted.
+fn watch_file(path: &str, options: &Options) {
+    loop {
+        // Literals from earlier versions of the file would only pile up.
+        literals::clear();
+        println!("[watch] Running {}.", path);
+        let mut watched = vec![PathBuf::from(path)];
+        match fs::read_to_string(path) {
+            Ok(contents) => {
+                HAD_ERROR.store(false, Ordering::Relaxed);
+                let mut interpreter = new_interpreter(script_dir(Path::new(path)), options);
+                let modules = interpreter.modules();
+                let source_id = SourceId::register(path);
+                let result = panic::catch_unwind(AssertUnwindSafe(|| {
+                    run_in(&mut interpreter, &contents, source_id, options)
+                }));
+                diagnostics::flush(&StdoutSink);
+                match result {
+                    Err(payload) => println!("{}", panic_message(&*payload)),
+                    Ok(Some(code)) => println!("[watch] Exited with code {}.", code),
+                    Ok(None) => {}
+                }
+                watched.extend(modules.borrow().files().cloned());
+            }
+            Err(e) => println!("Could not read {}: {}.", path, e),
+        }
+        println!("[watch] Waiting for changes.");
+        wait_for_change(&watched);
+    }
+}
+
+/// Polls until any of `paths` is modified, created or removed.
+fn wait_for_change(paths: &[PathBuf]) {
+    let modified = || -> Vec<Option<SystemTime>> {
+        paths
+            .iter()
+            .map(|path| fs::metadata(path).and_then(|m| m.modified()).ok())
+            .collect()
+    };
+    let before = modified();
+    while modified() == before {
+        thread::sleep(Duration::from_millis(200));
+    }
+    // Editors often save in several steps; let them finish.
+    thread::sleep(Duration::from_millis(50));
+}
+
+/// Runs the code given with `-e`, relative to the working directory.
+fn run_eval(source: &str, options: &Options) {
+    run_source(source, SourceId::ANONYMOUS, options);
+}
+
+/// Runs a whole program piped in on stdin (`lox -`).
+fn run_stdin(options: &Options) {
+    let mut source = String::new();
+    if let Err(e) = io::stdin().read_to_string(&mut source) {
+        println!("Could not read stdin: {}.", e);
+        process::exit(66);
+    }
+    run_source(&source, SourceId::register("<stdin>"), options);
+}
+
+/// Runs the script bundled into this executable, with every argument
+/// passed to the script rather than read as jlox's own options.
+fn run_bundled(bundled: bundle::Bundled) {
+    let options = Options {
+        allow_fs: bundled.allow_fs,
+        allow_env: bundled.allow_env,
+        ..Options::default()
+    };
+    natives::set_script_args(env::args().skip(1).collect());
+    run_source(&bundled.source, SourceId::register(&bundled.name), &options);
+}
+
+fn run_source(source: &str, source_id: SourceId, options: &Options) {
+    let interpreter = new_interpreter(Path::new("."), options);
+    if let Some(code) = run_program(interpreter, source, source_id, options) {
+        process::exit(code);
+    }
+    if HAD_ERROR.load(Ordering::Relaxed) {
+        process::exit(65);
+    }
+}
+
+/// Prints the program as parsed, before any folding: as Lox, or with
+/// `--json` as a syntax tree.
+fn print_ast(path: &str, options: &Options) {
+    let contents = read_script(path);
+    diagnostics::reset();
+    let tokens = Scanner::new(&contents, SourceId::register(path)).tokens();
+    let mut parser = Parser::new(tokens);
+    let statements = parser.parse();
+    diagnostics::flush(&StdoutSink);
+    if HAD_ERROR.load(Ordering::Relaxed) {
+        process::exit(65);
+    }
+    let arena = parser.into_arena();
+    if options.json {
+        println!("{}", ast_json::to_json(&arena, &statements));
+        return;
+    }
+    for statement in statements {
+        println!("{}", arena.show(statement));
+    }
+}
+
+/// Prints who calls whom in the script, as text or with `--dot` as a
+/// Graphviz graph.
+fn print_callgraph(path: &str, options: &Options) {
+    let contents = read_script(path);
+    diagnostics::reset();
+    let tokens = Scanner::new(&contents, SourceId::register(path)).tokens();
+    let mut parser = Parser::new(tokens);
+    let statements = parser.parse();
+    diagnostics::flush(&StdoutSink);
+    if HAD_ERROR.load(Ordering::Relaxed) {
+        process::exit(65);
+    }
+    let graph = callgraph::build(&parser.into_arena(), &statements);
+    if options.dot {
+        print!("{}", graph.to_dot());
+    } else {
+        print!("{}", graph.to_text());
+    }
+}
+
+/// Scans and parses each file without running it, reporting every static
+/// error found. Exits with 65 if any file has one.
+fn check_files(paths: &[String], options: &Options) {
+    let json = options.diagnostics_format == DiagnosticsFormat::Json;
+    // As JSON, everything is written at the end as one document.
+    let collected = RefCell::new(Vec::new());
+    let suppressed = Cell::new(0);
+    let collect = Callback(|event| match event {
+        OutputEvent::Diagnostic(diagnostic) => collected.borrow_mut().push(diagnostic.to_json()),
+        OutputEvent::DiagnosticsSuppressed(count) => suppressed.set(suppressed.get() + count),
+        _ => {}
+    });
+    let sink: &dyn OutputSink = if json { &collect } else { &StdoutSink };
+
+    let mut failed = false;
+    for path in paths {
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(e) => {
+                let message = format!("Could not read {}: {}.", path, e);
+                // Stdout is kept for the JSON document.
+                if json {
+                    eprintln!("{}", message);
+                } else {
+                    println!("{}", message);
+                }
+                failed = true;
+                continue;
+            }
+        };
+        HAD_ERROR.store(false, Ordering::Relaxed);
+        diagnostics::reset();
+        capture::analyze(&contents, SourceId::register(path));
+        diagnostics::flush(sink);
+        failed |= HAD_ERROR.load(Ordering::Relaxed);
+    }
+    if json {
+        let report = Json::object([
+            ("diagnostics", Json::Array(collected.take())),
+            ("suppressed", Json::from(suppressed.get())),
+        ]);
+        println!("{}", report);
+    }
+    if failed {
+        process::exit(65);
+    }
+}
+
+/// Formats each file to stdout, or back into the file with `--write`.
+/// Files that don't parse are reported and left alone.
+fn format_files(paths: &[String], options: &Options) {
+    let mut failed = false;
+    for path in paths {
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(e) => {
+                println!("Could not read {}: {}.", path, e);
+                failed = true;
+                continue;
+            }
+        };
+        HAD_ERROR.store(false, Ordering::Relaxed);
+        diagnostics::reset();
+        let tokens = Scanner::new(&contents, SourceId::register(path)).scan_tokens();
+        Parser::new(tokens.clone()).parse();
+        diagnostics::flush(&StdoutSink);
+        if HAD_ERROR.load(Ordering::Relaxed) {
+            failed = true;
+            continue;
+        }
+        let formatted = formatter::format(&contents, &tokens);
+        if !options.write {
+            print!("{}", formatted);
+        } else if formatted != contents {
+            if let Err(e) = fs::write(path, formatted) {
+                println!("Could not write {}: {}.", path, e);
+                failed = true;
+            }
+        }
+    }
+    if failed {
+        process::exit(65);
+    }
+}
+
+fn minify_file(path: &str, options: &Options) {
+    let contents = read_script(path);
+    diagnostics::reset();
+    let tokens = Scanner::new(&contents, SourceId::register(path)).scan_tokens();
+    let mut parser = Parser::new(tokens.clone());
+    let statements = parser.parse();
+    diagnostics::flush(&StdoutSink);
+    if HAD_ERROR.load(Ordering::Relaxed) {
+        process::exit(65);
+    }
+    let mut arena = parser.into_arena();
+    println!(
+        "{}",
+        Minifier::new(&mut arena, &tokens, &Operators::default(), options.flatten)
+            .minify(statements)
+    );
+}
+
+/// Runs every file in `dir` with a fresh interpreter, then prints a one-line
+/// JSON summary. Stops at the first failure unless `--keep-going` is set.
+fn run_all(dir: &str, options: &Options) {
+    // Stdout is only for the summary, so this goes to stderr.
+    let entries = fs::read_dir(dir).unwrap_or_else(|e| {
+        eprintln!("Could not read {}: {}.", dir, e);
+        process::exit(66);
+    });
+    let mut paths: Vec<_> = entries
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| path.is_file() && path.extension().is_some_and(|ext| ext == "lox"))
+        .collect();
+    paths.sort();
+
+    let mut results = Vec::new();
+    let mut failed = 0;
+    for path in paths {
+        let mut output = Vec::new();
+        let (status, message) = match fs::read_to_string(&path) {
+            Err(e) => ("error", e.to_string()),
+            Ok(contents) => {
+                HAD_ERROR.store(false, Ordering::Relaxed);
+                let (result, lines) = run_captured(&contents, &path, options);
+                output = lines;
+                match result {
+                    Err(payload) if payload.is::<RuntimeError>() => {
+                        ("runtime-error", panic_message(&*payload))
+                    }
+                    Err(payload) => ("panic", panic_message(&*payload)),
+                    Ok(Some(code)) if code != 0 => ("exit", format!("exited with code {}", code)),
+                    Ok(_) if HAD_ERROR.load(Ordering::Relaxed) => ("error", String::new()),
+                    Ok(_) => ("ok", String::new()),
+                }
+            }
+        };
+
+        if status != "ok" {
+            failed += 1;
+        }
+        let output: Vec<String> = output.iter().map(|line| json::quote(line)).collect();
+        results.push(format!(
+            "{{\"path\":{},\"status\":\"{}\",\"message\":{},\"output\":[{}]}}",
+            json::quote(&path.display().to_string()),
+            status,
+            json::quote(&message),
+            output.join(",")
+        ));
+        if status != "ok" && !options.keep_going {
+            break;
+        }
+    }
+    if options.verbose {
+        let pool = literals::stats();
+        StdoutSink.emit(OutputEvent::TraceLine(format!(
+            "literal pool: {} strings, {} bytes, {} bytes saved",
+            pool.strings, pool.bytes, pool.saved
+        )));
+    }
+
+    println!(
+        "{{\"passed\":{},\"failed\":{},\"files\":[{}]}}",
+        results.len() - failed,
+        failed,
+        results.join(",")
+    );
+    if failed > 0 {
+        process::exit(70);
+    }
+}
+
+/// Runs the script at `path`, whose contents are `source`, collecting what
+/// it prints and reports as lines rather than writing them to stdout, where
+/// `run-all` writes its summary. Also returns the code passed to `exit()`,
+/// if the script called it.
+fn run_captured(
+    source: &str,
+    path: &Path,
+    options: &Options,
+) -> (thread::Result<Option<i32>>, Vec<String>) {
+    let lines = Rc::new(RefCell::new(Vec::new()));
+    let sink = {
+        let lines = Rc::clone(&lines);
+        Rc::new(Callback(move |event| {
+            let line = match event {
+                OutputEvent::PrintValue(value) => preview::format(&value),
+                OutputEvent::Diagnostic(diagnostic) => diagnostic.to_string(),
+                OutputEvent::DiagnosticsSuppressed(count) => {
+                    format!("... {} additional errors suppressed.", count)
+                }
+                OutputEvent::Result(_) => return,
+                OutputEvent::TraceLine(line) => return eprintln!("{}", line),
+            };
+            lines.borrow_mut().push(line);
+        }))
+    };
+    let mut interpreter = new_interpreter(script_dir(path), options);
+    interpreter.set_output(sink.clone());
+    let source_id = SourceId::register(&path.display().to_string());
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        run_in(&mut interpreter, source, source_id, options)
+    }));
+    diagnostics::flush(&*sink);
+    (result, lines.take())
+}
+
+fn run_prompt(options: &Options) {
+    let mut editor = LineEditor::new();
+    let mut interpreter = new_interpreter(Path::new("."), options);
+    interpreter.echo_results(true);
+    interrupt::install(interpreter.cancellation_token());
+    preview::set_limits(options.print_limits.or(PrintLimits::REPL));
+    let mut buffer = String::new();
+    loop {
+        let prompt = if buffer.is_empty() { "> " } else { ".. " };
+        let input = match editor.read_line(prompt) {
+            Ok(ReadLine::Line(input)) => input,
+            Ok(ReadLine::Interrupted) => {
+                buffer.clear();
+                continue;
+            }
+            Ok(ReadLine::Eof) | Err(_) => break,
+        };
+        if buffer.is_empty() && input.trim().is_empty() {
+            continue;
+        }
+        editor.add_history(&input);
+
+        if buffer.is_empty() && input.trim_start().starts_with(':') {
+            let command = meta_command(input.trim(), &mut interpreter, &mut editor);
+            let (source, source_id) = match command {
+                MetaCommand::Done => continue,
+                MetaCommand::Quit => break,
+                MetaCommand::Run(source, source_id) => (source, source_id),
+            };
+            if let Some(code) = run_entry(&mut interpreter, &source, source_id, options) {
+                process::exit(code);
+            }
+            HAD_ERROR.store(false, Ordering::Relaxed);
+            continue;
+        }
+
+        buffer.push_str(&input);
+        buffer.push('\n');
+        if needs_more_input(&buffer) {
+            continue;
+        }
+
+        let source = std::mem::take(&mut buffer);
+        if let Some(code) = run_entry(
+            &mut interpreter,
+            source.trim(),
+            SourceId::ANONYMOUS,
+            options,
+        ) {
+            process::exit(code);
+        }
+        HAD_ERROR.store(false, Ordering::Relaxed);
+    }
+}
+
+/// Runs a REPL entry. If a runtime error, Ctrl-C or the budget stops it, the
+/// session carries on with what ran before that.
+fn run_entry(
+    interpreter: &mut Interpreter,
+    source: &str,
+    source_id: SourceId,
+    options: &Options,
+) -> Option<i32> {
+    // Ctrl-C pressed at the prompt isn't meant for this entry.
+    interpreter.cancellation_token().take();
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        run_in(interpreter, source, source_id, options)
+    }));
+    match result {
+        Ok(code) => code,
+        Err(payload)
+            if payload.is::<RuntimeError>()
+                || payload.is::<Cancelled>()
+                || payload.is::<BudgetExceeded>() =>
+        {
+            diagnostics::flush(&*interpreter.output());
+            println!("{}", panic_message(&*payload));
+            None
+        }
+        Err(payload) => panic::resume_unwind(payload),
+    }
+}
+
+enum MetaCommand {
+    Done,
+    Quit,
+    /// Source to run in the session, from `:load`.
+    Run(String, SourceId),
+}
+
+const REPL_HELP: &str = "\
+:env             list the session's global variables
+:explore <expr>  show how an expression parses, and evaluate its parts
+:load <file>     run a file in this session
+:reset           forget every global defined so far
+:set <limit> <n> change how much of a value is printed (n may be 'none');
+                 :set alone lists the limits
+:whereis <name>  show where a function or class was declared
+:help            show this message
+:quit            leave the REPL (Ctrl-D works too)";
+
+fn meta_command(line: &str, interpreter: &mut Interpreter, editor: &mut LineEditor) -> MetaCommand {
+    let (command, argument) = line.split_once(' ').unwrap_or((line, ""));
+    match (command, argument.trim()) {
+        (":env", _) => {
+            for (name, value) in interpreter.globals_iter() {
+                println!("{} = {}", name, value);
+            }
+        }
+        (":explore", "") => println!("Usage: :explore <expression>"),
+        (":explore", source) => explorer::explore(source, interpreter, editor),
+        (":load", "") => println!("Usage: :load <file>"),
+        (":load", path) => match fs::read_to_string(path) {
+            Ok(source) => return MetaCommand::Run(source, SourceId::register(path)),
+            Err(e) => println!("Could not read '{}': {}.", path, e),
+        },
+        (":reset", _) => interpreter.clear_globals(true),
+        (":set", "") => println!("{}", preview::limits()),
+        (":set", setting) => {
+            let (name, value) = setting.split_once(' ').unwrap_or((setting, ""));
+            let mut limits = preview::limits();
+            match limits.set(name, value.trim()) {
+                Ok(()) => preview::set_limits(limits),
+                Err(message) => println!("{}", message),
+            }
+        }
+        (":whereis", "") => println!("Usage: :whereis <name>"),
+        (":whereis", name) => match interpreter.global(name) {
+            Some(value) => match value.definition() {
+                Some(location) => println!("{} is declared at {}.", name, location),
+                None if matches!(value, Value::Callable(_) | Value::Class(_)) => {
+                    println!("{} is built in.", name)
+                }
+                None => println!("{} is {}, not a function or class.", name, value),
+            },
+            None => println!("Undefined variable '{}'.", name),
+        },
+        (":help", _) => println!("{}", REPL_HELP),
+        (":quit", _) => return MetaCommand::Quit,
+        _ => println!("Unknown command '{}'. Type :help for a list.", command),
+    }
+    MetaCommand::Done
+}
+
+/// Whether REPL input stops inside a block, parenthesis or string, so the
+/// next line should be read as a continuation rather than run on its own.
+fn needs_more_input(source: &str) -> bool {
+    let mut depth = 0;
+    let mut in_string = false;
+    let mut chars = source.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => in_string = !in_string,
+            _ if in_string => {}
+            '/' if chars.peek() == Some(&'/') => {
+                chars.find(|&c| c == '\n');
+            }
+            '{' | '(' => depth += 1,
+            '}' | ')' => depth -= 1,
+            _ => {}
+        }
+    }
+    in_string || depth > 0
+}
+
+fn script_dir(path: &Path) -> &Path {
+    match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    }
+}
+
+fn new_interpreter(base_dir: &Path, options: &Options) -> Interpreter {
+    let mut interpreter = Interpreter::with_numbers(options.numbers());
+    interpreter.set_base_dir(base_dir.to_path_buf());
+    if options.allow_fs {
+        interpreter.allow_fs();
+    }
+    if options.allow_env {
+        interpreter.allow_env();
+    }
+    interpreter.error_values(options.error_values);
+    if let Some(max_depth) = options.max_depth {
+        interpreter.set_max_depth(max_depth);
+    }
+    interpreter.set_budget(Budget {
+        max_steps: options.max_steps,
+        timeout: options.timeout,
+    });
+    interpreter
+}
+
+/// Runs a whole program, profiling it with `--profile`, tracking coverage
+/// with `--coverage` and recording it with `--record`.
+fn run_program(
+    interpreter: Interpreter,
+    source: &str,
+    source_id: SourceId,
+    options: &Options,
+) -> Option<i32> {
+    if !options.profile && options.coverage.is_none() {
+        return record_program(interpreter, source, source_id, options);
+    }
+    if options.profile {
+        profiler::start();
+    }
+    if options.coverage.is_some() {
+        coverage::start();
+    }
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        record_program(interpreter, source, source_id, options)
+    }));
+    if options.profile {
+        eprint!("{}", profiler::report());
+    }
+    if let Some(path) = &options.coverage {
+        write_coverage(path);
+    }
+    result.unwrap_or_else(|payload| panic::resume_unwind(payload))
+}
+
+fn write_coverage(path: &str) {
+    if let Err(e) = coverage::write(path) {
+        println!("Could not write {}: {}.", path, e);
+    }
+}
+
+/// Runs a whole program, saving a trace of its output to the file given
+/// with `--record`, if any.
+fn record_program(
+    mut interpreter: Interpreter,
+    source: &str,
+    source_id: SourceId,
+    options: &Options,
+) -> Option<i32> {
+    let Some(record) = &options.record else {
+        return run_in(&mut interpreter, source, source_id, options);
+    };
+    let recorder = Rc::new(Recorder::new(interpreter.output()));
+    interpreter.set_output(recorder.clone());
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        run_in(&mut interpreter, source, source_id, options)
+    }));
+    let outcome = match &result {
+        Ok(exit_code) => Outcome::Exit(exit_code.unwrap_or(0)),
+        Err(payload) => Outcome::Panic(panic_message(&**payload)),
+    };
+    if let Err(e) = fs::write(record, recorder.finish(outcome).to_string()) {
+        println!("Could not write {}: {}.", record, e);
+    }
+    result.unwrap_or_else(|payload| panic::resume_unwind(payload))
+}
+
+/// Runs the script at `script_path` against a trace recorded with
+/// `--record`, reporting the first event that differs.
+fn replay_compare(trace_path: &str, script_path: &str, options: &Options) {
+    let trace = fs::read_to_string(trace_path)
+        .map_err(|e| e.to_string())
+        .and_then(|contents| Json::parse(&contents));
+    let expected = match trace {
+        Ok(Json::Array(events)) => events,
+        Ok(_) => {
+            println!("{} is not a trace.", trace_path);
+            process::exit(65);
+        }
+        Err(e) => {
+            println!("Could not read {}: {}.", trace_path, e);
+            process::exit(66);
+        }
+    };
+    let contents = match fs::read_to_string(script_path) {
+        Ok(contents) => contents,
+        Err(e) => {
+            println!("Could not read {}: {}.", script_path, e);
+            process::exit(66);
+        }
+    };
+
+    let comparer = Rc::new(Comparer::new(expected));
+    let mut interpreter = new_interpreter(script_dir(Path::new(script_path)), options);
+    interpreter.set_output(comparer.clone());
+    let source_id = SourceId::register(script_path);
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        run_in(&mut interpreter, &contents, source_id, options)
+    }));
+    let outcome = match result {
+        Ok(exit_code) => Outcome::Exit(exit_code.unwrap_or(0)),
+        Err(payload) => Outcome::Panic(panic_message(&*payload)),
+    };
+    match comparer.finish(outcome) {
+        Some(divergence) => {
+            println!("{}", divergence);
+            process::exit(1);
+        }
+        None => println!("Traces match ({} events).", comparer.matched()),
+    }
+}
+
+/// The message a panic was raised with, for runtime errors caught at the
+/// top level.
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    payload
+        .downcast_ref::<String>()
+        .cloned()
+        .or_else(|| payload.downcast_ref::<&str>().map(|s| s.to_string()))
+        .or_else(|| {
+            payload
+                .downcast_ref::<RuntimeError>()
+                .map(RuntimeError::to_string)
+        })
+        .or_else(|| {
+            payload
+                .downcast_ref::<Cancelled>()
+                .map(Cancelled::to_string)
+        })
+        .or_else(|| {
+            payload
+                .downcast_ref::<BudgetExceeded>()
+                .map(BudgetExceeded::to_string)
+        })
+        .unwrap_or_default()
+}
+
+/// Runs `source` in an existing interpreter, so a REPL session keeps its
+/// globals between entries.
+fn run_in(
+    interpreter: &mut Interpreter,
+    source: &str,
+    source_id: SourceId,
+    options: &Options,
+) -> Option<i32> {
+    diagnostics::reset();
+    let started = Instant::now();
+    let output = interpreter.output();
+    let trace = |phase: &str, since: Instant| {
+        if options.verbose {
+            let line = format!("{}: {:.3}ms", phase, since.elapsed().as_secs_f64() * 1000.0);
+            output.emit(OutputEvent::TraceLine(line));
+        }
+    };
+
+    let cache = options
+        .cache
+        .then(|| cache::path(Path::new(&source_id.name())));
+    let cached = cache
+        .as_deref()
+        .and_then(|path| cache::load(path, source, source_id));
+    // Statements that failed to parse are missing, so names they declared
+    // would be resolved wrongly.
+    let mut parsed = true;
+    let (ast, statements) = match cached {
+        Some((ast, statements)) => {
+            trace("load cache", started);
+            (Arc::new(ast), statements)
+        }
+        None => {
+            let scanner =
+                Scanner::with_operators(source, source_id, Arc::clone(interpreter.operators()));
+            trace("scanner setup", started);
+            // The parser scans as it goes, so this is the time for both.
+            let parsing = Instant::now();
+            let (ast, statements) = folder::parse_and_fold(Parser::new(scanner.tokens()));
+            trace("parse", parsing);
+            parsed = !diagnostics::any_pending();
+            // A cached program is run without reporting anything, so only
+            // one that parsed cleanly can be cached.
+            if let (Some(path), true) = (&cache, parsed) {
+                cache::save(path, source, source_id, &ast, &statements);
+            }
+            (ast, statements)
+        }
+    };
+    // A script that doesn't parse, or does something the resolver rejects,
+    // like assigning a constant, doesn't run at all.
+    if parsed {
+        resolver::check(&ast, &statements);
+    }
+    if diagnostics::any_pending() {
+        diagnostics::flush(&*interpreter.output());
+        return None;
+    }
+    if options.dump_folded {
+        for statement in &statements {
+            println!("{}", ast.show(*statement));
+        }
+        return None;
+    }
+    let running = Instant::now();
+    let exit_code = interpreter.interpret(&ast, statements);
+    trace("run", running);
+    diagnostics::flush(&*interpreter.output());
+    exit_code
+}
+
+fn error_at_token(token: &Token, message: &str) {
+    let (line, column, length) = (token.line, token.column, token_length(token));
+    report(
+        token.source,
+        line,
+        column,
+        length,
+        &token_location(token),
+        message,
+    );
+}
+
+/// Reports a diagnostic from `rule` at `token` with the severity configured
+/// for it, which may be none.
+fn lint(rule: &'static str, token: &Token, message: &str) {
+    let severity = diagnostics::severity(rule);
+    if severity == Severity::Allow {
+        return;
+    }
+    diagnostics::push(diagnostics::Diagnostic {
+        severity,
+        source: token.source,
+        line: token.line,
+        column: token.column,
+        length: token_length(token),
+        location: token_location(token),
+        message: message.to_string(),
+        code: Some(rule),
+    });
+    if severity == Severity::Deny {
+        HAD_ERROR.store(true, Ordering::Relaxed);
+    }
+}
+
+fn token_length(token: &Token) -> u32 {
+    token.lexeme().chars().count() as u32
+}
+
+fn token_location(token: &Token) -> String {
+    if token.token_type == TokenType::Eof {
+        " at end".to_string()
+    } else {
+        format!(" at '{}'", token.lexeme())
+    }
+}
+
+fn report(source: SourceId, line: u32, column: u32, length: u32, where1: &str, message: &str) {
+    diagnostics::push(diagnostics::Diagnostic {
+        severity: Severity::Deny,
+        source,
+        line,
+        column,
+        length,
+        location: where1.to_string(),
+        message: message.to_string(),
+        code: None,
+    });
+    HAD_ERROR.store(true, Ordering::Relaxed);
+}
@@ -0,0 +1,103 @@
+//! Line editing for the REPL and `explore`, by rustyline: cursor movement,
+//! history (kept in `~/.lox_history` between sessions) and Ctrl-C/Ctrl-D.
+//! On wasm32, which rustyline isn't built for, lines are always read as they
+//! are from a pipe.
+
+use std::io::{self, Write};
+#[cfg(not(target_arch = "wasm32"))]
+use std::{env, io::IsTerminal, path::PathBuf};
+
+#[cfg(not(target_arch = "wasm32"))]
+use rustyline::{error::ReadlineError, DefaultEditor};
+
+/// What the user did at the prompt.
+pub enum ReadLine {
+    Line(String),
+    /// Ctrl-C: the current line is discarded.
+    #[cfg_attr(target_arch = "wasm32", allow(dead_code))]
+    Interrupted,
+    /// Ctrl-D on an empty line, or end of input.
+    Eof,
+}
+
+pub struct LineEditor {
+    /// `None` if rustyline couldn't set up the terminal, in which case
+    /// lines are read as they are from a pipe.
+    #[cfg(not(target_arch = "wasm32"))]
+    editor: Option<DefaultEditor>,
+    #[cfg(not(target_arch = "wasm32"))]
+    history_path: Option<PathBuf>,
+}
+
+impl LineEditor {
+    #[cfg(target_arch = "wasm32")]
+    pub fn new() -> Self {
+        LineEditor {}
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn new() -> Self {
+        let history_path = env::var_os("HOME").map(|home| PathBuf::from(home).join(".lox_history"));
+        let mut editor = DefaultEditor::new().ok();
+        if let (Some(editor), Some(path)) = (&mut editor, &history_path) {
+            // There's no history before the first session.
+            let _ = editor.load_history(path);
+        }
+        LineEditor {
+            editor,
+            history_path,
+        }
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    pub fn add_history(&mut self, _line: &str) {}
+
+    /// Remembers `line` for up-arrow recall, in this session and the next.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn add_history(&mut self, line: &str) {
+        let Some(editor) = &mut self.editor else {
+            return;
+        };
+        if line.trim().is_empty() || !editor.add_history_entry(line).unwrap_or(false) {
+            return;
+        }
+        if let Some(path) = &self.history_path {
+            // History is a convenience; failing to save it isn't an error.
+            let _ = editor.append_history(path);
+        }
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    pub fn read_line(&mut self, prompt: &str) -> io::Result<ReadLine> {
+        read_buffered(prompt)
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn read_line(&mut self, prompt: &str) -> io::Result<ReadLine> {
+        let editor = match &mut self.editor {
+            Some(editor) if io::stdin().is_terminal() => editor,
+            _ => return read_buffered(prompt),
+        };
+        match editor.readline(prompt) {
+            Ok(line) => Ok(ReadLine::Line(line)),
+            Err(ReadlineError::Interrupted) => Ok(ReadLine::Interrupted),
+            Err(ReadlineError::Eof) => Ok(ReadLine::Eof),
+            Err(ReadlineError::Io(error)) => Err(error),
+            Err(error) => Err(io::Error::other(error)),
+        }
+    }
+}
+
+fn read_buffered(prompt: &str) -> io::Result<ReadLine> {
+    // Piped input still gets the prompt, so a transcript reads like a
+    // session.
+    print!("{}", prompt);
+    io::stdout().flush()?;
+    let mut line = String::new();
+    if io::stdin().read_line(&mut line)? == 0 {
+        return Ok(ReadLine::Eof);
+    }
+    Ok(ReadLine::Line(
+        line.trim_end_matches(['\n', '\r']).to_string(),
+    ))
+}
@@ -0,0 +1,78 @@
+//! The pool string literals are interned in. Hosts that compile many
+//! programs can watch it with `stats` and empty it with `clear`.
+
+use std::{
+    collections::BTreeSet,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+};
+
+/// String literals seen so far by any scanner, so identical literals in
+/// different programs share one allocation. Programs that are compiled by
+/// the thousand (rules, templates) repeat the same few strings endlessly.
+static POOL: Mutex<BTreeSet<Arc<str>>> = Mutex::new(BTreeSet::new());
+
+// Bytes that would have been allocated again had the pool not had them.
+static SAVED: AtomicUsize = AtomicUsize::new(0);
+
+/// The pooled copy of `s`, added to the pool if it's new.
+pub fn intern(s: &str) -> Arc<str> {
+    let mut pool = POOL.lock().unwrap();
+    if let Some(shared) = pool.get(s) {
+        SAVED.fetch_add(s.len(), Ordering::Relaxed);
+        return Arc::clone(shared);
+    }
+    let shared: Arc<str> = Arc::from(s);
+    pool.insert(Arc::clone(&shared));
+    shared
+}
+
+/// Empties the pool. Programs already compiled keep their literals; they
+/// just stop being shared with programs compiled from now on.
+pub fn clear() {
+    POOL.lock().unwrap().clear();
+    SAVED.store(0, Ordering::Relaxed);
+}
+
+/// How many distinct literals are pooled, how many bytes they take, and
+/// how many bytes sharing them has saved since the pool was last cleared.
+#[derive(Debug, Clone, Copy)]
+pub struct PoolStats {
+    pub strings: usize,
+    pub bytes: usize,
+    pub saved: usize,
+}
+
+pub fn stats() -> PoolStats {
+    let pool = POOL.lock().unwrap();
+    PoolStats {
+        strings: pool.len(),
+        bytes: pool.iter().map(|s| s.len()).sum(),
+        saved: SAVED.load(Ordering::Relaxed),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn identical_literals_share_an_allocation() {
+        let a = intern("shared between programs");
+        let b = intern(&String::from("shared between programs"));
+        assert!(Arc::ptr_eq(&a, &b));
+        assert!(!Arc::ptr_eq(&a, &intern("another literal")));
+    }
+
+    #[test]
+    fn stats_count_the_pooled_strings() {
+        intern("counted once");
+        intern("counted once");
+        let stats = stats();
+        assert!(stats.strings >= 1);
+        assert!(stats.bytes >= "counted once".len());
+        assert!(stats.saved >= "counted once".len());
+    }
+}
@@ -1,10 +1,44 @@
-use std::collections::HashMap;
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    rc::Rc,
+    sync::atomic::{AtomicU64, Ordering},
+};
 
-use crate::{interpreter::Value, Token};
+use crate::{function::Function, interpreter::Value, symbol::Symbol, Token};
+
+// The id the next class declaration gets.
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
 
 #[derive(Debug, Clone)]
 pub struct LoxClass {
     pub name: String,
+    /// Tells classes apart where their names can't: a class declared again
+    /// under the same name, shadowed, or reloaded gets a new id.
+    pub id: u64,
+    /// The name in the class's declaration.
+    pub declaration: Option<Token>,
+    pub methods: HashMap<Symbol, Rc<Function>>,
+}
+
+impl LoxClass {
+    pub fn new(name: String) -> Self {
+        LoxClass {
+            name,
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+            declaration: None,
+            methods: HashMap::new(),
+        }
+    }
+
+    /// The class declared as `name`, with `methods`.
+    pub fn declared(name: Token, methods: HashMap<Symbol, Rc<Function>>) -> Self {
+        LoxClass {
+            declaration: Some(name.clone()),
+            methods,
+            ..LoxClass::new(name.lexeme().to_string())
+        }
+    }
 }
 
 impl std::fmt::Display for LoxClass {
@@ -15,21 +49,27 @@ impl std::fmt::Display for LoxClass {
 
 #[derive(Debug, Clone)]
 pub struct LoxInstance {
-    pub klass: LoxClass,
-    pub fields: HashMap<String, Value>,
+    pub klass: Rc<LoxClass>,
+    pub fields: HashMap<Symbol, Value>,
 }
 
 impl LoxInstance {
-    pub fn get(&self, name: Token) -> Value {
-        if let Some(value) = self.fields.get(&name.lexeme) {
-            *value
+    /// The field `name` of `instance`, or else its class's method `name`
+    /// bound to it, or else nil.
+    pub fn get(instance: &Rc<RefCell<LoxInstance>>, name: Symbol) -> Value {
+        let this = instance.borrow();
+        if let Some(value) = this.fields.get(&name) {
+            value.clone()
+        } else if let Some(method) = this.klass.methods.get(&name) {
+            let bound = method.bind(Value::Instance(Rc::clone(instance)));
+            Value::Callable(Rc::new(bound))
         } else {
             Value::None
         }
     }
 
     pub fn set(&mut self, name: Token, value: Value) {
-        self.fields.insert(name.lexeme.clone(), value);
+        self.fields.insert(name.symbol, value);
     }
 }
 
@@ -0,0 +1,453 @@
+//! `jlox lsp`: a language server, so editors can show Lox's diagnostics as
+//! the code is typed, jump to where a name is declared, describe it on
+//! hover and outline a file's declarations.
+//!
+//! The client sends JSON-RPC messages on stdin, each after a
+//! `Content-Length` header, and reads replies and notifications the same
+//! way on stdout. Documents are sent whole on every change and analyzed
+//! again from scratch, which is quick for files of the size Lox programs
+//! are.
+
+use std::{
+    collections::HashMap,
+    io::{self, BufRead, Write},
+    panic::{self, AssertUnwindSafe},
+    process,
+    sync::atomic::Ordering,
+};
+
+use crate::{
+    arena::Arena,
+    capture,
+    diagnostics::{self, Diagnostic, Severity},
+    json::Json,
+    resolver::{self, DeclarationKind, Resolution},
+    source::SourceId,
+    statement::{Stmt, StmtId},
+    token::Token,
+};
+
+// JSON-RPC's error codes.
+const PARSE_ERROR: f64 = -32700.0;
+const INVALID_REQUEST: f64 = -32600.0;
+const METHOD_NOT_FOUND: f64 = -32601.0;
+
+/// Serves one client until it asks the server to exit.
+pub fn run() -> io::Result<()> {
+    // A bug found while analyzing a document is logged, not shown to the
+    // client as a crash.
+    panic::set_hook(Box::new(|info| eprintln!("jlox lsp: {}", info)));
+    let mut server = Server {
+        documents: HashMap::new(),
+        shut_down: false,
+        output: io::stdout(),
+    };
+    let mut input = io::stdin().lock();
+    while let Some(body) = read_message(&mut input)? {
+        match Json::parse(&body) {
+            Ok(message) => server.handle(&message)?,
+            Err(e) => server.reply_error(Json::Null, PARSE_ERROR, &e)?,
+        }
+    }
+    Ok(())
+}
+
+/// Reads the body of the next message, or `None` once the client has
+/// closed stdin.
+pub(crate) fn read_message(input: &mut impl BufRead) -> io::Result<Option<String>> {
+    let mut length = None;
+    loop {
+        let mut header = String::new();
+        if input.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("Content-Length") {
+                length = value.trim().parse().ok();
+            }
+        }
+    }
+    let Some(length) = length else {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "message without a Content-Length header",
+        ));
+    };
+    let mut body = vec![0; length];
+    input.read_exact(&mut body)?;
+    String::from_utf8(body)
+        .map(Some)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+/// An open document and what analyzing it last found.
+struct Document {
+    text: String,
+    arena: Arena,
+    statements: Vec<StmtId>,
+    resolution: Resolution,
+}
+
+struct Server {
+    documents: HashMap<String, Document>,
+    shut_down: bool,
+    output: io::Stdout,
+}
+
+impl Server {
+    fn handle(&mut self, message: &Json) -> io::Result<()> {
+        let method = message.get("method").and_then(Json::as_str).unwrap_or("");
+        let params = message.get("params").unwrap_or(&Json::Null);
+        let Some(id) = message.get("id").cloned() else {
+            return self.notified(method, params);
+        };
+        if self.shut_down {
+            return self.reply_error(id, INVALID_REQUEST, "The server has shut down.");
+        }
+        let result = match method {
+            "initialize" => capabilities(),
+            "shutdown" => {
+                self.shut_down = true;
+                Json::Null
+            }
+            "textDocument/definition" => self.definition(params),
+            "textDocument/hover" => self.hover(params),
+            "textDocument/documentSymbol" => self.symbols(params),
+            _ => {
+                let message = format!("Unknown method '{}'.", method);
+                return self.reply_error(id, METHOD_NOT_FOUND, &message);
+            }
+        };
+        self.send(Json::object([
+            ("jsonrpc", Json::from("2.0")),
+            ("id", id),
+            ("result", result),
+        ]))
+    }
+
+    fn notified(&mut self, method: &str, params: &Json) -> io::Result<()> {
+        let uri = document_uri(params).unwrap_or("").to_string();
+        match method {
+            "textDocument/didOpen" => {
+                let text = params.get("textDocument").and_then(|d| d.get("text"));
+                self.update(uri, text.and_then(Json::as_str).unwrap_or(""))
+            }
+            "textDocument/didChange" => {
+                // Only whole documents are asked for, so the last change
+                // has all of the text.
+                let text = match params.get("contentChanges") {
+                    Some(Json::Array(changes)) => changes.last().and_then(|c| c.get("text")),
+                    _ => None,
+                };
+                match text.and_then(Json::as_str) {
+                    Some(text) => self.update(uri, text),
+                    None => Ok(()),
+                }
+            }
+            "textDocument/didClose" => {
+                self.documents.remove(&uri);
+                self.publish(&uri, "", Vec::new())
+            }
+            "exit" => process::exit(if self.shut_down { 0 } else { 1 }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Analyzes the new `text` of the document at `uri` and sends its
+    /// diagnostics.
+    fn update(&mut self, uri: String, text: &str) -> io::Result<()> {
+        let analyzed = panic::catch_unwind(AssertUnwindSafe(|| analyze(&uri, text)));
+        let (document, diagnostics) = match analyzed {
+            Ok(analyzed) => analyzed,
+            // Whatever was found last time stands.
+            Err(_) => return Ok(()),
+        };
+        self.publish(&uri, text, diagnostics)?;
+        self.documents.insert(uri, document);
+        Ok(())
+    }
+
+    fn publish(&mut self, uri: &str, text: &str, diagnostics: Vec<Diagnostic>) -> io::Result<()> {
+        let diagnostics = diagnostics
+            .iter()
+            .map(|diagnostic| {
+                let start = position(text, diagnostic.line, diagnostic.column);
+                let end = position(text, diagnostic.line, diagnostic.column + diagnostic.length);
+                let severity = match diagnostic.severity {
+                    Severity::Warn => 2.0,
+                    _ => 1.0,
+                };
+                Json::object([
+                    ("range", range(start, end)),
+                    ("severity", Json::from(severity)),
+                    ("code", diagnostic.code.map_or(Json::Null, Json::from)),
+                    ("source", Json::from("jlox")),
+                    ("message", Json::from(diagnostic.message.as_str())),
+                ])
+            })
+            .collect();
+        self.send(Json::object([
+            ("jsonrpc", Json::from("2.0")),
+            ("method", Json::from("textDocument/publishDiagnostics")),
+            (
+                "params",
+                Json::object([
+                    ("uri", Json::from(uri)),
+                    ("diagnostics", Json::Array(diagnostics)),
+                ]),
+            ),
+        ]))
+    }
+
+    fn definition(&self, params: &Json) -> Json {
+        let Some((uri, document, declaration)) = self.declaration_at(params) else {
+            return Json::Null;
+        };
+        let name = &document.resolution.declarations[declaration].name;
+        Json::object([
+            ("uri", Json::from(uri)),
+            ("range", token_range(&document.text, name)),
+        ])
+    }
+
+    fn hover(&self, params: &Json) -> Json {
+        let Some((_, document, declaration)) = self.declaration_at(params) else {
+            return Json::Null;
+        };
+        let signature = describe(&document.resolution, declaration);
+        Json::object([(
+            "contents",
+            Json::object([
+                ("kind", Json::from("markdown")),
+                ("value", Json::from(format!("```lox\n{}\n```", signature))),
+            ]),
+        )])
+    }
+
+    fn symbols(&self, params: &Json) -> Json {
+        let document = document_uri(params).and_then(|uri| self.documents.get(uri));
+        match document {
+            Some(document) => Json::Array(outline(
+                &document.text,
+                &document.arena,
+                &document.statements,
+            )),
+            None => Json::Null,
+        }
+    }
+
+    /// The document a position request is about, and the index of the
+    /// declaration of the name at its position, if there's one there.
+    fn declaration_at<'a>(&'a self, params: &'a Json) -> Option<(&'a str, &'a Document, usize)> {
+        let uri = document_uri(params)?;
+        let document = self.documents.get(uri)?;
+        let position = params.get("position")?;
+        let line = position.get("line")?.as_f64()? as u32 + 1;
+        let character = position.get("character")?.as_f64()? as u32;
+        let column = column(&document.text, line, character);
+        let covers = |name: &Token| {
+            let length = name.lexeme().chars().count() as u32;
+            name.line == line && (name.column..=name.column + length).contains(&column)
+        };
+        let resolution = &document.resolution;
+        let declaration = resolution
+            .references
+            .iter()
+            .find(|(name, _)| covers(name))
+            .map(|(_, declaration)| *declaration)
+            .or_else(|| {
+                resolution
+                    .declarations
+                    .iter()
+                    .position(|declaration| covers(&declaration.name))
+            })?;
+        Some((uri, document, declaration))
+    }
+
+    fn reply_error(&mut self, id: Json, code: f64, message: &str) -> io::Result<()> {
+        self.send(Json::object([
+            ("jsonrpc", Json::from("2.0")),
+            ("id", id),
+            (
+                "error",
+                Json::object([("code", Json::from(code)), ("message", Json::from(message))]),
+            ),
+        ]))
+    }
+
+    fn send(&mut self, message: Json) -> io::Result<()> {
+        let body = message.to_string();
+        write!(
+            self.output,
+            "Content-Length: {}\r\n\r\n{}",
+            body.len(),
+            body
+        )?;
+        self.output.flush()
+    }
+}
+
+fn capabilities() -> Json {
+    Json::object([
+        (
+            "capabilities",
+            Json::object([
+                // Whole documents, on open and on every change.
+                ("textDocumentSync", Json::from(1.0)),
+                ("definitionProvider", Json::from(true)),
+                ("hoverProvider", Json::from(true)),
+                ("documentSymbolProvider", Json::from(true)),
+            ]),
+        ),
+        (
+            "serverInfo",
+            Json::object([
+                ("name", Json::from("jlox")),
+                ("version", Json::from(env!("CARGO_PKG_VERSION"))),
+            ]),
+        ),
+    ])
+}
+
+/// Scans, parses and resolves `text`, as `jlox check` would.
+fn analyze(uri: &str, text: &str) -> (Document, Vec<Diagnostic>) {
+    diagnostics::reset();
+    let (arena, statements) = capture::analyze(text, SourceId::register(uri));
+    let found = diagnostics::ordered(diagnostics::take());
+    crate::HAD_ERROR.store(false, Ordering::Relaxed);
+    // Unlike `check`, names are resolved even when some statements didn't
+    // parse, so the rest of the file can still be navigated.
+    let resolution = resolver::scopes(&arena, &statements);
+    let document = Document {
+        text: text.to_string(),
+        arena,
+        statements,
+        resolution,
+    };
+    (document, found)
+}
+
+fn document_uri(params: &Json) -> Option<&str> {
+    params.get("textDocument")?.get("uri")?.as_str()
+}
+
+/// How a declaration is shown on hover.
+fn describe(resolution: &Resolution, index: usize) -> String {
+    let declaration = &resolution.declarations[index];
+    let name = declaration.name.lexeme();
+    let params = |params: &[Token]| itertools::join(params.iter().map(Token::lexeme), ", ");
+    match &declaration.kind {
+        DeclarationKind::Variable => format!("var {}", name),
+        DeclarationKind::Constant => format!("const {}", name),
+        DeclarationKind::Parameter => format!("(parameter) {}", name),
+        DeclarationKind::Function(parameters) => format!("fun {}({})", name, params(parameters)),
+        DeclarationKind::Method(parameters, class) => {
+            let class = resolution.declarations[*class].name.lexeme();
+            format!("(method) {}.{}({})", class, name, params(parameters))
+        }
+        DeclarationKind::Class => format!("class {}", name),
+        DeclarationKind::Import => format!("(import) {}", name),
+    }
+}
+
+/// The variables, functions and classes declared at the top of a file, with
+/// each class's methods inside it.
+fn outline(text: &str, arena: &Arena, statements: &[StmtId]) -> Vec<Json> {
+    // The kinds LSP numbers them as.
+    const CLASS: f64 = 5.0;
+    const METHOD: f64 = 6.0;
+    const FUNCTION: f64 = 12.0;
+    const VARIABLE: f64 = 13.0;
+    const CONSTANT: f64 = 14.0;
+
+    let symbol = |name: &Token, kind: f64, children: Vec<Json>| {
+        let range = token_range(text, name);
+        Json::object([
+            ("name", Json::from(name.lexeme())),
+            ("kind", Json::from(kind)),
+            ("range", range.clone()),
+            ("selectionRange", range),
+            ("children", Json::Array(children)),
+        ])
+    };
+    let mut symbols = Vec::new();
+    for statement in statements {
+        let mut statement = arena.stmt(*statement);
+        if let Stmt::Export(_, declaration) = statement {
+            statement = arena.stmt(*declaration);
+        }
+        match statement {
+            Stmt::Var(name, _) => symbols.push(symbol(name, VARIABLE, Vec::new())),
+            Stmt::Const(name, _) => symbols.push(symbol(name, CONSTANT, Vec::new())),
+            Stmt::Destructure(names, _) => {
+                symbols.extend(names.iter().map(|name| symbol(name, VARIABLE, Vec::new())))
+            }
+            Stmt::Function(name, _, _) => symbols.push(symbol(name, FUNCTION, Vec::new())),
+            Stmt::Class(name, methods) => {
+                let methods = methods
+                    .iter()
+                    .filter_map(|method| match arena.stmt(*method) {
+                        Stmt::Function(name, _, _) => Some(symbol(name, METHOD, Vec::new())),
+                        _ => None,
+                    })
+                    .collect();
+                symbols.push(symbol(name, CLASS, methods));
+            }
+            _ => {}
+        }
+    }
+    symbols
+}
+
+fn token_range(text: &str, token: &Token) -> Json {
+    let length = token.lexeme().chars().count() as u32;
+    range(
+        position(text, token.line, token.column),
+        position(text, token.line, token.column + length),
+    )
+}
+
+fn range(start: Json, end: Json) -> Json {
+    Json::object([("start", start), ("end", end)])
+}
+
+/// An LSP position for a 1-based line and column. Lines count from 0 in
+/// LSP, and characters in UTF-16 code units rather than characters.
+fn position(text: &str, line: u32, column: u32) -> Json {
+    let line_text = text
+        .lines()
+        .nth(line.saturating_sub(1) as usize)
+        .unwrap_or("");
+    let character: usize = line_text
+        .chars()
+        .take(column.saturating_sub(1) as usize)
+        .map(char::len_utf16)
+        .sum();
+    Json::object([
+        ("line", Json::from(line.saturating_sub(1) as usize)),
+        ("character", Json::from(character)),
+    ])
+}
+
+/// The 1-based column of the character `character` UTF-16 code units into
+/// `line`.
+fn column(text: &str, line: u32, character: u32) -> u32 {
+    let line_text = text
+        .lines()
+        .nth(line.saturating_sub(1) as usize)
+        .unwrap_or("");
+    let mut units = 0;
+    let mut column = 1;
+    for c in line_text.chars() {
+        if units >= character as usize {
+            break;
+        }
+        units += c.len_utf16();
+        column += 1;
+    }
+    column
+}
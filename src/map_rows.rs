@@ -0,0 +1,279 @@
+//! `jlox map-rows`: evaluates one expression against every row of a CSV or
+//! JSON file, awk-style. The expression is parsed once; each row is then
+//! bound to `row` in turn, with its columns as fields, so
+//! `--expr 'row.price * row.qty'` works out a total per line.
+
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    fs,
+    io::{self, Write},
+    path::Path,
+    rc::Rc,
+    sync::{atomic::Ordering, Arc},
+};
+
+use crate::{
+    arena::Arena,
+    cli::Options,
+    diagnostics,
+    expression::ExprId,
+    folder,
+    interpreter::Value,
+    json::Json,
+    lox_class::{LoxClass, LoxInstance},
+    natives,
+    output::StdoutSink,
+    parser::Parser,
+    scanner::Scanner,
+    source::SourceId,
+    statement::{Stmt, StmtId},
+    symbol::Symbol,
+};
+
+/// How results are written: one per line either way.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum RowFormat {
+    /// A CSV file with a single `result` column.
+    Csv,
+    /// JSON Lines: one JSON value per line.
+    Json,
+}
+
+/// Evaluates `options.expr` for each row of the file at `path` and returns
+/// the exit code: 65 if the expression doesn't parse, 66 if the file can't
+/// be read and 70 if the expression failed on any row.
+pub fn run(path: &str, options: &Options) -> i32 {
+    let Some(source) = &options.expr else {
+        println!("'map-rows' expects the expression to evaluate, as --expr <code>.");
+        return 64;
+    };
+    let (ast, expr) = match compile(source) {
+        Ok(compiled) => compiled,
+        Err(code) => return code,
+    };
+    let rows = match read_rows(path) {
+        Ok(rows) => rows,
+        Err(e) => {
+            println!("Could not read {}: {}", path, e);
+            return 66;
+        }
+    };
+
+    let mut interpreter = crate::new_interpreter(Path::new("."), options);
+    // One bad row shouldn't stop the rest.
+    interpreter.error_values(true);
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    if options.row_format == RowFormat::Csv {
+        let _ = writeln!(out, "result");
+    }
+    let mut failed = 0;
+    for (index, row) in rows.into_iter().enumerate() {
+        interpreter.globals.borrow_mut().define("row", row);
+        let value = match interpreter.evaluate_in(&ast, expr) {
+            Value::Error(message) => {
+                eprintln!("{}: row {}: {}", path, index + 1, message);
+                failed += 1;
+                Value::None
+            }
+            value => value,
+        };
+        diagnostics::flush(&StdoutSink);
+        if let Some(code) = natives::take_exit_request() {
+            return code;
+        }
+        let line = match options.row_format {
+            RowFormat::Csv => csv_field(&value),
+            RowFormat::Json => to_json(&value).to_string(),
+        };
+        if writeln!(out, "{}", line).is_err() {
+            // Whatever was reading the output has gone away.
+            return 0;
+        }
+    }
+    if failed > 0 {
+        70
+    } else {
+        0
+    }
+}
+
+/// Parses and folds the expression, reporting any static errors.
+fn compile(source: &str) -> Result<(Arc<Arena>, ExprId), i32> {
+    diagnostics::reset();
+    let source = format!("{};", source.trim_end().trim_end_matches(';'));
+    let tokens = Scanner::new(&source, SourceId::register("--expr")).tokens();
+    let (ast, statements) = folder::parse_and_fold(Parser::new(tokens));
+    diagnostics::flush(&StdoutSink);
+    if crate::HAD_ERROR.load(Ordering::Relaxed) {
+        return Err(65);
+    }
+    match <[StmtId; 1]>::try_from(statements).map(|[statement]| ast.stmt(statement)) {
+        Ok(&Stmt::Expression(expr)) => Ok((Arc::clone(&ast), expr)),
+        _ => {
+            println!("--expr must be a single expression.");
+            Err(65)
+        }
+    }
+}
+
+/// The rows of a `.json` file, or of a CSV file otherwise.
+fn read_rows(path: &str) -> Result<Vec<Value>, String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("{}.", e))?;
+    if Path::new(path).extension().is_some_and(|ext| ext == "json") {
+        json_rows(&text)
+    } else {
+        csv_rows(&text)
+    }
+}
+
+/// The records of a CSV file with a header line, each as an instance with
+/// a field per column. Fields that read as numbers become numbers, empty
+/// ones nil, and missing ones are left out.
+fn csv_rows(text: &str) -> Result<Vec<Value>, String> {
+    let mut records = parse_csv(text)?.into_iter();
+    let header = records.next().unwrap_or_default();
+    let class = Rc::new(LoxClass::new("Row".to_string()));
+    Ok(records
+        .map(|record| {
+            let fields = header
+                .iter()
+                .zip(record)
+                .map(|(name, field)| (Symbol::intern(name), field_value(&field)))
+                .collect();
+            instance(&class, fields)
+        })
+        .collect())
+}
+
+/// Splits CSV text into records of fields, as RFC 4180 describes: a field
+/// may be quoted, and then hold commas, line breaks and doubled quotes.
+fn parse_csv(text: &str) -> Result<Vec<Vec<String>>, String> {
+    let mut records = Vec::new();
+    let mut record = Vec::new();
+    let mut field = String::new();
+    let mut line = 1;
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if field.is_empty() => {
+                let start = line;
+                loop {
+                    match chars.next() {
+                        Some('"') if chars.peek() == Some(&'"') => {
+                            chars.next();
+                            field.push('"');
+                        }
+                        Some('"') => break,
+                        Some(c) => {
+                            if c == '\n' {
+                                line += 1;
+                            }
+                            field.push(c);
+                        }
+                        None => {
+                            return Err(format!("unterminated quoted field on line {}.", start))
+                        }
+                    }
+                }
+            }
+            ',' => record.push(std::mem::take(&mut field)),
+            '\r' if chars.peek() == Some(&'\n') => {}
+            '\n' => {
+                line += 1;
+                record.push(std::mem::take(&mut field));
+                records.push(std::mem::take(&mut record));
+            }
+            c => field.push(c),
+        }
+    }
+    if !field.is_empty() || !record.is_empty() {
+        record.push(field);
+        records.push(record);
+    }
+    Ok(records)
+}
+
+fn field_value(field: &str) -> Value {
+    if field.is_empty() {
+        return Value::None;
+    }
+    // `inf` and `NaN` parse as numbers but are far likelier to be text.
+    match field.trim().parse::<f64>() {
+        Ok(n) if n.is_finite() => Value::Number(n),
+        _ => Value::String(field.into()),
+    }
+}
+
+/// The rows of a JSON file holding an array. Objects become instances, so
+/// their members read as fields; other elements are bound as they are.
+fn json_rows(text: &str) -> Result<Vec<Value>, String> {
+    let Json::Array(items) = Json::parse(text)? else {
+        return Err("expected an array of rows.".to_string());
+    };
+    let class = Rc::new(LoxClass::new("Row".to_string()));
+    Ok(items.iter().map(|item| from_json(item, &class)).collect())
+}
+
+fn from_json(json: &Json, class: &Rc<LoxClass>) -> Value {
+    match json {
+        Json::Null => Value::None,
+        Json::Bool(b) => Value::Boolean(*b),
+        Json::Number(n) => Value::Number(*n),
+        Json::String(s) => Value::String(s.as_str().into()),
+        Json::Array(items) => Value::List(Rc::new(RefCell::new(
+            items.iter().map(|item| from_json(item, class)).collect(),
+        ))),
+        Json::Object(entries) => instance(
+            class,
+            entries
+                .iter()
+                .map(|(key, value)| (Symbol::intern(key), from_json(value, class)))
+                .collect(),
+        ),
+    }
+}
+
+fn instance(class: &Rc<LoxClass>, fields: HashMap<Symbol, Value>) -> Value {
+    Value::Instance(Rc::new(RefCell::new(LoxInstance {
+        klass: Rc::clone(class),
+        fields,
+    })))
+}
+
+/// A result as a CSV field: nil is left empty, and text is quoted when it
+/// would otherwise be misread.
+fn csv_field(value: &Value) -> String {
+    let text = match value {
+        Value::None => return String::new(),
+        value => value.to_string(),
+    };
+    if text.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text
+    }
+}
+
+fn to_json(value: &Value) -> Json {
+    match value {
+        Value::None => Json::Null,
+        Value::Boolean(b) => Json::Bool(*b),
+        Value::Number(n) => Json::Number(*n),
+        Value::String(s) => Json::String(s.to_string()),
+        Value::List(items) => Json::Array(items.borrow().iter().map(to_json).collect()),
+        Value::Instance(instance) => {
+            let instance = instance.borrow();
+            let mut fields: Vec<_> = instance.fields.iter().collect();
+            fields.sort_by_key(|(name, _)| name.as_str());
+            Json::Object(
+                fields
+                    .into_iter()
+                    .map(|(name, value)| (name.to_string(), to_json(value)))
+                    .collect(),
+            )
+        }
+        other => Json::String(other.to_string()),
+    }
+}
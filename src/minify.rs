@@ -0,0 +1,616 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::{
+    arena::Arena,
+    expression::{Expr, ExprId, ExprVisitor},
+    operators::{Operators, Precedence},
+    source::Span,
+    statement::{ImportTarget, Stmt, StmtId},
+    symbol::Symbol,
+    token::{Literal, Token, TokenType},
+};
+
+const KEYWORDS: [&str; 16] = [
+    "and", "class", "else", "false", "for", "fun", "if", "nil", "or", "print", "return", "super",
+    "this", "true", "var", "while",
+];
+
+/// Shrinks a parsed program: locals get short generated names, and the
+/// result is re-emitted with only the whitespace and parentheses needed to
+/// parse back to the same tree. Globals, properties and methods keep their
+/// names so the program's observable interface is unchanged. Renamed
+/// nodes are added to the arena the program was parsed into.
+pub struct Minifier<'a> {
+    arena: &'a mut Arena,
+    // The program's word operators, which a generated name mustn't be.
+    operators: &'a Operators,
+    flatten: bool,
+    scopes: Vec<HashMap<String, Span>>,
+    reserved: HashSet<String>,
+    next_name: usize,
+}
+
+impl<'a> Minifier<'a> {
+    pub fn new(
+        arena: &'a mut Arena,
+        tokens: &[Token],
+        operators: &'a Operators,
+        flatten: bool,
+    ) -> Self {
+        let reserved = tokens
+            .iter()
+            .filter(|t| t.token_type == TokenType::Identifier)
+            .map(|t| t.lexeme().to_string())
+            .collect();
+        Minifier {
+            arena,
+            operators,
+            flatten,
+            scopes: Vec::new(),
+            reserved,
+            next_name: 0,
+        }
+    }
+
+    pub fn minify(&mut self, statements: Vec<StmtId>) -> String {
+        let mut statements = self.rename_stmts(statements);
+        if self.flatten {
+            statements = flatten_stmts(self.arena, statements);
+        }
+
+        let mut emitter = Emitter {
+            arena: self.arena,
+            out: String::new(),
+        };
+        for stmt in statements {
+            emitter.stmt(stmt);
+        }
+        emitter.out
+    }
+
+    fn fresh_name(&mut self) -> String {
+        loop {
+            let mut n = self.next_name;
+            self.next_name += 1;
+            let mut name = String::new();
+            loop {
+                name.insert(0, (b'a' + (n % 26) as u8) as char);
+                if n < 26 {
+                    break;
+                }
+                n = n / 26 - 1;
+            }
+            if !KEYWORDS.contains(&name.as_str())
+                && self.operators.word(&name).is_none()
+                && !self.reserved.contains(&name)
+            {
+                return name;
+            }
+        }
+    }
+
+    fn declare(&mut self, name: Token) -> Token {
+        if self.scopes.is_empty() {
+            return name;
+        }
+        let short = Span::add(&self.fresh_name());
+        self.scopes
+            .last_mut()
+            .unwrap()
+            .insert(name.lexeme().to_string(), short);
+        Token {
+            span: short,
+            symbol: Symbol::intern(short.text()),
+            ..name
+        }
+    }
+
+    fn lookup(&self, name: Token) -> Token {
+        for scope in self.scopes.iter().rev() {
+            if let Some(short) = scope.get(name.lexeme()) {
+                return Token {
+                    span: *short,
+                    symbol: Symbol::intern(short.text()),
+                    ..name
+                };
+            }
+        }
+        name
+    }
+
+    fn rename_stmts(&mut self, statements: Vec<StmtId>) -> Vec<StmtId> {
+        statements
+            .into_iter()
+            .map(|stmt| self.rename_stmt(stmt))
+            .collect()
+    }
+
+    fn rename_function(
+        &mut self,
+        params: Vec<Token>,
+        body: Vec<StmtId>,
+    ) -> (Vec<Token>, Vec<StmtId>) {
+        self.scopes.push(HashMap::new());
+        let params = params.into_iter().map(|p| self.declare(p)).collect();
+        let body = self.rename_stmts(body);
+        self.scopes.pop();
+        (params, body)
+    }
+
+    fn rename_stmt(&mut self, stmt: StmtId) -> StmtId {
+        let renamed = match self.arena.stmt(stmt).clone() {
+            Stmt::Expression(e) => Stmt::Expression(self.rename_id(e)),
+            Stmt::Print(keyword, e) => Stmt::Print(keyword, self.rename_id(e)),
+            Stmt::Var(name, init) => {
+                let init = init.map(|e| self.rename_id(e));
+                Stmt::Var(self.declare(name), init)
+            }
+            Stmt::Const(name, init) => {
+                let init = self.rename_id(init);
+                Stmt::Const(self.declare(name), init)
+            }
+            Stmt::Destructure(names, init) => {
+                let init = self.rename_id(init);
+                let names = names.into_iter().map(|name| self.declare(name)).collect();
+                Stmt::Destructure(names, init)
+            }
+            Stmt::Block(stmts) => {
+                self.scopes.push(HashMap::new());
+                let stmts = self.rename_stmts(stmts);
+                self.scopes.pop();
+                Stmt::Block(stmts)
+            }
+            Stmt::If(condition, then_stmt, else_stmt) => Stmt::If(
+                self.rename_id(condition),
+                self.rename_stmt(then_stmt),
+                else_stmt.map(|s| self.rename_stmt(s)),
+            ),
+            Stmt::While(condition, body) => {
+                Stmt::While(self.rename_id(condition), self.rename_stmt(body))
+            }
+            Stmt::ForIn(name, iterable, body) => {
+                let iterable = self.rename_id(iterable);
+                self.scopes.push(HashMap::new());
+                let name = self.declare(name);
+                let body = self.rename_stmt(body);
+                self.scopes.pop();
+                Stmt::ForIn(name, iterable, body)
+            }
+            Stmt::Function(name, params, body) => {
+                let name = self.declare(name);
+                let (params, body) = self.rename_function(params, body);
+                Stmt::Function(name, params, body)
+            }
+            Stmt::Return(keyword, value) => Stmt::Return(keyword, value.map(|e| self.rename_id(e))),
+            Stmt::Class(name, methods) => {
+                let name = self.declare(name);
+                let methods = methods
+                    .into_iter()
+                    .map(|method| match self.arena.stmt(method).clone() {
+                        Stmt::Function(method_name, params, body) => {
+                            let (params, body) = self.rename_function(params, body);
+                            self.arena
+                                .alloc_stmt(Stmt::Function(method_name, params, body))
+                        }
+                        _ => method,
+                    })
+                    .collect();
+                Stmt::Class(name, methods)
+            }
+            Stmt::Import(keyword, path, ImportTarget::Alias(alias)) => {
+                Stmt::Import(keyword, path, ImportTarget::Alias(self.declare(alias)))
+            }
+            Stmt::Import(keyword, path, ImportTarget::Lazy(alias)) => {
+                Stmt::Import(keyword, path, ImportTarget::Lazy(self.declare(alias)))
+            }
+            Stmt::Import(keyword, path, ImportTarget::Names(names)) => {
+                // These must keep the exported names, but still shadow any
+                // renamed outer binding of the same name.
+                if let Some(scope) = self.scopes.last_mut() {
+                    for name in &names {
+                        scope.insert(name.lexeme().to_string(), name.span);
+                    }
+                }
+                Stmt::Import(keyword, path, ImportTarget::Names(names))
+            }
+            Stmt::Import(..) => return stmt,
+            Stmt::Export(keyword, declaration) => {
+                Stmt::Export(keyword, self.rename_stmt(declaration))
+            }
+        };
+        self.arena.alloc_stmt(renamed)
+    }
+
+    fn rename_expr(&mut self, expr: ExprId) -> Expr {
+        self.arena.expr(expr).clone().accept(self)
+    }
+
+    fn rename_id(&mut self, expr: ExprId) -> ExprId {
+        let renamed = self.rename_expr(expr);
+        self.arena.alloc_expr(renamed)
+    }
+}
+
+impl ExprVisitor<Expr> for Minifier<'_> {
+    fn visit_grouping_expr(&mut self, expr: ExprId) -> Expr {
+        let expr = self.rename_expr(expr);
+        Expr::group(self.arena, expr)
+    }
+
+    fn visit_literal_expr(&self, literal: Literal) -> Expr {
+        Expr::literal(literal)
+    }
+
+    fn visit_unary_expr(&mut self, op: Token, r: ExprId) -> Expr {
+        let right = self.rename_expr(r);
+        Expr::unary(self.arena, op, right)
+    }
+
+    fn visit_binary_expr(&mut self, l: ExprId, op: Token, r: ExprId) -> Expr {
+        let (left, right) = (self.rename_expr(l), self.rename_expr(r));
+        Expr::binary(self.arena, left, op, right)
+    }
+
+    fn visit_var_expr(&mut self, t: Token) -> Expr {
+        Expr::variable(self.lookup(t))
+    }
+
+    fn visit_assign_expr(&mut self, name: Token, value: ExprId) -> Expr {
+        let value = self.rename_expr(value);
+        let name = self.lookup(name);
+        Expr::assign(self.arena, name, value)
+    }
+
+    fn visit_logical_expr(&mut self, l: ExprId, op: Token, r: ExprId) -> Expr {
+        let (left, right) = (self.rename_expr(l), self.rename_expr(r));
+        Expr::logical(self.arena, left, op, right)
+    }
+
+    fn visit_call_expr(&mut self, c: ExprId, paren: Token, args: Vec<ExprId>) -> Expr {
+        let callee = self.rename_expr(c);
+        let args = args.into_iter().map(|a| self.rename_expr(a)).collect();
+        Expr::call(self.arena, callee, paren, args)
+    }
+
+    fn visit_get_expr(&mut self, expr: ExprId, name: Token) -> Expr {
+        let expr = self.rename_expr(expr);
+        Expr::get(self.arena, expr, name)
+    }
+
+    fn visit_set_expr(&mut self, object: ExprId, name: Token, value: ExprId) -> Expr {
+        let (object, value) = (self.rename_expr(object), self.rename_expr(value));
+        Expr::set(self.arena, object, name, value)
+    }
+
+    fn visit_tuple_expr(&mut self, comma: Token, values: Vec<ExprId>) -> Expr {
+        Expr::Tuple(
+            comma,
+            values.into_iter().map(|v| self.rename_id(v)).collect(),
+        )
+    }
+}
+
+fn declares(arena: &Arena, stmt: StmtId) -> bool {
+    matches!(
+        arena.stmt(stmt),
+        Stmt::Var(..)
+            | Stmt::Const(..)
+            | Stmt::Destructure(..)
+            | Stmt::Function(..)
+            | Stmt::Class(..)
+            | Stmt::Import(..)
+            | Stmt::Export(..)
+    )
+}
+
+/// Splices blocks that declare nothing into the surrounding statement list,
+/// and unwraps single-statement blocks used as branch or loop bodies.
+fn flatten_stmts(arena: &mut Arena, statements: Vec<StmtId>) -> Vec<StmtId> {
+    let mut flat = Vec::new();
+    for stmt in statements {
+        let stmt = flatten_stmt(arena, stmt);
+        match arena.stmt(stmt) {
+            Stmt::Block(inner) if !inner.iter().any(|&s| declares(arena, s)) => flat.extend(inner),
+            _ => flat.push(stmt),
+        }
+    }
+    flat
+}
+
+fn flatten_body(arena: &mut Arena, stmt: StmtId) -> StmtId {
+    let stmt = flatten_stmt(arena, stmt);
+    match arena.stmt(stmt) {
+        Stmt::Block(inner) if inner.len() == 1 && !declares(arena, inner[0]) => inner[0],
+        _ => stmt,
+    }
+}
+
+fn flatten_stmt(arena: &mut Arena, stmt: StmtId) -> StmtId {
+    let flattened = match arena.stmt(stmt).clone() {
+        Stmt::Block(stmts) => Stmt::Block(flatten_stmts(arena, stmts)),
+        Stmt::If(condition, then_stmt, else_stmt) => {
+            let mut then_branch = flatten_body(arena, then_stmt);
+            // Unwrapping `{ if (b) x; }` in front of an `else` would let the
+            // inner `if` steal it.
+            if else_stmt.is_some() && matches!(arena.stmt(then_branch), Stmt::If(..)) {
+                then_branch = arena.alloc_stmt(Stmt::Block(vec![then_branch]));
+            }
+            let else_branch = else_stmt.map(|s| flatten_body(arena, s));
+            Stmt::If(condition, then_branch, else_branch)
+        }
+        Stmt::While(condition, body) => Stmt::While(condition, flatten_body(arena, body)),
+        Stmt::ForIn(name, iterable, body) => Stmt::ForIn(name, iterable, flatten_body(arena, body)),
+        Stmt::Function(name, params, body) => {
+            Stmt::Function(name, params, flatten_stmts(arena, body))
+        }
+        Stmt::Class(name, methods) => Stmt::Class(
+            name,
+            methods
+                .into_iter()
+                .map(|m| flatten_stmt(arena, m))
+                .collect(),
+        ),
+        _ => return stmt,
+    };
+    arena.alloc_stmt(flattened)
+}
+
+fn precedence(arena: &Arena, expr: &Expr) -> u8 {
+    match expr {
+        Expr::Assign(..) | Expr::Set(..) => 1,
+        Expr::Logical(_, op, _) if op.token_type == TokenType::Or => 2,
+        Expr::Logical(..) => 3,
+        Expr::Binary(_, op, _) => binary_precedence(op.token_type),
+        Expr::Unary(..) | Expr::Literal(Literal::Number(_)) => 9,
+        Expr::Call(..) | Expr::Get(..) => 10,
+        Expr::Grouping(e) => precedence(arena, arena.expr(*e)),
+        _ => 11,
+    }
+}
+
+fn binary_precedence(token_type: TokenType) -> u8 {
+    match token_type {
+        TokenType::EqualEqual | TokenType::BangEqual => 4,
+        TokenType::Operator(Precedence::Equality) => 4,
+        TokenType::Greater | TokenType::GreaterEqual | TokenType::Less | TokenType::LessEqual => 5,
+        TokenType::Operator(Precedence::Comparison) => 5,
+        TokenType::DotDot | TokenType::DotDotEqual => 6,
+        TokenType::Minus | TokenType::Plus => 7,
+        TokenType::Operator(Precedence::Term) => 7,
+        _ => 8,
+    }
+}
+
+struct Emitter<'a> {
+    arena: &'a Arena,
+    out: String,
+}
+
+impl Emitter<'_> {
+    /// Appends a token, separating it from the previous one only when the
+    /// two would otherwise run together into a single word.
+    fn token(&mut self, text: &str) {
+        let is_word = |c: char| c.is_ascii_alphanumeric() || c == '_';
+        if let (Some(last), Some(first)) = (self.out.chars().last(), text.chars().next()) {
+            if is_word(last) && is_word(first) {
+                self.out.push(' ');
+            }
+        }
+        self.out.push_str(text);
+    }
+
+    fn stmt(&mut self, stmt: StmtId) {
+        match self.arena.stmt(stmt) {
+            Stmt::Expression(e) => {
+                self.expr(*e, 1);
+                self.token(";");
+            }
+            Stmt::Print(_, e) => {
+                self.token("print");
+                self.expr(*e, 1);
+                self.token(";");
+            }
+            Stmt::Var(name, init) => {
+                self.token("var");
+                self.token(name.lexeme());
+                if let Some(init) = init {
+                    self.token("=");
+                    self.expr(*init, 1);
+                }
+                self.token(";");
+            }
+            Stmt::Const(name, init) => {
+                self.token("const");
+                self.token(name.lexeme());
+                self.token("=");
+                self.expr(*init, 1);
+                self.token(";");
+            }
+            Stmt::Destructure(names, init) => {
+                self.token("var");
+                self.token("(");
+                for (i, name) in names.iter().enumerate() {
+                    if i > 0 {
+                        self.token(",");
+                    }
+                    self.token(name.lexeme());
+                }
+                self.token(")");
+                self.token("=");
+                self.expr(*init, 1);
+                self.token(";");
+            }
+            Stmt::Block(stmts) => {
+                self.token("{");
+                for s in stmts {
+                    self.stmt(*s);
+                }
+                self.token("}");
+            }
+            Stmt::If(condition, then_stmt, else_stmt) => {
+                self.token("if");
+                self.token("(");
+                self.expr(*condition, 1);
+                self.token(")");
+                self.stmt(*then_stmt);
+                if let Some(else_stmt) = else_stmt {
+                    self.token("else");
+                    self.stmt(*else_stmt);
+                }
+            }
+            Stmt::While(condition, body) => {
+                self.token("while");
+                self.token("(");
+                self.expr(*condition, 1);
+                self.token(")");
+                self.stmt(*body);
+            }
+            Stmt::ForIn(name, iterable, body) => {
+                self.token("for");
+                self.token("(");
+                self.token("var");
+                self.token(name.lexeme());
+                self.token("in");
+                self.expr(*iterable, 1);
+                self.token(")");
+                self.stmt(*body);
+            }
+            Stmt::Function(name, params, body) => {
+                self.token("fun");
+                self.function(name, params, body);
+            }
+            Stmt::Return(_, value) => {
+                self.token("return");
+                if let Some(value) = value {
+                    self.expr(*value, 1);
+                }
+                self.token(";");
+            }
+            Stmt::Class(name, methods) => {
+                self.token("class");
+                self.token(name.lexeme());
+                self.token("{");
+                for method in methods {
+                    if let Stmt::Function(name, params, body) = self.arena.stmt(*method) {
+                        self.function(name, params, body);
+                    }
+                }
+                self.token("}");
+            }
+            Stmt::Import(_, path, target) => {
+                self.token("import");
+                if let ImportTarget::Lazy(_) = target {
+                    self.token("lazy");
+                }
+                if let ImportTarget::Names(names) = target {
+                    self.token("{");
+                    for (i, name) in names.iter().enumerate() {
+                        if i > 0 {
+                            self.token(",");
+                        }
+                        self.token(name.lexeme());
+                    }
+                    self.token("}");
+                    self.token("from");
+                }
+                self.token(path.lexeme());
+                if let ImportTarget::Alias(alias) | ImportTarget::Lazy(alias) = target {
+                    self.token("as");
+                    self.token(alias.lexeme());
+                }
+                self.token(";");
+            }
+            Stmt::Export(_, declaration) => {
+                self.token("export");
+                self.stmt(*declaration);
+            }
+        }
+    }
+
+    fn function(&mut self, name: &Token, params: &[Token], body: &[StmtId]) {
+        self.token(name.lexeme());
+        self.token("(");
+        for (i, param) in params.iter().enumerate() {
+            if i > 0 {
+                self.token(",");
+            }
+            self.token(param.lexeme());
+        }
+        self.token(")");
+        self.token("{");
+        for s in body {
+            self.stmt(*s);
+        }
+        self.token("}");
+    }
+
+    fn expr(&mut self, expr: ExprId, min_precedence: u8) {
+        let expr = self.arena.expr(expr);
+        let parenthesize = precedence(self.arena, expr) < min_precedence;
+        if parenthesize {
+            self.token("(");
+        }
+        match expr {
+            Expr::Grouping(e) => self.expr(*e, 0),
+            Expr::Literal(Literal::String(s)) => self.token(&format!("\"{}\"", s)),
+            Expr::Literal(Literal::None) => self.token("nil"),
+            Expr::Literal(l) => self.token(&l.to_string()),
+            Expr::Variable(name) => self.token(name.lexeme()),
+            Expr::Assign(name, value) => {
+                self.token(name.lexeme());
+                self.token("=");
+                self.expr(*value, 1);
+            }
+            Expr::Binary(l, op, r) | Expr::Logical(l, op, r) => {
+                let p = precedence(self.arena, expr);
+                // `a..b..c` doesn't parse, so a range on the left needs parentheses.
+                let left = if matches!(op.token_type, TokenType::DotDot | TokenType::DotDotEqual) {
+                    p + 1
+                } else {
+                    p
+                };
+                self.expr(*l, left);
+                self.token(op.lexeme());
+                self.expr(*r, p + 1);
+            }
+            Expr::Unary(op, r) => {
+                self.token(op.lexeme());
+                self.expr(*r, 9);
+            }
+            Expr::Call(callee, _, args) => {
+                self.expr(*callee, 10);
+                self.token("(");
+                for (i, arg) in args.iter().enumerate() {
+                    if i > 0 {
+                        self.token(",");
+                    }
+                    self.expr(*arg, 1);
+                }
+                self.token(")");
+            }
+            Expr::Get(object, name) => {
+                self.expr(*object, 10);
+                self.token(".");
+                self.token(name.lexeme());
+            }
+            Expr::Set(object, name, value) => {
+                self.expr(*object, 10);
+                self.token(".");
+                self.token(name.lexeme());
+                self.token("=");
+                self.expr(*value, 1);
+            }
+            Expr::Tuple(_, values) => {
+                for (i, value) in values.iter().enumerate() {
+                    if i > 0 {
+                        self.token(",");
+                    }
+                    self.expr(*value, 1);
+                }
+            }
+        }
+        if parenthesize {
+            self.token(")");
+        }
+    }
+}
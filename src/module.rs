@@ -0,0 +1,159 @@
+use std::{
+    cell::RefCell,
+    collections::{BTreeSet, HashMap, HashSet},
+    env, fs,
+    path::{Path, PathBuf},
+    rc::Rc,
+    sync::Arc,
+};
+
+use crate::{
+    diagnostics, folder, interpreter::Interpreter, interpreter::Value, natives, parser::Parser,
+    resolver, scanner::Scanner, source::SourceId,
+};
+
+/// The bindings a module made visible with `export`. Exports are replaced
+/// in place by `reload`, so everyone holding the module sees the new values.
+#[derive(Debug)]
+pub struct Module {
+    pub path: PathBuf,
+    pub exports: RefCell<HashMap<String, Value>>,
+    loader: Interpreter,
+}
+
+impl Module {
+    pub fn export(&self, name: &str) -> Option<Value> {
+        self.exports.borrow().get(name).cloned()
+    }
+
+    /// Re-reads and re-executes the module's file, replacing its exports.
+    pub fn reload(&self) -> Result<(), String> {
+        let exports = execute(&self.loader, &self.path, &self.name())?;
+        *self.exports.borrow_mut() = exports;
+        Ok(())
+    }
+
+    /// The module's file name, for messages.
+    pub fn name(&self) -> String {
+        self.path.file_name().map_or_else(
+            || self.path.display().to_string(),
+            |n| n.to_string_lossy().into_owned(),
+        )
+    }
+}
+
+/// The value bound by `import lazy`: the module is loaded the first time
+/// one of its members is read.
+#[derive(Debug)]
+pub struct LazyModule {
+    path: String,
+    loader: Interpreter,
+    module: RefCell<Option<Rc<Module>>>,
+}
+
+impl LazyModule {
+    pub fn new(path: String, loader: Interpreter) -> Self {
+        LazyModule {
+            path,
+            loader,
+            module: RefCell::new(None),
+        }
+    }
+
+    pub fn force(&self) -> Result<Rc<Module>, String> {
+        if let Some(module) = self.module.borrow().as_ref() {
+            return Ok(Rc::clone(module));
+        }
+        let module = load(&self.loader, &self.path)?;
+        *self.module.borrow_mut() = Some(Rc::clone(&module));
+        Ok(module)
+    }
+}
+
+/// Modules loaded so far, shared by every interpreter in a run so each file
+/// is executed at most once.
+#[derive(Debug, Default)]
+pub struct ModuleCache {
+    loaded: HashMap<PathBuf, Rc<Module>>,
+    loading: HashSet<PathBuf>,
+    // Every module file an import has named, even ones that failed to load.
+    files: BTreeSet<PathBuf>,
+}
+
+impl ModuleCache {
+    /// The module files imports have tried to load, loaded or not.
+    pub fn files(&self) -> impl Iterator<Item = &PathBuf> {
+        self.files.iter()
+    }
+}
+
+/// Loads the module at `path` (relative to the importing file's directory),
+/// running it in its own interpreter so its globals stay private.
+pub fn load(importer: &Interpreter, path: &str) -> Result<Rc<Module>, String> {
+    let full_path = importer.base_dir().join(path);
+    let full_path = full_path
+        .canonicalize()
+        .map_err(|_| format!("Could not find module '{}'.", path))?;
+
+    let cache = importer.modules();
+    cache.borrow_mut().files.insert(full_path.clone());
+    if let Some(module) = cache.borrow().loaded.get(&full_path) {
+        return Ok(Rc::clone(module));
+    }
+    if !cache.borrow_mut().loading.insert(full_path.clone()) {
+        return Err(format!("Circular import of module '{}'.", path));
+    }
+
+    let result = execute(importer, &full_path, path);
+    cache.borrow_mut().loading.remove(&full_path);
+
+    let module = Rc::new(Module {
+        path: full_path.clone(),
+        exports: RefCell::new(result?),
+        loader: importer.for_module(importer.base_dir().to_path_buf()),
+    });
+    cache
+        .borrow_mut()
+        .loaded
+        .insert(full_path, Rc::clone(&module));
+    Ok(module)
+}
+
+fn execute(
+    importer: &Interpreter,
+    full_path: &Path,
+    path: &str,
+) -> Result<HashMap<String, Value>, String> {
+    let source = fs::read_to_string(full_path)
+        .map_err(|e| format!("Could not read module '{}': {}.", path, e))?;
+
+    // Name the file relative to the working directory when possible, the way
+    // the main script is usually given.
+    let cwd = env::current_dir().unwrap_or_default();
+    let name = full_path
+        .strip_prefix(&cwd)
+        .unwrap_or(full_path)
+        .display()
+        .to_string();
+    let operators = Arc::clone(importer.operators());
+    let tokens = Scanner::with_operators(&source, SourceId::register(&name), operators).tokens();
+    // Checked and folded like the main script. Its errors come out with the
+    // one reported at the import.
+    let (ast, statements) = folder::parse_and_fold(Parser::new(tokens));
+    if diagnostics::any_pending() {
+        return Err(format!("Could not parse module '{}'.", path));
+    }
+    resolver::check(&ast, &statements);
+    if diagnostics::any_pending() {
+        return Err(format!("Could not load module '{}'.", path));
+    }
+
+    let dir = full_path.parent().unwrap_or(Path::new(".")).to_path_buf();
+    let mut interpreter = importer.for_module(dir);
+    if let Some(code) = interpreter.interpret(&ast, statements) {
+        // An `exit` inside a module ends the whole program, not just the
+        // module, so hand the request back to the importer.
+        natives::request_exit(code);
+    }
+    Ok(interpreter.exported_values())
+}
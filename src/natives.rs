@@ -0,0 +1,878 @@
+use std::{
+    cell::{Cell, RefCell},
+    env, fs,
+    io::Write,
+    rc::Rc,
+    sync::Arc,
+    thread,
+    time::Duration,
+};
+
+use crate::{
+    arena::Arena,
+    dependencies,
+    diagnostics::{self, Diagnostic},
+    environment::Environment,
+    function::Function,
+    interpreter::{Interpreter, RuntimeError, Value},
+    iterate,
+    statement::StmtId,
+    token::{Literal, Token},
+};
+
+pub type NativeFn = fn(&mut NativeContext, &[Value]) -> Result<Value, String>;
+
+/// What a native is given besides its arguments: the interpreter running
+/// it, so it can call back into Lox and reach the globals.
+pub struct NativeContext<'a> {
+    interpreter: &'a mut Interpreter,
+}
+
+impl<'a> NativeContext<'a> {
+    pub fn new(interpreter: &'a mut Interpreter) -> Self {
+        NativeContext { interpreter }
+    }
+
+    /// Calls `callee`, which may run any Lox code, including natives that
+    /// call back in turn.
+    pub fn call(&mut self, callee: &Value, args: Vec<Value>) -> Result<Value, String> {
+        self.interpreter.call_value(callee, args)
+    }
+
+    /// The global called `name`, natives included.
+    pub fn global(&self, name: &str) -> Option<Value> {
+        self.interpreter.globals.borrow().lookup(name)
+    }
+
+    /// The line the function calling this native was called from, or `None`
+    /// at the top level.
+    pub fn caller_line(&self) -> Option<u32> {
+        self.interpreter.caller_line()
+    }
+
+    pub fn define_global(&mut self, name: &str, value: Value) {
+        self.interpreter.globals.borrow_mut().define(name, value);
+    }
+}
+
+/// An entry in one of the native tables below.
+struct Builtin {
+    name: &'static str,
+    arity: usize,
+    body: NativeFn,
+}
+
+const CONSTANTS: &[(&str, f64)] = &[("PI", std::f64::consts::PI), ("E", std::f64::consts::E)];
+
+/// The natives every interpreter starts with. Rather than being defined
+/// one by one in each new interpreter, they're found here by `lookup` the
+/// first time a script uses them.
+const TABLES: &[&[Builtin]] = &[
+    CORE,
+    MATH,
+    RANDOM,
+    CONVERSIONS,
+    LISTS,
+    #[cfg(feature = "units")]
+    UNITS,
+];
+
+/// Finds the default native or constant called `name`.
+pub fn lookup(name: &str) -> Option<Value> {
+    if let Some((_, value)) = CONSTANTS.iter().find(|(constant, _)| *constant == name) {
+        return Some(Value::Number(*value));
+    }
+    TABLES
+        .iter()
+        .flat_map(|table| table.iter())
+        .find(|builtin| builtin.name == name)
+        .map(|builtin| native(builtin.name, builtin.arity, builtin.body))
+}
+
+/// The names `lookup` can find.
+pub fn names() -> Vec<&'static str> {
+    CONSTANTS
+        .iter()
+        .map(|(name, _)| *name)
+        .chain(
+            TABLES
+                .iter()
+                .flat_map(|table| table.iter())
+                .map(|builtin| builtin.name),
+        )
+        .collect()
+}
+
+/// Whether `name` is a native, counting those only defined with
+/// `--allow-fs` or `--allow-env`.
+pub fn is_native(name: &str) -> bool {
+    TABLES
+        .iter()
+        .chain([&FS, &ENV])
+        .flat_map(|table| table.iter())
+        .any(|builtin| builtin.name == name)
+}
+
+/// What a native can do besides compute its result. `jlox audit` flags the
+/// natives a script uses that have one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Effect {
+    /// Reads or writes files.
+    Io,
+    /// Reads or changes the process's state: its environment, its exit
+    /// code, its threads.
+    Process,
+}
+
+impl Effect {
+    pub fn name(self) -> &'static str {
+        match self {
+            Effect::Io => "io",
+            Effect::Process => "process",
+        }
+    }
+}
+
+const EFFECTS: &[(&str, Effect)] = &[
+    ("readFile", Effect::Io),
+    ("writeFile", Effect::Io),
+    ("appendFile", Effect::Io),
+    ("reload", Effect::Io),
+    ("getenv", Effect::Process),
+    ("exit", Effect::Process),
+    ("sleep", Effect::Process),
+    ("parallelMap", Effect::Process),
+];
+
+pub fn effect(name: &str) -> Option<Effect> {
+    EFFECTS
+        .iter()
+        .find(|(native, _)| *native == name)
+        .map(|(_, effect)| *effect)
+}
+
+fn define_all(globals: &mut Environment, table: &[Builtin]) {
+    for builtin in table {
+        globals.define(
+            builtin.name,
+            native(builtin.name, builtin.arity, builtin.body),
+        );
+    }
+}
+
+pub fn native(name: &'static str, arity: usize, body: NativeFn) -> Value {
+    Value::Callable(Rc::new(Function::Native {
+        name,
+        arity,
+        body: Box::new(body),
+    }))
+}
+
+fn number_arg(args: &[Value], index: usize, name: &str) -> Result<f64, String> {
+    match args.get(index) {
+        Some(Value::Number(n)) => Ok(*n),
+        Some(other) => Err(format!(
+            "Argument {} to '{}' must be a number, got {}.",
+            index + 1,
+            name,
+            other
+        )),
+        None => Err(format!("Missing argument {} to '{}'.", index + 1, name)),
+    }
+}
+
+fn string_arg<'a>(args: &'a [Value], index: usize, name: &str) -> Result<&'a str, String> {
+    match args.get(index) {
+        Some(Value::String(s)) => Ok(s),
+        Some(other) => Err(format!(
+            "Argument {} to '{}' must be a string, got {}.",
+            index + 1,
+            name,
+            other
+        )),
+        None => Err(format!("Missing argument {} to '{}'.", index + 1, name)),
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn since_epoch() -> Duration {
+    use std::time::{SystemTime, UNIX_EPOCH};
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("Could not retrieve time.")
+}
+
+/// A browser has no system clock to read, so the page supplies the time.
+#[cfg(target_arch = "wasm32")]
+fn since_epoch() -> Duration {
+    Duration::from_secs_f64(crate::playground::now_millis() / 1000.0)
+}
+
+const CORE: &[Builtin] = &[
+    Builtin {
+        name: "clock",
+        arity: 0,
+        body: |_, _| Ok(Value::Number(since_epoch().as_secs_f64())),
+    },
+    Builtin {
+        name: "clockMillis",
+        arity: 0,
+        body: |_, _| Ok(Value::Number(since_epoch().as_millis() as f64)),
+    },
+    Builtin {
+        name: "sleep",
+        arity: 1,
+        body: |_, args| {
+            let millis = number_arg(args, 0, "sleep")?;
+            if !millis.is_finite() || millis < 0.0 {
+                return Err("Argument to 'sleep' must be a non-negative number.".to_string());
+            }
+            // A browser page can't block, so there's no sleeping there.
+            if cfg!(target_arch = "wasm32") {
+                return Err("'sleep' isn't available in this build.".to_string());
+            }
+            thread::sleep(Duration::from_secs_f64(millis / 1000.0));
+            Ok(Value::None)
+        },
+    },
+    Builtin {
+        name: "exit",
+        arity: 1,
+        body: |_, args| {
+            let code = number_arg(args, 0, "exit")?;
+            if code.fract() != 0.0 || code < i32::MIN as f64 || code > i32::MAX as f64 {
+                return Err("Argument to 'exit' must be an integer.".to_string());
+            }
+            request_exit(code as i32);
+            Ok(Value::None)
+        },
+    },
+    // Fails with the message, reported at the call's line, when the
+    // condition is falsy.
+    Builtin {
+        name: "assert",
+        arity: 2,
+        body: |_, args| match args.first() {
+            None | Some(Value::None) | Some(Value::Boolean(false)) => {
+                let message = args.get(1).map_or(String::new(), |m| m.to_string());
+                Err(format!("Assertion failed: {}", message))
+            }
+            Some(_) => Ok(Value::None),
+        },
+    },
+    // Re-executes a module's file so code holding the module (for example
+    // through `import ... as m`) sees its new exports.
+    Builtin {
+        name: "reload",
+        arity: 1,
+        body: |_, args| {
+            let module = match args.first() {
+                Some(Value::Module(module)) => Rc::clone(module),
+                Some(Value::LazyModule(lazy)) => lazy.force()?,
+                _ => return Err("Argument to 'reload' must be a module.".to_string()),
+            };
+            module.reload()?;
+            Ok(Value::Module(module))
+        },
+    },
+    Builtin {
+        name: "definitionOf",
+        arity: 1,
+        body: |_, args| match &args[0] {
+            value @ (Value::Callable(_) | Value::Class(_)) => Ok(value
+                .definition()
+                .map_or(Value::None, |location| Value::String(location.into()))),
+            other => Err(format!(
+                "Argument to 'definitionOf' must be a function or class, got {}.",
+                other
+            )),
+        },
+    },
+    Builtin {
+        name: "typeof",
+        arity: 1,
+        body: |_, args| {
+            let name = args.first().map_or("nil", Value::type_name);
+            Ok(Value::String(name.into()))
+        },
+    },
+    // Two classes with the same name, or an instance of each, can be told
+    // apart by this where printing them can't.
+    Builtin {
+        name: "classId",
+        arity: 1,
+        body: |_, args| match &args[0] {
+            Value::Class(class) => Ok(Value::Number(class.id as f64)),
+            Value::Instance(instance) => Ok(Value::Number(instance.borrow().klass.id as f64)),
+            other => Err(format!(
+                "Argument to 'classId' must be a class or instance, got {}.",
+                other.type_name()
+            )),
+        },
+    },
+    Builtin {
+        name: "callerLine",
+        arity: 0,
+        body: |context, _| {
+            Ok(context
+                .caller_line()
+                .map_or(Value::None, |line| Value::Number(line as f64)))
+        },
+    },
+];
+
+const MATH: &[Builtin] = &[
+    Builtin {
+        name: "sqrt",
+        arity: 1,
+        body: |_, args| {
+            let n = number_arg(args, 0, "sqrt")?;
+            if n < 0.0 {
+                return Err("Can't take the square root of a negative number.".to_string());
+            }
+            Ok(Value::Number(n.sqrt()))
+        },
+    },
+    Builtin {
+        name: "abs",
+        arity: 1,
+        body: |_, args| Ok(Value::Number(number_arg(args, 0, "abs")?.abs())),
+    },
+    Builtin {
+        name: "floor",
+        arity: 1,
+        body: |_, args| Ok(Value::Number(number_arg(args, 0, "floor")?.floor())),
+    },
+    Builtin {
+        name: "ceil",
+        arity: 1,
+        body: |_, args| Ok(Value::Number(number_arg(args, 0, "ceil")?.ceil())),
+    },
+    Builtin {
+        name: "pow",
+        arity: 2,
+        body: |_, args| {
+            let base = number_arg(args, 0, "pow")?;
+            let exponent = number_arg(args, 1, "pow")?;
+            Ok(Value::Number(base.powf(exponent)))
+        },
+    },
+    Builtin {
+        name: "min",
+        arity: 2,
+        body: |_, args| {
+            let a = number_arg(args, 0, "min")?;
+            let b = number_arg(args, 1, "min")?;
+            Ok(Value::Number(a.min(b)))
+        },
+    },
+    Builtin {
+        name: "max",
+        arity: 2,
+        body: |_, args| {
+            let a = number_arg(args, 0, "max")?;
+            let b = number_arg(args, 1, "max")?;
+            Ok(Value::Number(a.max(b)))
+        },
+    },
+    Builtin {
+        name: "sin",
+        arity: 1,
+        body: |_, args| Ok(Value::Number(number_arg(args, 0, "sin")?.sin())),
+    },
+    Builtin {
+        name: "cos",
+        arity: 1,
+        body: |_, args| Ok(Value::Number(number_arg(args, 0, "cos")?.cos())),
+    },
+];
+
+thread_local! {
+    static RNG_STATE: Cell<u64> = Cell::new(since_epoch().as_nanos() as u64);
+}
+
+/// Reseeds the generator behind `random` and `randomInt` so runs are reproducible.
+pub fn seed_random(seed: u64) {
+    RNG_STATE.with(|state| state.set(seed));
+}
+
+// SplitMix64: small, fast, and good enough for scripting.
+fn next_random() -> u64 {
+    RNG_STATE.with(|state| {
+        let next = state.get().wrapping_add(0x9E37_79B9_7F4A_7C15);
+        state.set(next);
+        let mut z = next;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    })
+}
+
+// Uniform in [0, 1) using the top 53 bits.
+fn random_float() -> f64 {
+    (next_random() >> 11) as f64 / (1u64 << 53) as f64
+}
+
+const RANDOM: &[Builtin] = &[
+    Builtin {
+        name: "random",
+        arity: 0,
+        body: |_, _| Ok(Value::Number(random_float())),
+    },
+    // Both bounds are inclusive.
+    Builtin {
+        name: "randomInt",
+        arity: 2,
+        body: |_, args| {
+            let lo = number_arg(args, 0, "randomInt")?;
+            let hi = number_arg(args, 1, "randomInt")?;
+            if lo.fract() != 0.0 || hi.fract() != 0.0 {
+                return Err("Arguments to 'randomInt' must be integers.".to_string());
+            }
+            if lo > hi {
+                return Err(format!(
+                    "'randomInt' lower bound {} is greater than upper bound {}.",
+                    lo, hi
+                ));
+            }
+            Ok(Value::Number(
+                lo + (random_float() * (hi - lo + 1.0)).floor(),
+            ))
+        },
+    },
+];
+
+const CONVERSIONS: &[Builtin] = &[
+    // Returns nil when the string isn't a finite number.
+    Builtin {
+        name: "num",
+        arity: 1,
+        body: |_, args| {
+            if let Some(Value::Number(n)) = args.first() {
+                return Ok(Value::Number(*n));
+            }
+            let text = string_arg(args, 0, "num")?;
+            match text.trim().parse::<f64>() {
+                Ok(n) if n.is_finite() => Ok(Value::Number(n)),
+                _ => Ok(Value::None),
+            }
+        },
+    },
+    Builtin {
+        name: "toFixed",
+        arity: 2,
+        body: |_, args| {
+            let n = number_arg(args, 0, "toFixed")?;
+            let digits = number_arg(args, 1, "toFixed")?;
+            if digits.fract() != 0.0 || !(0.0..=100.0).contains(&digits) {
+                return Err(
+                    "Digits for 'toFixed' must be an integer between 0 and 100.".to_string()
+                );
+            }
+            Ok(Value::String(format!("{:.*}", digits as usize, n).into()))
+        },
+    },
+];
+
+/// Like file access, reading the environment is opt-in (`--allow-env`).
+pub fn define_env(globals: &mut Environment) {
+    define_all(globals, ENV);
+}
+
+const ENV: &[Builtin] = &[
+    // Returns nil if the variable is unset or isn't valid Unicode.
+    Builtin {
+        name: "getenv",
+        arity: 1,
+        body: |_, args| {
+            let name = string_arg(args, 0, "getenv")?;
+            Ok(env::var(name).map_or(Value::None, |value| Value::String(value.into())))
+        },
+    },
+];
+
+#[cfg(feature = "units")]
+const UNITS: &[Builtin] = &[
+    Builtin {
+        name: "convert",
+        arity: 2,
+        body: |_, args| {
+            let symbol = string_arg(args, 1, "convert")?;
+            crate::units::convert(&args[0], symbol)
+        },
+    },
+    Builtin {
+        name: "magnitude",
+        arity: 1,
+        body: |_, args| match &args[0] {
+            Value::Quantity(n, _) => Ok(Value::Number(*n)),
+            other => Err(format!("'magnitude' expects a quantity, got {}.", other)),
+        },
+    },
+    Builtin {
+        name: "unit",
+        arity: 1,
+        body: |_, args| match &args[0] {
+            Value::Quantity(_, unit) => Ok(Value::String(unit.symbol.into())),
+            other => Err(format!("'unit' expects a quantity, got {}.", other)),
+        },
+    },
+];
+
+/// File access is opt-in (`--allow-fs`) so embedded scripts stay sandboxed
+/// by default.
+pub fn define_fs(globals: &mut Environment) {
+    define_all(globals, FS);
+}
+
+const FS: &[Builtin] = &[
+    // Returns nil if the file can't be read.
+    Builtin {
+        name: "readFile",
+        arity: 1,
+        body: |_, args| {
+            let path = string_arg(args, 0, "readFile")?;
+            Ok(fs::read_to_string(path).map_or(Value::None, |text| Value::String(text.into())))
+        },
+    },
+    Builtin {
+        name: "writeFile",
+        arity: 2,
+        body: |_, args| {
+            let path = string_arg(args, 0, "writeFile")?;
+            let contents = string_arg(args, 1, "writeFile")?;
+            Ok(Value::Boolean(fs::write(path, contents).is_ok()))
+        },
+    },
+    Builtin {
+        name: "appendFile",
+        arity: 2,
+        body: |_, args| {
+            let path = string_arg(args, 0, "appendFile")?;
+            let contents = string_arg(args, 1, "appendFile")?;
+            let written = fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .and_then(|mut file| file.write_all(contents.as_bytes()));
+            Ok(Value::Boolean(written.is_ok()))
+        },
+    },
+];
+
+thread_local! {
+    static EXIT_REQUEST: Cell<Option<i32>> = const { Cell::new(None) };
+}
+
+/// Asks the running interpreter to unwind and finish with `code`.
+pub fn request_exit(code: i32) {
+    EXIT_REQUEST.with(|request| request.set(Some(code)));
+}
+
+/// The exit code requested by `exit()`, if execution is unwinding.
+pub fn exit_requested() -> Option<i32> {
+    EXIT_REQUEST.with(Cell::get)
+}
+
+/// Clears the pending exit request, returning its code.
+pub fn take_exit_request() -> Option<i32> {
+    EXIT_REQUEST.with(Cell::take)
+}
+
+thread_local! {
+    static SCRIPT_ARGS: RefCell<Vec<String>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Sets the command-line arguments that follow the script path, returned
+/// by `args()`.
+pub fn set_script_args(args: Vec<String>) {
+    SCRIPT_ARGS.with(|script_args| *script_args.borrow_mut() = args);
+}
+
+const LISTS: &[Builtin] = &[
+    Builtin {
+        name: "args",
+        arity: 0,
+        body: |_, _| {
+            let items = SCRIPT_ARGS.with(|script_args| {
+                script_args
+                    .borrow()
+                    .iter()
+                    .map(|arg| Value::String(arg.as_str().into()))
+                    .collect()
+            });
+            Ok(Value::List(Rc::new(RefCell::new(items))))
+        },
+    },
+    Builtin {
+        name: "len",
+        arity: 1,
+        body: |_, args| match args.first() {
+            Some(Value::String(s)) => Ok(Value::Number(s.chars().count() as f64)),
+            Some(Value::List(items)) => Ok(Value::Number(items.borrow().len() as f64)),
+            Some(other) => Err(format!(
+                "Argument to 'len' must be a string or list, got {}.",
+                other
+            )),
+            None => Err("Missing argument 1 to 'len'.".to_string()),
+        },
+    },
+    // Returns nil when the index is out of range.
+    Builtin {
+        name: "get",
+        arity: 2,
+        body: |_, args| {
+            let Some(Value::List(items)) = args.first() else {
+                return Err("Argument 1 to 'get' must be a list.".to_string());
+            };
+            let index = number_arg(args, 1, "get")?;
+            if index.fract() != 0.0 || index < 0.0 {
+                return Ok(Value::None);
+            }
+            Ok(items
+                .borrow()
+                .get(index as usize)
+                .cloned()
+                .unwrap_or(Value::None))
+        },
+    },
+    // The list is copied first, so `fn` may change it without affecting
+    // the result.
+    Builtin {
+        name: "map",
+        arity: 2,
+        body: |context, args| {
+            let Some(Value::List(items)) = args.get(1) else {
+                return Err("Argument 2 to 'map' must be a list.".to_string());
+            };
+            let items = items.borrow().clone();
+            let mut results = Vec::with_capacity(items.len());
+            for item in items {
+                results.push(context.call(&args[0], vec![item])?);
+            }
+            Ok(Value::List(Rc::new(RefCell::new(results))))
+        },
+    },
+    Builtin {
+        name: "sort",
+        arity: 2,
+        body: sort,
+    },
+    // What `for (var x in collection)` would step through, as a list.
+    Builtin {
+        name: "toList",
+        arity: 1,
+        body: |_, args| match iterate::items(&args[0]) {
+            Some(items) => Ok(Value::List(Rc::new(RefCell::new(items.collect())))),
+            None => Err(format!(
+                "Argument to 'toList' must be a list, range or instance, got {}.",
+                args[0].type_name()
+            )),
+        },
+    },
+    Builtin {
+        name: "parallelMap",
+        arity: 2,
+        body: parallel_map,
+    },
+];
+
+/// `sort(xs, compare)` returns a sorted copy of `xs`. `compare(a, b)` must
+/// return a negative number when `a` goes first, a positive one when `b`
+/// does, and 0 when either order will do; equal elements keep their order.
+fn sort(context: &mut NativeContext, args: &[Value]) -> Result<Value, String> {
+    let Some(Value::List(items)) = args.first() else {
+        return Err("Argument 1 to 'sort' must be a list.".to_string());
+    };
+    let items = items.borrow().clone();
+    let mut precedes =
+        |a: &Value, b: &Value| match context.call(&args[1], vec![a.clone(), b.clone()])? {
+            Value::Number(n) => Ok(n < 0.0),
+            other => Err(format!(
+                "The function passed to 'sort' must return a number, not {}.",
+                other.type_name()
+            )),
+        };
+    let sorted = merge_sort(items, &mut precedes)?;
+    Ok(Value::List(Rc::new(RefCell::new(sorted))))
+}
+
+/// A stable merge sort. Unlike `slice::sort_by` it copes with comparisons
+/// that fail or don't give a consistent order, as a Lox function's may not.
+fn merge_sort(
+    mut items: Vec<Value>,
+    precedes: &mut dyn FnMut(&Value, &Value) -> Result<bool, String>,
+) -> Result<Vec<Value>, String> {
+    if items.len() <= 1 {
+        return Ok(items);
+    }
+    let right = items.split_off(items.len() / 2);
+    let mut left = merge_sort(items, precedes)?.into_iter().peekable();
+    let mut right = merge_sort(right, precedes)?.into_iter().peekable();
+    let mut merged = Vec::with_capacity(left.len() + right.len());
+    while let (Some(l), Some(r)) = (left.peek(), right.peek()) {
+        // Taking from the left unless the right strictly precedes it keeps
+        // equal elements in order.
+        let next = if precedes(r, l)? {
+            &mut right
+        } else {
+            &mut left
+        };
+        merged.extend(next.next());
+    }
+    merged.extend(left);
+    merged.extend(right);
+    Ok(merged)
+}
+
+/// Natives with no side effects and no hidden state, which `parallelMap`
+/// workers may call.
+const PURE_NATIVES: &[&str] = &[
+    "PI", "E", "sqrt", "abs", "floor", "ceil", "pow", "min", "max", "sin", "cos", "num", "toFixed",
+    "len", "get", "typeof", "assert",
+];
+
+/// `parallelMap(fn, xs)` calls `fn` on every element of `xs` across several
+/// threads, each with its own interpreter. Values can't cross threads, so
+/// `fn` may only read its parameter, itself and pure natives, and the
+/// elements and results must be nil, booleans, numbers or strings.
+fn parallel_map(context: &mut NativeContext, args: &[Value]) -> Result<Value, String> {
+    let Some(Value::Callable(function)) = args.first() else {
+        return Err("Argument 1 to 'parallelMap' must be a function.".to_string());
+    };
+    let Function::User {
+        name,
+        params,
+        body,
+        ast,
+        ..
+    } = &**function
+    else {
+        return Err("Argument 1 to 'parallelMap' can't be a native function.".to_string());
+    };
+    if params.len() != 1 {
+        return Err("Function passed to 'parallelMap' must take one argument.".to_string());
+    }
+    let Some(Value::List(items)) = args.get(1) else {
+        return Err("Argument 2 to 'parallelMap' must be a list.".to_string());
+    };
+
+    let dependencies = dependencies::of_function(function);
+    if let Some(written) = dependencies.writes.iter().next() {
+        return Err(format!(
+            "Function passed to 'parallelMap' can't assign to '{}'.",
+            written
+        ));
+    }
+    if let Some(read) = dependencies
+        .reads
+        .iter()
+        .find(|read| **read != name.lexeme() && !PURE_NATIVES.contains(&read.as_str()))
+    {
+        return Err(format!(
+            "Function passed to 'parallelMap' can't read '{}'.",
+            read
+        ));
+    }
+
+    let inputs = items
+        .borrow()
+        .iter()
+        .map(to_literal)
+        .collect::<Result<Vec<_>, _>>()?;
+    // A browser can't start threads, so there the list is mapped in place.
+    #[cfg(target_arch = "wasm32")]
+    let chunks = [Ok::<_, Box<dyn std::any::Any + Send>>(map_chunk(
+        name, params, body, ast, &inputs,
+    ))];
+    #[cfg(not(target_arch = "wasm32"))]
+    let chunks = {
+        let workers = thread::available_parallelism().map_or(1, |n| n.get());
+        let chunk_size = inputs.len().div_ceil(workers).max(1);
+        thread::scope(|scope| {
+            let handles: Vec<_> = inputs
+                .chunks(chunk_size)
+                .map(|chunk| scope.spawn(|| map_chunk(name, params, body, ast, chunk)))
+                .collect();
+            handles
+                .into_iter()
+                .map(|handle| handle.join())
+                .collect::<Vec<_>>()
+        })
+    };
+
+    let mut results = Vec::with_capacity(inputs.len());
+    for chunk in chunks {
+        let (outputs, diagnostics) =
+            chunk.map_err(|payload| match payload.downcast::<RuntimeError>() {
+                Ok(error) => error.message,
+                Err(_) => "A 'parallelMap' worker panicked.".to_string(),
+            })?;
+        for diagnostic in diagnostics {
+            diagnostics::push(diagnostic);
+        }
+        diagnostics::flush(&*context.interpreter.output());
+        results.extend(outputs?.iter().map(from_literal));
+    }
+    Ok(Value::List(Rc::new(RefCell::new(results))))
+}
+
+/// Runs on a worker thread: rebuilds the function in a fresh interpreter
+/// and maps it over `chunk`. Errors reported along the way are returned
+/// rather than left queued on the worker.
+#[allow(clippy::type_complexity)]
+fn map_chunk(
+    name: &Token,
+    params: &[Token],
+    body: &[StmtId],
+    ast: &Arc<Arena>,
+    chunk: &[Literal],
+) -> (Result<Vec<Literal>, String>, Vec<Diagnostic>) {
+    let mut interpreter = Interpreter::new();
+    let function = Rc::new(Function::User {
+        body: body.to_vec(),
+        ast: Arc::clone(ast),
+        params: params.to_vec(),
+        name: name.clone(),
+        enclosing: Rc::clone(&interpreter.globals),
+    });
+    interpreter
+        .globals
+        .borrow_mut()
+        .define(name.symbol, Value::Callable(Rc::clone(&function)));
+
+    let outputs = chunk
+        .iter()
+        .map(|input| {
+            let output = function.call(&mut interpreter, vec![from_literal(input)])?;
+            to_literal(&output)
+        })
+        .collect();
+    (outputs, diagnostics::take())
+}
+
+fn to_literal(value: &Value) -> Result<Literal, String> {
+    match value {
+        Value::None => Ok(Literal::None),
+        Value::Boolean(b) => Ok(Literal::Boolean(*b)),
+        Value::Number(n) => Ok(Literal::Number(*n)),
+        Value::String(s) => Ok(Literal::String(s.as_ref().into())),
+        #[cfg(feature = "units")]
+        Value::Quantity(n, unit) => Ok(Literal::Quantity(*n, unit)),
+        other => Err(format!(
+            "'parallelMap' only works with nil, booleans, numbers and strings, got {}.",
+            other
+        )),
+    }
+}
+
+fn from_literal(literal: &Literal) -> Value {
+    match literal {
+        Literal::None => Value::None,
+        Literal::Boolean(b) => Value::Boolean(*b),
+        Literal::Number(n) => Value::Number(*n),
+        Literal::String(s) => Value::String(Arc::clone(s)),
+        #[cfg(feature = "units")]
+        Literal::Quantity(n, unit) => Value::Quantity(*n, unit),
+    }
+}
@@ -0,0 +1,197 @@
+use std::fmt::Debug;
+
+/// Arithmetic used by the interpreter for `+`, `-`, `*` and `/` on numbers.
+/// Values are still carried around as `f64`; a backend decides how the
+/// operation itself is carried out. Backends are shared between threads,
+/// as an `Engine` may spawn interpreters on any of them.
+pub trait NumberBackend: Debug + Send + Sync {
+    fn add(&self, left: f64, right: f64) -> f64;
+    fn sub(&self, left: f64, right: f64) -> f64;
+    fn mul(&self, left: f64, right: f64) -> f64;
+    fn div(&self, left: f64, right: f64) -> f64;
+
+    /// Whether this is plain IEEE-754 arithmetic, which compiled code can
+    /// do in its place.
+    fn is_float(&self) -> bool {
+        false
+    }
+}
+
+/// Plain IEEE-754 double arithmetic, the default.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FloatBackend;
+
+impl NumberBackend for FloatBackend {
+    fn add(&self, left: f64, right: f64) -> f64 {
+        left + right
+    }
+
+    fn sub(&self, left: f64, right: f64) -> f64 {
+        left - right
+    }
+
+    fn mul(&self, left: f64, right: f64) -> f64 {
+        left * right
+    }
+
+    fn div(&self, left: f64, right: f64) -> f64 {
+        left / right
+    }
+
+    fn is_float(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(feature = "decimal")]
+pub use decimal::DecimalBackend;
+
+#[cfg(feature = "decimal")]
+mod decimal {
+    use rust_decimal::Decimal;
+
+    use super::{FloatBackend, NumberBackend};
+
+    /// Base-10 arithmetic on `rust_decimal`'s 96-bit decimals: operands are
+    /// read back as the shortest decimal that round-trips, so `0.1 + 0.2`
+    /// is exactly `0.3`. Falls back to float arithmetic when an operand or
+    /// a result doesn't fit.
+    #[derive(Debug, Clone, Copy, Default)]
+    pub struct DecimalBackend;
+
+    fn to_decimal(n: f64) -> Option<Decimal> {
+        if !n.is_finite() {
+            return None;
+        }
+        Decimal::from_str_exact(&n.to_string()).ok()
+    }
+
+    fn to_f64(d: Decimal) -> f64 {
+        d.normalize().to_string().parse().unwrap_or(f64::NAN)
+    }
+
+    fn apply(
+        left: f64,
+        right: f64,
+        op: fn(Decimal, Decimal) -> Option<Decimal>,
+        fallback: fn(&FloatBackend, f64, f64) -> f64,
+    ) -> f64 {
+        to_decimal(left)
+            .zip(to_decimal(right))
+            .and_then(|(l, r)| op(l, r))
+            .map_or_else(|| fallback(&FloatBackend, left, right), to_f64)
+    }
+
+    impl NumberBackend for DecimalBackend {
+        fn add(&self, left: f64, right: f64) -> f64 {
+            apply(left, right, Decimal::checked_add, FloatBackend::add)
+        }
+
+        fn sub(&self, left: f64, right: f64) -> f64 {
+            apply(left, right, Decimal::checked_sub, FloatBackend::sub)
+        }
+
+        fn mul(&self, left: f64, right: f64) -> f64 {
+            apply(left, right, Decimal::checked_mul, FloatBackend::mul)
+        }
+
+        fn div(&self, left: f64, right: f64) -> f64 {
+            apply(left, right, Decimal::checked_div, FloatBackend::div)
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn sums_decimal_fractions_exactly() {
+            assert_eq!(DecimalBackend.add(0.1, 0.2), 0.3);
+            assert_eq!(DecimalBackend.sub(0.3, 0.1), 0.2);
+            assert_eq!(DecimalBackend.mul(1.1, 1.1), 1.21);
+            assert_eq!(DecimalBackend.div(1.0, 4.0), 0.25);
+        }
+
+        #[test]
+        fn falls_back_to_floats() {
+            assert_eq!(DecimalBackend.div(1.0, 0.0), f64::INFINITY);
+            assert_eq!(DecimalBackend.mul(1e300, 1e10), f64::INFINITY);
+            assert!(DecimalBackend.add(f64::NAN, 1.0).is_nan());
+        }
+    }
+}
+
+#[cfg(feature = "rational")]
+pub use rational::RationalBackend;
+
+#[cfg(feature = "rational")]
+mod rational {
+    use num_rational::Rational64;
+    use num_traits::{CheckedAdd, CheckedDiv, CheckedMul, CheckedSub, ToPrimitive};
+
+    use super::{FloatBackend, NumberBackend};
+
+    /// Exact fractions on `num-rational`: each operand is read back as the
+    /// simplest fraction that the float approximates, so `1 / 3 * 3` is
+    /// exactly `1`. Falls back to float arithmetic when an operand or a
+    /// result doesn't fit in 64-bit numerators and denominators.
+    #[derive(Debug, Clone, Copy, Default)]
+    pub struct RationalBackend;
+
+    fn to_rational(n: f64) -> Option<Rational64> {
+        let rational = Rational64::approximate_float(n)?;
+        // A float too big or too precise for the fraction is kept as it is.
+        (rational.to_f64()? == n).then_some(rational)
+    }
+
+    fn apply(
+        left: f64,
+        right: f64,
+        op: fn(&Rational64, &Rational64) -> Option<Rational64>,
+        fallback: fn(&FloatBackend, f64, f64) -> f64,
+    ) -> f64 {
+        to_rational(left)
+            .zip(to_rational(right))
+            .and_then(|(l, r)| op(&l, &r))
+            .and_then(|result| result.to_f64())
+            .unwrap_or_else(|| fallback(&FloatBackend, left, right))
+    }
+
+    impl NumberBackend for RationalBackend {
+        fn add(&self, left: f64, right: f64) -> f64 {
+            apply(left, right, Rational64::checked_add, FloatBackend::add)
+        }
+
+        fn sub(&self, left: f64, right: f64) -> f64 {
+            apply(left, right, Rational64::checked_sub, FloatBackend::sub)
+        }
+
+        fn mul(&self, left: f64, right: f64) -> f64 {
+            apply(left, right, Rational64::checked_mul, FloatBackend::mul)
+        }
+
+        fn div(&self, left: f64, right: f64) -> f64 {
+            apply(left, right, Rational64::checked_div, FloatBackend::div)
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn keeps_fractions_exact() {
+            let third = RationalBackend.div(1.0, 3.0);
+            assert_eq!(RationalBackend.mul(third, 3.0), 1.0);
+            assert_eq!(RationalBackend.add(0.1, 0.2), 0.3);
+            assert_eq!(RationalBackend.sub(third, third), 0.0);
+        }
+
+        #[test]
+        fn falls_back_to_floats() {
+            assert_eq!(RationalBackend.div(1.0, 0.0), f64::INFINITY);
+            assert_eq!(RationalBackend.mul(1e300, 1e10), f64::INFINITY);
+            assert!(RationalBackend.add(f64::NAN, 1.0).is_nan());
+        }
+    }
+}
@@ -0,0 +1,189 @@
+//! Infix operators added by an embedder, for notations Lox doesn't have
+//! built in, without touching the scanner, parser or interpreter.
+//!
+//! An operator is either a run of symbol characters (`<>`, `**`, `|>`) or
+//! a word (`per`, `in`). Operators live in an `Operators` registry owned
+//! by an interpreter or an `Engine`, so two hosts in one process can give
+//! the same symbol different meanings. Once registered, an operator is
+//! scanned as a single `TokenType::Operator` token carrying its
+//! precedence, parsed at that precedence, and evaluated by calling its
+//! handler with both operands.
+
+use serde::{Deserialize, Serialize};
+
+use crate::interpreter::Value;
+use crate::scanner::keyword;
+
+/// Which built-in operators a registered one binds like. It's parsed in the
+/// same loop as them, so it's left-associative and mixes with them freely:
+/// a `Term` operator binds looser than `*` and as tightly as `+`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Precedence {
+    Equality,
+    Comparison,
+    Term,
+    Factor,
+}
+
+pub type Handler = fn(&Value, &Value) -> Result<Value, String>;
+
+#[derive(Debug, Clone)]
+struct Operator {
+    symbol: String,
+    precedence: Precedence,
+    handler: Handler,
+}
+
+/// The operators a program is scanned and run with.
+#[derive(Debug, Clone)]
+pub struct Operators {
+    operators: Vec<Operator>,
+}
+
+const SYMBOL_CHARS: &str = "!#$%&*+-/<=>?@^|~:";
+const BUILT_IN: &[&str] = &[
+    "!", "!=", "=", "==", ">", ">=", "<", "<=", "-", "+", "/", "*",
+];
+
+/// The operators every interpreter starts with: none, or `->` in a build
+/// with the `units` feature.
+impl Default for Operators {
+    fn default() -> Self {
+        #[allow(unused_mut)]
+        let mut operators = Operators {
+            operators: Vec::new(),
+        };
+        #[cfg(feature = "units")]
+        crate::units::register_operators(&mut operators);
+        operators
+    }
+}
+
+impl Operators {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds an operator, or replaces the handler and precedence of one
+    /// already registered with the same symbol. Programs scanned with this
+    /// registry afterwards can use it.
+    pub fn register(
+        &mut self,
+        symbol: &str,
+        precedence: Precedence,
+        handler: Handler,
+    ) -> Result<(), String> {
+        validate(symbol)?;
+        match self.operators.iter_mut().find(|op| op.symbol == symbol) {
+            Some(existing) => {
+                existing.precedence = precedence;
+                existing.handler = handler;
+            }
+            None => self.operators.push(Operator {
+                symbol: symbol.to_string(),
+                precedence,
+                handler,
+            }),
+        }
+        Ok(())
+    }
+
+    /// The length and precedence of the longest registered symbol operator
+    /// `source` starts with. Word operators are found by `word` once a
+    /// whole word is read.
+    pub(crate) fn match_symbol(&self, source: &str) -> Option<(usize, Precedence)> {
+        self.operators
+            .iter()
+            .filter(|op| {
+                !op.symbol
+                    .starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+            })
+            .filter(|op| source.starts_with(&op.symbol))
+            .map(|op| (op.symbol.len(), op.precedence))
+            .max_by_key(|&(length, _)| length)
+    }
+
+    /// The precedence of `word`, if it's a registered word operator.
+    pub(crate) fn word(&self, word: &str) -> Option<Precedence> {
+        self.find(word).map(|op| op.precedence)
+    }
+
+    pub(crate) fn handler(&self, symbol: &str) -> Option<Handler> {
+        self.find(symbol).map(|op| op.handler)
+    }
+
+    fn find(&self, symbol: &str) -> Option<&Operator> {
+        self.operators.iter().find(|op| op.symbol == symbol)
+    }
+}
+
+fn validate(symbol: &str) -> Result<(), String> {
+    let mut chars = symbol.chars();
+    let is_word = match chars.next() {
+        None => return Err("An operator can't be empty.".to_string()),
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => true,
+        Some(c) if SYMBOL_CHARS.contains(c) => false,
+        Some(c) => return Err(format!("An operator can't start with '{}'.", c)),
+    };
+    let valid = if is_word {
+        chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+    } else {
+        chars.all(|c| SYMBOL_CHARS.contains(c))
+    };
+    if !valid {
+        return Err(format!(
+            "'{}' mixes symbols with letters or other characters.",
+            symbol
+        ));
+    }
+    if BUILT_IN.contains(&symbol) || keyword(symbol).is_some() {
+        return Err(format!("'{}' is already part of Lox.", symbol));
+    }
+    if symbol.starts_with("//") {
+        return Err(format!("'{}' would start a comment.", symbol));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn first(left: &Value, _: &Value) -> Result<Value, String> {
+        Ok(left.clone())
+    }
+
+    #[test]
+    fn matches_the_longest_symbol() {
+        let mut operators = Operators::new();
+        operators
+            .register("<>", Precedence::Equality, first)
+            .unwrap();
+        operators.register("<>>", Precedence::Term, first).unwrap();
+        assert_eq!(operators.match_symbol("<>> 1"), Some((3, Precedence::Term)));
+        assert_eq!(
+            operators.match_symbol("<> 1"),
+            Some((2, Precedence::Equality))
+        );
+        assert_eq!(operators.match_symbol("< 1"), None);
+    }
+
+    #[test]
+    fn registries_are_separate() {
+        let mut a = Operators::new();
+        a.register("per", Precedence::Factor, first).unwrap();
+        assert_eq!(a.word("per"), Some(Precedence::Factor));
+        assert_eq!(Operators::new().word("per"), None);
+    }
+
+    #[test]
+    fn rejects_symbols_lox_already_uses() {
+        let mut operators = Operators::new();
+        for symbol in ["", "==", "and", "//", "a+", "1x"] {
+            assert!(
+                operators.register(symbol, Precedence::Term, first).is_err(),
+                "{symbol}"
+            );
+        }
+    }
+}
@@ -0,0 +1,61 @@
+use std::fmt::{self, Debug};
+
+use crate::{diagnostics::Diagnostic, interpreter::Value, preview};
+
+/// Everything a program run produces for the user, so frontends other than
+/// the terminal (notebooks, editors, GUIs) can render it their own way.
+#[derive(Debug, Clone)]
+pub enum OutputEvent {
+    /// A value written by a `print` statement.
+    PrintValue(Value),
+    Diagnostic(Diagnostic),
+    /// How many diagnostics were dropped by `--max-errors`.
+    DiagnosticsSuppressed(usize),
+    /// The value of the last expression statement in an entry, when the
+    /// interpreter is asked to echo results (as the REPL does).
+    Result(Value),
+    /// Extra information from tracing tools, kept apart from program output.
+    TraceLine(String),
+}
+
+/// Where an interpreter sends its `OutputEvent`s.
+pub trait OutputSink: Debug {
+    fn emit(&self, event: OutputEvent);
+}
+
+/// The command line's sink: formats events as text on stdout, with trace
+/// lines on stderr.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StdoutSink;
+
+impl OutputSink for StdoutSink {
+    fn emit(&self, event: OutputEvent) {
+        match event {
+            OutputEvent::PrintValue(value) => println!("{}", preview::format(&value)),
+            OutputEvent::Diagnostic(diagnostic) => println!("{}", diagnostic),
+            OutputEvent::DiagnosticsSuppressed(count) => {
+                println!("... {} additional errors suppressed.", count)
+            }
+            // Echoing nil after every call to a procedure is just noise.
+            OutputEvent::Result(Value::None) => {}
+            OutputEvent::Result(value) => println!("{}", preview::format(&value)),
+            OutputEvent::TraceLine(line) => eprintln!("{}", line),
+        }
+    }
+}
+
+/// Hands every event to a closure, for example one that pushes it onto a
+/// channel read by the frontend.
+pub struct Callback<F: Fn(OutputEvent)>(pub F);
+
+impl<F: Fn(OutputEvent)> OutputSink for Callback<F> {
+    fn emit(&self, event: OutputEvent) {
+        (self.0)(event)
+    }
+}
+
+impl<F: Fn(OutputEvent)> Debug for Callback<F> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Callback")
+    }
+}
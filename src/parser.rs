@@ -1,34 +1,109 @@
+use crate::arena::Arena;
 use crate::expression::*;
-use crate::statement::Stmt;
+use crate::operators::Precedence;
+use crate::statement::{ImportTarget, Stmt, StmtId};
 use crate::token::*;
+use std::fmt;
+use std::mem;
 use std::result::Result;
 
+/// A syntax error: the token the parser had reached and what it expected
+/// there. Each one is reported as a diagnostic when it's found and kept in
+/// `Parser::errors`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    pub token: Token,
+    pub kind: ParseErrorKind,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParseErrorKind {
+    /// A token of this type had to come next, like the `;` ending a
+    /// statement.
+    Expected(TokenType),
+    /// A word that's only a keyword in one place had to come next, like
+    /// `from` in an import.
+    ExpectedWord(&'static str),
+    ExpectedExpression,
+    /// `export` wasn't followed by a class, function or variable.
+    ExpectedDeclaration,
+    InvalidAssignmentTarget,
+    TooManyParameters,
+    TooManyArguments,
+    /// `export` inside a block or function.
+    NestedExport,
+    /// A `{` with no `}` before the end of the file. The error is at the
+    /// `{`.
+    UnclosedBlock,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// Parses tokens as it takes them, so it can be handed a `Scanner` and
+/// the source is never held as a whole list of tokens. Only the token
+/// being looked at and the one before it are kept.
 pub struct Parser {
-    tokens: Vec<Token>,
-    current: u32,
+    tokens: Box<dyn Iterator<Item = Token>>,
+    current: Token,
+    previous: Token,
+    errors: Vec<ParseError>,
+    arena: Arena,
 }
 
 impl Parser {
-    pub fn new(tokens: Vec<Token>) -> Self {
-        Self { tokens, current: 0 }
+    /// `tokens` must end with `Eof`, as a scanner's do.
+    pub fn new<I>(tokens: I) -> Self
+    where
+        I: IntoIterator<Item = Token>,
+        I::IntoIter: 'static,
+    {
+        let mut tokens = tokens.into_iter();
+        let current = tokens.next().expect("no tokens to parse, not even Eof");
+        Self {
+            tokens: Box::new(tokens),
+            previous: current.clone(),
+            current,
+            errors: Vec::new(),
+            arena: Arena::new(),
+        }
+    }
+
+    /// Every syntax error found so far, in the order they were found.
+    pub fn errors(&self) -> &[ParseError] {
+        &self.errors
     }
 
-    pub fn parse(&mut self) -> Vec<Stmt> {
-        let mut statements: Vec<Stmt> = Vec::new();
+    /// The nodes of everything parsed, which the ids `parse` returned
+    /// index.
+    pub fn into_arena(self) -> Arena {
+        self.arena
+    }
+
+    pub fn parse(&mut self) -> Vec<StmtId> {
+        let mut statements: Vec<StmtId> = Vec::new();
         while !self.is_at_end() {
             if let Some(dec) = self.declaration() {
-                statements.push(dec);
+                statements.push(self.arena.alloc_stmt(dec));
             }
         }
 
         statements
     }
 
-    fn statement(&mut self) -> Result<Stmt, String> {
+    fn statement(&mut self) -> Result<Stmt, ParseError> {
         if self.match_token(vec![TokenType::For]) {
             self.for_statement()
         } else if self.match_token(vec![TokenType::If]) {
             self.if_statement()
+        } else if self.match_token(vec![TokenType::Import]) {
+            self.import_statement()
         } else if self.match_token(vec![TokenType::Print]) {
             self.print_statement()
         } else if self.match_token(vec![TokenType::Return]) {
@@ -42,15 +117,25 @@ impl Parser {
         }
     }
 
-    fn for_statement(&mut self) -> Result<Stmt, String> {
+    /// Desugars `for` into a `while` inside blocks. The new nodes reuse the
+    /// tokens of the clauses they came from, so runtime errors in the
+    /// condition or increment still point at the user's source; a clause
+    /// that fails to parse fails the whole loop rather than leaving a
+    /// differently shaped one behind.
+    fn for_statement(&mut self) -> Result<Stmt, ParseError> {
         self.consume(TokenType::LeftParen, "Expect '(' after 'for'.")?;
 
         let initializer: Option<Stmt> = if self.match_token(vec![TokenType::Semicolon]) {
             None
         } else if self.match_token(vec![TokenType::Var]) {
-            self.var_declaration().ok()
+            let name = self.consume(TokenType::Identifier, "Expect variable name.")?;
+            // `in` is only a keyword here, so it stays usable as a name.
+            if self.match_contextual("in") {
+                return self.for_in_statement(name);
+            }
+            Some(self.var_initializer(name)?)
         } else {
-            self.expression_statement().ok()
+            Some(self.expression_statement()?)
         };
 
         let condition = if self.check(TokenType::Semicolon) {
@@ -63,26 +148,41 @@ impl Parser {
         let increment: Option<Expr> = if self.check(TokenType::RightParen) {
             None
         } else {
-            self.expression().ok()
+            Some(self.expression()?)
         };
         self.consume(TokenType::RightParen, "Expect ')' after for clauses.")?;
 
         let mut body = self.statement()?;
 
         if let Some(inc) = increment {
-            body = Stmt::Block(vec![body, Stmt::Expression(inc)]);
+            let increment = Stmt::Expression(self.arena.alloc_expr(inc));
+            let body_id = self.arena.alloc_stmt(body);
+            body = Stmt::Block(vec![body_id, self.arena.alloc_stmt(increment)]);
         }
 
-        body = Stmt::While(condition, Box::new(body));
+        let condition = self.arena.alloc_expr(condition);
+        body = Stmt::While(condition, self.arena.alloc_stmt(body));
 
         if let Some(init) = initializer {
-            body = Stmt::Block(vec![init, body]);
+            body = Stmt::Block(vec![
+                self.arena.alloc_stmt(init),
+                self.arena.alloc_stmt(body),
+            ]);
         }
 
         Ok(body)
     }
 
-    fn if_statement(&mut self) -> Result<Stmt, String> {
+    /// The rest of `for (var name in iterable) body`, after `in`.
+    fn for_in_statement(&mut self, name: Token) -> Result<Stmt, ParseError> {
+        let iterable = self.expression()?;
+        self.consume(TokenType::RightParen, "Expect ')' after for clauses.")?;
+        let body = self.statement()?;
+        let iterable = self.arena.alloc_expr(iterable);
+        Ok(Stmt::ForIn(name, iterable, self.arena.alloc_stmt(body)))
+    }
+
+    fn if_statement(&mut self) -> Result<Stmt, ParseError> {
         self.consume(TokenType::LeftParen, "Expect '(' after 'if'.")?;
         let condition = self.expression()?;
         self.consume(TokenType::RightParen, "Expect ')' after if condition.")?;
@@ -95,23 +195,77 @@ impl Parser {
         };
 
         Ok(Stmt::If(
-            condition,
-            Box::new(then_branch),
-            Box::new(else_branch),
+            self.arena.alloc_expr(condition),
+            self.arena.alloc_stmt(then_branch),
+            else_branch.map(|branch| self.arena.alloc_stmt(branch)),
         ))
     }
 
-    fn print_statement(&mut self) -> Result<Stmt, String> {
+    fn import_statement(&mut self) -> Result<Stmt, ParseError> {
+        let keyword = self.previous().clone();
+
+        if self.match_token(vec![TokenType::LeftBrace]) {
+            let mut names = Vec::new();
+            loop {
+                names.push(self.consume(TokenType::Identifier, "Expect name to import.")?);
+                if !self.match_token(vec![TokenType::Comma]) {
+                    break;
+                }
+            }
+            self.consume(TokenType::RightBrace, "Expect '}' after imported names.")?;
+            self.consume_contextual("from", "Expect 'from' after imported names.")?;
+            let path = self.consume(TokenType::String, "Expect module path after 'from'.")?;
+            self.consume(TokenType::Semicolon, "Expect ';' after import.")?;
+            return Ok(Stmt::Import(keyword, path, ImportTarget::Names(names)));
+        }
+
+        if self.match_contextual("lazy") {
+            let path = self.consume(TokenType::String, "Expect module path after 'lazy'.")?;
+            self.consume_contextual("as", "Expect 'as' after lazy import path.")?;
+            let alias = self.consume(TokenType::Identifier, "Expect name after 'as'.")?;
+            self.consume(TokenType::Semicolon, "Expect ';' after import.")?;
+            return Ok(Stmt::Import(keyword, path, ImportTarget::Lazy(alias)));
+        }
+
+        let path = self.consume(TokenType::String, "Expect module path after 'import'.")?;
+        let target = if self.match_contextual("as") {
+            ImportTarget::Alias(self.consume(TokenType::Identifier, "Expect name after 'as'.")?)
+        } else {
+            ImportTarget::All
+        };
+        self.consume(TokenType::Semicolon, "Expect ';' after import.")?;
+        Ok(Stmt::Import(keyword, path, target))
+    }
+
+    fn export_declaration(&mut self) -> Result<Stmt, ParseError> {
+        let keyword = self.previous().clone();
+        let declaration = if self.match_token(vec![TokenType::Class]) {
+            self.class_declaration()?
+        } else if self.match_token(vec![TokenType::Fun]) {
+            self.function("function".to_owned())?
+        } else if self.match_token(vec![TokenType::Var]) {
+            self.var_declaration()?
+        } else if self.match_token(vec![TokenType::Const]) {
+            self.const_declaration()?
+        } else {
+            let message = "Expect declaration after 'export'.";
+            return Err(self.error_at_peek(ParseErrorKind::ExpectedDeclaration, message));
+        };
+        Ok(Stmt::Export(keyword, self.arena.alloc_stmt(declaration)))
+    }
+
+    fn print_statement(&mut self) -> Result<Stmt, ParseError> {
+        let keyword = self.previous().clone();
         let value = self.expression()?;
         self.consume(TokenType::Semicolon, "Expect ';' after value.")?;
-        Ok(Stmt::Print(value))
+        Ok(Stmt::Print(keyword, self.arena.alloc_expr(value)))
     }
 
-    fn return_statement(&mut self) -> Result<Stmt, String> {
+    fn return_statement(&mut self) -> Result<Stmt, ParseError> {
         let keyword = self.previous().clone();
         let value = if !self.check(TokenType::Semicolon) {
             if let Ok(ex) = self.expression() {
-                Some(ex)
+                Some(self.return_values(ex))
             } else {
                 None
             }
@@ -119,13 +273,67 @@ impl Parser {
             None
         };
 
-        self.consume(TokenType::Semicolon, "Expect ';' after return value.");
-        Ok(Stmt::Return(keyword, value))
+        self.consume(TokenType::Semicolon, "Expect ';' after return value.")?;
+        Ok(Stmt::Return(
+            keyword,
+            value.map(|value| self.arena.alloc_expr(value)),
+        ))
     }
 
-    fn var_declaration(&mut self) -> Result<Stmt, String> {
+    /// `first` alone, or the tuple of it and the values after it in
+    /// `return a, b;`.
+    fn return_values(&mut self, first: Expr) -> Expr {
+        if !self.match_token(vec![TokenType::Comma]) {
+            return first;
+        }
+        let comma = self.previous().clone();
+        let mut values = vec![self.arena.alloc_expr(first)];
+        while let Ok(value) = self.expression() {
+            values.push(self.arena.alloc_expr(value));
+            if !self.match_token(vec![TokenType::Comma]) {
+                break;
+            }
+        }
+        Expr::Tuple(comma, values)
+    }
+
+    fn var_declaration(&mut self) -> Result<Stmt, ParseError> {
+        if self.match_token(vec![TokenType::LeftParen]) {
+            return self.destructure_declaration();
+        }
         let name = self.consume(TokenType::Identifier, "Expect variable name.")?;
+        self.var_initializer(name)
+    }
+
+    fn const_declaration(&mut self) -> Result<Stmt, ParseError> {
+        let name = self.consume(TokenType::Identifier, "Expect constant name.")?;
+        self.consume(TokenType::Equal, "Expect '=' after constant name.")?;
+        let initializer = self.expression()?;
+        self.consume(
+            TokenType::Semicolon,
+            "Expect ';' after constant declaration.",
+        )?;
+        Ok(Stmt::Const(name, self.arena.alloc_expr(initializer)))
+    }
+
+    /// `var (a, b) = initializer;`, after its `(`.
+    fn destructure_declaration(&mut self) -> Result<Stmt, ParseError> {
+        let mut names = vec![self.consume(TokenType::Identifier, "Expect variable name.")?];
+        while self.match_token(vec![TokenType::Comma]) {
+            names.push(self.consume(TokenType::Identifier, "Expect variable name.")?);
+        }
+        self.consume(TokenType::RightParen, "Expect ')' after variable names.")?;
+        self.consume(TokenType::Equal, "Expect '=' after variable names.")?;
+        let initializer = self.expression()?;
+        self.consume(
+            TokenType::Semicolon,
+            "Expect ';' after variable declaration.",
+        )?;
+        Ok(Stmt::Destructure(names, self.arena.alloc_expr(initializer)))
+    }
 
+    /// The rest of a `var` declaration, after its name.
+    fn var_initializer(&mut self, name: Token) -> Result<Stmt, ParseError> {
         let initializer = if self.match_token(vec![TokenType::Equal]) {
             self.expression().ok()
         } else {
@@ -136,28 +344,30 @@ impl Parser {
             TokenType::Semicolon,
             "Expect ';' after variable declaration.",
         )?;
-        Ok(Stmt::Var(name, initializer))
+        Ok(Stmt::Var(
+            name,
+            initializer.map(|value| self.arena.alloc_expr(value)),
+        ))
     }
 
-    fn while_statement(&mut self) -> Result<Stmt, String> {
+    fn while_statement(&mut self) -> Result<Stmt, ParseError> {
         self.consume(TokenType::LeftParen, "Expect '(' after 'while'.")?;
         let condition = self.expression()?;
         self.consume(TokenType::RightParen, "Expect ')' after condition.")?;
         let body = self.statement()?;
 
-        Ok(Stmt::While(condition, Box::new(body)))
+        let condition = self.arena.alloc_expr(condition);
+        Ok(Stmt::While(condition, self.arena.alloc_stmt(body)))
     }
 
-    fn expression_statement(&mut self) -> Result<Stmt, String> {
+    fn expression_statement(&mut self) -> Result<Stmt, ParseError> {
         let expr = self.expression()?;
         self.consume(TokenType::Semicolon, "Expect ';' after expression.")?;
-        Ok(Stmt::Expression(expr))
+        Ok(Stmt::Expression(self.arena.alloc_expr(expr)))
     }
 
-    fn function(&mut self, kind: String) -> Result<Stmt, String> {
-        let name = self
-            .consume(TokenType::Identifier, &format!("Expect {} name.", kind))
-            .unwrap();
+    fn function(&mut self, kind: String) -> Result<Stmt, ParseError> {
+        let name = self.consume(TokenType::Identifier, &format!("Expect {} name.", kind))?;
         self.consume(
             TokenType::LeftParen,
             &format!("Expect '(' after {} name.", kind),
@@ -166,7 +376,8 @@ impl Parser {
         if !self.check(TokenType::RightParen) {
             loop {
                 if parameters.len() >= 255 {
-                    crate::error_at_token(self.peek(), "Can't have more than 255 parameters");
+                    let message = "Can't have more than 255 parameters";
+                    self.error_at_peek(ParseErrorKind::TooManyParameters, message);
                 }
 
                 if let Ok(param) = self.consume(TokenType::Identifier, "Expect parameter name.") {
@@ -188,60 +399,71 @@ impl Parser {
         Ok(Stmt::Function(name, parameters, body))
     }
 
-    fn block(&mut self) -> Vec<Stmt> {
-        let mut statements: Vec<Stmt> = Vec::new();
+    /// Parses the statements after a `{`. An unclosed block is reported at
+    /// its opening brace and closed at end of file, so the statements
+    /// gathered so far are still returned.
+    fn block(&mut self) -> Vec<StmtId> {
+        let opening = self.previous().clone();
+        let mut statements: Vec<StmtId> = Vec::new();
 
         while !self.check(TokenType::RightBrace) && !self.is_at_end() {
             if let Some(dec) = self.declaration() {
-                statements.push(dec);
+                if let Stmt::Export(keyword, _) = &dec {
+                    let message = "Can only export top-level declarations.";
+                    self.error(keyword.clone(), ParseErrorKind::NestedExport, message);
+                }
+                statements.push(self.arena.alloc_stmt(dec));
             }
         }
 
-        self.consume(TokenType::RightBrace, "Expect '}' after block.")
-            .unwrap();
+        if !self.match_token(vec![TokenType::RightBrace]) {
+            let message = "Expect '}' to close this block.";
+            self.error(opening, ParseErrorKind::UnclosedBlock, message);
+        }
         statements
     }
 
-    fn expression(&mut self) -> Result<Expr, String> {
+    fn expression(&mut self) -> Result<Expr, ParseError> {
         self.assignment()
     }
 
-    fn assignment(&mut self) -> Result<Expr, String> {
+    fn assignment(&mut self) -> Result<Expr, ParseError> {
         let expr = self.or()?;
 
         if self.match_token(vec![TokenType::Equal]) {
             let equals = self.previous().clone();
             let value: Expr = self.assignment()?;
             if let Expr::Variable(token) = expr {
-                return Ok(Expr::assign(token, value));
+                return Ok(Expr::assign(&mut self.arena, token, value));
             } else if let Expr::Get(get, name) = expr {
-                return Ok(Expr::set(*get, name, value));
+                return Ok(Expr::Set(get, name, self.arena.alloc_expr(value)));
             }
-            return Err(format!("Invalid assignment target. {}", equals));
+            let message = "Invalid assignment target.";
+            return Err(self.error(equals, ParseErrorKind::InvalidAssignmentTarget, message));
         }
 
         Ok(expr)
     }
 
-    fn or(&mut self) -> Result<Expr, String> {
+    fn or(&mut self) -> Result<Expr, ParseError> {
         let mut expr = self.and()?;
 
         while self.match_token(vec![TokenType::Or]) {
             let operator = self.previous().clone();
             let right = self.and()?;
-            expr = Expr::logical(expr, operator, right);
+            expr = Expr::logical(&mut self.arena, expr, operator, right);
         }
 
         Ok(expr)
     }
 
-    fn and(&mut self) -> Result<Expr, String> {
+    fn and(&mut self) -> Result<Expr, ParseError> {
         let mut expr = self.equality()?;
 
         while self.match_token(vec![TokenType::And]) {
             let operator = self.previous().clone();
             let right = self.equality()?;
-            expr = Expr::logical(expr, operator, right);
+            expr = Expr::logical(&mut self.arena, expr, operator, right);
         }
 
         Ok(expr)
@@ -252,12 +474,14 @@ impl Parser {
             if let Ok(stmt) = self.class_declaration() {
                 Some(stmt)
             } else {
+                self.synchronize();
                 None
             }
         } else if self.match_token(vec![TokenType::Fun]) {
             if let Ok(f) = self.function("function".to_owned()) {
                 Some(f)
             } else {
+                self.synchronize();
                 None
             }
         } else if self.match_token(vec![TokenType::Var]) {
@@ -267,6 +491,20 @@ impl Parser {
                 self.synchronize();
                 None
             }
+        } else if self.match_token(vec![TokenType::Const]) {
+            if let Ok(stmt) = self.const_declaration() {
+                Some(stmt)
+            } else {
+                self.synchronize();
+                None
+            }
+        } else if self.match_token(vec![TokenType::Export]) {
+            if let Ok(stmt) = self.export_declaration() {
+                Some(stmt)
+            } else {
+                self.synchronize();
+                None
+            }
         } else if let Ok(stmt) = self.statement() {
             Some(stmt)
         } else {
@@ -275,94 +513,111 @@ impl Parser {
         }
     }
 
-    fn class_declaration(&self) -> Result<Stmt, String> {
+    fn class_declaration(&mut self) -> Result<Stmt, ParseError> {
         let name = self.consume(TokenType::Identifier, "Expect class name.")?;
         self.consume(TokenType::LeftBrace, "Expect '{' before class body.")?;
 
         let mut methods = Vec::new();
         while !self.check(TokenType::RightBrace) && !self.is_at_end() {
-            if let Ok(stmt) = self.function("method".to_owned()) {
-                methods.push(stmt);
-            }
+            let method = self.function("method".to_owned())?;
+            methods.push(self.arena.alloc_stmt(method));
         }
 
-        self.consume(TokenType::RightBrace, "Expect '}' after class body.");
+        self.consume(TokenType::RightBrace, "Expect '}' after class body.")?;
 
         Ok(Stmt::Class(name, methods))
     }
 
-    fn equality(&mut self) -> Result<Expr, String> {
+    fn equality(&mut self) -> Result<Expr, ParseError> {
         let mut expr: Expr = self.comparison()?;
 
-        while self.match_token(vec![TokenType::BangEqual, TokenType::EqualEqual]) {
+        while self.match_token(vec![TokenType::BangEqual, TokenType::EqualEqual])
+            || self.match_operator(Precedence::Equality)
+        {
             let operator = self.previous().clone();
             let right = self.comparison()?;
-            expr = Expr::binary(expr, operator, right);
+            expr = Expr::binary(&mut self.arena, expr, operator, right);
         }
 
         Ok(expr)
     }
 
-    fn comparison(&mut self) -> Result<Expr, String> {
-        let mut expr = self.term()?;
+    fn comparison(&mut self) -> Result<Expr, ParseError> {
+        let mut expr = self.range()?;
 
         while self.match_token(vec![
             TokenType::Greater,
             TokenType::GreaterEqual,
             TokenType::Less,
             TokenType::LessEqual,
-        ]) {
+        ]) || self.match_operator(Precedence::Comparison)
+        {
             let operator = self.previous().clone();
-            let right = self.term()?;
-            expr = Expr::binary(expr, operator, right);
+            let right = self.range()?;
+            expr = Expr::binary(&mut self.arena, expr, operator, right);
         }
 
         Ok(expr)
     }
 
-    fn term(&mut self) -> Result<Expr, String> {
+    /// `a..b` or `a..=b`. Ranges don't chain, so `a..b..c` is an error.
+    fn range(&mut self) -> Result<Expr, ParseError> {
+        let expr = self.term()?;
+        if self.match_token(vec![TokenType::DotDot, TokenType::DotDotEqual]) {
+            let operator = self.previous().clone();
+            let right = self.term()?;
+            return Ok(Expr::binary(&mut self.arena, expr, operator, right));
+        }
+        Ok(expr)
+    }
+
+    fn term(&mut self) -> Result<Expr, ParseError> {
         let mut expr = self.factor()?;
 
-        while self.match_token(vec![TokenType::Minus, TokenType::Plus]) {
+        while self.match_token(vec![TokenType::Minus, TokenType::Plus])
+            || self.match_operator(Precedence::Term)
+        {
             let operator = self.previous().clone();
             let right = self.factor()?;
-            expr = Expr::Binary(Box::new(expr), operator, Box::new(right));
+            expr = Expr::binary(&mut self.arena, expr, operator, right);
         }
 
         Ok(expr)
     }
 
-    fn factor(&mut self) -> Result<Expr, String> {
+    fn factor(&mut self) -> Result<Expr, ParseError> {
         let mut expr = self.unary()?;
 
-        while self.match_token(vec![TokenType::Slash, TokenType::Star]) {
+        while self.match_token(vec![TokenType::Slash, TokenType::Star])
+            || self.match_operator(Precedence::Factor)
+        {
             let operator = self.previous().clone();
             let right = self.unary()?;
-            expr = Expr::binary(expr, operator, right);
+            expr = Expr::binary(&mut self.arena, expr, operator, right);
         }
 
         Ok(expr)
     }
 
-    fn unary(&mut self) -> Result<Expr, String> {
+    fn unary(&mut self) -> Result<Expr, ParseError> {
         if self.match_token(vec![TokenType::Bang, TokenType::Minus]) {
             let operator = self.previous().clone();
             let right = self.unary()?;
-            Ok(Expr::unary(operator, right))
+            Ok(Expr::unary(&mut self.arena, operator, right))
         } else {
             self.call()
         }
     }
 
-    fn call(&mut self) -> Result<Expr, String> {
+    fn call(&mut self) -> Result<Expr, ParseError> {
         let mut expr = self.primary()?;
 
         loop {
             if self.match_token(vec![TokenType::LeftParen]) {
-                expr = self.finish_call(expr);
+                expr = self.finish_call(expr)?;
             } else if self.match_token(vec![TokenType::Dot]) {
                 let name = self.consume(TokenType::Identifier, "Expect property name after '.'.")?;
-                expr = Expr::get(expr, name);
+                expr = Expr::get(&mut self.arena, expr, name);
             } else {
                 break;
             }
@@ -371,12 +626,13 @@ impl Parser {
         Ok(expr)
     }
 
-    fn finish_call(&mut self, callee: Expr) -> Expr {
+    fn finish_call(&mut self, callee: Expr) -> Result<Expr, ParseError> {
         let mut arguments = Vec::new();
         if !self.check(TokenType::RightParen) {
             loop {
                 if arguments.len() >= 255 {
-                    crate::error_at_token(self.peek(), "Can't have more than 255 arguments.");
+                    let message = "Can't have more than 255 arguments.";
+                    self.error_at_peek(ParseErrorKind::TooManyArguments, message);
                 }
                 if let Ok(x) = self.expression() {
                     arguments.push(x);
@@ -388,14 +644,12 @@ impl Parser {
             }
         }
 
-        let paren = self
-            .consume(TokenType::RightParen, "Expect ')' after arguments.")
-            .unwrap();
+        let paren = self.consume(TokenType::RightParen, "Expect ')' after arguments.")?;
 
-        Expr::call(callee, paren, arguments)
+        Ok(Expr::call(&mut self.arena, callee, paren, arguments))
     }
 
-    fn primary(&mut self) -> Result<Expr, String> {
+    fn primary(&mut self) -> Result<Expr, ParseError> {
         if self.match_token(vec![TokenType::False]) {
             return Ok(Expr::literal(Literal::Boolean(false)));
         }
@@ -407,25 +661,25 @@ impl Parser {
         }
 
         if self.match_token(vec![TokenType::Number, TokenType::String]) {
-            return Ok(Expr::literal(self.previous().clone().literal));
+            return Ok(Expr::literal(self.previous().literal()));
         }
 
-        if self.match_token(vec![TokenType::Identifier]) {
+        // `this` is looked up like a variable, which a bound method defines.
+        if self.match_token(vec![TokenType::Identifier, TokenType::This]) {
             return Ok(Expr::variable(self.previous().clone()));
         }
 
         if self.match_token(vec![TokenType::LeftParen]) {
             let expr = self.expression()?;
             self.consume(TokenType::RightParen, "Expect ')' after expression.")?;
-            return Ok(Expr::group(expr));
+            return Ok(Expr::group(&mut self.arena, expr));
         }
-        crate::error_at_token(self.peek(), "Expect expression");
-        Err("Parser error".to_string())
+        Err(self.error_at_peek(ParseErrorKind::ExpectedExpression, "Expect expression"))
     }
 
     fn match_token(&mut self, token_types: Vec<TokenType>) -> bool {
         for token_type in token_types {
-            if self.check(token_type.clone()) {
+            if self.check(token_type) {
                 self.advance();
                 return true;
             }
@@ -433,16 +687,48 @@ impl Parser {
         false
     }
 
+    /// Matches a registered operator that binds at `precedence`.
+    fn match_operator(&mut self, precedence: Precedence) -> bool {
+        if self.check(TokenType::Operator(precedence)) {
+            self.advance();
+            return true;
+        }
+        false
+    }
+
+    /// Matches an identifier used as a keyword only in certain positions,
+    /// like `as` and `from` in imports.
+    fn match_contextual(&mut self, word: &str) -> bool {
+        if self.check(TokenType::Identifier) && self.peek().lexeme() == word {
+            self.advance();
+            return true;
+        }
+        false
+    }
+
+    fn consume_contextual(
+        &mut self,
+        word: &'static str,
+        message: &str,
+    ) -> Result<Token, ParseError> {
+        if self.match_contextual(word) {
+            Ok(self.previous().clone())
+        } else {
+            Err(self.error_at_peek(ParseErrorKind::ExpectedWord(word), message))
+        }
+    }
+
     fn check(&self, token_type: TokenType) -> bool {
         if self.is_at_end() {
             return false;
         }
-        return self.peek().token_type == token_type;
+        self.peek().token_type == token_type
     }
 
     fn advance(&mut self) -> &Token {
         if !self.is_at_end() {
-            self.current += 1;
+            let next = self.tokens.next().expect("tokens ended before Eof");
+            self.previous = mem::replace(&mut self.current, next);
         }
         self.previous()
     }
@@ -452,24 +738,37 @@ impl Parser {
     }
 
     fn peek(&self) -> &Token {
-        let current_us = usize::try_from(self.current).unwrap();
-        &self.tokens[current_us]
+        &self.current
     }
 
     fn previous(&self) -> &Token {
-        let current_us = usize::try_from(self.current).unwrap();
-        &self.tokens[current_us - 1]
+        &self.previous
     }
 
-    fn consume(&mut self, token_type: TokenType, message: &str) -> Result<Token, String> {
+    fn consume(&mut self, token_type: TokenType, message: &str) -> Result<Token, ParseError> {
         if self.check(token_type) {
             Ok(self.advance().clone())
         } else {
-            crate::error_at_token(self.peek(), message);
-            Err("Parse error".to_string())
+            Err(self.error_at_peek(ParseErrorKind::Expected(token_type), message))
         }
     }
 
+    /// Reports a syntax error at `token` and records it.
+    fn error(&mut self, token: Token, kind: ParseErrorKind, message: &str) -> ParseError {
+        crate::error_at_token(&token, message);
+        let error = ParseError {
+            token,
+            kind,
+            message: message.to_string(),
+        };
+        self.errors.push(error.clone());
+        error
+    }
+
+    fn error_at_peek(&mut self, kind: ParseErrorKind, message: &str) -> ParseError {
+        self.error(self.peek().clone(), kind, message)
+    }
+
     fn synchronize(&mut self) {
         self.advance();
 
@@ -482,11 +781,14 @@ impl Parser {
                 TokenType::Class
                 | TokenType::Fun
                 | TokenType::Var
+                | TokenType::Const
                 | TokenType::For
                 | TokenType::If
                 | TokenType::While
                 | TokenType::Print
-                | TokenType::Return => {
+                | TokenType::Return
+                | TokenType::Import
+                | TokenType::Export => {
                     return;
                 }
                 _ => {}
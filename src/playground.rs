@@ -0,0 +1,186 @@
+//! `eval`: text in, text out, for hosts like a browser playground that
+//! only want to run a program and show what it did.
+//!
+//! Built for `wasm32-unknown-unknown`, which has no clock, threads, files
+//! or stdin, the crate exports `run` to JavaScript through wasm-bindgen.
+//! The kernel's libzmq doesn't build for that target, so leave it out:
+//!
+//! ```text
+//! cargo rustc --lib --release --target wasm32-unknown-unknown \
+//!     --no-default-features --crate-type cdylib
+//! wasm-bindgen --target web --out-dir pkg \
+//!     target/wasm32-unknown-unknown/release/crafting_rust.wasm
+//! ```
+//!
+//! The page passes `run` the program and a function each for the lines it
+//! prints and the errors it reports:
+//!
+//! ```text
+//! import init, { run } from "./pkg/crafting_rust.js";
+//! await init();
+//! const clean = run(source, line => output.append(line + "\n"), line => errors.append(line + "\n"));
+//! ```
+//!
+//! Panics abort on that target rather than unwind, so a runtime error is
+//! passed to the error function by the panic hook and then traps. The page
+//! should catch the trap and start a fresh instance for the next run.
+
+use std::{
+    cell::RefCell,
+    panic::{self, AssertUnwindSafe},
+    rc::Rc,
+    sync::atomic::Ordering,
+};
+
+use crate::{
+    diagnostics, folder,
+    interpreter::Interpreter,
+    natives,
+    output::{Callback, OutputEvent},
+    parser::Parser,
+    preview,
+    scanner::Scanner,
+    source::SourceId,
+};
+
+#[cfg(target_arch = "wasm32")]
+pub(crate) use exports::now_millis;
+
+/// Runs `source` in a fresh interpreter and returns what it printed and
+/// the errors it reported, a line each, in the order they happened.
+pub fn eval(source: &str) -> String {
+    let transcript = Rc::new(RefCell::new(String::new()));
+    let line = {
+        let transcript = Rc::clone(&transcript);
+        Rc::new(move |text: &str| push_line(&transcript, text))
+    };
+    run(source, line.clone(), line);
+    transcript.take()
+}
+
+/// Runs `source` in a fresh interpreter, passing each line it prints to
+/// `print` as it's printed. Returns the errors it reported, a line each,
+/// or nothing if it ran cleanly.
+pub fn eval_with_print(source: &str, print: impl Fn(&str) + 'static) -> String {
+    let errors = Rc::new(RefCell::new(String::new()));
+    let error = {
+        let errors = Rc::clone(&errors);
+        Rc::new(move |text: &str| push_line(&errors, text))
+    };
+    run(source, Rc::new(print), error);
+    errors.take()
+}
+
+fn push_line(text: &RefCell<String>, line: &str) {
+    let mut text = text.borrow_mut();
+    text.push_str(line);
+    text.push('\n');
+}
+
+/// Runs `source` as `jlox run` would, with printed values formatted as it
+/// prints them.
+fn run(source: &str, print: Rc<dyn Fn(&str)>, error: Rc<dyn Fn(&str)>) {
+    let sink = {
+        let error = Rc::clone(&error);
+        Rc::new(Callback(move |event| match event {
+            OutputEvent::PrintValue(value) => print(&preview::format(&value)),
+            OutputEvent::Diagnostic(diagnostic) => error(&diagnostic.to_string()),
+            OutputEvent::DiagnosticsSuppressed(count) => {
+                error(&format!("... {} additional errors suppressed.", count))
+            }
+            OutputEvent::Result(_) | OutputEvent::TraceLine(_) => {}
+        }))
+    };
+
+    crate::HAD_ERROR.store(false, Ordering::Relaxed);
+    diagnostics::reset();
+    let mut interpreter = Interpreter::new();
+    interpreter.set_output(sink.clone());
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        let tokens = Scanner::new(source, SourceId::ANONYMOUS).tokens();
+        let (ast, statements) = folder::parse_and_fold(Parser::new(tokens));
+        diagnostics::flush(&*sink);
+        interpreter.interpret(&ast, statements)
+    }));
+    diagnostics::flush(&*sink);
+    crate::HAD_ERROR.store(false, Ordering::Relaxed);
+    natives::take_exit_request();
+    if let Err(payload) = result {
+        error(&crate::panic_message(&*payload));
+    }
+}
+
+/// What the page calls, and what it provides.
+#[cfg(target_arch = "wasm32")]
+mod exports {
+    use std::{
+        cell::{Cell, RefCell},
+        panic,
+        rc::Rc,
+    };
+
+    use js_sys::Function;
+    use wasm_bindgen::prelude::*;
+
+    #[wasm_bindgen]
+    extern "C" {
+        /// Milliseconds since the Unix epoch, by the page's clock.
+        #[wasm_bindgen(js_namespace = Date, js_name = now)]
+        pub(crate) fn now_millis() -> f64;
+    }
+
+    thread_local! {
+        /// The error function of the program being run, for the panic hook.
+        static REPORT_ERROR: RefCell<Option<Function>> = const { RefCell::new(None) };
+    }
+
+    fn call(function: &Function, text: &str) {
+        // An exception thrown by the page's function is the page's to see.
+        let _ = function.call1(&JsValue::NULL, &JsValue::from_str(text));
+    }
+
+    /// Installs the panic hook when the module is instantiated: nothing
+    /// unwinds on wasm32, so the hook is the last chance to report a
+    /// runtime error before the instance traps. Panics outside `run` go to
+    /// whatever hook was there before.
+    #[wasm_bindgen(start)]
+    pub fn start() {
+        let previous = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            let message = crate::panic_message(info.payload());
+            let reported = REPORT_ERROR.with(|report| match &*report.borrow() {
+                Some(report) => {
+                    call(report, &message);
+                    true
+                }
+                None => false,
+            });
+            if !reported {
+                previous(info);
+            }
+        }));
+    }
+
+    /// Runs `source`, calling `print` with each line it prints and `error`
+    /// with each error it reports. Returns whether it ran without errors.
+    #[wasm_bindgen(js_name = run)]
+    pub fn run_js(source: &str, print: Function, error: Function) -> bool {
+        let clean = Rc::new(Cell::new(true));
+        let report = {
+            let clean = Rc::clone(&clean);
+            let error = error.clone();
+            move |text: &str| {
+                clean.set(false);
+                call(&error, text);
+            }
+        };
+        REPORT_ERROR.with(|report| *report.borrow_mut() = Some(error));
+        super::run(
+            source,
+            Rc::new(move |text: &str| call(&print, text)),
+            Rc::new(report),
+        );
+        REPORT_ERROR.with(|report| report.borrow_mut().take());
+        clean.get()
+    }
+}
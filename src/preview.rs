@@ -0,0 +1,146 @@
+//! How printed values are cut short, so printing a huge list or a long
+//! string doesn't flood the terminal. Only what's shown is affected: the
+//! values themselves, and `Value`'s `Display`, are left whole.
+
+use std::{cell::Cell, fmt::Write};
+
+use crate::interpreter::Value;
+
+/// Limits on what's printed. `None` means no limit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct PrintLimits {
+    /// Characters of a string shown before the rest is left out.
+    pub max_string: Option<usize>,
+    /// Elements of a list shown before the rest are left out.
+    pub max_items: Option<usize>,
+    /// How many lists deep to show; deeper ones print as `[...]`.
+    pub max_nesting: Option<usize>,
+}
+
+impl PrintLimits {
+    /// What the REPL uses for any limit not given on the command line.
+    pub const REPL: PrintLimits = PrintLimits {
+        max_string: Some(1000),
+        max_items: Some(100),
+        max_nesting: Some(6),
+    };
+
+    const NAMES: &'static [&'static str] = &["max-string", "max-items", "max-nesting"];
+
+    /// These limits, with any that aren't set taken from `defaults`.
+    pub fn or(self, defaults: PrintLimits) -> PrintLimits {
+        PrintLimits {
+            max_string: self.max_string.or(defaults.max_string),
+            max_items: self.max_items.or(defaults.max_items),
+            max_nesting: self.max_nesting.or(defaults.max_nesting),
+        }
+    }
+
+    /// Sets the limit called `name` (as in the command-line flag, without
+    /// the dashes) to `value`, a count or "none".
+    pub fn set(&mut self, name: &str, value: &str) -> Result<(), String> {
+        let limit = match name {
+            "max-string" => &mut self.max_string,
+            "max-items" => &mut self.max_items,
+            "max-nesting" => &mut self.max_nesting,
+            _ => {
+                return Err(format!(
+                    "Unknown limit '{}'. Expected one of {}.",
+                    name,
+                    Self::NAMES.join(", ")
+                ))
+            }
+        };
+        *limit = match value {
+            "none" => None,
+            _ => Some(
+                value
+                    .parse()
+                    .map_err(|_| format!("{} expects a non-negative integer or 'none'.", name))?,
+            ),
+        };
+        Ok(())
+    }
+}
+
+impl std::fmt::Display for PrintLimits {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let limits = [self.max_string, self.max_items, self.max_nesting];
+        for (i, (name, limit)) in Self::NAMES.iter().zip(limits).enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            match limit {
+                Some(limit) => write!(f, "{} {}", name, limit)?,
+                None => write!(f, "{} none", name)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+thread_local! {
+    static LIMITS: Cell<PrintLimits> = const {
+        Cell::new(PrintLimits {
+            max_string: None,
+            max_items: None,
+            max_nesting: None,
+        })
+    };
+}
+
+/// Sets the limits `format` applies on this thread.
+pub fn set_limits(limits: PrintLimits) {
+    LIMITS.with(|l| l.set(limits));
+}
+
+pub fn limits() -> PrintLimits {
+    LIMITS.with(Cell::get)
+}
+
+/// `value` as `print` shows it, within this thread's limits.
+pub fn format(value: &Value) -> String {
+    let mut out = String::new();
+    write_value(&mut out, value, &limits(), 0);
+    out
+}
+
+// `depth` is how many lists `value` is inside.
+fn write_value(out: &mut String, value: &Value, limits: &PrintLimits, depth: usize) {
+    match value {
+        Value::String(s) => match limits.max_string {
+            Some(max) if s.chars().count() > max => {
+                let shown: String = s.chars().take(max).collect();
+                let hidden = s.chars().count() - max;
+                let _ = write!(out, "{}... {} more characters", shown, hidden);
+            }
+            _ => out.push_str(s),
+        },
+        Value::List(_) if limits.max_nesting.is_some_and(|max| depth >= max) => {
+            out.push_str("[...]")
+        }
+        Value::List(items) => {
+            let items = items.borrow();
+            let shown = limits
+                .max_items
+                .map_or(items.len(), |max| max.min(items.len()));
+            out.push('[');
+            for (i, item) in items[..shown].iter().enumerate() {
+                if i > 0 {
+                    out.push_str(", ");
+                }
+                write_value(out, item, limits, depth + 1);
+            }
+            if shown < items.len() {
+                if shown > 0 {
+                    out.push_str(", ");
+                }
+                let _ = write!(out, "... {} more items", items.len() - shown);
+            }
+            out.push(']');
+        }
+        other => {
+            let _ = write!(out, "{}", other);
+        }
+    }
+}
@@ -0,0 +1,103 @@
+//! `--profile`: how often each function was called and how long those calls
+//! took, reported when the program ends.
+
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    fmt::Write,
+    time::{Duration, Instant},
+};
+
+#[derive(Default)]
+struct Entry {
+    calls: u64,
+    total: Duration,
+    // Calls to this function still running, so that time spent in a
+    // recursive call isn't counted again by the calls around it.
+    active: usize,
+}
+
+thread_local! {
+    static PROFILE: RefCell<Option<HashMap<String, Entry>>> = const { RefCell::new(None) };
+}
+
+/// Starts recording calls, forgetting any recorded before.
+pub fn start() {
+    PROFILE.with(|profile| *profile.borrow_mut() = Some(HashMap::new()));
+}
+
+pub fn enabled() -> bool {
+    PROFILE.with(|profile| profile.borrow().is_some())
+}
+
+/// Notes that a call to `name` began, returning when. Pass that to `exit`
+/// when the call returns.
+pub fn enter(name: &str) -> Instant {
+    PROFILE.with(|profile| {
+        if let Some(profile) = profile.borrow_mut().as_mut() {
+            let entry = profile.entry(name.to_string()).or_default();
+            entry.calls += 1;
+            entry.active += 1;
+        }
+    });
+    Instant::now()
+}
+
+pub fn exit(name: &str, started: Instant) {
+    let elapsed = started.elapsed();
+    PROFILE.with(|profile| {
+        if let Some(entry) = profile.borrow_mut().as_mut().and_then(|p| p.get_mut(name)) {
+            entry.active -= 1;
+            if entry.active == 0 {
+                entry.total += elapsed;
+            }
+        }
+    });
+}
+
+/// Stops recording and describes what was recorded, slowest function
+/// first. Times include the functions each one called.
+pub fn report() -> String {
+    let Some(profile) = PROFILE.with(|profile| profile.borrow_mut().take()) else {
+        return String::new();
+    };
+    let mut entries: Vec<(String, Entry)> = profile.into_iter().collect();
+    entries.sort_by(|(a_name, a), (b_name, b)| {
+        b.total
+            .cmp(&a.total)
+            .then(b.calls.cmp(&a.calls))
+            .then(a_name.cmp(b_name))
+    });
+
+    let width = entries
+        .iter()
+        .map(|(name, _)| name.len())
+        .chain(["function".len()])
+        .max()
+        .unwrap_or(0);
+    let mut report = String::new();
+    let _ = writeln!(
+        report,
+        "{:<width$}  {:>8}  {:>12}  {:>12}",
+        "function", "calls", "total", "per call"
+    );
+    for (name, entry) in &entries {
+        let total = millis(entry.total);
+        let _ = writeln!(
+            report,
+            "{:<width$}  {:>8}  {:>10.3}ms  {:>10.3}ms",
+            name,
+            entry.calls,
+            total,
+            total / entry.calls as f64
+        );
+    }
+    if entries.is_empty() {
+        report.push_str("(no functions were called)\n");
+    }
+    report
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
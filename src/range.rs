@@ -0,0 +1,32 @@
+//! Ranges, made by `a..b`, which stops before `b`, and `a..=b`, which
+//! includes it. They count up by one from `a` and hold only their bounds,
+//! so a long range costs no more than a short one.
+
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Range {
+    pub start: f64,
+    pub end: f64,
+    pub inclusive: bool,
+}
+
+impl Range {
+    /// The `index`th number in the range, counting from 0, if it has one.
+    pub fn get(self, index: usize) -> Option<f64> {
+        let n = self.start + index as f64;
+        let inside = if self.inclusive {
+            n <= self.end
+        } else {
+            n < self.end
+        };
+        inside.then_some(n)
+    }
+}
+
+impl fmt::Display for Range {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let dots = if self.inclusive { "..=" } else { ".." };
+        write!(f, "{}{}{}", self.start, dots, self.end)
+    }
+}
@@ -0,0 +1,179 @@
+//! Recording what a run printed as JSON (`--record`), and checking a later
+//! run against such a recording (`replay-compare`), so that changes to the
+//! interpreter can be shown not to change what programs do.
+
+use std::{
+    cell::{Cell, RefCell},
+    fmt,
+    rc::Rc,
+};
+
+use crate::{
+    interpreter::Value,
+    json::Json,
+    output::{OutputEvent, OutputSink},
+};
+
+/// How an event is written to a trace. Trace lines are left out, since
+/// they're timings and the like that differ from run to run. Diagnostics
+/// keep their position but not their file, which may have moved.
+pub fn to_json(event: &OutputEvent) -> Option<Json> {
+    let value = |value: &Value| {
+        Json::object([
+            ("type", Json::from(value.type_name())),
+            ("value", Json::from(value.to_string())),
+        ])
+    };
+    let json = match event {
+        OutputEvent::PrintValue(printed) => {
+            Json::object([("kind", Json::from("print")), ("value", value(printed))])
+        }
+        OutputEvent::Result(result) => {
+            Json::object([("kind", Json::from("result")), ("value", value(result))])
+        }
+        OutputEvent::Diagnostic(diagnostic) => Json::object([
+            ("kind", Json::from("diagnostic")),
+            ("line", Json::from(diagnostic.line as usize)),
+            ("column", Json::from(diagnostic.column as usize)),
+            ("location", Json::from(diagnostic.location.as_str())),
+            ("message", Json::from(diagnostic.message.as_str())),
+        ]),
+        OutputEvent::DiagnosticsSuppressed(count) => Json::object([
+            ("kind", Json::from("suppressed")),
+            ("count", Json::from(*count)),
+        ]),
+        OutputEvent::TraceLine(_) => return None,
+    };
+    Some(json)
+}
+
+/// How a run ended: the code passed to `exit()` (0 if it wasn't called), or
+/// the message of the runtime error that stopped it.
+pub enum Outcome {
+    Exit(i32),
+    Panic(String),
+}
+
+impl Outcome {
+    fn to_json(&self) -> Json {
+        match self {
+            Outcome::Exit(code) => Json::object([
+                ("kind", Json::from("exit")),
+                ("code", Json::from(*code as f64)),
+            ]),
+            Outcome::Panic(message) => Json::object([
+                ("kind", Json::from("panic")),
+                ("message", Json::from(message.as_str())),
+            ]),
+        }
+    }
+}
+
+/// Passes events on to another sink, keeping a copy of each for the trace.
+#[derive(Debug)]
+pub struct Recorder {
+    inner: Rc<dyn OutputSink>,
+    events: RefCell<Vec<Json>>,
+}
+
+impl Recorder {
+    pub fn new(inner: Rc<dyn OutputSink>) -> Self {
+        Recorder {
+            inner,
+            events: RefCell::new(Vec::new()),
+        }
+    }
+
+    /// The finished trace, ending with how the run ended.
+    pub fn finish(&self, outcome: Outcome) -> Json {
+        let mut events = self.events.take();
+        events.push(outcome.to_json());
+        Json::Array(events)
+    }
+}
+
+impl OutputSink for Recorder {
+    fn emit(&self, event: OutputEvent) {
+        if let Some(json) = to_json(&event) {
+            self.events.borrow_mut().push(json);
+        }
+        self.inner.emit(event);
+    }
+}
+
+/// The first place a run's events differ from a trace's. `None` on either
+/// side means that run ended first.
+pub struct Divergence {
+    pub index: usize,
+    pub expected: Option<Json>,
+    pub actual: Option<Json>,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let show = |event: &Option<Json>| match event {
+            Some(event) => event.to_string(),
+            None => "(end of run)".to_string(),
+        };
+        writeln!(f, "Traces diverge at event {}:", self.index + 1)?;
+        writeln!(f, "  expected: {}", show(&self.expected))?;
+        write!(f, "  actual:   {}", show(&self.actual))
+    }
+}
+
+/// Checks each event against a recorded trace as it's emitted, noting the
+/// first that doesn't match. Nothing is printed.
+#[derive(Debug)]
+pub struct Comparer {
+    expected: Vec<Json>,
+    seen: Cell<usize>,
+    divergence: RefCell<Option<(usize, Option<Json>)>>,
+}
+
+impl Comparer {
+    pub fn new(expected: Vec<Json>) -> Self {
+        Comparer {
+            expected,
+            seen: Cell::new(0),
+            divergence: RefCell::new(None),
+        }
+    }
+
+    /// How many events have matched so far.
+    pub fn matched(&self) -> usize {
+        match &*self.divergence.borrow() {
+            Some((index, _)) => *index,
+            None => self.seen.get(),
+        }
+    }
+
+    /// Compares how the run ended, then reports the first divergence, if any.
+    pub fn finish(&self, outcome: Outcome) -> Option<Divergence> {
+        self.compare(Some(outcome.to_json()));
+        if self.seen.get() < self.expected.len() {
+            self.compare(None);
+        }
+        let (index, actual) = self.divergence.take()?;
+        Some(Divergence {
+            index,
+            expected: self.expected.get(index).cloned(),
+            actual,
+        })
+    }
+
+    fn compare(&self, actual: Option<Json>) {
+        let index = self.seen.get();
+        self.seen.set(index + 1);
+        if self.divergence.borrow().is_none() && self.expected.get(index) != actual.as_ref() {
+            *self.divergence.borrow_mut() = Some((index, actual));
+        }
+    }
+}
+
+impl OutputSink for Comparer {
+    fn emit(&self, event: OutputEvent) {
+        if let Some(json) = to_json(&event) {
+            self.compare(Some(json));
+        }
+    }
+}
@@ -1,48 +1,172 @@
+//! Static checks over variable scopes, run before a program is executed,
+//! and what each name refers to, for editors.
+
 use std::collections::HashMap;
 
 use crate::{
-    expression::{Expr, ExprVisitor},
-    interpreter::Interpreter,
-    returns::Return,
-    statement::{Stmt, StmtVisitor},
-    Token,
+    arena::Arena,
+    expression::{ExprId, ExprVisitor},
+    statement::{ImportTarget, Stmt, StmtId, StmtVisitor},
+    token::{Literal, Token, TokenType},
 };
 
-struct Resolver {
-    interpreter: Interpreter,
-    scopes: Vec<HashMap<String, bool>>,
+/// Reports scope errors in `statements`, such as a local variable read in
+/// its own initializer, and the `unused-variable` and `shadowing` lints.
+pub fn resolve(arena: &Arena, statements: &[StmtId]) -> Resolution {
+    Resolver::new(arena, true).run(statements)
 }
 
-impl Resolver {
-    fn resolve_stmts(&self, statements: Vec<Stmt>) {
-        for statement in statements {
-            self.resolve_stmt(statement);
+/// Reports the scope errors in `statements` that stop a program from
+/// running, without the lints, which only `jlox check` reports.
+pub fn check(arena: &Arena, statements: &[StmtId]) {
+    let mut resolver = Resolver::new(arena, true);
+    resolver.linting = false;
+    resolver.run(statements);
+}
+
+/// Works out what the names in `statements` refer to without reporting
+/// anything, so it can run on a program that doesn't parse.
+pub fn scopes(arena: &Arena, statements: &[StmtId]) -> Resolution {
+    Resolver::new(arena, false).run(statements)
+}
+
+/// As `scopes`, for the body of a function taking `params`, as though it
+/// were declared on its own.
+pub fn function_scopes(arena: &Arena, params: &[Token], body: &[StmtId]) -> Resolution {
+    let mut resolver = Resolver::new(arena, false);
+    resolver.resolve_function(params.to_vec(), body.to_vec());
+    resolver.finish()
+}
+
+/// As `scopes`, for an expression evaluated outside any block.
+pub fn expression_scopes(arena: &Arena, expr: ExprId) -> Resolution {
+    let mut resolver = Resolver::new(arena, false);
+    resolver.resolve_expr(expr);
+    resolver.finish()
+}
+
+/// The declarations in a program and the names that refer to them.
+#[derive(Debug, Default)]
+pub struct Resolution {
+    pub declarations: Vec<Declaration>,
+    /// Each variable read or assigned, with the index in `declarations` of
+    /// what it refers to. Names declared nowhere, like natives, are left
+    /// out.
+    pub references: Vec<(Token, usize)>,
+    /// The variables that refer to no declaration, each with whether it
+    /// was read rather than assigned.
+    pub free: Vec<(Token, bool)>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Declaration {
+    pub name: Token,
+    pub kind: DeclarationKind,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DeclarationKind {
+    Variable,
+    Constant,
+    Parameter,
+    Function(Vec<Token>),
+    /// A method, with the index of its class's declaration.
+    Method(Vec<Token>, usize),
+    Class,
+    Import,
+}
+
+struct Resolver<'a> {
+    arena: &'a Arena,
+    scopes: Vec<HashMap<String, Local>>,
+    /// Top-level names, which can be used before they're declared, as in
+    /// functions that call each other.
+    globals: HashMap<String, usize>,
+    /// Names found in no enclosing scope, resolved against `globals` at
+    /// the end, each with whether it was read rather than assigned.
+    unresolved: Vec<(Token, bool)>,
+    resolution: Resolution,
+    reporting: bool,
+    /// Whether lints are reported along with errors.
+    linting: bool,
+    /// Class bodies the resolver is in, where `this` can be used.
+    classes: usize,
+    /// Function bodies the resolver is in, each with an `arguments` local
+    /// of its own.
+    functions: usize,
+}
+
+struct Local {
+    name: Token,
+    declaration: usize,
+    /// Whether its declaration has finished.
+    defined: bool,
+    used: bool,
+    /// Only `var`s are reported when unused: parameters are often ignored
+    /// on purpose, as in a callback.
+    is_var: bool,
+}
+
+impl<'a> Resolver<'a> {
+    fn new(arena: &'a Arena, reporting: bool) -> Resolver<'a> {
+        Resolver {
+            arena,
+            scopes: Vec::new(),
+            globals: HashMap::new(),
+            unresolved: Vec::new(),
+            resolution: Resolution::default(),
+            reporting,
+            linting: reporting,
+            classes: 0,
+            functions: 0,
         }
     }
 
-    fn resolve_stmt(&mut self, stmt: Stmt) {
-        stmt.accept(self);
+    fn run(mut self, statements: &[StmtId]) -> Resolution {
+        for statement in statements {
+            self.resolve_stmt(*statement);
+        }
+        self.finish()
     }
 
-    fn resolve_expr(&mut self, expr: Expr) {
-        expr.accept(self);
+    /// Resolves the names found in no enclosing scope against the globals.
+    fn finish(mut self) -> Resolution {
+        for (name, read) in std::mem::take(&mut self.unresolved) {
+            if let Some(&declaration) = self.globals.get(name.lexeme()) {
+                if !read {
+                    self.check_assignable(&name, declaration);
+                }
+                self.resolution.references.push((name, declaration));
+            } else {
+                self.resolution.free.push((name, read));
+            }
+        }
+        self.resolution
     }
 
-    fn resolve_local(&mut self, name: Token) {
-        for i in (0..=self.scopes.len()).rev() {
-            if self.scopes.get(i).unwrap().contains_key(&name.lexeme) {
-                self.interpreter.resolve(name, self.scopes.len() - 1 - i);
-            }
+    fn resolve_stmts(&mut self, statements: Vec<StmtId>) {
+        for statement in statements {
+            self.resolve_stmt(statement);
         }
     }
 
-    fn resolve_function(&self, name: Token, params: Vec<Token>, body: Vec<Stmt>) {
+    fn resolve_stmt(&mut self, stmt: StmtId) {
+        self.arena.stmt(stmt).accept(self);
+    }
+
+    fn resolve_expr(&mut self, expr: ExprId) {
+        self.arena.expr(expr).accept(self);
+    }
+
+    fn resolve_function(&mut self, params: Vec<Token>, body: Vec<StmtId>) {
         self.begin_scope();
+        self.functions += 1;
         for param in params {
-            self.declare(param);
+            self.declare(param.clone(), DeclarationKind::Parameter);
             self.define(param);
         }
         self.resolve_stmts(body);
+        self.functions -= 1;
         self.end_scope();
     }
 
@@ -51,152 +175,302 @@ impl Resolver {
     }
 
     fn end_scope(&mut self) {
-        self.scopes.pop();
+        let Some(scope) = self.scopes.pop() else {
+            return;
+        };
+        if !self.linting {
+            return;
+        }
+        for local in scope.values() {
+            if local.is_var && !local.used && !local.name.lexeme().starts_with('_') {
+                let message = format!("Local variable '{}' is never read.", local.name.lexeme());
+                crate::lint("unused-variable", &local.name, &message);
+            }
+        }
+    }
+
+    /// Records a declaration, and returns its index.
+    fn record(&mut self, name: Token, kind: DeclarationKind) -> usize {
+        let declarations = &mut self.resolution.declarations;
+        declarations.push(Declaration { name, kind });
+        declarations.len() - 1
+    }
+
+    fn declare(&mut self, name: Token, kind: DeclarationKind) {
+        let declaration = self.record(name.clone(), kind);
+        let Some((scope, enclosing)) = self.scopes.split_last_mut() else {
+            self.globals
+                .entry(name.lexeme().to_string())
+                .or_insert(declaration);
+            return;
+        };
+        if self.linting
+            && enclosing
+                .iter()
+                .any(|outer| outer.contains_key(name.lexeme()))
+        {
+            let message = format!("'{}' shadows a local in an enclosing scope.", name.lexeme());
+            crate::lint("shadowing", &name, &message);
+        }
+        let local = Local {
+            name: name.clone(),
+            declaration,
+            defined: false,
+            used: false,
+            is_var: false,
+        };
+        scope.insert(name.lexeme().to_string(), local);
     }
 
-    fn declare(&mut self, name: Token) {
-        if !self.scopes.is_empty() {
-            if let Some(mut scope) = self.scopes.last_mut() {
-                scope.insert(name.lexeme, false);
+    /// Notes what the variable `name` refers to, marking it used if it's
+    /// a local and `read`.
+    fn reference(&mut self, name: Token, read: bool) {
+        let innermost = self
+            .scopes
+            .iter_mut()
+            .rev()
+            .find_map(|scope| scope.get_mut(name.lexeme()));
+        match innermost {
+            Some(local) => {
+                local.used |= read;
+                let declaration = local.declaration;
+                if !read {
+                    self.check_assignable(&name, declaration);
+                }
+                self.resolution.references.push((name, declaration));
             }
+            // The function's own list of its arguments, which has no
+            // declaration to refer to.
+            None if self.functions > 0 && name.lexeme() == "arguments" => {}
+            None => self.unresolved.push((name, read)),
+        }
+    }
+
+    /// Reports assigning `name` if it refers to a constant.
+    fn check_assignable(&self, name: &Token, declaration: usize) {
+        let kind = &self.resolution.declarations[declaration].kind;
+        if self.reporting && *kind == DeclarationKind::Constant {
+            let message = format!("Can't assign to constant '{}'.", name.lexeme());
+            crate::error_at_token(name, &message);
         }
     }
 
     fn define(&mut self, name: Token) {
-        if !self.scopes.is_empty() {
-            if let Some(mut scope) = self.scopes.last_mut() {
-                scope.insert(name.lexeme, true);
-            }
+        if let Some(local) = self
+            .scopes
+            .last_mut()
+            .and_then(|s| s.get_mut(name.lexeme()))
+        {
+            local.defined = true;
         }
     }
 }
 
-impl StmtVisitor<()> for Resolver {
-    fn visit_block_stmt(&mut self, statements: Vec<Stmt>) -> Result<(), Return> {
+impl StmtVisitor<()> for Resolver<'_> {
+    fn visit_block_stmt(&mut self, statements: Vec<StmtId>) {
         self.begin_scope();
         self.resolve_stmts(statements);
         self.end_scope();
-        Ok(())
     }
 
-    fn visit_var_stmt(
-        &mut self,
-        name: crate::Token,
-        initializer: Option<crate::expression::Expr>,
-    ) -> Result<(), Return> {
-        self.declare(name);
+    fn visit_var_stmt(&mut self, name: Token, initializer: Option<ExprId>) {
+        self.declare(name.clone(), DeclarationKind::Variable);
+        if let Some(local) = self
+            .scopes
+            .last_mut()
+            .and_then(|s| s.get_mut(name.lexeme()))
+        {
+            local.is_var = true;
+        }
         if let Some(init) = initializer {
             self.resolve_expr(init);
         }
         self.define(name);
-        Ok(())
     }
 
-    fn visit_function_stmt(
-        &mut self,
-        name: Token,
-        params: Vec<Token>,
-        body: Vec<Stmt>,
-    ) -> Result<(), Return> {
-        self.declare(name);
+    fn visit_const_stmt(&mut self, name: Token, initializer: ExprId) {
+        self.declare(name.clone(), DeclarationKind::Constant);
+        if let Some(local) = self
+            .scopes
+            .last_mut()
+            .and_then(|s| s.get_mut(name.lexeme()))
+        {
+            local.is_var = true;
+        }
+        self.resolve_expr(initializer);
         self.define(name);
+    }
+
+    fn visit_destructure_stmt(&mut self, names: Vec<Token>, initializer: ExprId) {
+        for name in &names {
+            self.declare(name.clone(), DeclarationKind::Variable);
+            if let Some(local) = self
+                .scopes
+                .last_mut()
+                .and_then(|s| s.get_mut(name.lexeme()))
+            {
+                local.is_var = true;
+            }
+        }
+        self.resolve_expr(initializer);
+        for name in names {
+            self.define(name);
+        }
+    }
 
-        self.resolve_function(name, params, body);
-        Ok(())
+    fn visit_function_stmt(&mut self, name: Token, params: Vec<Token>, body: Vec<StmtId>) {
+        self.declare(name.clone(), DeclarationKind::Function(params.clone()));
+        self.define(name);
+        self.resolve_function(params, body);
     }
 
-    fn visit_expression_stmt(&mut self, stmt: Expr) -> Result<(), Return> {
+    fn visit_expression_stmt(&mut self, stmt: ExprId) {
         self.resolve_expr(stmt);
-        Ok(())
     }
 
-    fn visit_if_stmt(
-        &mut self,
-        condition: Expr,
-        then_stmt: Box<Stmt>,
-        else_stmt: Box<Option<Stmt>>,
-    ) -> Result<(), Return> {
+    fn visit_if_stmt(&mut self, condition: ExprId, then_stmt: StmtId, else_stmt: Option<StmtId>) {
         self.resolve_expr(condition);
-        self.resolve_stmt(*then_stmt);
-        if let Some(else_branch) = *else_stmt {
+        self.resolve_stmt(then_stmt);
+        if let Some(else_branch) = else_stmt {
             self.resolve_stmt(else_branch);
         }
-        Ok(())
     }
 
-    fn visit_print_stmt(&mut self, stmt: Expr) -> Result<(), Return> {
+    fn visit_print_stmt(&mut self, stmt: ExprId) {
         self.resolve_expr(stmt);
-        Ok(())
     }
 
-    fn visit_return_stmt(&mut self, keyword: Token, value: Option<Expr>) -> Result<(), Return> {
+    fn visit_return_stmt(&mut self, _: Token, value: Option<ExprId>) {
         if let Some(val) = value {
             self.resolve_expr(val);
         }
-        Ok(())
     }
 
-    fn visit_while_stmt(&mut self, condition: Expr, body: Box<Stmt>) -> Result<(), Return> {
+    fn visit_while_stmt(&mut self, condition: ExprId, body: StmtId) {
         self.resolve_expr(condition);
-        self.resolve_stmt(*body);
-        Ok(())
+        self.resolve_stmt(body);
+    }
+
+    fn visit_for_in_stmt(&mut self, name: Token, iterable: ExprId, body: StmtId) {
+        self.resolve_expr(iterable);
+        self.begin_scope();
+        self.declare(name.clone(), DeclarationKind::Variable);
+        if let Some(local) = self
+            .scopes
+            .last_mut()
+            .and_then(|s| s.get_mut(name.lexeme()))
+        {
+            local.is_var = true;
+        }
+        self.define(name);
+        self.resolve_stmt(body);
+        self.end_scope();
+    }
+
+    fn visit_class_stmt(&mut self, name: Token, methods: Vec<StmtId>) {
+        self.declare(name.clone(), DeclarationKind::Class);
+        let class = self.resolution.declarations.len() - 1;
+        self.define(name);
+        self.classes += 1;
+        for method in methods {
+            if let Stmt::Function(name, params, body) = self.arena.stmt(method) {
+                // Methods are looked up on instances, not in a scope.
+                self.record(name.clone(), DeclarationKind::Method(params.clone(), class));
+                self.resolve_function(params.clone(), body.clone());
+            }
+        }
+        self.classes -= 1;
     }
-}
 
-impl ExprVisitor<()> for Resolver {
-    fn visit_var_expr(&self, t: Token) -> () {
-        if let Some(scope) = self.scopes.last() {}
-        if !self.scopes.is_empty() {
-            if let Some(val) = self.scopes.last().unwrap().get(&t.lexeme) {
-                if val == &false {
-                    Error("");
+    fn visit_import_stmt(&mut self, _: Token, _: Token, target: ImportTarget) {
+        match target {
+            ImportTarget::All => {}
+            ImportTarget::Alias(alias) | ImportTarget::Lazy(alias) => {
+                self.declare(alias.clone(), DeclarationKind::Import);
+                self.define(alias);
+            }
+            ImportTarget::Names(names) => {
+                for name in names {
+                    self.declare(name.clone(), DeclarationKind::Import);
+                    self.define(name);
                 }
             }
         }
+    }
 
-        self.resolve_local(t);
-        ()
+    fn visit_export_stmt(&mut self, keyword: Token, declaration: StmtId) {
+        if self.reporting && !self.scopes.is_empty() {
+            crate::error_at_token(&keyword, "Can only export top-level declarations.");
+        }
+        self.resolve_stmt(declaration);
     }
+}
 
-    fn visit_assign_expr(&mut self, name: Token, value: Box<Expr>) -> () {
-        self.resolve_expr(*value);
-        self.resolve_local(name);
-        ()
+impl ExprVisitor<()> for Resolver<'_> {
+    fn visit_var_expr(&mut self, t: Token) {
+        // `this` is bound by calling a method, not declared anywhere.
+        if t.token_type == TokenType::This {
+            if self.reporting && self.classes == 0 {
+                crate::error_at_token(&t, "Can't use 'this' outside of a class.");
+            }
+            return;
+        }
+        let in_initializer = self
+            .scopes
+            .last()
+            .and_then(|scope| scope.get(t.lexeme()))
+            .is_some_and(|local| !local.defined);
+        if self.reporting && in_initializer {
+            crate::error_at_token(&t, "Can't read local variable in its own initializer.");
+        }
+        self.reference(t, true);
     }
 
-    fn visit_binary_expr(&mut self, l: Box<Expr>, op: Token, r: Box<Expr>) -> () {
-        self.resolve_expr(*l);
-        self.resolve_expr(*r);
-        ()
+    fn visit_assign_expr(&mut self, name: Token, value: ExprId) {
+        self.resolve_expr(value);
+        self.reference(name, false);
     }
 
-    fn visit_call_expr(&mut self, c: Box<Expr>, paren: Token, args: Vec<Expr>) -> () {
-        self.resolve_expr(*c);
+    fn visit_binary_expr(&mut self, l: ExprId, _: Token, r: ExprId) {
+        self.resolve_expr(l);
+        self.resolve_expr(r);
+    }
 
+    fn visit_call_expr(&mut self, c: ExprId, _: Token, args: Vec<ExprId>) {
+        self.resolve_expr(c);
         for arg in args {
             self.resolve_expr(arg);
         }
+    }
 
-        ()
+    fn visit_grouping_expr(&mut self, expr: ExprId) {
+        self.resolve_expr(expr);
     }
 
-    fn visit_grouping_expr(&mut self, expr: Box<Expr>) -> () {
-        self.resolve_expr(*expr);
-        ()
+    fn visit_literal_expr(&self, _: Literal) {}
+
+    fn visit_logical_expr(&mut self, l: ExprId, _: Token, r: ExprId) {
+        self.resolve_expr(l);
+        self.resolve_expr(r);
     }
 
-    fn visit_literal_expr(&self, literal: crate::Literal) -> () {
-        ()
+    fn visit_unary_expr(&mut self, _: Token, r: ExprId) {
+        self.resolve_expr(r);
     }
 
-    fn visit_logical_expr(&mut self, l: Box<Expr>, op: Token, r: Box<Expr>) -> () {
-        self.resolve_expr(*l);
-        self.resolve_expr(*r);
-        ()
+    fn visit_get_expr(&mut self, expr: ExprId, _: Token) {
+        // Properties are looked up dynamically, so only the object resolves.
+        self.resolve_expr(expr);
     }
 
-    fn visit_unary_expr(&mut self, op: Token, r: Box<Expr>) -> () {
-        self.resolve_expr(*r);
-        ()
+    fn visit_set_expr(&mut self, object: ExprId, _: Token, value: ExprId) {
+        self.resolve_expr(value);
+        self.resolve_expr(object);
+    }
+
+    fn visit_tuple_expr(&mut self, _: Token, values: Vec<ExprId>) {
+        for value in values {
+            self.resolve_expr(value);
+        }
     }
 }
@@ -1,5 +1,10 @@
 use crate::interpreter::Value;
 
-pub struct Return {
-  pub value: Value,
-}
\ No newline at end of file
+/// Unwinds statement execution back through `execute_block`.
+pub enum Return {
+    /// A `return` statement, caught by the enclosing function call.
+    Value(Value),
+    /// `exit(code)`, which isn't caught until `interpret` hands the code back
+    /// to the host.
+    Exit(i32),
+}
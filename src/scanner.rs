@@ -1,64 +1,81 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::Arc;
+
+use crate::literals;
+use crate::operators::Operators;
+use crate::source::{SourceId, Span};
 use crate::token::*;
-use std::collections::HashMap;
 
+/// Turns source into tokens one at a time, as an iterator: errors come out
+/// where they were found, between the tokens around them, and the last
+/// token is always `Eof`.
 pub struct Scanner {
-    source: String,
-    tokens: Vec<Token>,
+    source: &'static str,
+    // All of `source`, which tokens' spans are slices of.
+    text: Span,
+    // What the last character scanned produced and the iterator hasn't
+    // handed out yet: a number with a bad exponent is an error then a token.
+    pending: VecDeque<Result<Token, ScanError>>,
+    finished: bool,
     start: u32,
     current: u32,
     line: u32,
-    keywords: HashMap<String, TokenType>,
+    // Where the current line starts, less the extra bytes of any multi-byte
+    // characters on it so far, so `current - line_start` counts characters;
+    // and the column the token being scanned started at (strings can span
+    // lines).
+    line_start: u32,
+    start_column: u32,
+    source_id: SourceId,
+    // The infix operators registered beyond Lox's own.
+    operators: Arc<Operators>,
 }
 
 impl Scanner {
-    pub fn new(source: &str) -> Scanner {
-        // TODO: Think about converting keywords to enum
-        let mut keywords = HashMap::new();
-        keywords.insert(String::from("and"), TokenType::And);
-        keywords.insert(String::from("class"), TokenType::Class);
-        keywords.insert(String::from("else"), TokenType::Else);
-        keywords.insert(String::from("false"), TokenType::False);
-        keywords.insert(String::from("for"), TokenType::For);
-        keywords.insert(String::from("fun"), TokenType::Fun);
-        keywords.insert(String::from("if"), TokenType::If);
-        keywords.insert(String::from("nil"), TokenType::Nil);
-        keywords.insert(String::from("or"), TokenType::Or);
-        keywords.insert(String::from("print"), TokenType::Print);
-        keywords.insert(String::from("return"), TokenType::Return);
-        keywords.insert(String::from("super"), TokenType::Super);
-        keywords.insert(String::from("this"), TokenType::This);
-        keywords.insert(String::from("true"), TokenType::True);
-        keywords.insert(String::from("var"), TokenType::Var);
-        keywords.insert(String::from("while"), TokenType::While);
+    pub fn new(source: &str, source_id: SourceId) -> Scanner {
+        Self::with_operators(source, source_id, Arc::default())
+    }
+
+    /// A scanner that recognizes `operators` as well as Lox's own, such as
+    /// the ones registered with the interpreter that will run the program.
+    pub fn with_operators(source: &str, source_id: SourceId, operators: Arc<Operators>) -> Scanner {
+        // Editors on Windows often start UTF-8 files with a byte order mark.
+        let source = source.strip_prefix('\u{feff}').unwrap_or(source);
+        let text = Span::add(source);
         Scanner {
-            source: String::from(source),
-            tokens: Vec::new(),
+            source: text.text(),
+            text,
+            pending: VecDeque::new(),
+            finished: false,
             start: 0,
             current: 0,
             line: 1,
-            keywords,
+            line_start: 0,
+            start_column: 1,
+            source_id,
+            operators,
         }
     }
 
+    /// Scans the whole source, reporting its errors.
     pub fn scan_tokens(&mut self) -> Vec<Token> {
-        while !self.is_at_end() {
-            self.start = self.current;
-            self.scan_token();
-        }
+        self.by_ref().filter_map(reported).collect()
+    }
 
-        self.tokens.push(Token::new(
-            TokenType::Eof,
-            String::from(""),
-            Literal::None,
-            self.line,
-        ));
-        // for token in self.tokens.iter() {
-        //     println!("{:?}", token);
-        // }
-        self.tokens.clone()
+    /// The tokens, with each error reported as the scanner reaches it, for
+    /// a parser to take as it goes.
+    pub fn tokens(self) -> impl Iterator<Item = Token> {
+        self.filter_map(reported)
     }
 
     fn scan_token(&mut self) {
+        let rest = self.source_substring(self.start, self.source.len() as u32);
+        if let Some((length, precedence)) = self.operators.match_symbol(rest) {
+            self.current += length as u32;
+            self.add_token(TokenType::Operator(precedence));
+            return;
+        }
         let c: char = self.advance();
         match c {
             '(' => {
@@ -76,8 +93,23 @@ impl Scanner {
             ',' => {
                 self.add_token(TokenType::Comma);
             }
+            '.' if is_digit(self.peek()) => {
+                self.digits(is_digit);
+                self.error("A number needs a digit before its '.', as in 0.5.");
+                let fraction = self
+                    .source_substring(self.start, self.current)
+                    .replace('_', "");
+                let number = format!("0{}", fraction).parse().unwrap_or(0.0);
+                self.add_token_full(TokenType::Number, Literal::Number(number));
+            }
             '.' => {
-                self.add_token(TokenType::Dot);
+                if !self.match_token('.') {
+                    self.add_token(TokenType::Dot);
+                } else if self.match_token('=') {
+                    self.add_token(TokenType::DotDotEqual);
+                } else {
+                    self.add_token(TokenType::DotDot);
+                }
             }
             '-' => {
                 self.add_token(TokenType::Minus);
@@ -133,6 +165,7 @@ impl Scanner {
             }
             '\n' => {
                 self.line += 1;
+                self.line_start = self.current;
             }
             '"' => {
                 self.string();
@@ -148,7 +181,7 @@ impl Scanner {
                 } else if is_alpha(c) {
                     self.identifier();
                 } else {
-                    panic!("Lexical error on {}", self.line)
+                    self.error(&format!("Unexpected character '{}'.", c));
                 }
             }
         };
@@ -160,97 +193,184 @@ impl Scanner {
         }
 
         let text = self.source_substring(self.start, self.current);
-        let token_type: TokenType = match self.keywords.get(text) {
-            Some(x) => x.to_owned(),
-            None => TokenType::Identifier,
+        let token_type = match (keyword(text), self.operators.word(text)) {
+            (Some(token_type), _) => token_type,
+            (None, Some(precedence)) => TokenType::Operator(precedence),
+            (None, None) => TokenType::Identifier,
         };
 
         self.add_token(token_type);
     }
 
     fn source_substring(&self, start: u32, current: u32) -> &str {
-        let s = self.source.as_str();
+        let s = self.source;
         let start_us = usize::try_from(start).unwrap();
         let current_us = usize::try_from(current).unwrap();
-        let value = &s[start_us..current_us];
-        value
+        &s[start_us..current_us]
     }
 
     fn number(&mut self) {
-        while is_digit(self.peek()) {
-            self.advance();
+        if self.source_substring(self.start, self.current) == "0"
+            && matches!(self.peek(), 'x' | 'X')
+        {
+            return self.hex_number();
         }
+        self.digits(is_digit);
 
         if self.peek() == '.' && is_digit(self.peek_next()) {
             self.advance();
+            self.digits(is_digit);
+        } else if self.peek() == '.' && !is_alpha(self.peek_next()) && self.peek_next() != '.' {
+            // Not `1.abs` or `1..5`, so the dot was meant to start a
+            // fraction.
+            self.advance();
+            self.error("A number needs digits after its '.', as in 1.0.");
+        }
 
-            while is_digit(self.peek()) {
+        if matches!(self.peek(), 'e' | 'E') {
+            self.advance();
+            if matches!(self.peek(), '+' | '-') {
                 self.advance();
             }
+            if is_digit(self.peek()) {
+                self.digits(is_digit);
+            } else {
+                self.error("Expected digits in the exponent of a number, as in 1e9.");
+            }
         }
 
-        let value = self.source_substring(self.start, self.current);
-        let number: f64 = value.parse().unwrap();
+        let value = self
+            .source_substring(self.start, self.current)
+            .replace('_', "");
+        // A malformed number has been reported already; the token just
+        // needs some value.
+        let number: f64 = value.parse().unwrap_or(0.0);
 
+        #[cfg(feature = "units")]
+        if is_alpha(self.peek()) {
+            return self.quantity(number);
+        }
+
+        self.add_token_full(TokenType::Number, Literal::Number(number));
+    }
+
+    /// The rest of a number after its `0x`, like `0xFF`.
+    fn hex_number(&mut self) {
+        self.advance();
+        if !self.peek().is_ascii_hexdigit() {
+            self.error("Expected hex digits after '0x'.");
+        }
+        self.digits(|c| c.is_ascii_hexdigit());
+        let digits = self.source_substring(self.start + 2, self.current);
+        let number = digits
+            .chars()
+            .filter_map(|c| c.to_digit(16))
+            .fold(0.0, |number, digit| number * 16.0 + digit as f64);
         self.add_token_full(TokenType::Number, Literal::Number(number));
     }
 
+    /// Consumes a run of digits, which may be split up by single
+    /// underscores, as in `1_000_000`.
+    fn digits(&mut self, is_digit: fn(char) -> bool) {
+        while is_digit(self.peek()) || self.peek() == '_' {
+            if self.advance() == '_' && !is_digit(self.peek()) {
+                self.error("An underscore in a number must be between two digits.");
+            }
+        }
+    }
+
+    /// A number with a unit straight after it, like `5km`.
+    #[cfg(feature = "units")]
+    fn quantity(&mut self, number: f64) {
+        let unit_start = self.current;
+        while is_alpha_numeric(self.peek()) {
+            self.advance();
+        }
+        let symbol = self.source_substring(unit_start, self.current);
+        match crate::units::lookup(symbol) {
+            Some(unit) => self.add_token_full(TokenType::Number, Literal::Quantity(number, unit)),
+            None => {
+                self.error(&format!("Unknown unit '{}'.", symbol));
+                self.add_token_full(TokenType::Number, Literal::Number(number));
+            }
+        }
+    }
+
     fn string(&mut self) {
+        let start_line = self.line;
         while self.peek() != '"' && !self.is_at_end() {
             if self.peek() == '\n' {
                 self.line += 1;
+                self.line_start = self.current + 1;
             }
             self.advance();
         }
 
         if self.is_at_end() {
-            panic!("Unterminated string. {}", self.line);
+            // Reported where the string starts rather than at the end of
+            // the file, since that's the quote missing its partner.
+            self.pending.push_back(Err(ScanError {
+                message: "Unterminated string.".to_string(),
+                source: self.source_id,
+                line: start_line,
+                column: self.start_column,
+            }));
         } else {
             self.advance();
 
             let value = self.source_substring(self.start + 1, self.current - 1);
+            // A string spanning lines in a file saved with Windows line
+            // endings shouldn't hold a stray '\r' at the end of each one.
+            let value = value.replace("\r\n", "\n");
 
-            self.add_token_full(TokenType::String, Literal::String(value.to_owned()));
+            self.add_token_full(TokenType::String, Literal::String(literals::intern(&value)));
         }
     }
 
+    /// A lexical error at the token being scanned. Scanning carries on, so
+    /// every error in the source is found in one pass.
+    fn error(&mut self, message: &str) {
+        self.pending.push_back(Err(ScanError {
+            message: message.to_string(),
+            source: self.source_id,
+            line: self.line,
+            column: self.start_column,
+        }));
+    }
+
     fn match_token(&mut self, expected: char) -> bool {
-        if self.is_at_end() {
+        if self.is_at_end() || self.peek() != expected {
             return false;
         }
-        let current_us = usize::try_from(self.current).unwrap();
-        if self.source.chars().nth(current_us).unwrap() != expected {
-            return false;
-        }
-        self.current += 1;
+        self.advance();
         true
     }
 
+    // Positions are byte offsets into the source, so these step over a
+    // whole character at a time, however many bytes it takes.
+
     fn peek(&self) -> char {
-        if self.is_at_end() {
-            return '\0';
-        }
-        let current_us = usize::try_from(self.current).unwrap();
-        return self.source.chars().nth(current_us).unwrap();
+        self.rest().chars().next().unwrap_or('\0')
     }
 
     fn peek_next(&self) -> char {
-        if self.current + 1 >= self.source.len().try_into().unwrap() {
-            return '\0';
-        }
-        let current_us = usize::try_from(self.current).unwrap();
-        return self.source.chars().nth(current_us).unwrap();
+        self.rest().chars().nth(1).unwrap_or('\0')
     }
 
     fn is_at_end(&self) -> bool {
-        self.current >= self.source.len().try_into().unwrap()
+        self.current as usize >= self.source.len()
     }
 
     fn advance(&mut self) -> char {
-        let n = self.current;
-        self.current += 1;
-        let n_us = usize::try_from(n).unwrap();
-        self.source.chars().nth(n_us).unwrap()
+        let c = self.peek();
+        self.current += c.len_utf8() as u32;
+        // Columns count characters, not bytes.
+        self.line_start += c.len_utf8() as u32 - 1;
+        c
+    }
+
+    fn rest(&self) -> &'static str {
+        &self.source[self.current as usize..]
     }
 
     fn add_token(&mut self, token_type: TokenType) {
@@ -258,24 +378,112 @@ impl Scanner {
     }
 
     fn add_token_full(&mut self, token_type: TokenType, literal: Literal) {
-        let text = self.source_substring(self.start, self.current);
-        self.tokens.push(Token::new(
+        self.pending.push_back(Ok(Token::new(
             token_type,
-            String::from(text),
+            self.text.slice(self.start, self.current),
             literal,
             self.line,
-        ));
+            self.start_column,
+            self.source_id,
+        )));
+    }
+}
+
+impl Iterator for Scanner {
+    type Item = Result<Token, ScanError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(item) = self.pending.pop_front() {
+                return Some(item);
+            }
+            if self.finished {
+                return None;
+            }
+            if self.is_at_end() {
+                self.finished = true;
+                return Some(Ok(Token::new(
+                    TokenType::Eof,
+                    self.text.slice(self.current, self.current),
+                    Literal::None,
+                    self.line,
+                    self.current - self.line_start + 1,
+                    self.source_id,
+                )));
+            }
+            self.start = self.current;
+            self.start_column = self.current - self.line_start + 1;
+            self.scan_token();
+        }
+    }
+}
+
+/// A lexical error: a character that starts no token, a malformed number
+/// or a string missing its closing quote.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScanError {
+    pub message: String,
+    pub source: SourceId,
+    pub line: u32,
+    pub column: u32,
+}
+
+impl ScanError {
+    /// Reports the error as a diagnostic, like the parser's errors.
+    pub fn report(&self) {
+        crate::report(self.source, self.line, self.column, 0, "", &self.message);
     }
 }
 
+impl fmt::Display for ScanError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (source, line, column) = (self.source.name(), self.line, self.column);
+        write!(f, "{}:{}:{}: Error: {}", source, line, column, self.message)
+    }
+}
+
+impl std::error::Error for ScanError {}
+
+fn reported(item: Result<Token, ScanError>) -> Option<Token> {
+    item.map_err(|error| error.report()).ok()
+}
+
+/// The keyword spelled `text`, if it is one. A match rather than a map, so
+/// creating a scanner costs nothing beyond copying the source.
+pub fn keyword(text: &str) -> Option<TokenType> {
+    let token_type = match text {
+        "and" => TokenType::And,
+        "class" => TokenType::Class,
+        "const" => TokenType::Const,
+        "else" => TokenType::Else,
+        "export" => TokenType::Export,
+        "false" => TokenType::False,
+        "for" => TokenType::For,
+        "fun" => TokenType::Fun,
+        "if" => TokenType::If,
+        "import" => TokenType::Import,
+        "nil" => TokenType::Nil,
+        "or" => TokenType::Or,
+        "print" => TokenType::Print,
+        "return" => TokenType::Return,
+        "super" => TokenType::Super,
+        "this" => TokenType::This,
+        "true" => TokenType::True,
+        "var" => TokenType::Var,
+        "while" => TokenType::While,
+        _ => return None,
+    };
+    Some(token_type)
+}
+
 fn is_alpha_numeric(c: char) -> bool {
     is_alpha(c) || is_digit(c)
 }
 
 fn is_alpha(c: char) -> bool {
-    (c >= 'a' && c <= 'z') || (c >= 'A' && c <= 'Z') || c == '_'
+    c.is_ascii_alphabetic() || c == '_'
 }
 
 fn is_digit(c: char) -> bool {
-    c >= '0' && c <= '9'
+    c.is_ascii_digit()
 }
@@ -0,0 +1,217 @@
+//! `ScriptEngine`: what an application hosting several scripting languages
+//! needs from each of them, so Lox can sit behind the same abstraction as
+//! rhai or Lua. `LoxEngine` is this crate's implementation; see
+//! `examples/script_engine.rs` for an adapter written against the trait.
+
+use std::{
+    fmt,
+    panic::{self, AssertUnwindSafe},
+    sync::{atomic::Ordering, Arc},
+};
+
+use crate::{
+    budget::BudgetExceeded,
+    cancel::Cancelled,
+    diagnostics, folder,
+    interpreter::{Interpreter, RuntimeError, Value},
+    natives::{self, NativeFn},
+    parser::Parser,
+    scanner::Scanner,
+    source::SourceId,
+    statement::Stmt,
+};
+
+pub trait ScriptEngine {
+    /// What scripts and the host pass each other.
+    type Value;
+    /// Why loading, evaluating or calling failed.
+    type Error: fmt::Display;
+    /// How the host functions given to `register` are written.
+    type Native;
+
+    /// Runs `source` as the script `name`, keeping what it declares for
+    /// later calls to `eval` and `call`.
+    fn load(&mut self, name: &str, source: &str) -> Result<(), Self::Error>;
+
+    /// Runs `source`, returning the value of its last statement if that's
+    /// an expression.
+    fn eval(&mut self, source: &str) -> Result<Self::Value, Self::Error>;
+
+    /// Calls the global function `name`.
+    fn call(&mut self, name: &str, args: Vec<Self::Value>) -> Result<Self::Value, Self::Error>;
+
+    /// Makes `function` callable from scripts as `name`.
+    fn register(&mut self, name: &'static str, arity: usize, function: Self::Native);
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum LoxError {
+    /// The source didn't scan or parse. Each diagnostic is formatted as the
+    /// command line prints it.
+    Static(Vec<String>),
+    /// The script stopped with a runtime error.
+    Runtime(String),
+    /// The script called `exit()` with this code.
+    Exit(i32),
+    /// The interpreter's `CancellationToken` was triggered.
+    Cancelled,
+    /// The script went past the interpreter's `Budget`.
+    BudgetExceeded(BudgetExceeded),
+}
+
+impl fmt::Display for LoxError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LoxError::Static(diagnostics) => write!(f, "{}", diagnostics.join("\n")),
+            LoxError::Runtime(message) => write!(f, "{}", message),
+            LoxError::Exit(code) => write!(f, "The script exited with code {}.", code),
+            LoxError::Cancelled => write!(f, "{}", Cancelled),
+            LoxError::BudgetExceeded(exceeded) => write!(f, "{}", exceeded),
+        }
+    }
+}
+
+impl std::error::Error for LoxError {}
+
+/// A Lox interpreter behind `ScriptEngine`. Globals persist from one call
+/// to the next, as in the REPL.
+#[derive(Debug, Clone)]
+pub struct LoxEngine {
+    interpreter: Interpreter,
+}
+
+impl Default for LoxEngine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LoxEngine {
+    pub fn new() -> Self {
+        LoxEngine {
+            interpreter: Interpreter::new(),
+        }
+    }
+
+    /// Scripts run in `interpreter`, such as one spawned from an `Engine`.
+    pub fn with_interpreter(interpreter: Interpreter) -> Self {
+        LoxEngine { interpreter }
+    }
+
+    /// The interpreter scripts run in, for settings the trait doesn't
+    /// cover, such as where `print` goes or file access.
+    pub fn interpreter(&mut self) -> &mut Interpreter {
+        &mut self.interpreter
+    }
+
+    fn run(&mut self, source: &str, source_id: SourceId) -> Result<Value, LoxError> {
+        crate::HAD_ERROR.store(false, Ordering::Relaxed);
+        diagnostics::reset();
+        let operators = Arc::clone(self.interpreter.operators());
+        let tokens = Scanner::with_operators(source, source_id, operators).tokens();
+        let (ast, mut statements) = folder::parse_and_fold(Parser::new(tokens));
+        let errors = diagnostics::take();
+        if !errors.is_empty() {
+            return Err(LoxError::Static(
+                errors.iter().map(ToString::to_string).collect(),
+            ));
+        }
+        let last = match statements.last().map(|&statement| ast.stmt(statement)) {
+            Some(&Stmt::Expression(expr)) => {
+                statements.pop();
+                Some(expr)
+            }
+            _ => None,
+        };
+        self.guard(|interpreter| {
+            if let Some(code) = interpreter.interpret(&ast, statements) {
+                return Err(LoxError::Exit(code));
+            }
+            match last {
+                Some(expr) => Ok(interpreter.evaluate_in(&ast, expr)),
+                None => Ok(Value::None),
+            }
+        })
+    }
+
+    /// Runs `body`, turning a runtime error, a reported error or a call to
+    /// `exit()` into an `Err`.
+    fn guard(
+        &mut self,
+        body: impl FnOnce(&mut Interpreter) -> Result<Value, LoxError>,
+    ) -> Result<Value, LoxError> {
+        let result = panic::catch_unwind(AssertUnwindSafe(|| body(&mut self.interpreter)));
+        let reported = diagnostics::take();
+        crate::HAD_ERROR.store(false, Ordering::Relaxed);
+        let exit = natives::take_exit_request();
+
+        let value = match result {
+            Ok(value) => value?,
+            Err(payload) if payload.is::<Cancelled>() => return Err(LoxError::Cancelled),
+            Err(payload) => match payload.downcast::<RuntimeError>() {
+                Ok(error) => return Err(LoxError::Runtime(error.to_string())),
+                Err(payload) => match payload.downcast::<BudgetExceeded>() {
+                    Ok(exceeded) => return Err(LoxError::BudgetExceeded(*exceeded)),
+                    // A bug in the interpreter, which the panic hook has
+                    // reported.
+                    Err(payload) => return Err(LoxError::Runtime(crate::panic_message(&*payload))),
+                },
+            },
+        };
+        if let Some(code) = exit {
+            return Err(LoxError::Exit(code));
+        }
+        if let Some(error) = reported.first() {
+            return Err(LoxError::Runtime(error.to_string()));
+        }
+        Ok(value)
+    }
+}
+
+impl ScriptEngine for LoxEngine {
+    type Value = Value;
+    type Error = LoxError;
+    type Native = NativeFn;
+
+    fn load(&mut self, name: &str, source: &str) -> Result<(), LoxError> {
+        self.run(source, SourceId::register(name)).map(|_| ())
+    }
+
+    fn eval(&mut self, source: &str) -> Result<Value, LoxError> {
+        self.run(source, SourceId::ANONYMOUS)
+    }
+
+    fn call(&mut self, name: &str, args: Vec<Value>) -> Result<Value, LoxError> {
+        let global = self.interpreter.globals.borrow().get_local(name);
+        let function = match global.or_else(|| natives::lookup(name)) {
+            Some(Value::Callable(function)) => function,
+            Some(other) => {
+                return Err(LoxError::Runtime(format!(
+                    "'{}' is a {}, not a function.",
+                    name,
+                    other.type_name()
+                )))
+            }
+            None => return Err(LoxError::Runtime(format!("Undefined function '{}'.", name))),
+        };
+        if args.len() != function.arity() {
+            return Err(LoxError::Runtime(format!(
+                "'{}' expects {} argument(s) but got {}.",
+                name,
+                function.arity(),
+                args.len()
+            )));
+        }
+        let callee = Value::Callable(function);
+        self.guard(|interpreter| {
+            interpreter.start_budget();
+            interpreter
+                .call_value(&callee, args)
+                .map_err(LoxError::Runtime)
+        })
+    }
+
+    fn register(&mut self, name: &'static str, arity: usize, function: NativeFn) {
+        self.interpreter.register_native(name, arity, function);
+    }
+}
@@ -0,0 +1,110 @@
+use std::{ops::Range, sync::Mutex};
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// Identifies the file a token came from, so diagnostics can name it even
+/// when the error happens inside an imported module.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Default)]
+pub struct SourceId(usize);
+
+// Shared between threads since `parallelMap` workers report errors on
+// tokens scanned by the main thread.
+static FILES: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+impl SourceId {
+    /// Code with no file behind it: the REPL and `-e`.
+    pub const ANONYMOUS: SourceId = SourceId(0);
+
+    /// Returns the id for `name`, adding it to the file table the first time.
+    pub fn register(name: &str) -> SourceId {
+        let mut files = FILES.lock().unwrap();
+        let index = match files.iter().position(|file| file == name) {
+            Some(index) => index,
+            None => {
+                files.push(name.to_string());
+                files.len() - 1
+            }
+        };
+        SourceId(index + 1)
+    }
+
+    pub fn name(self) -> String {
+        match self.0 {
+            0 => "<input>".to_string(),
+            n => FILES.lock().unwrap()[n - 1].clone(),
+        }
+    }
+}
+
+// A source is serialized as its name, since ids are only meaningful within
+// the process that registered them.
+
+impl Serialize for SourceId {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.name())
+    }
+}
+
+impl<'de> Deserialize<'de> for SourceId {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        Ok(if name == SourceId::ANONYMOUS.name() {
+            SourceId::ANONYMOUS
+        } else {
+            SourceId::register(&name)
+        })
+    }
+}
+
+// Every text a scanner has been given. Tokens point into these rather than
+// owning copies of their lexemes, so the texts are kept for as long as the
+// process runs, like the file names above.
+static TEXTS: Mutex<Vec<&'static str>> = Mutex::new(Vec::new());
+
+/// Where a token's lexeme is: a byte range of a text added with `Span::add`.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Span {
+    text: u32,
+    start: u32,
+    end: u32,
+}
+
+impl Span {
+    /// Keeps `text` for tokens to point into, returning a span covering all
+    /// of it.
+    pub fn add(text: &str) -> Span {
+        let text: &'static str = Box::leak(text.into());
+        let mut texts = TEXTS.lock().unwrap();
+        texts.push(text);
+        Span {
+            text: (texts.len() - 1) as u32,
+            start: 0,
+            end: text.len() as u32,
+        }
+    }
+
+    /// The part of this span from byte `start` to byte `end`.
+    pub fn slice(self, start: u32, end: u32) -> Span {
+        Span {
+            text: self.text,
+            start: self.start + start,
+            end: self.start + end,
+        }
+    }
+
+    /// Where this span is in the text it was cut from, in bytes.
+    pub fn range(self) -> Range<usize> {
+        self.start as usize..self.end as usize
+    }
+
+    pub fn text(self) -> &'static str {
+        let text = TEXTS.lock().unwrap()[self.text as usize];
+        &text[self.start as usize..self.end as usize]
+    }
+}
+
+impl std::fmt::Debug for Span {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{:?}", self.text())
+    }
+}
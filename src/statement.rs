@@ -1,68 +1,107 @@
-use std::{cell::RefCell, rc::Rc};
+use serde::{Deserialize, Serialize};
 
-use crate::{
-    environment::Environment, expression::Expr, interpreter::Interpreter, returns::Return,
-    token::Token,
-};
+use crate::{expression::ExprId, token::Token};
 
-#[derive(PartialEq, Clone, Debug)]
+/// A statement, as an index into the `Arena` it was parsed into.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct StmtId(u32);
+
+impl StmtId {
+    pub(crate) fn new(index: usize) -> StmtId {
+        StmtId(index as u32)
+    }
+
+    pub(crate) fn index(self) -> usize {
+        self.0 as usize
+    }
+}
+
+/// What an `import` binds in the importing scope.
+#[derive(PartialEq, Clone, Debug, Serialize, Deserialize)]
+pub enum ImportTarget {
+    /// `import "path";` defines every export directly.
+    All,
+    /// `import "path" as m;` binds the module object to `m`.
+    Alias(Token),
+    /// `import { a, b } from "path";` defines only the listed exports.
+    Names(Vec<Token>),
+    /// `import lazy "path" as m;` binds a module that is only loaded on
+    /// first member access.
+    Lazy(Token),
+}
+
+#[derive(PartialEq, Clone, Debug, Serialize, Deserialize)]
 pub enum Stmt {
-    Expression(Expr),
-    Print(Expr),
-    Var(Token, Option<Expr>),
-    Block(Vec<Stmt>),
-    If(Expr, Box<Stmt>, Box<Option<Stmt>>),
-    While(Expr, Box<Stmt>),
-    Function(Token, Vec<Token>, Vec<Stmt>),
-    Return(Token, Option<Expr>),
-    Class(Token, Vec<Stmt>),
+    Expression(ExprId),
+    Print(Token, ExprId),
+    Var(Token, Option<ExprId>),
+    /// `const NAME = initializer;`, a variable that can't be assigned.
+    Const(Token, ExprId),
+    /// `var (a, b) = initializer;`, which binds each name to the element
+    /// at its position in a list of as many values.
+    Destructure(Vec<Token>, ExprId),
+    Block(Vec<StmtId>),
+    If(ExprId, StmtId, Option<StmtId>),
+    While(ExprId, StmtId),
+    /// `for (var name in iterable) body`, with `name` bound afresh for
+    /// each item.
+    ForIn(Token, ExprId, StmtId),
+    Function(Token, Vec<Token>, Vec<StmtId>),
+    Return(Token, Option<ExprId>),
+    Class(Token, Vec<StmtId>),
+    Import(Token, Token, ImportTarget),
+    Export(Token, StmtId),
 }
 
 impl Stmt {
-    pub fn accept(&self, visitor: &mut Interpreter) -> Result<(), Return> {
+    pub fn accept<T>(&self, visitor: &mut dyn StmtVisitor<T>) -> T {
         match self {
-            Self::Expression(e) => visitor.visit_expression_stmt(e.clone()),
-            Self::Print(e) => visitor.visit_print_stmt(e.clone()),
-            Self::Var(t, e) => visitor.visit_var_stmt(t.clone(), e.clone()),
+            Self::Expression(e) => visitor.visit_expression_stmt(*e),
+            Self::Print(_, e) => visitor.visit_print_stmt(*e),
+            Self::Var(t, e) => visitor.visit_var_stmt(t.clone(), *e),
+            Self::Const(t, e) => visitor.visit_const_stmt(t.clone(), *e),
+            Self::Destructure(names, init) => visitor.visit_destructure_stmt(names.clone(), *init),
             Self::Block(stmts) => visitor.visit_block_stmt(stmts.clone()),
             Self::If(condition, then_stmt, else_stmt) => {
-                visitor.visit_if_stmt(condition.clone(), then_stmt.clone(), else_stmt.clone())
+                visitor.visit_if_stmt(*condition, *then_stmt, *else_stmt)
             }
-            Self::While(condition, body) => {
-                visitor.visit_while_stmt(condition.clone(), body.clone())
+            Self::While(condition, body) => visitor.visit_while_stmt(*condition, *body),
+            Self::ForIn(name, iterable, body) => {
+                visitor.visit_for_in_stmt(name.clone(), *iterable, *body)
             }
             Self::Function(name, params, body) => {
                 visitor.visit_function_stmt(name.clone(), params.clone(), body.clone())
             }
-            Self::Return(keyword, value) => {
-                visitor.visit_return_stmt(keyword.clone(), value.clone())
+            Self::Return(keyword, value) => visitor.visit_return_stmt(keyword.clone(), *value),
+            Self::Class(name, methods) => visitor.visit_class_stmt(name.clone(), methods.clone()),
+            Self::Import(keyword, path, target) => {
+                visitor.visit_import_stmt(keyword.clone(), path.clone(), target.clone())
             }
-            Self::Class(name, methods) => {
-                // visitor.visit_
-                Ok(())
+            Self::Export(keyword, declaration) => {
+                visitor.visit_export_stmt(keyword.clone(), *declaration)
             }
         }
     }
 }
 
 pub trait StmtVisitor<T> {
-    fn visit_expression_stmt(&mut self, stmt: Expr) -> Result<(), Return>;
-    fn visit_print_stmt(&mut self, stmt: Expr) -> Result<(), Return>;
-    fn visit_var_stmt(&mut self, name: Token, initializer: Option<Expr>) -> Result<(), Return>;
-    fn visit_block_stmt(&mut self, statements: Vec<Stmt>) -> Result<(), Return>;
+    fn visit_expression_stmt(&mut self, stmt: ExprId) -> T;
+    fn visit_print_stmt(&mut self, stmt: ExprId) -> T;
+    fn visit_var_stmt(&mut self, name: Token, initializer: Option<ExprId>) -> T;
+    fn visit_const_stmt(&mut self, name: Token, initializer: ExprId) -> T;
+    fn visit_destructure_stmt(&mut self, names: Vec<Token>, initializer: ExprId) -> T;
+    fn visit_block_stmt(&mut self, statements: Vec<StmtId>) -> T;
     fn visit_if_stmt(
         &mut self,
-        condition: Expr,
-        then_stmt: Box<Stmt>,
-        else_stmt: Box<Option<Stmt>>,
-    ) -> Result<(), Return>;
-    fn visit_while_stmt(&mut self, condition: Expr, body: Box<Stmt>) -> Result<(), Return>;
-    fn visit_function_stmt(
-        &mut self,
-        name: Token,
-        params: Vec<Token>,
-        body: Vec<Stmt>,
-    ) -> Result<(), Return>;
-    fn visit_return_stmt(&mut self, keyword: Token, value: Option<Expr>) -> Result<(), Return>;
-    fn visit_class_stmt(&self, name: Token, methods: Vec<Stmt>) -> Result<(), Return>;
+        condition: ExprId,
+        then_stmt: StmtId,
+        else_stmt: Option<StmtId>,
+    ) -> T;
+    fn visit_while_stmt(&mut self, condition: ExprId, body: StmtId) -> T;
+    fn visit_for_in_stmt(&mut self, name: Token, iterable: ExprId, body: StmtId) -> T;
+    fn visit_function_stmt(&mut self, name: Token, params: Vec<Token>, body: Vec<StmtId>) -> T;
+    fn visit_return_stmt(&mut self, keyword: Token, value: Option<ExprId>) -> T;
+    fn visit_class_stmt(&mut self, name: Token, methods: Vec<StmtId>) -> T;
+    fn visit_import_stmt(&mut self, keyword: Token, path: Token, target: ImportTarget) -> T;
+    fn visit_export_stmt(&mut self, keyword: Token, declaration: StmtId) -> T;
 }
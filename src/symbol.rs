@@ -0,0 +1,69 @@
+//! Interned names. Each distinct identifier is stored once, and scopes and
+//! instances key their bindings on its id rather than hashing the text on
+//! every lookup.
+
+use std::{collections::HashMap, sync::Mutex};
+
+// Shared between threads since `parallelMap` workers look up names scanned
+// by the main thread.
+static INTERNER: Mutex<Interner> = Mutex::new(Interner {
+    ids: None,
+    names: Vec::new(),
+});
+
+struct Interner {
+    // `None` until the first name is interned, as `HashMap::new` isn't const.
+    ids: Option<HashMap<&'static str, Symbol>>,
+    names: Vec<&'static str>,
+}
+
+/// A name, as an index into the interner. Two symbols are equal exactly
+/// when their names are.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Symbol(u32);
+
+impl Symbol {
+    /// The symbol for `name`, adding it to the interner the first time.
+    /// Interned names are kept for as long as the process runs.
+    pub fn intern(name: &str) -> Symbol {
+        let mut interner = INTERNER.lock().unwrap();
+        let Interner { ids, names } = &mut *interner;
+        let ids = ids.get_or_insert_with(HashMap::new);
+        if let Some(symbol) = ids.get(name) {
+            return *symbol;
+        }
+        let name: &'static str = Box::leak(name.into());
+        let symbol = Symbol(names.len() as u32);
+        names.push(name);
+        ids.insert(name, symbol);
+        symbol
+    }
+
+    pub fn as_str(self) -> &'static str {
+        INTERNER.lock().unwrap().names[self.0 as usize]
+    }
+}
+
+impl From<&str> for Symbol {
+    fn from(name: &str) -> Self {
+        Symbol::intern(name)
+    }
+}
+
+impl From<String> for Symbol {
+    fn from(name: String) -> Self {
+        Symbol::intern(&name)
+    }
+}
+
+impl std::fmt::Display for Symbol {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl std::fmt::Debug for Symbol {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{:?}", self.as_str())
+    }
+}
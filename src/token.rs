@@ -1,4 +1,14 @@
-#[derive(Debug, Clone, Copy, PartialEq)]
+use std::sync::{Arc, Mutex};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    operators::Precedence,
+    source::{SourceId, Span},
+    symbol::Symbol,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
 pub enum TokenType {
     // Single-character tokens.
     LeftParen,
@@ -22,6 +32,13 @@ pub enum TokenType {
     GreaterEqual,
     Less,
     LessEqual,
+    // `..` and `..=`, ranges excluding and including their end.
+    DotDot,
+    DotDotEqual,
+
+    // An infix operator registered by an embedder (see `operators`), and
+    // how tightly it binds.
+    Operator(Precedence),
 
     // Literals.
     Identifier,
@@ -31,11 +48,14 @@ pub enum TokenType {
     // Keywords.
     And,
     Class,
+    Const,
     Else,
+    Export,
     False,
     Fun,
     For,
     If,
+    Import,
     Nil,
     Or,
     Print,
@@ -49,12 +69,17 @@ pub enum TokenType {
     Eof,
 }
 
-#[derive(Debug, Clone, PartialEq)]
+/// Serialized as the bare value, `null` for none, and a quantity as its
+/// number and unit symbol.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
 pub enum Literal {
-    String(String),
+    String(Arc<str>),
     Number(f64),
     Boolean(bool),
     None,
+    #[cfg(feature = "units")]
+    Quantity(f64, &'static crate::units::Unit),
 }
 
 impl std::fmt::Display for Literal {
@@ -63,40 +88,147 @@ impl std::fmt::Display for Literal {
             Self::String(s) => write!(f, "{}", s),
             Self::Number(n) => write!(f, "{}", n),
             Self::Boolean(b) => write!(f, "{}", b),
+            #[cfg(feature = "units")]
+            Self::Quantity(n, unit) => write!(f, "{}{}", n, unit.symbol),
             Self::None => write!(f, "none"),
         }
     }
 }
 
-#[derive(Debug, Clone, PartialEq)]
+// The literals of every token that has one, which is few of them. Tokens
+// keep an index into this instead of a `Literal` each.
+static LITERALS: Mutex<Vec<Literal>> = Mutex::new(Vec::new());
+
+/// A token's literal value, as an index into `LITERALS` counted from 1.
+/// Zero means the token has none.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LiteralId(u32);
+
+impl LiteralId {
+    pub const NONE: LiteralId = LiteralId(0);
+
+    pub fn add(literal: Literal) -> LiteralId {
+        if literal == Literal::None {
+            return LiteralId::NONE;
+        }
+        let mut literals = LITERALS.lock().unwrap();
+        literals.push(literal);
+        LiteralId(literals.len() as u32)
+    }
+
+    pub fn get(self) -> Literal {
+        match self.0 {
+            0 => Literal::None,
+            n => LITERALS.lock().unwrap()[n as usize - 1].clone(),
+        }
+    }
+}
+
+/// A scanned token. It holds no strings of its own, only where its lexeme
+/// is in the source and which literal it has, so copying one is cheap.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(into = "TokenData", from = "TokenData")]
 pub struct Token {
     pub token_type: TokenType,
-    pub lexeme: String,
-    pub literal: Literal,
+    pub span: Span,
+    pub literal: LiteralId,
+    /// The lexeme, interned. Strings and numbers, whose lexemes are too
+    /// varied to be worth keeping, get the empty name.
+    pub symbol: Symbol,
     pub line: u32,
+    pub column: u32,
+    pub source: SourceId,
+}
+
+/// A token as it's serialized: with its lexeme and literal spelled out
+/// rather than as indices into tables only this process has.
+#[derive(Serialize, Deserialize)]
+struct TokenData {
+    #[serde(rename = "type")]
+    token_type: TokenType,
+    lexeme: String,
+    line: u32,
+    column: u32,
+    /// The lexeme's byte range in its source.
+    start: usize,
+    end: usize,
+    source: SourceId,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    literal: Option<Literal>,
+}
+
+impl From<Token> for TokenData {
+    fn from(token: Token) -> Self {
+        let range = token.span.range();
+        TokenData {
+            token_type: token.token_type,
+            lexeme: token.lexeme().to_string(),
+            line: token.line,
+            column: token.column,
+            start: range.start,
+            end: range.end,
+            source: token.source,
+            literal: Some(token.literal()).filter(|literal| *literal != Literal::None),
+        }
+    }
+}
+
+/// A deserialized token's span covers a copy of its lexeme alone, so its
+/// byte range starts at 0; its line and column still place it.
+impl From<TokenData> for Token {
+    fn from(data: TokenData) -> Self {
+        Token::new(
+            data.token_type,
+            Span::add(&data.lexeme),
+            data.literal.unwrap_or(Literal::None),
+            data.line,
+            data.column,
+            data.source,
+        )
+    }
 }
 
 impl std::fmt::Display for Token {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        write!(
-            f,
-            "{}",
-            self.lexeme
-        )
+        write!(f, "{}", self.lexeme())
     }
 }
 
 impl Token {
-    pub fn new(token_type: TokenType, lexeme: String, literal: Literal, line: u32) -> Token {
+    pub fn new(
+        token_type: TokenType,
+        span: Span,
+        literal: Literal,
+        line: u32,
+        column: u32,
+        source: SourceId,
+    ) -> Token {
+        let symbol = match token_type {
+            TokenType::String | TokenType::Number => Symbol::intern(""),
+            _ => Symbol::intern(span.text()),
+        };
         Token {
             token_type,
-            lexeme,
-            literal,
+            span,
+            literal: LiteralId::add(literal),
+            symbol,
             line,
+            column,
+            source,
         }
     }
 
-    pub fn to_string(&self) -> String {
-        format!("{:?} {} {}", self.token_type, self.lexeme, self.literal)
+    pub fn lexeme(&self) -> &'static str {
+        self.span.text()
+    }
+
+    pub fn literal(&self) -> Literal {
+        self.literal.get()
+    }
+
+    /// The token's type, lexeme and literal, for debugging. `Display` shows
+    /// just the lexeme.
+    pub fn describe(&self) -> String {
+        format!("{:?} {} {}", self.token_type, self.lexeme(), self.literal())
     }
 }
@@ -0,0 +1,202 @@
+//! Numbers with units, behind the `units` feature.
+//!
+//! A number literal followed directly by a unit (`5km`, `250g`, `90min`)
+//! is a quantity. Quantities of the same dimension can be added, subtracted
+//! and compared whatever their units, and scaled by plain numbers; mixing
+//! dimensions is an error. `5km -> "mi"` converts to another unit.
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::{
+    interpreter::Value,
+    number::{FloatBackend, NumberBackend},
+    operators::{Operators, Precedence},
+    token::TokenType,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Dimension {
+    Length,
+    Mass,
+    Time,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Unit {
+    pub symbol: &'static str,
+    pub dimension: Dimension,
+    // How many of the dimension's base unit (m, kg, s) one of this is.
+    factor: f64,
+}
+
+const UNITS: &[Unit] = &[
+    unit("mm", Dimension::Length, 0.001),
+    unit("cm", Dimension::Length, 0.01),
+    unit("m", Dimension::Length, 1.0),
+    unit("km", Dimension::Length, 1000.0),
+    unit("in", Dimension::Length, 0.0254),
+    unit("ft", Dimension::Length, 0.3048),
+    unit("yd", Dimension::Length, 0.9144),
+    unit("mi", Dimension::Length, 1609.344),
+    unit("mg", Dimension::Mass, 0.000001),
+    unit("g", Dimension::Mass, 0.001),
+    unit("kg", Dimension::Mass, 1.0),
+    unit("t", Dimension::Mass, 1000.0),
+    unit("oz", Dimension::Mass, 0.028349523125),
+    unit("lb", Dimension::Mass, 0.45359237),
+    unit("ms", Dimension::Time, 0.001),
+    unit("s", Dimension::Time, 1.0),
+    unit("min", Dimension::Time, 60.0),
+    unit("h", Dimension::Time, 3600.0),
+    unit("d", Dimension::Time, 86400.0),
+];
+
+const fn unit(symbol: &'static str, dimension: Dimension, factor: f64) -> Unit {
+    Unit {
+        symbol,
+        dimension,
+        factor,
+    }
+}
+
+pub fn lookup(symbol: &str) -> Option<&'static Unit> {
+    UNITS.iter().find(|unit| unit.symbol == symbol)
+}
+
+// A unit is serialized as its symbol.
+
+impl Serialize for Unit {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.symbol)
+    }
+}
+
+impl<'de> Deserialize<'de> for &'static Unit {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let symbol = String::deserialize(deserializer)?;
+        lookup(&symbol)
+            .ok_or_else(|| serde::de::Error::custom(format!("unknown unit '{}'", symbol)))
+    }
+}
+
+/// Adds `->`, which converts a quantity to the unit named on its right.
+pub fn register_operators(operators: &mut Operators) {
+    operators
+        .register("->", Precedence::Comparison, |left, right| match right {
+            Value::String(symbol) => convert(left, symbol),
+            _ => Err("The right of '->' must be a unit name.".to_string()),
+        })
+        .expect("'->' is a valid operator");
+}
+
+/// `quantity` expressed in the unit called `symbol`.
+pub fn convert(quantity: &Value, symbol: &str) -> Result<Value, String> {
+    let Value::Quantity(magnitude, from) = quantity else {
+        return Err(format!("Can only convert quantities, got {}.", quantity));
+    };
+    let to = lookup(symbol).ok_or_else(|| format!("Unknown unit '{}'.", symbol))?;
+    Ok(Value::Quantity(
+        in_unit(&FloatBackend, *magnitude, from, to)?,
+        to,
+    ))
+}
+
+fn in_unit(
+    numbers: &dyn NumberBackend,
+    magnitude: f64,
+    from: &Unit,
+    to: &Unit,
+) -> Result<f64, String> {
+    if from.dimension != to.dimension {
+        return Err(format!("Can't convert {} to {}.", from.symbol, to.symbol));
+    }
+    if from == to {
+        return Ok(magnitude);
+    }
+    Ok(numbers.div(numbers.mul(magnitude, from.factor), to.factor))
+}
+
+/// Whether two quantities are the same amount, in whatever units.
+pub fn equal(left: (f64, &Unit), right: (f64, &Unit)) -> bool {
+    in_unit(&FloatBackend, right.0, right.1, left.1).is_ok_and(|right| left.0 == right)
+}
+
+/// Carries out `op` when either operand is a quantity, or returns `None` to
+/// leave it to the usual rules. Equality is left to `Value`'s `PartialEq`.
+pub fn binary(
+    numbers: &dyn NumberBackend,
+    op: TokenType,
+    left: &Value,
+    right: &Value,
+) -> Option<Result<Value, String>> {
+    use Value::{Number, Quantity};
+
+    if !matches!(left, Quantity(..)) && !matches!(right, Quantity(..)) {
+        return None;
+    }
+    let mismatch = || {
+        format!(
+            "Can't use {} with {} and {}.",
+            operator_name(op),
+            left,
+            right
+        )
+    };
+    let result = match (op, left, right) {
+        (TokenType::EqualEqual | TokenType::BangEqual | TokenType::Operator(_), _, _) => {
+            return None
+        }
+        (TokenType::Plus | TokenType::Minus, Quantity(l, lu), Quantity(r, ru)) => {
+            in_unit(numbers, *r, ru, lu)
+                .map_err(|_| mismatch())
+                .map(|r| {
+                    let sum = if op == TokenType::Plus {
+                        numbers.add(*l, r)
+                    } else {
+                        numbers.sub(*l, r)
+                    };
+                    Quantity(sum, lu)
+                })
+        }
+        (TokenType::Star, Quantity(q, unit), Number(n))
+        | (TokenType::Star, Number(n), Quantity(q, unit)) => {
+            Ok(Quantity(numbers.mul(*q, *n), unit))
+        }
+        (TokenType::Slash, Quantity(..), Number(n)) if *n == 0.0 => {
+            Err("Division by zero.".to_string())
+        }
+        (TokenType::Slash, Quantity(q, unit), Number(n)) => Ok(Quantity(numbers.div(*q, *n), unit)),
+        (TokenType::Slash, Quantity(l, lu), Quantity(r, ru)) => in_unit(numbers, *r, ru, lu)
+            .map_err(|_| mismatch())
+            .map(|r| Number(numbers.div(*l, r))),
+        (
+            TokenType::Greater | TokenType::GreaterEqual | TokenType::Less | TokenType::LessEqual,
+            Quantity(l, lu),
+            Quantity(r, ru),
+        ) => in_unit(numbers, *r, ru, lu)
+            .map_err(|_| mismatch())
+            .map(|r| {
+                Value::Boolean(match op {
+                    TokenType::Greater => *l > r,
+                    TokenType::GreaterEqual => *l >= r,
+                    TokenType::Less => *l < r,
+                    _ => *l <= r,
+                })
+            }),
+        (TokenType::Star, Quantity(..), Quantity(..)) => {
+            Err("Can't multiply two quantities; units don't combine.".to_string())
+        }
+        _ => Err(mismatch()),
+    };
+    Some(result)
+}
+
+fn operator_name(op: TokenType) -> &'static str {
+    match op {
+        TokenType::Plus => "'+'",
+        TokenType::Minus => "'-'",
+        TokenType::Star => "'*'",
+        TokenType::Slash => "'/'",
+        _ => "a comparison",
+    }
+}
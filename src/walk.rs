@@ -0,0 +1,94 @@
+//! Traversal of values for hosts: serializers, validators and UIs implement
+//! `ValueVisitor` for the parts they care about and let `Value::walk` find
+//! them in nested lists and instances.
+
+use std::rc::Rc;
+
+use crate::interpreter::Value;
+
+/// Called by `Value::walk` for each value it reaches. Every method has a
+/// default that does nothing, so a visitor only implements what it needs;
+/// kinds of value it doesn't know about, including ones added to `Value`
+/// later, go to `visit_other`.
+pub trait ValueVisitor {
+    fn visit_nil(&mut self) {}
+    fn visit_boolean(&mut self, _value: bool) {}
+    fn visit_number(&mut self, _value: f64) {}
+    fn visit_string(&mut self, _value: &str) {}
+
+    /// Called before a list's elements are walked. Returning false skips
+    /// them, and `exit_list` isn't called.
+    fn enter_list(&mut self, _len: usize) -> bool {
+        true
+    }
+    fn exit_list(&mut self) {}
+
+    /// Called before an instance's fields are walked, in order of name.
+    /// Returning false skips them, and `exit_instance` isn't called.
+    fn enter_instance(&mut self, _class: &str, _fields: usize) -> bool {
+        true
+    }
+    /// Called with each field's name, before its value is walked.
+    fn visit_field(&mut self, _name: &str) {}
+    fn exit_instance(&mut self) {}
+
+    /// A list or instance reached again from inside itself. It isn't walked
+    /// a second time.
+    fn visit_cycle(&mut self, _value: &Value) {}
+
+    /// Functions, classes, modules, errors, and anything else.
+    fn visit_other(&mut self, _value: &Value) {}
+}
+
+impl Value {
+    /// Walks this value and everything inside it, depth first.
+    pub fn walk(&self, visitor: &mut dyn ValueVisitor) {
+        walk(self, visitor, &mut Vec::new());
+    }
+}
+
+// `path` holds the lists and instances being walked, to catch cycles.
+fn walk(value: &Value, visitor: &mut dyn ValueVisitor, path: &mut Vec<*const ()>) {
+    let address = match value {
+        Value::List(items) => Some(Rc::as_ptr(items) as *const ()),
+        Value::Instance(instance) => Some(Rc::as_ptr(instance) as *const ()),
+        _ => None,
+    };
+    if let Some(address) = address {
+        if path.contains(&address) {
+            return visitor.visit_cycle(value);
+        }
+        path.push(address);
+    }
+    match value {
+        Value::None => visitor.visit_nil(),
+        Value::Boolean(b) => visitor.visit_boolean(*b),
+        Value::Number(n) => visitor.visit_number(*n),
+        Value::String(s) => visitor.visit_string(s),
+        Value::List(items) => {
+            let items = items.borrow();
+            if visitor.enter_list(items.len()) {
+                for item in items.iter() {
+                    walk(item, visitor, path);
+                }
+                visitor.exit_list();
+            }
+        }
+        Value::Instance(instance) => {
+            let instance = instance.borrow();
+            if visitor.enter_instance(&instance.klass.name, instance.fields.len()) {
+                let mut fields: Vec<_> = instance.fields.iter().collect();
+                fields.sort_by_key(|(name, _)| name.as_str());
+                for (name, field) in fields {
+                    visitor.visit_field(name.as_str());
+                    walk(field, visitor, path);
+                }
+                visitor.exit_instance();
+            }
+        }
+        other => visitor.visit_other(other),
+    }
+    if address.is_some() {
+        path.pop();
+    }
+}
@@ -0,0 +1,62 @@
+//! `jlox run` caching a script's parsed program in a `.loxc` file beside
+//! it, and running from that until the script changes.
+
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+fn run(script: &Path, extra: &[&str]) -> String {
+    let output = Command::new(env!("CARGO_BIN_EXE_crafting_rust"))
+        .arg("run")
+        .args(extra)
+        .arg(script)
+        .output()
+        .expect("failed to start the interpreter");
+    String::from_utf8(output.stdout).unwrap()
+}
+
+/// The phases `--verbose` reports timings for, in order.
+fn phases(script: &Path) -> Vec<String> {
+    let output = Command::new(env!("CARGO_BIN_EXE_crafting_rust"))
+        .args(["run", "--verbose"])
+        .arg(script)
+        .output()
+        .expect("failed to start the interpreter");
+    String::from_utf8(output.stderr)
+        .unwrap()
+        .lines()
+        .map(|line| line.split(':').next().unwrap().to_string())
+        .collect()
+}
+
+#[test]
+fn reuses_the_cache_until_the_script_changes() {
+    let dir = std::env::temp_dir().join(format!("jlox-cache-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let script = dir.join("main.lox");
+    let cache = dir.join("main.loxc");
+    fs::write(
+        &script,
+        "fun twice(s) { return s + s; }\nprint twice(\"ab\");\n",
+    )
+    .unwrap();
+
+    assert_eq!(phases(&script), ["scanner setup", "parse", "run"]);
+    assert!(cache.exists());
+    assert_eq!(phases(&script), ["load cache", "run"]);
+    assert_eq!(run(&script, &[]), "abab\n");
+
+    fs::write(&script, "print 1 + 2;\n").unwrap();
+    assert_eq!(run(&script, &[]), "3\n");
+    assert_eq!(run(&script, &[]), "3\n");
+
+    fs::remove_file(&cache).unwrap();
+    assert_eq!(run(&script, &["--no-cache"]), "3\n");
+    assert!(!cache.exists());
+
+    // Errors are reported on every run, so a script with one isn't cached.
+    fs::write(&script, "print 1\n").unwrap();
+    assert!(run(&script, &[]).contains("Expect ';' after value."));
+    assert!(!cache.exists());
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,36 @@
+//! `check`, the programmatic `jlox check`: what it reports about programs
+//! with warnings and with syntax errors.
+
+use crafting_rust::{check, Severity};
+
+#[test]
+fn reports_warnings_with_their_rule() {
+    let diagnostics = check("{\n  var unused = 1;\n}\n");
+    assert_eq!(diagnostics.len(), 1);
+    let warning = &diagnostics[0];
+    assert_eq!(warning.severity, Severity::Warn);
+    assert_eq!(warning.code, Some("unused-variable"));
+    assert_eq!((warning.line, warning.column, warning.length), (2, 7, 6));
+    assert_eq!(warning.message, "Local variable 'unused' is never read.");
+}
+
+#[test]
+fn reports_syntax_errors_in_source_order() {
+    let diagnostics = check("print 1 +;\nvar = 2;\nprint \"ok\";\n");
+    let found: Vec<_> = diagnostics
+        .iter()
+        .map(|d| (d.severity, d.line, d.message.as_str()))
+        .collect();
+    assert_eq!(
+        found,
+        [
+            (Severity::Deny, 1, "Expect expression"),
+            (Severity::Deny, 2, "Expect variable name."),
+        ]
+    );
+}
+
+#[test]
+fn finds_nothing_in_a_correct_program() {
+    assert!(check("var a = 1;\nprint a;\n").is_empty());
+}
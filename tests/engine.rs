@@ -0,0 +1,129 @@
+//! `Engine`: interpreters spawned from one share its natives but nothing
+//! a script defines.
+
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::thread;
+
+use crafting_rust::operators::Precedence;
+use crafting_rust::script_engine::{LoxEngine, LoxError, ScriptEngine};
+use crafting_rust::{Engine, NativeContext, Value};
+
+fn double(_: &mut NativeContext, args: &[Value]) -> Result<Value, String> {
+    match args[0] {
+        Value::Number(n) => Ok(Value::Number(n * 2.0)),
+        _ => Err("double expects a number.".to_string()),
+    }
+}
+
+fn numbers(_: &mut NativeContext, _: &[Value]) -> Result<Value, String> {
+    let items = (1..=3).map(|n| Value::Number(n as f64)).collect();
+    Ok(Value::List(Rc::new(RefCell::new(items))))
+}
+
+fn isolate(engine: &Engine) -> LoxEngine {
+    LoxEngine::with_interpreter(engine.spawn())
+}
+
+#[test]
+fn isolates_share_registered_natives() {
+    let mut engine = Engine::new();
+    engine.register_native("double", 1, double);
+    let (mut a, mut b) = (isolate(&engine), isolate(&engine));
+    assert_eq!(a.eval("double(2);"), Ok(Value::Number(4.0)));
+    assert_eq!(b.eval("double(5);"), Ok(Value::Number(10.0)));
+}
+
+#[test]
+fn isolates_keep_their_own_globals() {
+    let mut engine = Engine::new();
+    engine.register_native("double", 1, double);
+    let (mut a, mut b) = (isolate(&engine), isolate(&engine));
+    a.eval("var x = 1; double = clock;").unwrap();
+    assert!(b.eval("x;").is_err());
+    assert_eq!(b.eval("double(1);"), Ok(Value::Number(2.0)));
+    assert_eq!(isolate(&engine).eval("double(3);"), Ok(Value::Number(6.0)));
+}
+
+fn spaceship(left: &Value, right: &Value) -> Result<Value, String> {
+    match (left, right) {
+        (Value::Number(l), Value::Number(r)) => Ok(Value::Number(l.total_cmp(r) as i8 as f64)),
+        _ => Err("<=> compares numbers.".to_string()),
+    }
+}
+
+#[test]
+fn operators_belong_to_the_engine_they_were_registered_on() {
+    let mut engine = Engine::new();
+    engine
+        .register_operator("<=>", Precedence::Comparison, spaceship)
+        .unwrap();
+    assert_eq!(
+        isolate(&engine).eval("1 + 1 <=> 3;"),
+        Ok(Value::Number(-1.0))
+    );
+    // An engine without it scans `<=` then `>`.
+    assert!(isolate(&Engine::new()).eval("1 <=> 3;").is_err());
+}
+
+#[test]
+fn threads_share_an_engine() {
+    let mut engine = Engine::new();
+    engine.register_native("double", 1, double);
+    let run = |n: u32| {
+        let engine = &engine;
+        move || {
+            isolate(engine)
+                .eval(&format!("double({n});"))
+                .unwrap()
+                .to_string()
+        }
+    };
+    let (a, b) = thread::scope(|s| {
+        let a = s.spawn(run(1));
+        let b = s.spawn(run(2));
+        (a.join().unwrap(), b.join().unwrap())
+    });
+    assert_eq!((a.as_str(), b.as_str()), ("2", "4"));
+    // And one can be handed over whole.
+    let moved = thread::spawn(move || isolate(&engine).eval("double(3);").unwrap().to_string());
+    assert_eq!(moved.join().unwrap(), "6");
+}
+
+#[test]
+fn deep_recursion_stops_at_the_depth_limit_on_a_small_stack() {
+    thread::Builder::new()
+        .stack_size(512 * 1024)
+        .spawn(|| {
+            let mut engine = LoxEngine::new();
+            let depth = "fun d(n) { if (n == 0) return 0; return 1 + d(n - 1); } d(900);";
+            assert_eq!(engine.eval(depth), Ok(Value::Number(900.0)));
+            assert_eq!(
+                engine.eval("fun f() {\n  return f();\n}\nf();"),
+                Err(LoxError::Runtime(
+                    "<input>:2:12: Stack overflow.".to_string()
+                ))
+            );
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+}
+
+#[test]
+fn parallel_map_reports_a_worker_runtime_error() {
+    let mut engine = Engine::new();
+    engine.register_native("numbers", 0, numbers);
+    let mut lox = isolate(&engine);
+    let doubled = lox.eval("fun twice(x) { return x * 2; } parallelMap(twice, numbers());");
+    assert_eq!(
+        doubled.map(|list| list.to_string()),
+        Ok("[2, 4, 6]".to_string())
+    );
+    match lox.eval("fun fail(x) { return -\"a\"; } parallelMap(fail, numbers());") {
+        Err(LoxError::Runtime(message)) => {
+            assert!(message.contains("must be a number"), "{}", message)
+        }
+        other => panic!("expected a runtime error, got {:?}", other),
+    }
+}
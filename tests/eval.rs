@@ -0,0 +1,25 @@
+//! `eval` and `eval_with_print`, the text-in, text-out API a browser
+//! playground uses.
+
+use std::{cell::RefCell, rc::Rc};
+
+use crafting_rust::{eval, eval_with_print};
+
+#[test]
+fn returns_output_and_errors_in_order() {
+    let transcript = eval("print 1 + 2;\nprint \"a\" + \"b\";\nprint -\"c\";\nprint 4;\n");
+    assert_eq!(transcript, "3\nab\nString(\"c\") must be a number\n");
+}
+
+#[test]
+fn passes_printed_lines_to_the_callback() {
+    let printed = Rc::new(RefCell::new(Vec::new()));
+    let errors = {
+        let printed = Rc::clone(&printed);
+        eval_with_print("print \"one\";\nprint 2;\nprint ;\n", move |line| {
+            printed.borrow_mut().push(line.to_string())
+        })
+    };
+    assert_eq!(*printed.borrow(), ["one", "2"]);
+    assert_eq!(errors, "<input>:3:7: Error at ';': Expect expression\n");
+}
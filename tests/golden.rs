@@ -0,0 +1,19 @@
+//! Runs the `.lox` programs under `tests/lox` through `jlox test`, so
+//! `cargo test` checks the interpreter against their `// expect:` comments.
+
+use std::path::Path;
+use std::process::Command;
+
+#[test]
+fn golden_files() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("lox");
+    let output = Command::new(env!("CARGO_BIN_EXE_crafting_rust"))
+        .arg("test")
+        .arg(&dir)
+        .output()
+        .expect("failed to start the interpreter");
+    let report = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "golden tests failed:\n{}", report);
+}
@@ -0,0 +1,151 @@
+//! `jlox kernel` talking to a frontend over ZeroMQ: signed requests get
+//! signed replies, a cell's output goes out on iopub, and a shutdown
+//! request ends the process.
+
+#![cfg(all(feature = "kernel", not(target_arch = "wasm32")))]
+
+use std::fs;
+use std::net::TcpListener;
+use std::process::Command;
+
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+const KEY: &[u8] = b"a-test-key";
+
+fn free_port() -> u16 {
+    TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port()
+}
+
+fn sign(parts: &[&[u8]]) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(KEY).unwrap();
+    for part in parts {
+        mac.update(part);
+    }
+    hex::encode(mac.finalize().into_bytes()).into_bytes()
+}
+
+fn send(socket: &zmq::Socket, msg_type: &str, content: &str) {
+    let header = format!(
+        "{{\"msg_id\":\"{0}\",\"session\":\"test\",\"username\":\"test\",\
+         \"msg_type\":\"{0}\",\"version\":\"5.3\"}}",
+        msg_type
+    );
+    let parts: [&[u8]; 4] = [header.as_bytes(), b"{}", b"{}", content.as_bytes()];
+    let mut frames = vec![b"<IDS|MSG>".to_vec(), sign(&parts)];
+    frames.extend(parts.iter().map(|part| part.to_vec()));
+    socket.send_multipart(frames, 0).unwrap();
+}
+
+/// The header and content of the next message on `socket`, after checking
+/// its signature.
+fn receive(socket: &zmq::Socket) -> (String, String) {
+    let frames = socket.recv_multipart(0).unwrap();
+    let split = frames
+        .iter()
+        .position(|frame| frame == b"<IDS|MSG>")
+        .unwrap();
+    let [signature, header, parent, metadata, content] = &frames[split + 1..] else {
+        panic!("a message with {} frames", frames.len());
+    };
+    assert_eq!(*signature, sign(&[header, parent, metadata, content]));
+    let text = |bytes: &[u8]| String::from_utf8(bytes.to_vec()).unwrap();
+    (text(header), text(content))
+}
+
+#[test]
+fn runs_cells_for_a_frontend() {
+    let ports: Vec<u16> = (0..5).map(|_| free_port()).collect();
+    let path = std::env::temp_dir().join(format!("jlox-kernel-{}.json", std::process::id()));
+    fs::write(
+        &path,
+        format!(
+            "{{\"ip\":\"127.0.0.1\",\"transport\":\"tcp\",\"signature_scheme\":\"hmac-sha256\",\
+             \"key\":\"{}\",\"shell_port\":{},\"control_port\":{},\"iopub_port\":{},\
+             \"stdin_port\":{},\"hb_port\":{}}}",
+            String::from_utf8_lossy(KEY),
+            ports[0],
+            ports[1],
+            ports[2],
+            ports[3],
+            ports[4]
+        ),
+    )
+    .unwrap();
+    let mut kernel = Command::new(env!("CARGO_BIN_EXE_crafting_rust"))
+        .arg("kernel")
+        .arg(&path)
+        .spawn()
+        .expect("failed to start the kernel");
+
+    let context = zmq::Context::new();
+    let connect = |socket_type, port: u16| {
+        let socket = context.socket(socket_type).unwrap();
+        socket.set_rcvtimeo(10_000).unwrap();
+        socket.set_linger(0).unwrap();
+        socket
+            .connect(&format!("tcp://127.0.0.1:{}", port))
+            .unwrap();
+        socket
+    };
+    let shell = connect(zmq::DEALER, ports[0]);
+    let control = connect(zmq::DEALER, ports[1]);
+    let iopub = connect(zmq::SUB, ports[2]);
+    iopub.set_subscribe(b"").unwrap();
+    let heartbeat = connect(zmq::REQ, ports[4]);
+
+    heartbeat.send("ping", 0).unwrap();
+    assert_eq!(heartbeat.recv_bytes(0).unwrap(), b"ping");
+
+    // Keep asking until iopub has connected, since a PUB socket drops
+    // whatever it sends before then.
+    loop {
+        send(&shell, "kernel_info_request", "{}");
+        let (header, content) = receive(&shell);
+        assert!(
+            header.contains("\"msg_type\":\"kernel_info_reply\""),
+            "{}",
+            header
+        );
+        assert!(content.contains("\"language_info\""), "{}", content);
+        if iopub.poll(zmq::POLLIN, 500).unwrap() > 0 {
+            break;
+        }
+    }
+    while iopub.poll(zmq::POLLIN, 200).unwrap() > 0 {
+        iopub.recv_multipart(0).unwrap();
+    }
+
+    send(
+        &shell,
+        "execute_request",
+        "{\"code\":\"print 1 + 2;\",\"silent\":false}",
+    );
+    let (_, content) = receive(&shell);
+    assert!(content.contains("\"status\":\"ok\""), "{}", content);
+    let mut streams = Vec::new();
+    loop {
+        let (header, content) = receive(&iopub);
+        if header.contains("\"msg_type\":\"stream\"") {
+            streams.push(content);
+        } else if content.contains("\"execution_state\":\"idle\"") {
+            break;
+        }
+    }
+    assert_eq!(streams, ["{\"name\":\"stdout\",\"text\":\"3\\n\"}"]);
+
+    send(&control, "shutdown_request", "{\"restart\":false}");
+    let (header, _) = receive(&control);
+    assert!(
+        header.contains("\"msg_type\":\"shutdown_reply\""),
+        "{}",
+        header
+    );
+    let status = kernel.wait().unwrap();
+    fs::remove_file(&path).unwrap();
+    assert!(status.success());
+}
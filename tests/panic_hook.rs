@@ -0,0 +1,25 @@
+//! Runtime errors unwind without running the panic hook, so embedding Lox
+//! leaves the host's hook alone. In a file of its own, since the hook is
+//! global to the process.
+
+use std::panic;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crafting_rust::script_engine::{LoxEngine, ScriptEngine};
+
+static CALLS: AtomicUsize = AtomicUsize::new(0);
+
+#[test]
+fn runtime_errors_leave_the_host_hook_alone() {
+    panic::set_hook(Box::new(|_| {
+        CALLS.fetch_add(1, Ordering::SeqCst);
+    }));
+    let mut engine = LoxEngine::new();
+    assert!(engine.eval("print -\"a\";").is_err());
+    assert_eq!(CALLS.load(Ordering::SeqCst), 0);
+
+    // The host's hook is still the one installed.
+    let _ = panic::catch_unwind(|| panic!("a bug in the host"));
+    let _ = panic::take_hook();
+    assert_eq!(CALLS.load(Ordering::SeqCst), 1);
+}
@@ -0,0 +1,36 @@
+//! `jlox repl` with its input piped in: a runtime error ends the entry,
+//! not the session.
+
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+#[test]
+fn runtime_errors_keep_the_session() {
+    let mut repl = Command::new(env!("CARGO_BIN_EXE_crafting_rust"))
+        .arg("repl")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("failed to start the interpreter");
+    repl.stdin
+        .take()
+        .unwrap()
+        .write_all(b"var a = 1;\nprint nope;\nprint -\"x\";\nprint a;\n")
+        .unwrap();
+    let output = repl.wait_with_output().unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "the REPL failed:\n{}", stdout);
+    let lines: Vec<&str> = stdout
+        .lines()
+        .map(|line| line.trim_start_matches("> "))
+        .collect();
+    assert_eq!(
+        lines,
+        [
+            "<input>:1:7: Undefined variable 'nope'.",
+            "String(\"x\") must be a number",
+            "1",
+            ""
+        ]
+    );
+}
@@ -0,0 +1,38 @@
+//! `jlox run`: a script with static errors reports them, runs none of its
+//! statements and exits 65.
+
+use std::fs;
+use std::process::{Command, Output};
+
+fn run(name: &str, source: &str) -> Output {
+    let path = std::env::temp_dir().join(format!("jlox-run-{}-{}.lox", name, std::process::id()));
+    fs::write(&path, source).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_crafting_rust"))
+        .arg("run")
+        .arg(&path)
+        .output()
+        .expect("failed to start the interpreter");
+    fs::remove_file(&path).unwrap();
+    output
+}
+
+#[test]
+fn a_script_that_does_not_parse_does_not_run() {
+    let output = run("syntax", "print \"before\";\nvar = 1;\nprint \"after\";\n");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("Expect variable name."), "{}", stdout);
+    assert!(!stdout.contains("before"), "{}", stdout);
+    assert_eq!(output.status.code(), Some(65));
+}
+
+#[test]
+fn a_resolver_error_exits_65() {
+    let output = run("resolve", "const LIMIT = 1;\nLIMIT = 2;\n");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains("Can't assign to constant 'LIMIT'."),
+        "{}",
+        stdout
+    );
+    assert_eq!(output.status.code(), Some(65));
+}
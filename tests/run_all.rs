@@ -0,0 +1,52 @@
+//! `jlox run-all`: stdout holds only the JSON summary, with each script's
+//! output inside it.
+
+use std::fs;
+use std::process::Command;
+
+#[test]
+fn summary_is_the_only_thing_on_stdout() {
+    let dir = std::env::temp_dir().join(format!("jlox-run-all-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("a.lox"), "print \"hello\";\n").unwrap();
+    fs::write(dir.join("b.lox"), "print 1;\nprint -\"x\";\n").unwrap();
+    fs::write(dir.join("a.expected"), "hello\n").unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_crafting_rust"))
+        .args(["run-all", "--keep-going"])
+        .arg(&dir)
+        .output()
+        .expect("failed to start the interpreter");
+    fs::remove_dir_all(&dir).unwrap();
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let path = |name: &str| format!("{:?}", dir.join(name).display().to_string());
+    assert_eq!(
+        stdout,
+        format!(
+            concat!(
+                "{{\"passed\":1,\"failed\":1,\"files\":[",
+                "{{\"path\":{},\"status\":\"ok\",\"message\":\"\",\"output\":[\"hello\"]}},",
+                "{{\"path\":{},\"status\":\"runtime-error\",",
+                "\"message\":\"String(\\\"x\\\") must be a number\",\"output\":[\"1\"]}}",
+                "]}}\n"
+            ),
+            path("a.lox"),
+            path("b.lox")
+        )
+    );
+    assert_eq!(output.status.code(), Some(70));
+}
+
+#[test]
+fn a_missing_directory_is_reported() {
+    let dir = std::env::temp_dir().join(format!("jlox-run-all-missing-{}", std::process::id()));
+    let output = Command::new(env!("CARGO_BIN_EXE_crafting_rust"))
+        .arg("run-all")
+        .arg(&dir)
+        .output()
+        .expect("failed to start the interpreter");
+    assert_eq!(output.stdout, b"");
+    assert!(String::from_utf8_lossy(&output.stderr).starts_with("Could not read "));
+    assert_eq!(output.status.code(), Some(66));
+}
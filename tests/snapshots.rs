@@ -0,0 +1,44 @@
+//! Checks each program under `tests/snapshots` against the `.snap` file
+//! next to it: what it printed, what it reported and the globals it left.
+//! Run with `UPDATE_SNAPSHOTS=1` to rewrite the files after a deliberate
+//! change, then review the diff.
+
+use std::{env, fs, path::Path};
+
+use crafting_rust::run_and_capture;
+
+#[test]
+fn snapshots() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("snapshots");
+    let update = env::var_os("UPDATE_SNAPSHOTS").is_some();
+    let mut paths: Vec<_> = fs::read_dir(&dir)
+        .expect("failed to read the snapshot corpus")
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "lox"))
+        .collect();
+    paths.sort();
+
+    let mut failures = Vec::new();
+    for path in &paths {
+        let source = fs::read_to_string(path).unwrap();
+        let actual = run_and_capture(&source).to_string();
+        let snapshot = path.with_extension("snap");
+        if update {
+            fs::write(&snapshot, &actual).unwrap();
+            continue;
+        }
+        match fs::read_to_string(&snapshot) {
+            Ok(expected) if expected == actual => {}
+            Ok(expected) => failures.push(format!(
+                "{} changed.\n--- expected\n{}--- actual\n{}",
+                path.display(),
+                expected,
+                actual
+            )),
+            Err(_) => failures.push(format!("{} has no snapshot.", path.display())),
+        }
+    }
+    assert!(failures.is_empty(), "{}", failures.join("\n"));
+}